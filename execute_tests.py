@@ -1,10 +1,11 @@
 import subprocess
 
 def run_rust_tests():
-    directories = ["rc", "rrc", "mset-mu-hash", "unf-arc-based-on-rc", "unf-arc-based-on-rrc", "s-rid-rc"]
+    # The crates are members of the workspace at the root of the repository, which shares a single Cargo.lock.
+    directories = ["rc", "rrc", "mset-mu-hash", "unf-arc-based-on-rc", "unf-arc-based-on-rrc", "s-rid-rc", "secure-channel", "messaging"]
     for directory in directories:
         try:
-            result = subprocess.run("cd " + directory + "/src/ ;cargo test;cd ../..", shell=True, text=True)
+            result = subprocess.run("cargo test -p " + directory, shell=True, text=True)
 
             if result.returncode == 0:
                 print(f"Directory {directory} tests passed successfully.")
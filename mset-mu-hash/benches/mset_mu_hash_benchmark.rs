@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, black_box};
+use mset_mu_hash::RistrettoHash;
+use sha2::Sha512;
+
+fn add_vs_extend_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Multiset hash of n elements");
+    for n in [10usize, 100, 1000] {
+        let elements: Vec<[u8; 8]> = (0..n).map(|i| i.to_be_bytes()).collect();
+
+        group.bench_with_input(BenchmarkId::new("add", n), &elements, |b, elements| {
+            b.iter(|| {
+                let mut hash = RistrettoHash::<Sha512>::default();
+                for element in elements.iter() {
+                    hash.add(element, 1);
+                }
+                black_box(hash.finalize())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("extend", n), &elements, |b, elements| {
+            b.iter(|| {
+                let mut hash = RistrettoHash::<Sha512>::default();
+                hash.extend(elements.iter().map(|element| (element, 1)));
+                black_box(hash.finalize())
+            })
+        });
+    }
+    group.finish();
+}
+
+// Lists all benchmark functions from the 'benches' group.
+criterion_group!(benches, add_vs_extend_benchmark);
+// Acts as a main function and runs all benchmarks in 'benches' group
+criterion_main!(benches);
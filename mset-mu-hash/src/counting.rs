@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use digest::{consts::U64, Digest, Output};
+
+use crate::hash::{encode_tuple, MultisetHashError, RistrettoHash};
+
+/// Wraps a `RistrettoHash` and keeps track of the cardinality of the hashed multiset.
+///
+/// In set mode (see `new_set`), every element may only be inserted once: inserting it a second time,
+/// or with a multiplicity above one, is rejected with `MultisetHashError::DuplicateElement` and leaves
+/// the hash untouched.
+#[derive(Clone)]
+pub struct CountingRistrettoHash<H: Digest> {
+    hash: RistrettoHash<H>,
+    cardinality: u128,
+    seen: Option<HashSet<Output<H>>>,
+}
+
+impl<H: Digest<OutputSize = U64> + Default> CountingRistrettoHash<H> {
+    /// Creates an empty multiset-hash, where elements may be added any number of times.
+    pub fn new() -> Self {
+        CountingRistrettoHash { hash: RistrettoHash::default(), cardinality: 0, seen: None }
+    }
+
+    /// Creates an empty set-hash, which rejects any element that was already inserted.
+    pub fn new_set() -> Self {
+        CountingRistrettoHash { hash: RistrettoHash::default(), cardinality: 0, seen: Some(HashSet::new()) }
+    }
+
+    /// Returns true if the hash rejects duplicate elements.
+    pub fn is_set(&self) -> bool {
+        self.seen.is_some()
+    }
+
+    /// Returns the number of elements in the multiset, counted with their multiplicities.
+    pub fn cardinality(&self) -> u128 {
+        self.cardinality
+    }
+
+    /// Adds the element to the hash multiplicity number of times.
+    pub fn add(&mut self, data: impl AsRef<[u8]>, multiplicity: u64) -> Result<(), MultisetHashError> {
+        if let Some(seen) = &mut self.seen {
+            if multiplicity > 1 {
+                return Err(MultisetHashError::DuplicateElement);
+            }
+            if multiplicity == 1 && !seen.insert(H::digest(data.as_ref())) {
+                return Err(MultisetHashError::DuplicateElement);
+            }
+        }
+        self.hash.try_add(data, multiplicity)?;
+        self.cardinality += u128::from(multiplicity);
+        Ok(())
+    }
+
+    /// Adds a structured element made of several fields, encoded with `encode_tuple`, multiplicity number of times.
+    pub fn add_tuple(&mut self, fields: &[&[u8]], multiplicity: u64) -> Result<(), MultisetHashError> {
+        self.add(encode_tuple(fields), multiplicity)
+    }
+
+    /// Returns the multiset hash together with the cardinality of the multiset.
+    pub fn finalize(self) -> ([u8; 32], u128) {
+        (self.hash.finalize(), self.cardinality)
+    }
+}
+
+impl<H: Digest<OutputSize = U64> + Default> Default for CountingRistrettoHash<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
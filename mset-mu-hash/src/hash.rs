@@ -1,10 +1,49 @@
 // Credits go to @cronokirby for heavily inspiring this crate based on this blog: https://cronokirby.com/posts/2021/07/on_multi_set_hashing/
 
-use curve25519_dalek::{ristretto::RistrettoPoint, scalar::Scalar};
+use curve25519_dalek::{ristretto::RistrettoPoint, scalar::Scalar, traits::VartimeMultiscalarMul};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use digest::{
-    consts::U64,
+    consts::{U32, U64},
     Digest, Reset, Update
 };
+use std::fmt;
+
+/// Errors returned by the fallible methods of `RistrettoHash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultisetHashError {
+    /// An element was started with `Update::update` and `end_update` has not been called yet.
+    ElementInProgress,
+    /// An element was inserted more than once into a hash in set mode.
+    DuplicateElement,
+}
+
+impl fmt::Display for MultisetHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultisetHashError::ElementInProgress => write!(f, "an element is still being updated, call end_update first"),
+            MultisetHashError::DuplicateElement => write!(f, "the element is already part of the set"),
+        }
+    }
+}
+
+impl std::error::Error for MultisetHashError {}
+
+/// Encodes a structured element (a tuple of byte fields) into a single unambiguous byte string.
+///
+/// The encoding is the number of fields followed by every field prefixed with its length, all
+/// lengths being 8 byte big-endian integers: `n || len(f_1) || f_1 || ... || len(f_n) || f_n`.
+/// Two different tuples therefore never encode to the same bytes, unlike a plain concatenation.
+pub fn encode_tuple(fields: &[&[u8]]) -> Vec<u8> {
+    let total_len = 8 + fields.iter().map(|field| 8 + field.len()).sum::<usize>();
+    let mut bytes = Vec::with_capacity(total_len);
+    bytes.extend_from_slice(&(fields.len() as u64).to_be_bytes());
+    for field in fields {
+        bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(field);
+    }
+    bytes
+}
 
 #[derive(Clone)]
 pub struct RistrettoHash<H> {
@@ -15,18 +54,92 @@ pub struct RistrettoHash<H> {
 
 impl<H: Digest<OutputSize = U64> + Default> RistrettoHash<H> {
     /// This function updates the multiset-hash with the given byte element multiplicity number of times.
+    ///
+    /// Panics if an element started with `update` has not been ended, see `try_add` for the fallible version.
     pub fn add(&mut self, data: impl AsRef<[u8]>, multiplicity: u64) {
-        if self.updating {
-            panic!("add called before end_update");
-        }
-        self.hash.update(data);
-        self.end_update(multiplicity);
+        self.try_add(data, multiplicity).expect("add called before end_update");
+    }
+
+    /// Same as `add`, but returns an error instead of panicking if an element started with `update` is still pending.
+    pub fn try_add(&mut self, data: impl AsRef<[u8]>, multiplicity: u64) -> Result<(), MultisetHashError> {
+        self.ensure_not_updating()?;
+        self.acc += Scalar::from(multiplicity) * RistrettoPoint::from_hash(H::new_with_prefix(data));
+        Ok(())
+    }
+
+    /// Removes the given byte element multiplicity number of times, undoing the corresponding `add`.
+    ///
+    /// The caller is responsible for only removing elements which were added: the hash of a multiset
+    /// with "negative" multiplicities is well defined but corresponds to no actual multiset.
+    /// Panics if an element started with `update` has not been ended, see `try_remove` for the fallible version.
+    pub fn remove(&mut self, data: impl AsRef<[u8]>, multiplicity: u64) {
+        self.try_remove(data, multiplicity).expect("remove called before end_update");
+    }
+
+    /// Same as `remove`, but returns an error instead of panicking if an element started with `update` is still pending.
+    pub fn try_remove(&mut self, data: impl AsRef<[u8]>, multiplicity: u64) -> Result<(), MultisetHashError> {
+        self.ensure_not_updating()?;
+        self.acc -= Scalar::from(multiplicity) * RistrettoPoint::from_hash(H::new_with_prefix(data));
+        Ok(())
+    }
+
+    /// Adds a structured element made of several fields, encoded with `encode_tuple`, multiplicity number of times.
+    ///
+    /// Panics if an element started with `update` has not been ended.
+    pub fn add_tuple(&mut self, fields: &[&[u8]], multiplicity: u64) {
+        self.add(encode_tuple(fields), multiplicity);
+    }
+
+    /// Starts a new element whose bytes are provided in several parts:
+    ///
+    /// let mut element = hash.begin_element()?;
+    /// element.update(b"hello ");
+    /// element.update(b"i am Nathan");
+    /// element.finish_element(1);
+    ///
+    /// The element only becomes part of the multiset once `finish_element` is called. Since the
+    /// returned builder borrows the hash, no other element can be added and the hash cannot be
+    /// finalized while it is alive. Dropping the builder without finishing it discards the element.
+    pub fn begin_element(&mut self) -> Result<ElementBuilder<'_, H>, MultisetHashError> {
+        self.ensure_not_updating()?;
+        Ok(ElementBuilder { multiset_hash: self, hash: H::default() })
+    }
+
+    /// Adds every `(element, multiplicity)` pair of the iterator to the multiset-hash at once.
+    ///
+    /// The result is the same as calling `add` on each pair, but the points are combined with a single
+    /// multiscalar multiplication rather than one scalar multiplication per element. With the `rayon`
+    /// feature enabled, hashing the elements to the curve is also done in parallel.
+    ///
+    /// Multiplicities are treated as public values: the variable-time multiscalar multiplication is used.
+    ///
+    /// Panics if an element started with `update` has not been ended, see `try_extend` for the fallible version.
+    pub fn extend<I, D>(&mut self, elements: I)
+    where
+        I: IntoIterator<Item = (D, u64)>,
+        D: AsRef<[u8]>,
+    {
+        self.try_extend(elements).expect("extend called before end_update");
+    }
+
+    /// Same as `extend`, but returns an error instead of panicking if an element started with `update` is still pending.
+    pub fn try_extend<I, D>(&mut self, elements: I) -> Result<(), MultisetHashError>
+    where
+        I: IntoIterator<Item = (D, u64)>,
+        D: AsRef<[u8]>,
+    {
+        self.ensure_not_updating()?;
+        let elements: Vec<(D, u64)> = elements.into_iter().collect();
+        let scalars = elements.iter().map(|(_, multiplicity)| Scalar::from(*multiplicity));
+        let points = hash_elements_to_points::<H, D>(&elements);
+        self.acc += RistrettoPoint::vartime_multiscalar_mul(scalars, points);
+        Ok(())
     }
 
     /// This function should be called to mark the end of an object provided with `update`.
     ///
-    /// This must always be called after calls to `update`, otherwise panics will happen
-    /// when finalizing or adding new objects.
+    /// This must always be called after calls to `update`, otherwise adding new objects or
+    /// finalizing fails (or panics for the non `try_` variants).
     ///
     /// If called without any prior calls to `update`, this function is equivalent
     /// to calling `add` with an empty slice.
@@ -39,15 +152,68 @@ impl<H: Digest<OutputSize = U64> + Default> RistrettoHash<H> {
     }
 
     /// Returns the hash corresponding to the multi-set hash of the RistrettoHash object.
+    ///
+    /// Panics if an element started with `update` has not been ended, see `try_finalize` for the fallible version.
     pub fn finalize(self) -> [u8; 32] {
-        let mut out: [u8;32] = [0; 32];
-        out.copy_from_slice(&self.acc.compress().as_bytes()[..]);
-        return out;
+        self.try_finalize().expect("finalize called before end_update")
+    }
+
+    /// Same as `finalize`, but refuses to produce a hash while an element started with `update` is still pending.
+    pub fn try_finalize(self) -> Result<[u8; 32], MultisetHashError> {
+        self.ensure_not_updating()?;
+        Ok(self.acc.compress().to_bytes())
+    }
+
+    fn ensure_not_updating(&self) -> Result<(), MultisetHashError> {
+        if self.updating {
+            return Err(MultisetHashError::ElementInProgress);
+        }
+        Ok(())
+    }
+}
+
+/// Collects the bytes of a single element of the multiset, see `RistrettoHash::begin_element`.
+pub struct ElementBuilder<'a, H> {
+    multiset_hash: &'a mut RistrettoHash<H>,
+    hash: H,
+}
+
+impl<'a, H: Digest<OutputSize = U64> + Default> ElementBuilder<'a, H> {
+    /// Appends bytes to the element being built.
+    pub fn update(&mut self, data: impl AsRef<[u8]>) -> &mut Self {
+        Digest::update(&mut self.hash, data);
+        self
+    }
+
+    /// Adds the element to the multiset the given multiplicity number of times.
+    pub fn finish_element(self, multiplicity: u64) {
+        self.multiset_hash.acc += Scalar::from(multiplicity) * RistrettoPoint::from_hash(self.hash);
     }
 }
 
+#[cfg(not(feature = "rayon"))]
+fn hash_elements_to_points<H, D>(elements: &[(D, u64)]) -> Vec<RistrettoPoint>
+where
+    H: Digest<OutputSize = U64> + Default,
+    D: AsRef<[u8]>,
+{
+    elements.iter().map(|(data, _)| RistrettoPoint::from_hash(H::new_with_prefix(data))).collect()
+}
+
+#[cfg(feature = "rayon")]
+fn hash_elements_to_points<H, D>(elements: &[(D, u64)]) -> Vec<RistrettoPoint>
+where
+    H: Digest<OutputSize = U64> + Default,
+    D: AsRef<[u8]>,
+{
+    // Hashing to the curve dominates the cost of a batch, so it is the part worth spreading over threads.
+    let data: Vec<&[u8]> = elements.iter().map(|(data, _)| data.as_ref()).collect();
+    data.par_iter().map(|data| RistrettoPoint::from_hash(H::new_with_prefix(data))).collect()
+}
+
 impl<H: Digest<OutputSize = U64> + Default> digest::OutputSizeUser for RistrettoHash<H> {
-    type OutputSize = <H as digest::OutputSizeUser>::OutputSize;
+    /// The multiset hash is a compressed Ristretto point, not an output of the underlying 64 byte digest.
+    type OutputSize = U32;
 }
 
 impl<H: Digest<OutputSize = U64> + Default> Default for RistrettoHash<H> {
@@ -76,8 +242,17 @@ impl<H: Digest<OutputSize = U64> + Default> Update for RistrettoHash<H> {
     }
 }
 
+#[cfg(feature = "get-size")]
+impl<H> get_size::GetSize for RistrettoHash<H> {
+    /// The state of the hasher and the accumulated point are stored inline.
+    fn get_heap_size(&self) -> usize {
+        0
+    }
+}
+
 impl<H: Digest<OutputSize = U64> + Default + Reset> Reset for RistrettoHash<H> {
-    /// Resets the hash object by initializing the accumulator to the generator of the elliptic curve.
+    /// Resets the hash object by initializing the accumulator to the identity of the elliptic curve,
+    /// and discards any element started with `update`.
     fn reset(&mut self) {
         Digest::reset(&mut self.hash);
         self.updating = false;
@@ -1,4 +1,6 @@
+mod counting;
 mod hash;
 mod tests;
 
-pub use hash::RistrettoHash;
\ No newline at end of file
+pub use counting::CountingRistrettoHash;
+pub use hash::{RistrettoHash, ElementBuilder, MultisetHashError, encode_tuple};
\ No newline at end of file
@@ -1,8 +1,8 @@
 #[cfg(test)]
 mod tests {
-    use digest::{Update, Reset};
+    use digest::{Update, Reset, OutputSizeUser};
     use sha2::Sha512;
-    use crate::RistrettoHash;
+    use crate::{RistrettoHash, MultisetHashError, CountingRistrettoHash, encode_tuple};
 
     #[test]
     fn implementation_has_safety() {
@@ -48,4 +48,207 @@ mod tests {
         let hash2 = RistrettoHash::<Sha512>::default();
         assert_eq!(hash1.finalize(), hash2.finalize());
     }
+
+    #[test]
+    fn extend_matches_repeated_add() {
+        let mut hash1 = RistrettoHash::<Sha512>::default();
+        hash1.add(b"cat", 2);
+        hash1.add(b"dog", 1);
+        hash1.add(b"bird", 7);
+
+        let mut hash2 = RistrettoHash::<Sha512>::default();
+        hash2.extend(vec![(b"dog".to_vec(), 1), (b"bird".to_vec(), 7), (b"cat".to_vec(), 2)]);
+
+        assert_eq!(hash1.finalize(), hash2.finalize());
+    }
+
+    #[test]
+    fn extend_can_be_mixed_with_add() {
+        let mut hash1 = RistrettoHash::<Sha512>::default();
+        for i in 0..100u32 {
+            hash1.add(i.to_be_bytes(), 1);
+        }
+
+        let mut hash2 = RistrettoHash::<Sha512>::default();
+        hash2.add(0u32.to_be_bytes(), 1);
+        hash2.extend((1..100u32).map(|i| (i.to_be_bytes(), 1)));
+
+        let empty = RistrettoHash::<Sha512>::default().finalize();
+        let mut hash3 = RistrettoHash::<Sha512>::default();
+        hash3.extend(Vec::<([u8; 4], u64)>::new());
+
+        assert_eq!(hash1.finalize(), hash2.finalize());
+        assert_eq!(hash3.finalize(), empty);
+    }
+
+    #[test]
+    fn element_builder_matches_add() {
+        let mut hash1 = RistrettoHash::<Sha512>::default();
+        hash1.add(b"hello i am Nathan", 3);
+
+        let mut hash2 = RistrettoHash::<Sha512>::default();
+        let mut element = hash2.begin_element().unwrap();
+        element.update(b"hello ").update(b"i am Nathan");
+        element.finish_element(3);
+
+        assert_eq!(hash1.finalize(), hash2.finalize());
+    }
+
+    #[test]
+    fn dropped_element_builder_is_discarded() {
+        let mut hash = RistrettoHash::<Sha512>::default();
+        hash.add(b"cat", 1);
+        {
+            let mut element = hash.begin_element().unwrap();
+            element.update(b"never finished");
+        }
+
+        let mut expected = RistrettoHash::<Sha512>::default();
+        expected.add(b"cat", 1);
+        assert_eq!(hash.finalize(), expected.finalize());
+    }
+
+    #[test]
+    fn pending_update_is_reported_as_error() {
+        let mut hash = RistrettoHash::<Sha512>::default();
+        hash.update(b"half an element");
+
+        assert_eq!(hash.try_add(b"cat", 1), Err(MultisetHashError::ElementInProgress));
+        assert_eq!(hash.try_extend(vec![(b"cat", 1)]), Err(MultisetHashError::ElementInProgress));
+        assert!(hash.begin_element().is_err());
+        assert_eq!(hash.clone().try_finalize(), Err(MultisetHashError::ElementInProgress));
+
+        hash.end_update(1);
+        assert!(hash.try_add(b"cat", 1).is_ok());
+        assert!(hash.try_finalize().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "finalize called before end_update")]
+    fn finalize_refuses_to_run_mid_element() {
+        let mut hash = RistrettoHash::<Sha512>::default();
+        hash.update(b"half an element");
+        hash.finalize();
+    }
+
+    #[test]
+    fn output_size_matches_finalized_hash() {
+        assert_eq!(<RistrettoHash<Sha512> as OutputSizeUser>::output_size(), RistrettoHash::<Sha512>::default().finalize().len());
+    }
+
+    #[test]
+    fn remove_undoes_add() {
+        let mut hash1 = RistrettoHash::<Sha512>::default();
+        hash1.add(b"cat", 3);
+        hash1.add(b"dog", 1);
+        hash1.remove(b"cat", 2);
+        hash1.remove(b"dog", 1);
+
+        let mut hash2 = RistrettoHash::<Sha512>::default();
+        hash2.add(b"cat", 1);
+        assert_eq!(hash1.finalize(), hash2.finalize());
+    }
+
+    #[test]
+    fn counting_hash_tracks_cardinality() {
+        let mut hash = CountingRistrettoHash::<Sha512>::new();
+        hash.add(b"cat", 2).unwrap();
+        hash.add(b"dog", 1).unwrap();
+        hash.add(b"cat", 3).unwrap();
+        assert_eq!(hash.cardinality(), 6);
+
+        let mut plain = RistrettoHash::<Sha512>::default();
+        plain.add(b"cat", 5);
+        plain.add(b"dog", 1);
+        assert_eq!(hash.finalize(), (plain.finalize(), 6));
+    }
+
+    #[test]
+    fn set_mode_rejects_duplicates() {
+        let mut hash = CountingRistrettoHash::<Sha512>::new_set();
+        assert!(hash.is_set());
+        hash.add(b"cat", 1).unwrap();
+        assert_eq!(hash.add(b"cat", 1), Err(MultisetHashError::DuplicateElement));
+        assert_eq!(hash.add(b"dog", 2), Err(MultisetHashError::DuplicateElement));
+        hash.add(b"dog", 1).unwrap();
+
+        let mut expected = CountingRistrettoHash::<Sha512>::new_set();
+        expected.add(b"dog", 1).unwrap();
+        expected.add(b"cat", 1).unwrap();
+        assert_eq!(hash.finalize(), expected.finalize());
+    }
+
+    #[test]
+    fn tuple_elements_are_unambiguous() {
+        assert_ne!(encode_tuple(&[b"ab", b"c"]), encode_tuple(&[b"a", b"bc"]));
+        assert_ne!(encode_tuple(&[b"abc"]), encode_tuple(&[b"abc", b""]));
+
+        // Adding the fields of two tuples as separate elements loses which fields belong together.
+        let mut separate1 = RistrettoHash::<Sha512>::default();
+        separate1.add(b"ordinal 1", 1);
+        separate1.add(b"content A", 1);
+        separate1.add(b"ordinal 2", 1);
+        separate1.add(b"content B", 1);
+        let mut separate2 = RistrettoHash::<Sha512>::default();
+        separate2.add(b"ordinal 1", 1);
+        separate2.add(b"content B", 1);
+        separate2.add(b"ordinal 2", 1);
+        separate2.add(b"content A", 1);
+        assert_eq!(separate1.finalize(), separate2.finalize());
+
+        let mut tuples1 = RistrettoHash::<Sha512>::default();
+        tuples1.add_tuple(&[b"ordinal 1", b"content A"], 1);
+        tuples1.add_tuple(&[b"ordinal 2", b"content B"], 1);
+        let mut tuples2 = RistrettoHash::<Sha512>::default();
+        tuples2.add_tuple(&[b"ordinal 1", b"content B"], 1);
+        tuples2.add_tuple(&[b"ordinal 2", b"content A"], 1);
+        assert_ne!(tuples1.finalize(), tuples2.finalize());
+    }
+
+    fn decode_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// Checks the known-answer vectors of test_vectors/, which are generated independently of this crate.
+    #[test]
+    fn known_answer_vectors_match() {
+        let vectors = include_str!("../test_vectors/mset_mu_hash_sha512.txt");
+        let mut hash = RistrettoHash::<Sha512>::default();
+        let mut name = "";
+        let mut nbr_checked_vectors = 0;
+        for line in vectors.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (key, value) = line.split_once(": ").unwrap();
+            match key {
+                "vector" => {
+                    name = value;
+                    hash = RistrettoHash::<Sha512>::default();
+                }
+                "add" => {
+                    let (element, multiplicity) = value.rsplit_once(' ').unwrap();
+                    hash.add(decode_hex(element), multiplicity.parse().unwrap());
+                }
+                "add_range" => {
+                    let bounds: Vec<u64> = value.split(' ').map(|bound| bound.parse().unwrap()).collect();
+                    hash.extend((bounds[0]..bounds[1]).map(|i| (i.to_be_bytes(), bounds[2])));
+                }
+                "hash" => {
+                    assert_eq!(hash.clone().finalize().to_vec(), decode_hex(value), "vector {}", name);
+                    nbr_checked_vectors += 1;
+                }
+                _ => panic!("unknown key {} in test vectors", key),
+            }
+        }
+        assert_eq!(nbr_checked_vectors, 12);
+    }
+
+    #[test]
+    fn tuple_encoding_matches_test_vectors() {
+        let mut hash = RistrettoHash::<Sha512>::default();
+        hash.add_tuple(&[b"ordinal", b"content"], 1);
+        assert_eq!(
+            encode_tuple(&[b"ordinal", b"content"]),
+            decode_hex("000000000000000200000000000000076f7264696e616c0000000000000007636f6e74656e74")
+        );
+        assert_eq!(hash.finalize().to_vec(), decode_hex("f2c0fc44791958b0ab910e126e78cafb8fe4d3496b469b2c38cb8493e8c9347f"));
+    }
 }
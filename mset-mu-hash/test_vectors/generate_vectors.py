@@ -0,0 +1,179 @@
+"""
+Generates the known-answer vectors of mset-mu-hash (mset_mu_hash_sha512.txt).
+
+This script is deliberately independent of the Rust code: it implements the ristretto255 group
+(RFC 9496) directly on top of Python integers, and only relies on hashlib for SHA-512.
+
+Canonical encoding of a multiset hash:
+    1. every element e (a byte string) is mapped to the point P(e) = ristretto255_from_uniform_bytes(SHA-512(e)),
+    2. the multiset {e_1: m_1, ..., e_n: m_n} is mapped to the point m_1 * P(e_1) + ... + m_n * P(e_n),
+       multiplicities being reduced modulo the group order,
+    3. the resulting point is written with the 32 byte ristretto255 encoding (the empty multiset gives 32 zero bytes).
+Structured elements (tuples of byte fields) are first encoded as
+    n || len(f_1) || f_1 || ... || len(f_n) || f_n
+with n and every length written as 8 byte big-endian integers.
+
+Run with `python3 generate_vectors.py > mset_mu_hash_sha512.txt`.
+"""
+import hashlib
+
+P = 2**255 - 19
+L = 2**252 + 27742317777372353535851937790883648493
+D = (-121665 * pow(121666, P - 2, P)) % P
+SQRT_M1 = 19681161376707505956807079304988542015446066515923890162744021073123829784752
+SQRT_AD_MINUS_ONE = 25063068953384623474111414158702152701244531502492656460079210482610430750235
+INVSQRT_A_MINUS_D = 54469307008909316920995813868745141605393597292927456921205312896311721017578
+ONE_MINUS_D_SQ = (1 - D * D) % P
+D_MINUS_ONE_SQ = ((D - 1) * (D - 1)) % P
+
+IDENTITY = (0, 1, 1, 0)
+
+
+def is_negative(x):
+    return (x % P) & 1
+
+
+def ct_abs(x):
+    x %= P
+    return (-x) % P if is_negative(x) else x
+
+
+def sqrt_ratio_m1(u, v):
+    u %= P
+    v %= P
+    r = (u * pow(v, 3, P)) * pow(u * pow(v, 7, P), (P - 5) // 8, P) % P
+    check = v * r * r % P
+    correct_sign_sqrt = check == u
+    flipped_sign_sqrt = check == (-u) % P
+    flipped_sign_sqrt_i = check == (-u * SQRT_M1) % P
+    if flipped_sign_sqrt or flipped_sign_sqrt_i:
+        r = SQRT_M1 * r % P
+    return correct_sign_sqrt or flipped_sign_sqrt, ct_abs(r)
+
+
+def elligator(t):
+    r = SQRT_M1 * t * t % P
+    u = (r + 1) * ONE_MINUS_D_SQ % P
+    v = (-1 - r * D) * (r + D) % P
+    was_square, s = sqrt_ratio_m1(u, v)
+    s_prime = (-ct_abs(s * t)) % P
+    s = s if was_square else s_prime
+    c = P - 1 if was_square else r
+    n = (c * (r - 1) * D_MINUS_ONE_SQ - v) % P
+    w0 = 2 * s * v % P
+    w1 = n * SQRT_AD_MINUS_ONE % P
+    w2 = (1 - s * s) % P
+    w3 = (1 + s * s) % P
+    return (w0 * w3 % P, w2 * w1 % P, w1 * w3 % P, w0 * w2 % P)
+
+
+def add(p1, p2):
+    x1, y1, z1, t1 = p1
+    x2, y2, z2, t2 = p2
+    a = (y1 - x1) * (y2 - x2) % P
+    b = (y1 + x1) * (y2 + x2) % P
+    c = t1 * 2 * D * t2 % P
+    d = z1 * 2 * z2 % P
+    e, f, g, h = b - a, d - c, d + c, b + a
+    return (e * f % P, g * h % P, f * g % P, e * h % P)
+
+
+def mul(scalar, point):
+    result = IDENTITY
+    scalar %= L
+    while scalar:
+        if scalar & 1:
+            result = add(result, point)
+        point = add(point, point)
+        scalar >>= 1
+    return result
+
+
+def encode(point):
+    x0, y0, z0, t0 = point
+    u1 = (z0 + y0) * (z0 - y0) % P
+    u2 = x0 * y0 % P
+    _, invsqrt = sqrt_ratio_m1(1, u1 * u2 * u2)
+    den1 = invsqrt * u1 % P
+    den2 = invsqrt * u2 % P
+    z_inv = den1 * den2 * t0 % P
+    rotate = is_negative(t0 * z_inv)
+    if rotate:
+        x, y, den_inv = y0 * SQRT_M1 % P, x0 * SQRT_M1 % P, den1 * INVSQRT_A_MINUS_D % P
+    else:
+        x, y, den_inv = x0, y0, den2
+    if is_negative(x * z_inv):
+        y = (-y) % P
+    s = ct_abs(den_inv * (z0 - y))
+    return s.to_bytes(32, "little")
+
+
+def from_uniform_bytes(data):
+    mask = (1 << 255) - 1
+    r0 = int.from_bytes(data[:32], "little") & mask
+    r1 = int.from_bytes(data[32:], "little") & mask
+    return add(elligator(r0), elligator(r1))
+
+
+def hash_to_point(element):
+    return from_uniform_bytes(hashlib.sha512(element).digest())
+
+
+def multiset_hash(elements):
+    acc = IDENTITY
+    for element, multiplicity in elements:
+        acc = add(acc, mul(multiplicity, hash_to_point(element)))
+    return encode(acc)
+
+
+def encode_tuple(fields):
+    out = len(fields).to_bytes(8, "big")
+    for field in fields:
+        out += len(field).to_bytes(8, "big") + field
+    return out
+
+
+def range_elements(start, end, multiplicity):
+    return [(i.to_bytes(8, "big"), multiplicity) for i in range(start, end)]
+
+
+# The ristretto255 base point must encode to the value given in RFC 9496, appendix A.1.
+BASE_POINT = (
+    15112221349535400772501151409588531511454012693041857206046113283949847762202,
+    46316835694926478169428394003475163141307993866256225615783033603165251855960,
+    1,
+    15112221349535400772501151409588531511454012693041857206046113283949847762202
+    * 46316835694926478169428394003475163141307993866256225615783033603165251855960 % P,
+)
+assert encode(BASE_POINT).hex() == "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76"
+assert encode(mul(2, BASE_POINT)).hex() == "6a493210f7499cd17fecb510ae0cea23a110e8d5b901f8acadd3095c73a3b919"
+
+VECTORS = [
+    ("empty_set", [], []),
+    ("single_empty_element", [(b"", 1)], []),
+    ("single_element", [(b"cat", 1)], []),
+    ("multiplicity_zero", [(b"cat", 0)], []),
+    ("multiplicity_two", [(b"cat", 2)], []),
+    ("multiplicity_max_u64", [(b"cat", 2**64 - 1)], []),
+    ("two_elements", [(b"cat", 1), (b"dog", 1)], []),
+    ("mixed_multiplicities", [(b"signal", 1), (b"whatsapp", 2), (b"facebook", 3)], []),
+    ("long_element", [(bytes(range(256)) * 4, 1)], []),
+    ("tuple_element", [(encode_tuple([b"ordinal", b"content"]), 1)], []),
+    ("large_set_1000", [], [(0, 1000, 1)]),
+    ("large_multiset_500", [], [(0, 500, 3)]),
+]
+
+print("# Known-answer vectors for RistrettoHash<Sha512>, generated by generate_vectors.py.")
+print("# add: <element as hex> <multiplicity>")
+print("# add_range: <start> <end> <multiplicity>, adds the 8 byte big-endian encodings of start..end")
+print("# hash: <expected multiset hash as hex>")
+for name, elements, ranges in VECTORS:
+    all_elements = list(elements)
+    print()
+    print("vector: " + name)
+    for element, multiplicity in elements:
+        print("add: %s %d" % (element.hex(), multiplicity))
+    for start, end, multiplicity in ranges:
+        print("add_range: %d %d %d" % (start, end, multiplicity))
+        all_elements += range_elements(start, end, multiplicity)
+    print("hash: " + multiset_hash(all_elements).hex())
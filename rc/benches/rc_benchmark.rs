@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion, black_box};
-use rc::{init_all, State, receive, send};
+use rc::{init_all, init_all_with_kem, State, receive, send};
 
 fn init_all_benchmark(c: &mut Criterion) {
     c.bench_function(
@@ -44,9 +44,29 @@ fn ratchet_decrypt_benchmark(c: &mut Criterion) {
     );
 }
 
+fn kem_ratchet_benchmark(c: &mut Criterion) {
+    let plaintext = black_box(
+        *b"J'ai mis cerbere en enfer."
+    );
+    let (mut alice_state, mut bob_state) = black_box(init_all_with_kem());
+    let associated_data = black_box(  [0;32]);
+
+    // Alice and Bob take turns, so that the DH ratchet steps and the KEM epochs complete.
+    c.bench_function(
+        "Ratchet send & receive with the KEM ratchet ", 
+        |b| b.iter(|| 
+            {
+                let (_, header, ciphertext) = send(&mut alice_state, &associated_data, &plaintext);
+                receive(&mut bob_state, &associated_data, header, &ciphertext);
+                let (_, header, ciphertext) = send(&mut bob_state, &associated_data, &plaintext);
+                receive(&mut alice_state, &associated_data, header, &ciphertext);}
+        )
+    );
+}
+
 
 // Lists all benchmark functions from the 'benches' group.
-criterion_group!(benches, init_all_benchmark, ratchet_encrypt_benchmark, ratchet_decrypt_benchmark);
+criterion_group!(benches, init_all_benchmark, ratchet_encrypt_benchmark, ratchet_decrypt_benchmark, kem_ratchet_benchmark);
 //criterion_group!(benches, send_receive_measures);
 // Acts as a main function and runs all benchamrks in 'benches' group
 criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,176 @@
+use std::io::{Read, Write};
+use std::mem::size_of;
+
+use aes::Aes256;
+use ccm::aead::{AeadInPlace, KeyInit};
+use ccm::consts::{U12, U16};
+use ccm::Ccm;
+use hex_literal::hex;
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{kdf_ck, send, Header, Ordinal, State};
+
+/// Length of the plaintext chunks of an attachment. Each ciphertext chunk is 16 bytes longer, for the tag.
+pub const ATTACHMENT_CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+type Aes256Ccm = Ccm<Aes256, U16, U12>;
+
+/// What the recipient of an attachment needs to decrypt it, sent in a normal rc message.
+///
+/// Since the pointer is an rc plaintext, the detection of rrc covers the attachment through its digest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttachmentPointer {
+    /// Key of this attachment only, derived from the sending chain, which the ratchet protects by encrypting the pointer.
+    pub key: [u8; 32],
+    /// Length of the plaintext.
+    pub len: u64,
+    /// SHA-256 of the encrypted attachment.
+    pub digest: [u8; 32],
+}
+
+impl AttachmentPointer {
+    const ENCODED_LEN: usize = 32 + size_of::<u64>() + 32;
+
+    /// Encodes the pointer as key || len || digest.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.key.to_vec();
+        bytes.extend_from_slice(&self.len.to_be_bytes());
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<AttachmentPointer, &'static str> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err("The attachment pointer has the wrong length.");
+        }
+        let (key, bytes) = bytes.split_at(32);
+        let (len, digest) = bytes.split_at(size_of::<u64>());
+        Ok(AttachmentPointer {
+            key: key.try_into().unwrap(),
+            len: u64::from_be_bytes(len.try_into().unwrap()),
+            digest: digest.try_into().unwrap(),
+        })
+    }
+}
+
+/// Derives the key of the next attachment from the message key of the next message `state` sends.
+///
+/// The number of attachments sent so far is part of the info, so two attachments encrypted before
+/// the same message still get different keys, which the zero prefix of the STREAM nonces requires.
+fn attachment_key(state: &mut State) -> [u8; 32] {
+    let (_, mk) = kdf_ck(&state.CKs);
+    let mut info = hex!("734f734174746163686d656e74").to_vec(); // 'sOsAttachment'
+    info.extend_from_slice(&state.attachments_sent.to_be_bytes());
+    state.attachments_sent += 1;
+    let hk = Hkdf::<Sha256>::new(None, &mk);
+    let mut key = [0u8; 32];
+    hk.expand(&info, &mut key).expect("32 is a valid length for Sha256 to output");
+    key
+}
+
+/// Nonce of the STREAM construction: a zero prefix (the key encrypts a single attachment) || counter || last chunk flag.
+fn chunk_nonce(counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[7..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Reads until `buffer` is full or the reader is exhausted, and returns the number of bytes read.
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return Err("Reading the attachment failed."),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypts an attachment from `reader` to `writer`, chunk by chunk, and returns its pointer.
+///
+/// The attachment is encrypted with AES-256-CCM in the STREAM construction (Hoang et al., "Online
+/// Authenticated-Encryption and its Nonce-Reuse Misuse-Resistance", 2015), so neither side holds it in
+/// memory. The key is derived from the sending chain of `state`, see `attachment_key`, and the pointer must
+/// then be sent to the recipient, with rc's `send` or with rrc, see `send_attachment`.
+pub fn encrypt_attachment<R: Read, W: Write>(state: &mut State, reader: &mut R, writer: &mut W) -> Result<AttachmentPointer, &'static str> {
+    let key = attachment_key(state);
+    let cipher = Aes256Ccm::new(&key.into());
+    let mut hasher = Sha256::new();
+    let mut len = 0u64;
+
+    let mut chunk = vec![0u8; ATTACHMENT_CHUNK_LEN];
+    let mut next = vec![0u8; ATTACHMENT_CHUNK_LEN];
+    let mut chunk_len = read_chunk(reader, &mut chunk)?;
+    for counter in 0u32.. {
+        // The chunk is the last one if the reader has nothing left after it.
+        let next_len = if chunk_len == ATTACHMENT_CHUNK_LEN { read_chunk(reader, &mut next)? } else { 0 };
+        let last = next_len == 0;
+        let mut buffer = chunk[..chunk_len].to_vec();
+        let tag = cipher
+            .encrypt_in_place_detached(&chunk_nonce(counter, last).into(), &[], &mut buffer)
+            .map_err(|_| "Encrypting the attachment failed.")?;
+        buffer.extend_from_slice(&tag);
+        hasher.update(&buffer);
+        writer.write_all(&buffer).map_err(|_| "Writing the attachment failed.")?;
+        len += chunk_len as u64;
+        if last {
+            return Ok(AttachmentPointer { key, len, digest: hasher.finalize().into() });
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        chunk_len = next_len;
+    }
+    Err("The attachment has too many chunks.")
+}
+
+/// Decrypts an attachment from `reader` to `writer`, and returns its length.
+///
+/// Every chunk is authenticated before it is written, and a truncated or reordered attachment is
+/// rejected. The digest of the pointer is only checked once the whole attachment is read, so on Err
+/// what was written must be discarded.
+pub fn decrypt_attachment<R: Read, W: Write>(pointer: &AttachmentPointer, reader: &mut R, writer: &mut W) -> Result<u64, &'static str> {
+    let cipher = Aes256Ccm::new(&pointer.key.into());
+    let mut hasher = Sha256::new();
+    let mut len = 0u64;
+
+    let mut chunk = vec![0u8; ATTACHMENT_CHUNK_LEN + TAG_LEN];
+    let mut next = vec![0u8; ATTACHMENT_CHUNK_LEN + TAG_LEN];
+    let mut chunk_len = read_chunk(reader, &mut chunk)?;
+    for counter in 0u32.. {
+        if chunk_len < TAG_LEN {
+            return Err("The attachment is truncated.");
+        }
+        let next_len = if chunk_len == chunk.len() { read_chunk(reader, &mut next)? } else { 0 };
+        let last = next_len == 0;
+        hasher.update(&chunk[..chunk_len]);
+        let (ciphertext, tag) = chunk[..chunk_len].split_at(chunk_len - TAG_LEN);
+        let mut buffer = ciphertext.to_vec();
+        cipher
+            .decrypt_in_place_detached(&chunk_nonce(counter, last).into(), &[], &mut buffer, ccm::Tag::from_slice(tag))
+            .map_err(|_| "The attachment is corrupted.")?;
+        writer.write_all(&buffer).map_err(|_| "Writing the attachment failed.")?;
+        len += buffer.len() as u64;
+        if last {
+            if <[u8; 32]>::from(hasher.finalize()) != pointer.digest || len != pointer.len {
+                return Err("The digest of the attachment does not match.");
+            }
+            return Ok(len);
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        chunk_len = next_len;
+    }
+    Err("The attachment has too many chunks.")
+}
+
+/// Encrypts an attachment under a key derived from the message key of the message carrying its pointer,
+/// and sends the pointer with rc. The recipient decodes the pointer from the plaintext `receive` returns,
+/// with `AttachmentPointer::decode`.
+pub fn send_attachment<R: Read, W: Write>(state: &mut State, associated_data: &[u8], reader: &mut R, writer: &mut W) -> Result<(Ordinal, Header, Vec<u8>), &'static str> {
+    let pointer = encrypt_attachment(state, reader, writer)?;
+    Ok(send(state, associated_data, &pointer.encode()))
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use crate::protocol::{receive, send, Header, State};
+
+pub type UserId = u32;
+pub type DeviceId = u32;
+
+/// Number of replaced sessions kept per device, to receive the messages which were in flight when a session was replaced.
+pub const MAX_INACTIVE_SESSIONS: usize = 4;
+
+/// Identifies one device of a user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceAddress {
+    pub user: UserId,
+    pub device: DeviceId,
+}
+
+/// A two-party session the `SessionManager` runs with one device, e.g. an rc `State` or, in rrc, an
+/// `RrcState` to run the detection layer per device pair.
+pub trait DeviceSession {
+    type Ciphertext: Clone;
+
+    /// Returns an error if the session refuses to send, e.g. an rrc session frozen by an alarm.
+    fn encrypt(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str>;
+    /// Must leave the session untouched if the ciphertext is rejected, apart from recording a detected attack.
+    fn decrypt(&mut self, associated_data: &[u8; 32], ciphertext: Self::Ciphertext) -> (bool, Vec<u8>);
+}
+
+impl DeviceSession for State {
+    type Ciphertext = (Header, Vec<u8>);
+
+    fn encrypt(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        let (_, header, ct) = send(self, associated_data, plaintext);
+        Ok((header, ct))
+    }
+
+    fn decrypt(&mut self, associated_data: &[u8; 32], (header, ct): Self::Ciphertext) -> (bool, Vec<u8>) {
+        // The ratchet may advance its chains before rejecting a ciphertext, so it receives on a copy.
+        let mut state = self.clone();
+        let (acc, _, pt) = receive(&mut state, associated_data, header, &ct);
+        if acc {
+            *self = state;
+        }
+        (acc, pt)
+    }
+}
+
+/// Sessions with one device, as in Sesame: the active session is used to send, and the inactive ones,
+/// most recent first, can still receive.
+#[derive(Clone)]
+pub struct DeviceRecord<S> {
+    /// Registration id of the device. A device which re-registers, e.g. after being reinstalled, gets a new one.
+    pub registration_id: u32,
+    pub active: S,
+    pub inactive: Vec<S>,
+}
+
+/// Sessions of one device with all the devices of the users it talks to, its own other devices included.
+#[derive(Clone)]
+pub struct SessionManager<S> {
+    pub own: DeviceAddress,
+    pub registration_id: u32,
+    pub devices: BTreeMap<UserId, BTreeMap<DeviceId, DeviceRecord<S>>>,
+}
+
+/// Ciphertext from one device to another.
+#[derive(Clone)]
+pub struct DeviceMessage<C> {
+    pub sender: DeviceAddress,
+    pub sender_registration_id: u32,
+    pub recipient: DeviceAddress,
+    pub ciphertext: C,
+}
+
+pub fn session_manager_init<S>(own: DeviceAddress, registration_id: u32) -> SessionManager<S> {
+    SessionManager { own, registration_id, devices: BTreeMap::new() }
+}
+
+/// Adds a session with a device.
+///
+/// If the device already has a session with the same registration id, the new session replaces it
+/// and the old one becomes inactive. If the device re-registered, all its sessions are stale and are dropped.
+pub fn add_session<S>(manager: &mut SessionManager<S>, address: DeviceAddress, registration_id: u32, session: S) {
+    let devices = manager.devices.entry(address.user).or_default();
+    match devices.remove(&address.device) {
+        Some(mut record) if record.registration_id == registration_id => {
+            record.inactive.insert(0, std::mem::replace(&mut record.active, session));
+            record.inactive.truncate(MAX_INACTIVE_SESSIONS);
+            devices.insert(address.device, record);
+        }
+        _ => {
+            devices.insert(address.device, DeviceRecord { registration_id, active: session, inactive: Vec::new() });
+        }
+    }
+}
+
+/// Updates the devices of a user to the list the server has, as (device, registration id) pairs.
+///
+/// The sessions with devices which are no longer listed or which re-registered are stale and are
+/// dropped. Returns the listed devices with which a session must be established before sending to the user.
+pub fn update_device_list<S>(manager: &mut SessionManager<S>, user: UserId, listed: &[(DeviceId, u32)]) -> Vec<DeviceAddress> {
+    let devices = manager.devices.entry(user).or_default();
+    devices.retain(|device, record| listed.contains(&(*device, record.registration_id)));
+    listed
+        .iter()
+        .filter(|(device, _)| !devices.contains_key(device) && (user, *device) != (manager.own.user, manager.own.device))
+        .map(|&(device, _)| DeviceAddress { user, device })
+        .collect()
+}
+
+/// Encrypts a message to every device of a user, and to the other devices of this user so that they keep a copy of the conversation.
+///
+/// A device whose session refuses to send, e.g. because an alarm froze it, gets no message: the
+/// caller sees which devices are missing from the recipients of the returned messages.
+pub fn encrypt_to_user<S: DeviceSession>(manager: &mut SessionManager<S>, user: UserId, associated_data: &[u8; 32], plaintext: &[u8]) -> Vec<DeviceMessage<S::Ciphertext>> {
+    let (own, registration_id) = (manager.own, manager.registration_id);
+    let mut messages = Vec::new();
+    for (&recipient_user, devices) in manager.devices.iter_mut() {
+        if recipient_user != user && recipient_user != own.user {
+            continue;
+        }
+        for (&device, record) in devices.iter_mut() {
+            let Ok(ciphertext) = record.active.encrypt(associated_data, plaintext) else {
+                continue;
+            };
+            messages.push(DeviceMessage {
+                sender: own,
+                sender_registration_id: registration_id,
+                recipient: DeviceAddress { user: recipient_user, device },
+                ciphertext,
+            });
+        }
+    }
+    messages
+}
+
+/// Decrypts a message from another device.
+///
+/// The active session is tried first, then the inactive ones; an inactive session which accepts the
+/// message becomes active, since the sender uses it.
+pub fn decrypt_from_device<S: DeviceSession>(manager: &mut SessionManager<S>, associated_data: &[u8; 32], msg: DeviceMessage<S::Ciphertext>) -> Result<Vec<u8>, &'static str> {
+    if msg.recipient != manager.own {
+        return Err("The message is for another device.");
+    }
+    let record = manager
+        .devices
+        .get_mut(&msg.sender.user)
+        .and_then(|devices| devices.get_mut(&msg.sender.device))
+        .ok_or("No session with this device.")?;
+    if record.registration_id != msg.sender_registration_id {
+        return Err("The session with this device is stale.");
+    }
+    if let (true, pt) = record.active.decrypt(associated_data, msg.ciphertext.clone()) {
+        return Ok(pt);
+    }
+    for i in 0..record.inactive.len() {
+        if let (true, pt) = record.inactive[i].decrypt(associated_data, msg.ciphertext.clone()) {
+            let session = record.inactive.remove(i);
+            record.inactive.insert(0, std::mem::replace(&mut record.active, session));
+            return Ok(pt);
+        }
+    }
+    Err("No session with this device accepts the message.")
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeSet, HashMap};
+use std::mem::size_of;
+
+use ed25519_dalek::{Keypair, PublicKey as VerifyingKey, Signature, Signer};
+use rand_core::{OsRng, RngCore};
+
+use crate::protocol::{decrypt, encrypt, kdf_ck, receive, send, skip_chain_keys, ChainKey, Header, MessageKey, State};
+
+/// Identifies a member of a group.
+pub type MemberId = u32;
+
+/// Number of generations of a member's sender key the other members keep, so that the messages it
+/// sent just before a rotation can still be received.
+const MAX_GENERATIONS: usize = 2;
+
+/// Associated data of the rc messages carrying a sender key distribution.
+const SENDER_KEY_LABEL: &[u8] = b"rc sender key distribution";
+
+/// Sending chain of this member (Signal's sender key), from which the keys of its group messages are derived.
+#[derive(Clone)]
+pub struct SenderKey {
+    /// Incremented each time the sender key is rotated.
+    pub generation: u32,
+    pub chain_key: ChainKey,
+    /// Number of the next message sent with this chain.
+    pub iteration: usize,
+    /// Ed25519 keypair signing the messages of this generation, stored as bytes since `Keypair` is not Clone.
+    pub signing_keypair: [u8; 64],
+}
+
+/// Sending chain of another member, as received in its distribution message.
+#[derive(Clone)]
+pub struct ReceivedSenderKey {
+    pub generation: u32,
+    pub chain_key: ChainKey,
+    /// Number of the next message expected in this chain.
+    pub iteration: usize,
+    pub verifying_key: VerifyingKey,
+    /// Keys of the messages skipped in this chain, by iteration.
+    pub skipped: HashMap<usize, MessageKey>,
+}
+
+/// What a member sends to each other member over their pairwise rc session, so that they can receive its group messages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SenderKeyDistribution {
+    pub sender: MemberId,
+    pub generation: u32,
+    pub iteration: usize,
+    pub chain_key: ChainKey,
+    pub verifying_key: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupMessage {
+    pub sender: MemberId,
+    pub generation: u32,
+    pub iteration: usize,
+    pub ciphertext: Vec<u8>,
+    /// Signature of the sender over the associated data and all the other fields.
+    pub signature: [u8; 64],
+}
+
+/// Group state of one member.
+#[derive(Clone)]
+pub struct GroupState {
+    pub own_id: MemberId,
+    /// Members of the group, this member included.
+    pub members: BTreeSet<MemberId>,
+    pub sender_key: SenderKey,
+    /// Sender keys of the other members, the newest generation last.
+    pub received: HashMap<MemberId, Vec<ReceivedSenderKey>>,
+}
+
+impl SenderKeyDistribution {
+    const ENCODED_LEN: usize = size_of::<MemberId>() + size_of::<u32>() + size_of::<usize>() + 64;
+
+    /// Encodes the distribution as sender || generation || iteration || chain_key || verifying_key.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::ENCODED_LEN);
+        bytes.extend_from_slice(&self.sender.to_be_bytes());
+        bytes.extend_from_slice(&self.generation.to_be_bytes());
+        bytes.extend_from_slice(&self.iteration.to_be_bytes());
+        bytes.extend_from_slice(&self.chain_key);
+        bytes.extend_from_slice(&self.verifying_key);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SenderKeyDistribution, &'static str> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err("The sender key distribution has the wrong length.");
+        }
+        let (sender, bytes) = bytes.split_at(size_of::<MemberId>());
+        let (generation, bytes) = bytes.split_at(size_of::<u32>());
+        let (iteration, bytes) = bytes.split_at(size_of::<usize>());
+        let (chain_key, verifying_key) = bytes.split_at(32);
+        Ok(SenderKeyDistribution {
+            sender: MemberId::from_be_bytes(sender.try_into().unwrap()),
+            generation: u32::from_be_bytes(generation.try_into().unwrap()),
+            iteration: usize::from_be_bytes(iteration.try_into().unwrap()),
+            chain_key: chain_key.try_into().unwrap(),
+            verifying_key: verifying_key.try_into().unwrap(),
+        })
+    }
+}
+
+fn generate_sender_key(generation: u32) -> SenderKey {
+    let mut chain_key = [0u8; 32];
+    OsRng.fill_bytes(&mut chain_key);
+    SenderKey { generation, chain_key, iteration: 0, signing_keypair: Keypair::generate(&mut OsRng).to_bytes() }
+}
+
+fn signing_keypair(sender_key: &SenderKey) -> Keypair {
+    Keypair::from_bytes(&sender_key.signing_keypair).expect("The keypair was encoded by Keypair::to_bytes().")
+}
+
+/// Associated data of the encryption: ad_len || ad || sender || generation || iteration.
+fn message_associated_data(associated_data: &[u8], sender: MemberId, generation: u32, iteration: usize) -> Vec<u8> {
+    let mut result = associated_data.len().to_be_bytes().to_vec();
+    result.extend_from_slice(associated_data);
+    result.extend_from_slice(&sender.to_be_bytes());
+    result.extend_from_slice(&generation.to_be_bytes());
+    result.extend_from_slice(&iteration.to_be_bytes());
+    result
+}
+
+/// Bytes signed by the sender: the associated data of the encryption followed by the ciphertext.
+fn signed_bytes(associated_data: &[u8], msg: &GroupMessage) -> Vec<u8> {
+    let mut result = message_associated_data(associated_data, msg.sender, msg.generation, msg.iteration);
+    result.extend_from_slice(&msg.ciphertext);
+    result
+}
+
+/// Initializes the group state of `own_id`, with a fresh sender key.
+///
+/// The sender key must then be sent to every other member with `send_sender_key`.
+pub fn group_init(own_id: MemberId, members: &[MemberId]) -> GroupState {
+    let mut members: BTreeSet<MemberId> = members.iter().copied().collect();
+    members.insert(own_id);
+    GroupState { own_id, members, sender_key: generate_sender_key(0), received: HashMap::new() }
+}
+
+/// Returns the current sender key of this member, as the other members need it.
+pub fn sender_key_distribution(group: &GroupState) -> SenderKeyDistribution {
+    SenderKeyDistribution {
+        sender: group.own_id,
+        generation: group.sender_key.generation,
+        iteration: group.sender_key.iteration,
+        chain_key: group.sender_key.chain_key,
+        verifying_key: signing_keypair(&group.sender_key).public.to_bytes(),
+    }
+}
+
+/// Sends the current sender key of this member over its pairwise rc session with another member.
+pub fn send_sender_key(group: &GroupState, session: &mut State) -> (Header, Vec<u8>) {
+    let (_, header, ct) = send(session, SENDER_KEY_LABEL, &sender_key_distribution(group).encode());
+    (header, ct)
+}
+
+/// Receives the sender key of `from` over the pairwise rc session with it.
+///
+/// Returns Err if the rc message is rejected, if `from` is not a member of the group or distributes
+/// the key of someone else, or if the key is not newer than the one this member already has.
+pub fn receive_sender_key(group: &mut GroupState, from: MemberId, session: &mut State, header: Header, ct: &[u8]) -> Result<(), &'static str> {
+    let (acc, _, bytes) = receive(session, SENDER_KEY_LABEL, header, ct);
+    if !acc {
+        return Err("The sender key distribution was rejected.");
+    }
+    let distribution = SenderKeyDistribution::decode(&bytes)?;
+    if distribution.sender != from {
+        return Err("The sender key distribution is not from the session's peer.");
+    }
+    if !group.members.contains(&from) || from == group.own_id {
+        return Err("The sender is not a member of the group.");
+    }
+    let verifying_key = VerifyingKey::from_bytes(&distribution.verifying_key).map_err(|_| "The verifying key is invalid.")?;
+    let keys = group.received.entry(from).or_default();
+    if keys.last().is_some_and(|latest| latest.generation >= distribution.generation) {
+        return Err("The sender key is not newer than the current one.");
+    }
+    keys.push(ReceivedSenderKey {
+        generation: distribution.generation,
+        chain_key: distribution.chain_key,
+        iteration: distribution.iteration,
+        verifying_key,
+        skipped: HashMap::new(),
+    });
+    if keys.len() > MAX_GENERATIONS {
+        keys.remove(0);
+    }
+    Ok(())
+}
+
+/// Encrypts a message to every member of the group with this member's sender key, and signs it.
+pub fn group_send(group: &mut GroupState, associated_data: &[u8], plaintext: &[u8]) -> GroupMessage {
+    let mk: MessageKey;
+    (group.sender_key.chain_key, mk) = kdf_ck(&group.sender_key.chain_key);
+    let (sender, generation, iteration) = (group.own_id, group.sender_key.generation, group.sender_key.iteration);
+    group.sender_key.iteration += 1;
+    let ciphertext = encrypt(&mk, plaintext, &message_associated_data(associated_data, sender, generation, iteration));
+    let mut msg = GroupMessage { sender, generation, iteration, ciphertext, signature: [0; 64] };
+    msg.signature = signing_keypair(&group.sender_key).sign(&signed_bytes(associated_data, &msg)).to_bytes();
+    msg
+}
+
+/// Decrypts a group message from another member, or returns Err if it is not authentic.
+///
+/// Messages may arrive out of order: as in rc, the keys of up to MAX_SKIP skipped messages are kept
+/// until they arrive. The state is left untouched if the message is rejected.
+pub fn group_receive(group: &mut GroupState, associated_data: &[u8], msg: &GroupMessage) -> Result<Vec<u8>, &'static str> {
+    if !group.members.contains(&msg.sender) {
+        return Err("The sender is not a member of the group.");
+    }
+    let keys = group.received.get_mut(&msg.sender).ok_or("No sender key was received from this member.")?;
+    let key = keys.iter_mut().find(|key| key.generation == msg.generation).ok_or("No sender key for this generation.")?;
+    let signature = Signature::try_from(&msg.signature[..]).map_err(|_| "The signature is invalid.")?;
+    key.verifying_key.verify_strict(&signed_bytes(associated_data, msg), &signature).map_err(|_| "The signature is invalid.")?;
+
+    let mut updated = key.clone();
+    let mk = if msg.iteration < updated.iteration {
+        updated.skipped.remove(&msg.iteration).ok_or("The key of this message is no longer available.")?
+    } else {
+        skip_chain_keys(&mut updated.chain_key, &mut updated.iteration, msg.iteration, &mut updated.skipped, |n| n)?;
+        let mk: MessageKey;
+        (updated.chain_key, mk) = kdf_ck(&updated.chain_key);
+        updated.iteration += 1;
+        mk
+    };
+    let plaintext = decrypt(&mk, &msg.ciphertext, &message_associated_data(associated_data, msg.sender, msg.generation, msg.iteration))?;
+    *key = updated;
+    Ok(plaintext)
+}
+
+/// Adds a member to the group, and returns the members this member's sender key must be sent to.
+///
+/// The new member receives the chain at its current iteration, from which the keys of earlier
+/// messages cannot be derived, so the sender key is not rotated.
+pub fn add_member(group: &mut GroupState, member: MemberId) -> Vec<MemberId> {
+    group.members.insert(member);
+    vec![member]
+}
+
+/// Removes a member from the group, forgets its sender keys and rotates this member's sender key,
+/// which the removed member knows. Returns the members the new sender key must be sent to.
+///
+/// Every remaining member must remove the member as well, otherwise the removed member can still
+/// read the messages they send.
+pub fn remove_member(group: &mut GroupState, member: MemberId) -> Vec<MemberId> {
+    group.members.remove(&member);
+    group.received.remove(&member);
+    group.sender_key = generate_sender_key(group.sender_key.generation + 1);
+    group.members.iter().copied().filter(|id| *id != group.own_id).collect()
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+use crate::mlkem::{decapsulate, encapsulate, keygen, CIPHERTEXT_LEN, ENCAPSULATION_KEY_LEN};
+
+/// Number of bytes of an encapsulation key or of a KEM ciphertext carried by one message.
+pub const KEM_CHUNK_LEN: usize = 64;
+/// mixed || epoch || kind || index || chunk length
+const FRAME_HEADER_LEN: usize = 2 * size_of::<u32>() + 1 + 2 * size_of::<u16>();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChunkKind {
+    None = 0,
+    EncapsulationKey = 1,
+    Ciphertext = 2,
+}
+
+/// What a message carries for the KEM ratchet, prefixed to its rc ciphertext and authenticated with it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct KemFrame {
+    /// Number of KEM epochs whose secrets are mixed into the root key of the sending chain.
+    pub mixed: u32,
+    epoch: u32,
+    kind: ChunkKind,
+    index: u16,
+    chunk: Vec<u8>,
+}
+
+/// ML-KEM-768 ratchet, run alongside the DH ratchet in a sparse manner.
+///
+/// In every KEM epoch, one party sends a fresh encapsulation key and the other one the ciphertext
+/// encapsulating a secret to it, both in chunks of `KEM_CHUNK_LEN` bytes carried by their messages.
+/// The chunks are sent in turn until the peer is known to have them all, so lost messages only delay
+/// the epoch. Once the party decapsulating has the secret, it mixes it into `kdf_rk` when creating
+/// its next sending chain, and the other party when deriving the matching receiving chain. The
+/// parties swap roles every epoch, so the secrets of both heal the session.
+#[derive(Clone)]
+pub struct KemRatchet {
+    /// Whether this party sends the encapsulation key of even epochs, i.e. is Alice.
+    pub initiator: bool,
+    /// KEM epoch in progress.
+    pub epoch: u32,
+    /// Number of KEM epochs whose secrets are mixed into the root key.
+    pub mixed: u32,
+    decapsulation_key: Option<Vec<u8>>,
+    /// Encapsulation key or ciphertext being sent, empty if none.
+    outgoing: Vec<u8>,
+    outgoing_kind: ChunkKind,
+    next_chunk: usize,
+    incoming: Vec<u8>,
+    received_chunks: Vec<bool>,
+    /// Secrets of the completed epochs which are not mixed into the root key yet.
+    pub(crate) secrets: BTreeMap<u32, [u8; 32]>,
+}
+
+impl KemFrame {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FRAME_HEADER_LEN + self.chunk.len());
+        bytes.extend_from_slice(&self.mixed.to_be_bytes());
+        bytes.extend_from_slice(&self.epoch.to_be_bytes());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&(self.chunk.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.chunk);
+        bytes
+    }
+
+    /// Decodes the frame at the start of `bytes`, and returns it with the rc ciphertext which follows.
+    pub(crate) fn decode(bytes: &[u8]) -> Result<(KemFrame, &[u8]), &'static str> {
+        if bytes.len() < FRAME_HEADER_LEN {
+            return Err("The KEM frame is truncated.");
+        }
+        let (header, rest) = bytes.split_at(FRAME_HEADER_LEN);
+        let chunk_len = u16::from_be_bytes(header[11..13].try_into().unwrap()) as usize;
+        if chunk_len > KEM_CHUNK_LEN || rest.len() < chunk_len {
+            return Err("The KEM frame is truncated.");
+        }
+        let kind = match header[8] {
+            0 => ChunkKind::None,
+            1 => ChunkKind::EncapsulationKey,
+            2 => ChunkKind::Ciphertext,
+            _ => return Err("The KEM frame is invalid."),
+        };
+        let (chunk, rest) = rest.split_at(chunk_len);
+        let frame = KemFrame {
+            mixed: u32::from_be_bytes(header[0..4].try_into().unwrap()),
+            epoch: u32::from_be_bytes(header[4..8].try_into().unwrap()),
+            kind,
+            index: u16::from_be_bytes(header[9..11].try_into().unwrap()),
+            chunk: chunk.to_vec(),
+        };
+        Ok((frame, rest))
+    }
+}
+
+impl KemRatchet {
+    pub(crate) fn new(initiator: bool) -> KemRatchet {
+        let mut kem = KemRatchet {
+            initiator,
+            epoch: 0,
+            mixed: 0,
+            decapsulation_key: None,
+            outgoing: Vec::new(),
+            outgoing_kind: ChunkKind::None,
+            next_chunk: 0,
+            incoming: Vec::new(),
+            received_chunks: Vec::new(),
+            secrets: BTreeMap::new(),
+        };
+        kem.start_epoch(0);
+        kem
+    }
+
+    /// Bytes held on the heap, for `GetSize`.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.decapsulation_key.as_ref().map_or(0, Vec::capacity)
+            + self.outgoing.capacity()
+            + self.incoming.capacity()
+            + self.received_chunks.capacity()
+            + self.secrets.len() * size_of::<(u32, [u8; 32])>()
+    }
+
+    fn sends_encapsulation_key(&self) -> bool {
+        (self.epoch % 2 == 0) == self.initiator
+    }
+
+    fn start_epoch(&mut self, epoch: u32) {
+        self.epoch = epoch;
+        self.incoming.clear();
+        self.received_chunks.clear();
+        self.next_chunk = 0;
+        if self.sends_encapsulation_key() {
+            let (encapsulation_key, decapsulation_key) = keygen();
+            self.decapsulation_key = Some(decapsulation_key);
+            self.outgoing = encapsulation_key;
+            self.outgoing_kind = ChunkKind::EncapsulationKey;
+        } else {
+            self.decapsulation_key = None;
+            self.outgoing = Vec::new();
+            self.outgoing_kind = ChunkKind::None;
+        }
+    }
+
+    /// Returns the frame of the next message, which carries the next chunk to send, if any.
+    pub(crate) fn next_frame(&mut self) -> KemFrame {
+        if self.outgoing.is_empty() {
+            return KemFrame { mixed: self.mixed, epoch: self.epoch, kind: ChunkKind::None, index: 0, chunk: Vec::new() };
+        }
+        let index = self.next_chunk % self.outgoing.len().div_ceil(KEM_CHUNK_LEN);
+        self.next_chunk += 1;
+        let end = (index * KEM_CHUNK_LEN + KEM_CHUNK_LEN).min(self.outgoing.len());
+        KemFrame {
+            mixed: self.mixed,
+            epoch: self.epoch,
+            kind: self.outgoing_kind,
+            index: index as u16,
+            chunk: self.outgoing[index * KEM_CHUNK_LEN..end].to_vec(),
+        }
+    }
+
+    /// Whether the secrets of the peer's chain, which mixes `peer_mixed` epochs, are all known.
+    pub(crate) fn can_mix(&self, peer_mixed: u32) -> bool {
+        peer_mixed >= self.mixed && (self.mixed..peer_mixed).all(|epoch| self.secrets.contains_key(&epoch))
+    }
+
+    /// Returns the secrets to mix into the receiving chain of the peer, which mixes `peer_mixed` epochs.
+    pub(crate) fn mix_receiving(&mut self, peer_mixed: u32) -> Vec<u8> {
+        let output = self.take_secrets(peer_mixed);
+        // The peer mixed the epoch this party encapsulated, so it is its turn to send an encapsulation key.
+        if peer_mixed > self.epoch {
+            self.start_epoch(peer_mixed);
+        }
+        output
+    }
+
+    /// Returns the secrets to mix into a new sending chain, i.e. those this party decapsulated, which the peer has.
+    pub(crate) fn mix_sending(&mut self) -> Vec<u8> {
+        let until = (self.mixed..self.epoch).take_while(|epoch| self.secrets.contains_key(epoch)).last().map_or(self.mixed, |epoch| epoch + 1);
+        self.take_secrets(until)
+    }
+
+    fn take_secrets(&mut self, until: u32) -> Vec<u8> {
+        let mut output = Vec::new();
+        while self.mixed < until {
+            output.extend_from_slice(&self.secrets.remove(&self.mixed).expect("checked by can_mix"));
+            self.mixed += 1;
+        }
+        output
+    }
+
+    /// Processes the frame of an authenticated message.
+    pub(crate) fn receive_frame(&mut self, frame: &KemFrame) {
+        if frame.epoch != self.epoch {
+            return;
+        }
+        match frame.kind {
+            ChunkKind::EncapsulationKey if !self.sends_encapsulation_key() && !self.secrets.contains_key(&self.epoch) => {
+                if let Some(encapsulation_key) = self.collect(frame, ENCAPSULATION_KEY_LEN) {
+                    if let Ok((secret, ciphertext)) = encapsulate(&encapsulation_key) {
+                        self.secrets.insert(self.epoch, secret);
+                        self.outgoing = ciphertext;
+                        self.outgoing_kind = ChunkKind::Ciphertext;
+                        self.next_chunk = 0;
+                    }
+                }
+            }
+            ChunkKind::Ciphertext if self.decapsulation_key.is_some() => {
+                if let Some(ciphertext) = self.collect(frame, CIPHERTEXT_LEN) {
+                    let decapsulation_key = self.decapsulation_key.take().unwrap();
+                    if let Ok(secret) = decapsulate(&decapsulation_key, &ciphertext) {
+                        self.secrets.insert(self.epoch, secret);
+                        self.start_epoch(self.epoch + 1);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Stores a chunk, and returns the whole value once all its chunks are received.
+    fn collect(&mut self, frame: &KemFrame, len: usize) -> Option<Vec<u8>> {
+        let chunks = len.div_ceil(KEM_CHUNK_LEN);
+        let start = frame.index as usize * KEM_CHUNK_LEN;
+        if frame.index as usize >= chunks || frame.chunk.len() != (len - start).min(KEM_CHUNK_LEN) {
+            return None;
+        }
+        if self.received_chunks.len() != chunks {
+            self.incoming = vec![0; len];
+            self.received_chunks = vec![false; chunks];
+        }
+        self.incoming[start..start + frame.chunk.len()].copy_from_slice(&frame.chunk);
+        self.received_chunks[frame.index as usize] = true;
+        if self.received_chunks.iter().all(|received| *received) {
+            self.received_chunks.clear();
+            Some(std::mem::take(&mut self.incoming))
+        } else {
+            None
+        }
+    }
+}
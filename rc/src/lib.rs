@@ -1,4 +1,19 @@
+mod attachment;
+mod devices;
+mod group;
+mod kem_ratchet;
+mod mlkem;
+mod padding;
 mod protocol;
+mod reset;
+mod sealed;
 mod tests;
 
-pub use protocol::{init_all, send, receive, Ordinal, Header, State, dh, generate_dh};
\ No newline at end of file
+pub use protocol::{init_all, init_all_with_kem, init_all_with_padding, send, receive, Ordinal, Header, State, dh, generate_dh, DiffieHellmanParameters};
+pub use group::{group_init, group_send, group_receive, sender_key_distribution, send_sender_key, receive_sender_key, add_member, remove_member, GroupMessage, GroupState, MemberId, ReceivedSenderKey, SenderKey, SenderKeyDistribution};
+pub use devices::{session_manager_init, add_session, update_device_list, encrypt_to_user, decrypt_from_device, DeviceAddress, DeviceId, DeviceMessage, DeviceRecord, DeviceSession, SessionManager, UserId, MAX_INACTIVE_SESSIONS};
+pub use attachment::{encrypt_attachment, decrypt_attachment, send_attachment, AttachmentPointer, ATTACHMENT_CHUNK_LEN};
+pub use kem_ratchet::{KemRatchet, KEM_CHUNK_LEN};
+pub use padding::{pad, unpad, PaddingPolicy};
+pub use sealed::{certificate_authority_init, certificate_authority_key, issue_sender_certificate, seal, unseal, sealed_send, unseal_message, CertificateAuthority, SealedEnvelope, SenderCertificate};
+pub use reset::{reset_request, reset_respond, reset_finish, reset_request_with_prekey, reset_accept_with_prekey, PendingReset, ResetRequest, ResetResponse};
@@ -0,0 +1,348 @@
+//! ML-KEM-768, as specified in FIPS 203, for the KEM ratchet.
+
+use rand_core::{OsRng, RngCore};
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use sha3::{Digest, Sha3_256, Sha3_512, Shake128, Shake256};
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+const N: usize = 256;
+const Q: u32 = 3329;
+const K: usize = 3;
+const ETA1: usize = 2;
+const ETA2: usize = 2;
+const DU: usize = 10;
+const DV: usize = 4;
+
+pub(crate) const ENCAPSULATION_KEY_LEN: usize = 384 * K + 32;
+pub(crate) const DECAPSULATION_KEY_LEN: usize = 768 * K + 96;
+pub(crate) const CIPHERTEXT_LEN: usize = 32 * (DU * K + DV);
+
+type Poly = [u32; N];
+
+/// floor(2^32 / q), for the Barrett reduction in `reduce()`.
+const BARRETT_Q: u64 = (1 << 32) / Q as u64;
+/// floor(2^32 / 2q), for the Barrett division in `compress()`.
+const BARRETT_2Q: u64 = (1 << 32) / (2 * Q as u64);
+
+// The arithmetic on secret coefficients never uses `/` or `%`, whose timing may depend on the operands
+// (KyberSlash), only multiplications, shifts and masks.
+
+/// x - q if x >= q, else x, for x < 2q.
+fn subtract_q(x: u32) -> u32 {
+    let x = x.wrapping_sub(Q);
+    x.wrapping_add(((x as i32) >> 31) as u32 & Q)
+}
+
+/// x mod q, for x < 2^32, by Barrett reduction: the estimated quotient is floor(x / q) or one less.
+pub(crate) fn reduce(x: u32) -> u32 {
+    let quotient = ((x as u64 * BARRETT_Q) >> 32) as u32;
+    subtract_q(x - quotient * Q)
+}
+
+/// 17^BitRev7(i) mod q, for i in 0..128.
+fn zetas() -> [u32; 128] {
+    let mut zetas = [0u32; 128];
+    for (i, zeta) in zetas.iter_mut().enumerate() {
+        *zeta = pow17((i as u8).reverse_bits() as u32 >> 1);
+    }
+    zetas
+}
+
+fn pow17(exponent: u32) -> u32 {
+    (0..exponent).fold(1, |acc, _| acc * 17 % Q)
+}
+
+fn ntt(f: &mut Poly, zetas: &[u32; 128]) {
+    let mut k = 1;
+    let mut len = 128;
+    while len >= 2 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = zetas[k];
+            k += 1;
+            for j in start..start + len {
+                let t = reduce(zeta * f[j + len]);
+                f[j + len] = subtract_q(f[j] + Q - t);
+                f[j] = subtract_q(f[j] + t);
+            }
+        }
+        len /= 2;
+    }
+}
+
+fn ntt_inverse(f: &mut Poly, zetas: &[u32; 128]) {
+    let mut k = 127;
+    let mut len = 2;
+    while len <= 128 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = zetas[k];
+            k -= 1;
+            for j in start..start + len {
+                let t = f[j];
+                f[j] = subtract_q(t + f[j + len]);
+                f[j + len] = reduce(zeta * subtract_q(f[j + len] + Q - t));
+            }
+        }
+        len *= 2;
+    }
+    for coefficient in f.iter_mut() {
+        *coefficient = reduce(*coefficient * 3303);
+    }
+}
+
+/// Product of two polynomials in the NTT domain.
+fn multiply_ntts(f: &Poly, g: &Poly, zetas: &[u32; 128]) -> Poly {
+    let mut h = [0u32; N];
+    for i in 0..128 {
+        // γ_i = 17^(2 BitRev7(i) + 1) = ζ_(64 + i/2), negated for odd i.
+        let gamma = if i % 2 == 0 { zetas[64 + i / 2] } else { Q - zetas[64 + i / 2] };
+        let (a0, a1, b0, b1) = (f[2 * i], f[2 * i + 1], g[2 * i], g[2 * i + 1]);
+        h[2 * i] = reduce(reduce(a0 * b0) + reduce(a1 * b1) * gamma);
+        h[2 * i + 1] = reduce(a0 * b1 + a1 * b0);
+    }
+    h
+}
+
+fn add(f: &Poly, g: &Poly) -> Poly {
+    std::array::from_fn(|i| subtract_q(f[i] + g[i]))
+}
+
+fn byte_encode(f: &Poly, d: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; 32 * d];
+    for (i, coefficient) in f.iter().enumerate() {
+        for j in 0..d {
+            let bit = i * d + j;
+            bytes[bit / 8] |= (((coefficient >> j) & 1) as u8) << (bit % 8);
+        }
+    }
+    bytes
+}
+
+fn byte_decode(bytes: &[u8], d: usize) -> Poly {
+    let mut f = [0u32; N];
+    for (i, coefficient) in f.iter_mut().enumerate() {
+        for j in 0..d {
+            let bit = i * d + j;
+            *coefficient |= (((bytes[bit / 8] >> (bit % 8)) & 1) as u32) << j;
+        }
+        if d == 12 {
+            // Below 2^12 < 2q, and secret when decoding the decapsulation key.
+            *coefficient = subtract_q(*coefficient);
+        }
+    }
+    f
+}
+
+pub(crate) fn compress(f: &Poly, d: usize) -> Poly {
+    // round(2^d x / q) = floor((2^(d+1) x + q) / 2q), with the division by 2q done by Barrett division:
+    // the estimated quotient is the floor or one less, in which case the remainder is at least 2q.
+    f.map(|x| {
+        let numerator = (x << (d + 1)) + Q;
+        let quotient = ((numerator as u64 * BARRETT_2Q) >> 32) as u32;
+        let remainder = numerator - quotient * 2 * Q;
+        let quotient = quotient + ((2 * Q - 1).wrapping_sub(remainder) >> 31);
+        quotient & ((1 << d) - 1)
+    })
+}
+
+fn decompress(f: &Poly, d: usize) -> Poly {
+    f.map(|y| (y * Q + (1 << (d - 1))) >> d)
+}
+
+/// Algorithm 7: samples a polynomial in the NTT domain from SHAKE128(ρ || j || i).
+fn sample_ntt(rho: &[u8], j: u8, i: u8) -> Poly {
+    let mut xof = Shake128::default();
+    xof.update(rho);
+    xof.update(&[j, i]);
+    let mut reader = xof.finalize_xof();
+    let mut f = [0u32; N];
+    let mut filled = 0;
+    let mut c = [0u8; 3];
+    while filled < N {
+        reader.read(&mut c);
+        let d1 = c[0] as u32 + 256 * (c[1] as u32 % 16);
+        let d2 = c[1] as u32 / 16 + 16 * c[2] as u32;
+        if d1 < Q {
+            f[filled] = d1;
+            filled += 1;
+        }
+        if d2 < Q && filled < N {
+            f[filled] = d2;
+            filled += 1;
+        }
+    }
+    f
+}
+
+/// Algorithm 8: samples a polynomial from the centered binomial distribution of parameter η.
+fn sample_poly_cbd(bytes: &[u8], eta: usize) -> Poly {
+    let bit = |index: usize| ((bytes[index / 8] >> (index % 8)) & 1) as u32;
+    let mut f = [0u32; N];
+    for (i, coefficient) in f.iter_mut().enumerate() {
+        let x: u32 = (0..eta).map(|j| bit(2 * i * eta + j)).sum();
+        let y: u32 = (0..eta).map(|j| bit(2 * i * eta + eta + j)).sum();
+        *coefficient = subtract_q(x + Q - y);
+    }
+    f
+}
+
+fn prf(seed: &[u8; 32], nonce: u8, eta: usize) -> Vec<u8> {
+    let mut xof = Shake256::default();
+    xof.update(seed);
+    xof.update(&[nonce]);
+    let mut output = vec![0u8; 64 * eta];
+    xof.finalize_xof().read(&mut output);
+    output
+}
+
+fn g(input: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut hasher = Sha3_512::new();
+    for part in input {
+        Digest::update(&mut hasher, part);
+    }
+    let output = hasher.finalize();
+    (output[..32].try_into().unwrap(), output[32..].try_into().unwrap())
+}
+
+fn h(input: &[u8]) -> [u8; 32] {
+    Sha3_256::digest(input).into()
+}
+
+fn j(z: &[u8], c: &[u8]) -> [u8; 32] {
+    let mut xof = Shake256::default();
+    xof.update(z);
+    xof.update(c);
+    let mut output = [0u8; 32];
+    xof.finalize_xof().read(&mut output);
+    output
+}
+
+/// Â, in the NTT domain, with Â[i][j] = SampleNTT(ρ || j || i).
+fn matrix(rho: &[u8]) -> [[Poly; K]; K] {
+    let mut a = [[[0u32; N]; K]; K];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = sample_ntt(rho, j as u8, i as u8);
+        }
+    }
+    a
+}
+
+/// Algorithm 13, K-PKE.KeyGen.
+fn pke_keygen(d: &[u8; 32], zetas: &[u32; 128]) -> (Vec<u8>, Vec<u8>) {
+    let (rho, sigma) = g(&[d, &[K as u8]]);
+    let a = matrix(&rho);
+    let mut nonce = 0u8;
+    let mut sample = |eta: usize| {
+        let mut f = sample_poly_cbd(&prf(&sigma, nonce, eta), eta);
+        nonce += 1;
+        ntt(&mut f, zetas);
+        f
+    };
+    let s: [Poly; K] = std::array::from_fn(|_| sample(ETA1));
+    let e: [Poly; K] = std::array::from_fn(|_| sample(ETA1));
+
+    let mut ek = Vec::with_capacity(ENCAPSULATION_KEY_LEN);
+    let mut dk = Vec::with_capacity(384 * K);
+    for i in 0..K {
+        let t = (0..K).fold(e[i], |acc, j| add(&acc, &multiply_ntts(&a[i][j], &s[j], zetas)));
+        ek.extend_from_slice(&byte_encode(&t, 12));
+        dk.extend_from_slice(&byte_encode(&s[i], 12));
+    }
+    ek.extend_from_slice(&rho);
+    (ek, dk)
+}
+
+/// Algorithm 14, K-PKE.Encrypt.
+fn pke_encrypt(ek: &[u8], m: &[u8; 32], r: &[u8; 32], zetas: &[u32; 128]) -> Vec<u8> {
+    let t: Vec<Poly> = ek[..384 * K].chunks(384).map(|bytes| byte_decode(bytes, 12)).collect();
+    let a = matrix(&ek[384 * K..]);
+    let mut nonce = 0u8;
+    let mut sample = |eta: usize| {
+        let f = sample_poly_cbd(&prf(r, nonce, eta), eta);
+        nonce += 1;
+        f
+    };
+    let mut y: [Poly; K] = std::array::from_fn(|_| sample(ETA1));
+    let e1: [Poly; K] = std::array::from_fn(|_| sample(ETA2));
+    let e2 = sample(ETA2);
+    for f in y.iter_mut() {
+        ntt(f, zetas);
+    }
+
+    let mut c = Vec::with_capacity(CIPHERTEXT_LEN);
+    for i in 0..K {
+        let mut u = (0..K).fold([0u32; N], |acc, j| add(&acc, &multiply_ntts(&a[j][i], &y[j], zetas)));
+        ntt_inverse(&mut u, zetas);
+        c.extend_from_slice(&byte_encode(&compress(&add(&u, &e1[i]), DU), DU));
+    }
+    let mut v = (0..K).fold([0u32; N], |acc, j| add(&acc, &multiply_ntts(&t[j], &y[j], zetas)));
+    ntt_inverse(&mut v, zetas);
+    let mu = decompress(&byte_decode(m, 1), 1);
+    c.extend_from_slice(&byte_encode(&compress(&add(&add(&v, &e2), &mu), DV), DV));
+    c
+}
+
+/// Algorithm 15, K-PKE.Decrypt.
+fn pke_decrypt(dk: &[u8], c: &[u8], zetas: &[u32; 128]) -> [u8; 32] {
+    let (c1, c2) = c.split_at(32 * DU * K);
+    let v = decompress(&byte_decode(c2, DV), DV);
+    let mut w = [0u32; N];
+    for i in 0..K {
+        let mut u = decompress(&byte_decode(&c1[32 * DU * i..32 * DU * (i + 1)], DU), DU);
+        ntt(&mut u, zetas);
+        let s = byte_decode(&dk[384 * i..384 * (i + 1)], 12);
+        w = add(&w, &multiply_ntts(&s, &u, zetas));
+    }
+    ntt_inverse(&mut w, zetas);
+    let w: Poly = std::array::from_fn(|i| subtract_q(v[i] + Q - w[i]));
+    byte_encode(&compress(&w, 1), 1).try_into().unwrap()
+}
+
+/// Algorithm 19, ML-KEM.KeyGen. Returns the encapsulation key and the decapsulation key.
+pub(crate) fn keygen() -> (Vec<u8>, Vec<u8>) {
+    let mut d = [0u8; 32];
+    let mut z = [0u8; 32];
+    OsRng.fill_bytes(&mut d);
+    OsRng.fill_bytes(&mut z);
+    keygen_internal(&d, &z)
+}
+
+/// Algorithm 16, ML-KEM.KeyGen_internal, which derives the keys from the seed d || z.
+pub(crate) fn keygen_internal(d: &[u8; 32], z: &[u8; 32]) -> (Vec<u8>, Vec<u8>) {
+    let (ek, mut dk) = pke_keygen(d, &zetas());
+    dk.extend_from_slice(&ek);
+    dk.extend_from_slice(&h(&ek));
+    dk.extend_from_slice(z);
+    (ek, dk)
+}
+
+/// Algorithm 20, ML-KEM.Encaps. Returns the shared secret and the ciphertext, or Err if the encapsulation key is invalid.
+pub(crate) fn encapsulate(ek: &[u8]) -> Result<([u8; 32], Vec<u8>), &'static str> {
+    if ek.len() != ENCAPSULATION_KEY_LEN
+        || ek[..384 * K].chunks(384).any(|bytes| byte_encode(&byte_decode(bytes, 12), 12) != bytes)
+    {
+        return Err("The encapsulation key is invalid.");
+    }
+    let mut m = [0u8; 32];
+    OsRng.fill_bytes(&mut m);
+    let (shared_secret, r) = g(&[&m, &h(ek)]);
+    Ok((shared_secret, pke_encrypt(ek, &m, &r, &zetas())))
+}
+
+/// Algorithm 21, ML-KEM.Decaps, which returns a pseudorandom secret if the ciphertext is invalid (implicit rejection).
+pub(crate) fn decapsulate(dk: &[u8], c: &[u8]) -> Result<[u8; 32], &'static str> {
+    if dk.len() != DECAPSULATION_KEY_LEN || c.len() != CIPHERTEXT_LEN {
+        return Err("The KEM ciphertext has the wrong length.");
+    }
+    let zetas = zetas();
+    let (dk_pke, rest) = dk.split_at(384 * K);
+    let (ek, rest) = rest.split_at(ENCAPSULATION_KEY_LEN);
+    let (ek_hash, z) = rest.split_at(32);
+    let m = pke_decrypt(dk_pke, c, &zetas);
+    let (shared_secret, r) = g(&[&m, ek_hash]);
+    let rejection_secret = j(z, c);
+    // The comparison and the choice of the secret are constant time, so that the time taken does not tell
+    // an attacker probing with modified ciphertexts whether the re-encryption matched.
+    let matches = pke_encrypt(ek, &m, &r, &zetas).ct_eq(c);
+    Ok(std::array::from_fn(|i| u8::conditional_select(&rejection_secret[i], &shared_secret[i], matches)))
+}
@@ -0,0 +1,78 @@
+/// How plaintexts are padded before encryption, so that the length of a ciphertext only reveals a bucket of lengths.
+///
+/// Padding is a 0x80 byte followed by zeros up to the padded length. It is removed after the
+/// ciphertext is authenticated, and both parties of a session must use the same policy: the policy is
+/// part of the associated data, so a message sent with another policy fails the authentication.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// Only the block padding of the cipher, which reveals the length of the plaintext to 16 bytes.
+    #[default]
+    None,
+    /// Pads to the next power of two, which reveals O(log L) bits with an overhead of up to 100%.
+    PowerOfTwo,
+    /// Padmé (Nikitin et al., "Reducing Metadata Leakage from Encrypted Files and Communication with
+    /// PURBs", 2019), which reveals O(log log L) bits with an overhead of at most 12%.
+    Padme,
+    /// Pads to a multiple of the given size, so that all the plaintexts shorter than it have the same length.
+    Fixed(usize),
+}
+
+impl PaddingPolicy {
+    /// Encodes the policy as a tag followed by the size of `Fixed`, as bound into the associated data of each message.
+    pub fn encode(&self) -> [u8; 9] {
+        let (tag, size) = match self {
+            PaddingPolicy::None => (0u8, 0),
+            PaddingPolicy::PowerOfTwo => (1, 0),
+            PaddingPolicy::Padme => (2, 0),
+            PaddingPolicy::Fixed(size) => (3, *size as u64),
+        };
+        let mut bytes = [tag; 9];
+        bytes[1..].copy_from_slice(&size.to_be_bytes());
+        bytes
+    }
+}
+
+/// Returns the padded length of `len` bytes, the 0x80 byte included.
+fn padded_len(len: usize, policy: PaddingPolicy) -> usize {
+    match policy {
+        PaddingPolicy::None => len,
+        PaddingPolicy::PowerOfTwo => len.next_power_of_two(),
+        PaddingPolicy::Padme => padme(len),
+        PaddingPolicy::Fixed(size) => len.div_ceil(size.max(1)) * size.max(1),
+    }
+}
+
+fn padme(len: usize) -> usize {
+    if len <= 2 {
+        return len;
+    }
+    let exponent = len.ilog2();
+    let exponent_bits = exponent.ilog2() + 1;
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// Pads `bytes` to the length of their bucket under the policy.
+pub fn pad(bytes: &[u8], policy: PaddingPolicy) -> Vec<u8> {
+    if policy == PaddingPolicy::None {
+        return bytes.to_vec();
+    }
+    let mut padded = Vec::with_capacity(padded_len(bytes.len() + 1, policy));
+    padded.extend_from_slice(bytes);
+    padded.push(0x80);
+    padded.resize(padded_len(bytes.len() + 1, policy), 0);
+    padded
+}
+
+/// Removes the padding added by `pad` with the same policy.
+///
+/// Only the padding `pad` would add is accepted, so a padded plaintext has a single encoding.
+pub fn unpad(padded: &[u8], policy: PaddingPolicy) -> Result<Vec<u8>, &'static str> {
+    if policy == PaddingPolicy::None {
+        return Ok(padded.to_vec());
+    }
+    match padded.iter().rposition(|byte| *byte != 0) {
+        Some(end) if padded[end] == 0x80 && padded.len() == padded_len(end + 1, policy) => Ok(padded[..end].to_vec()),
+        _ => Err("The padding of the message is invalid."),
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap};
+use std::{collections::HashMap, hash::Hash};
 
 use rand_core::OsRng;
 use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
@@ -9,7 +9,11 @@ use hmac::{Hmac, Mac};
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use zeroize::Zeroize;
 use bytevec::{ByteEncodable, ByteDecodable, BVSize, BVEncodeResult, BVDecodeResult};
+use bytevec::errors::{ByteVecError, BVExpectedSize};
+use get_size::GetSize;
 use std::mem::size_of;
+use crate::padding::{pad, unpad, PaddingPolicy};
+use crate::kem_ratchet::{KemFrame, KemRatchet};
 
 
 
@@ -39,13 +43,29 @@ pub fn init_all() -> (State, State) {
 
     let bob_ratchet_dh_params = generate_dh();
     
-    let alice_state = ratchet_init_alice(&shared_secret, &bob_ratchet_dh_params.public, &ratchet_shared_secret);
-    let bob_state = ratchet_init_bob(&shared_secret, bob_ratchet_dh_params, &ratchet_shared_secret);
+    let alice_state = ratchet_init_alice(shared_secret.as_bytes(), &bob_ratchet_dh_params.public, ratchet_shared_secret.as_bytes());
+    let bob_state = ratchet_init_bob(shared_secret.as_bytes(), bob_ratchet_dh_params, ratchet_shared_secret.as_bytes());
     return (alice_state, bob_state)
 
 
 }
 
+/// Same as init_all(), but both parties run the KEM ratchet alongside the DH ratchet, see `KemRatchet`.
+pub fn init_all_with_kem() -> (State, State) {
+    let (mut alice_state, mut bob_state) = init_all();
+    alice_state.kem = Some(KemRatchet::new(true));
+    bob_state.kem = Some(KemRatchet::new(false));
+    (alice_state, bob_state)
+}
+
+/// Same as init_all(), but both parties pad their plaintexts with the given policy.
+pub fn init_all_with_padding(padding: PaddingPolicy) -> (State, State) {
+    let (mut alice_state, mut bob_state) = init_all();
+    alice_state.padding = padding;
+    bob_state.padding = padding;
+    (alice_state, bob_state)
+}
+
 pub fn dh(user_dh_params: DiffieHellmanParameters, other_user_public: PublicKey) -> SharedSecret {
     return user_dh_params.secret.diffie_hellman(&other_user_public);
 }
@@ -58,19 +78,22 @@ pub fn generate_dh() -> DiffieHellmanParameters {
 
 
 type RootKey    = [u8; 32];
-type ChainKey   = [u8; 32];
-type MessageKey = [u8; 32];
+pub(crate) type ChainKey   = [u8; 32];
+pub(crate) type MessageKey = [u8; 32];
 /// This function is recommended to be implemented using HKDF with SHA-256 or SHA-512
 /// using rk as HKDF salt, dh_out as HKDF input key material, and an application-specific 
 /// byte sequence as HKDF info. The info value should be chosen to be distinct from other 
 /// uses of HKDF in the application.
-fn kdf_rk(rk: &[u8; 32], dh_out: SharedSecret) -> (RootKey, ChainKey) {
-    let ikm = dh_out.as_bytes();
+///
+/// The secrets of the KEM ratchet to mix, if any, are appended to dh_out in the input key material.
+fn kdf_rk(rk: &[u8; 32], dh_out: SharedSecret, kem_output: &[u8]) -> (RootKey, ChainKey) {
+    let mut ikm = dh_out.as_bytes().to_vec();
+    ikm.extend_from_slice(kem_output);
 
     let salt = rk;
     let info = hex!("734f73666f724550464c"); // 'sOsforEPFL'
 
-    let hk = Hkdf::<Sha256>::new(Some(&salt[..]), ikm);
+    let hk = Hkdf::<Sha256>::new(Some(&salt[..]), &ikm);
 
     let mut okm = [0u8; 64];
     hk.expand(&info, &mut okm)
@@ -85,7 +108,7 @@ fn kdf_rk(rk: &[u8; 32], dh_out: SharedSecret) -> (RootKey, ChainKey) {
 }
 
 type HmacSha256 = Hmac<Sha256>;
-fn kdf_ck(ck: &ChainKey) -> (ChainKey, MessageKey) {
+pub(crate) fn kdf_ck(ck: &ChainKey) -> (ChainKey, MessageKey) {
     let mut mac_msg = HmacSha256::new_from_slice(ck)
     .expect("HMAC can take key of any size");
     mac_msg.update(b"01");
@@ -159,6 +182,9 @@ pub(crate) fn decrypt(mk: &MessageKey, ciphertext: &[u8], associated_data: &[u8]
     iv.copy_from_slice(&okm[64..80]);
 
 
+    if ciphertext.len() < 32 {
+        return Err("The ciphertext is too short.");
+    }
     let mut hmac = HmacSha256::new_from_slice(&auth_key)
     .expect("HMAC can take key of any size");
     let mut hmac_input: Vec<u8> = associated_data.to_vec();
@@ -174,7 +200,7 @@ pub(crate) fn decrypt(mk: &MessageKey, ciphertext: &[u8], associated_data: &[u8]
     let ciphertext_without_hmac = ciphertext[..ciphertext.len() - 32].to_vec();
     let plaintext = Aes256CbcDec::new(&decryption_key.into(), &iv.into())
     .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext_without_hmac)
-    .unwrap();
+    .map_err(|_| "The padding of the plaintext is invalid.")?;
 
     return Ok(plaintext.to_vec());
 }
@@ -216,41 +242,65 @@ pub struct State {
     pub Nr: usize,
     pub PN: usize,
     pub MKSKIPPED: HashMap<(PublicKey, usize), MessageKey>,
-    pub epoch: usize
+    pub epoch: usize,
+    /// Epoch the session restarted from at its last reset, 0 if it was never reset, see the `reset` module.
+    pub reset_epoch: usize,
+    /// Padding of the plaintexts, which both parties must agree on, see `init_all_with_padding`.
+    pub padding: PaddingPolicy,
+    /// Post-quantum ratchet run alongside the DH ratchet, see `init_all_with_kem`.
+    pub kem: Option<KemRatchet>,
+    /// Number of attachments whose key was derived from the sending chain, see `encrypt_attachment`.
+    pub attachments_sent: u64
+}
+
+impl GetSize for State {
+    /// The skipped message keys and the KEM ratchet live on the heap, the keys of the Diffie-Hellman ratchet are stored inline.
+    fn get_heap_size(&self) -> usize {
+        self.MKSKIPPED.capacity() * size_of::<((PublicKey, usize), MessageKey)>()
+            + self.kem.as_ref().map_or(0, KemRatchet::heap_size)
+    }
 }
 
 #[allow(non_snake_case)]
-fn ratchet_init_alice(SK: &SharedSecret, bob_dh_public_key: &PublicKey, ratchet_shared_secret: &SharedSecret) -> State {
+pub(crate) fn ratchet_init_alice(SK: &[u8; 32], bob_dh_public_key: &PublicKey, ratchet_shared_secret: &[u8; 32]) -> State {
     let dh_pair = generate_dh();
-    let (root_key, chain_key) = kdf_rk(SK.as_bytes(), dh(dh_pair.clone(), *bob_dh_public_key));
+    let (root_key, chain_key) = kdf_rk(SK, dh(dh_pair.clone(), *bob_dh_public_key), &[]);
     State { 
          DHs: dh_pair,
          DHr: bob_dh_public_key.clone(), 
          RK: root_key, 
          CKs: chain_key, 
-         CKr: ratchet_shared_secret.to_bytes(), 
+         CKr: *ratchet_shared_secret, 
          Ns: 0, 
          Nr: 0, 
          PN: 0, 
          MKSKIPPED: HashMap::new(),
-         epoch: 0
+         epoch: 0,
+         reset_epoch: 0,
+         padding: PaddingPolicy::None,
+         kem: None,
+         attachments_sent: 0
         }
 }
 
 #[allow(non_snake_case)]
-fn ratchet_init_bob(SK: &SharedSecret, bob_dh_key_pair: DiffieHellmanParameters, ratchet_shared_secret: &SharedSecret) -> State {
+pub(crate) fn ratchet_init_bob(SK: &[u8; 32], bob_dh_key_pair: DiffieHellmanParameters, ratchet_shared_secret: &[u8; 32]) -> State {
     let filling_value = generate_dh().public;
     State { 
          DHs: bob_dh_key_pair,
          DHr: filling_value, 
-         RK: SK.to_bytes(), 
-         CKs: ratchet_shared_secret.to_bytes(), 
+         RK: *SK, 
+         CKs: *ratchet_shared_secret, 
          CKr: [0; 32], 
          Ns: 0, 
          Nr: 0, 
          PN: 0, 
          MKSKIPPED: HashMap::new(),
-         epoch: 0
+         epoch: 0,
+         reset_epoch: 0,
+         padding: PaddingPolicy::None,
+         kem: None,
+         attachments_sent: 0
         }
 }
 
@@ -278,43 +328,70 @@ fn skip_message_keys(state: &mut State, until: usize) -> Result<usize, &'static
     }
     // Initial state of receiving chain key before receiving first DH ratchet PK.
     if state.CKr != [0;32] {
-        while state.Nr < until {
-            let mk: MessageKey;
-            (state.CKr, mk) = kdf_ck(&state.CKr);
-            state.MKSKIPPED.insert((state.DHr, state.Nr), mk);
-            state.Nr += 1;
-        }
+        let dh_r = state.DHr;
+        skip_chain_keys(&mut state.CKr, &mut state.Nr, until, &mut state.MKSKIPPED, |n| (dh_r, n))?;
     }
     return Ok(1);
 }
 
-fn dh_ratchet(state: &mut State, header: &Header) -> () {
+/// Advances a receiving chain up to the message number `until`, storing the keys of the skipped
+/// messages under `key(n)`. Returns Err if more than MAX_SKIP messages would be skipped.
+pub(crate) fn skip_chain_keys<K: Hash + Eq>(
+    chain_key: &mut ChainKey,
+    n: &mut usize,
+    until: usize,
+    skipped: &mut HashMap<K, MessageKey>,
+    key: impl Fn(usize) -> K,
+) -> Result<(), &'static str> {
+    if *n + MAX_SKIP < until {
+        return Err("No such message exists.");
+    }
+    while *n < until {
+        let mk: MessageKey;
+        (*chain_key, mk) = kdf_ck(chain_key);
+        skipped.insert(key(*n), mk);
+        *n += 1;
+    }
+    Ok(())
+}
+
+/// `peer_kem_mixed` is the number of KEM epochs mixed into the sending chain of the peer, see `KemRatchet`.
+fn dh_ratchet(state: &mut State, header: &Header, peer_kem_mixed: u32) -> () {
     state.PN = state.Ns;
     state.Ns = 0;
     state.Nr = 0;
     state.DHr = header.dh_ratchet_key;
     
-    (state.RK, state.CKr) = kdf_rk(&state.RK, dh(state.DHs.clone(), state.DHr));
+    let kem_output = state.kem.as_mut().map_or(Vec::new(), |kem| kem.mix_receiving(peer_kem_mixed));
+    (state.RK, state.CKr) = kdf_rk(&state.RK, dh(state.DHs.clone(), state.DHr), &kem_output);
     // Clean memory from any secret keys
     state.DHs.secret.zeroize();
     state.DHs = generate_dh();
-    (state.RK, state.CKs) = kdf_rk(&state.RK, dh(state.DHs.clone(), state.DHr));
+    let kem_output = state.kem.as_mut().map_or(Vec::new(), KemRatchet::mix_sending);
+    (state.RK, state.CKs) = kdf_rk(&state.RK, dh(state.DHs.clone(), state.DHr), &kem_output);
     state.epoch += 1;
 
 }
 
 
 pub(crate) fn ratchet_decrypt(state: &mut State, header: Header, ciphertext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    ratchet_decrypt_with_kem(state, header, ciphertext, associated_data, 0)
+}
+
+fn ratchet_decrypt_with_kem(state: &mut State, header: Header, ciphertext: &[u8], associated_data: &[u8], peer_kem_mixed: u32) -> Result<Vec<u8>, &'static str> {
     let plaintext = try_skipped_message_keys(state, &header, ciphertext, associated_data);
     match plaintext {
         Ok(_) => return plaintext,
         Err("HMAC does not match, authentication failed.") => return Err("HMAC does not match, authentication failed."),
         _ => {
             if header.dh_ratchet_key != state.DHr {
+                if !state.kem.as_ref().map_or(true, |kem| kem.can_mix(peer_kem_mixed)) {
+                    return Err("The KEM secret of this chain is not available.");
+                }
                 match skip_message_keys(state, header.prev_chain_len) {
                     Err(_) => return Err("No such message exists."),
                     Ok(_) => {
-                        dh_ratchet(state, &header);
+                        dh_ratchet(state, &header, peer_kem_mixed);
                         match skip_message_keys(state, header.msg_nbr) {
                             Err(_) => return Err("No such message exists."),
                             Ok(_) => {
@@ -342,7 +419,7 @@ pub(crate) fn ratchet_decrypt(state: &mut State, header: Header, ciphertext: &[u
         },
     }
 }
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, PartialOrd, Ord)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, PartialOrd, Ord, GetSize)]
 pub struct Ordinal {
     pub epoch: usize,
     pub index: usize
@@ -367,6 +444,9 @@ impl ByteEncodable for Ordinal {
 impl ByteDecodable for Ordinal {
     /// Returns an instance of `Self` obtained from the deserialization of the provided byte buffer.
     fn decode<Size>(bytes: &[u8]) -> BVDecodeResult<Self> where Size: BVSize + ByteDecodable {
+        if bytes.len() != 2*size_of::<usize>() {
+            return Err(ByteVecError::BadSizeDecodeError { expected: BVExpectedSize::EqualTo(2*size_of::<usize>()), actual: bytes.len() });
+        }
         let ordinal_epoch = usize::from_be_bytes(bytes[0..size_of::<usize>()].try_into().unwrap());
         let ordinal_index = usize::from_be_bytes(bytes[size_of::<usize>()..2*size_of::<usize>()].try_into().unwrap());
 
@@ -376,15 +456,42 @@ impl ByteDecodable for Ordinal {
 
 
 pub fn send(state: &mut State, associated_data: &[u8], plaintext: &[u8]) -> (Ordinal, Header, Vec<u8>) {
-    let (header, ciphertext) = ratchet_encrypt(state, plaintext, associated_data);
+    let padded = pad(plaintext, state.padding);
+    let associated_data: &[u8] = &[associated_data, &state.padding.encode()].concat();
+    let (header, ciphertext) = match state.kem.as_mut().map(KemRatchet::next_frame) {
+        // The frame of the KEM ratchet is sent in the clear before the ciphertext, and authenticated as associated data.
+        Some(frame) => {
+            let mut frame = frame.encode();
+            let (header, ciphertext) = ratchet_encrypt(state, &padded, &[associated_data, &frame].concat());
+            frame.extend_from_slice(&ciphertext);
+            (header, frame)
+        }
+        None => ratchet_encrypt(state, &padded, associated_data),
+    };
     return (Ordinal{epoch: header.epoch, index: header.msg_nbr}, header, ciphertext)
 }
 
 pub fn receive(state: &mut State, associated_data: &[u8], header: Header, ciphertext: &[u8]) -> (bool, Ordinal, Vec<u8>) {
-    let decryption_result = ratchet_decrypt(state, header, ciphertext, associated_data);
+    let padding = state.padding;
+    // The padding is only removed from an authenticated plaintext, and the policy is authenticated with it.
+    let associated_data: &[u8] = &[associated_data, &padding.encode()].concat();
+    let decryption_result = match state.kem {
+        Some(_) => receive_with_kem(state, associated_data, header, ciphertext),
+        None => ratchet_decrypt(state, header, ciphertext, associated_data),
+    }
+    .and_then(|padded| unpad(&padded, padding));
     match decryption_result {
         Ok(val) => (true, Ordinal{epoch: header.epoch, index: header.msg_nbr}, val),
         Err(_) => return (false, Ordinal{epoch: 0, index: 0}, Vec::new())
     }
 }
 
+/// Decrypts a ciphertext prefixed with the frame of the KEM ratchet, and processes the frame once the ciphertext is authenticated.
+fn receive_with_kem(state: &mut State, associated_data: &[u8], header: Header, ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (frame, rest) = KemFrame::decode(ciphertext)?;
+    let associated_data = [associated_data, &ciphertext[..ciphertext.len() - rest.len()]].concat();
+    let ciphertext = rest;
+    let plaintext = ratchet_decrypt_with_kem(state, header, ciphertext, &associated_data, frame.mixed)?;
+    state.kem.as_mut().unwrap().receive_frame(&frame);
+    Ok(plaintext)
+}
@@ -0,0 +1,325 @@
+use std::mem::size_of;
+
+use hex_literal::hex;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use x25519_dalek::PublicKey;
+
+use crate::kem_ratchet::KemRatchet;
+use crate::padding::PaddingPolicy;
+use crate::protocol::{generate_dh, ratchet_init_alice, ratchet_init_bob, DiffieHellmanParameters, State};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// First message of a reset, which the initiator sends over the existing channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResetRequest {
+    pub ephemeral_key: [u8; 32],
+    /// Public prekey of the responder the new session is keyed with, None for the interactive handshake.
+    pub prekey: Option<[u8; 32]>,
+    /// Epoch the initiator proposes to restart the session from.
+    pub epoch: usize,
+    /// Transcript of the old session as seen by the initiator, opaque to rc.
+    pub transcript: Vec<u8>,
+    /// MAC under the static-static DH of the identity keys, so that only the peer can request a reset.
+    pub mac: [u8; 32],
+}
+
+/// Answer of the responder to an interactive `ResetRequest`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResetResponse {
+    /// Ratchet key of the responder in the new session.
+    pub ratchet_key: [u8; 32],
+    /// Epoch both parties restart the session from.
+    pub epoch: usize,
+    /// Transcript of the old session as seen by the responder, opaque to rc.
+    pub transcript: Vec<u8>,
+    /// Key confirmation MAC under the new session secret, which also covers the request.
+    pub mac: [u8; 32],
+}
+
+/// What the initiator keeps between `reset_request` and `reset_finish`.
+#[derive(Clone)]
+pub struct PendingReset {
+    pub ephemeral: DiffieHellmanParameters,
+    pub request: ResetRequest,
+    padding: PaddingPolicy,
+    kem: bool,
+}
+
+/// Encodes a transcript as its length on 4 bytes followed by its bytes.
+fn encode_transcript(bytes: &mut Vec<u8>, transcript: &[u8]) {
+    bytes.extend_from_slice(&(transcript.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(transcript);
+}
+
+/// Splits a length-prefixed transcript from the bytes which follow it.
+fn decode_transcript<'a>(bytes: &'a [u8], error: &'static str) -> Result<(&'a [u8], &'a [u8]), &'static str> {
+    if bytes.len() < size_of::<u32>() {
+        return Err(error);
+    }
+    let (len, bytes) = bytes.split_at(size_of::<u32>());
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if bytes.len() < len {
+        return Err(error);
+    }
+    Ok(bytes.split_at(len))
+}
+
+impl ResetRequest {
+    /// Length of an encoded request with an empty transcript.
+    const MIN_ENCODED_LEN: usize = 32 + 1 + 32 + size_of::<u64>() + size_of::<u32>() + 32;
+
+    /// Bytes covered by the MAC: ephemeral_key || prekey flag || prekey || epoch || transcript length || transcript.
+    fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::MIN_ENCODED_LEN + self.transcript.len());
+        bytes.extend_from_slice(&self.ephemeral_key);
+        bytes.push(self.prekey.is_some() as u8);
+        bytes.extend_from_slice(&self.prekey.unwrap_or([0; 32]));
+        bytes.extend_from_slice(&(self.epoch as u64).to_be_bytes());
+        encode_transcript(&mut bytes, &self.transcript);
+        bytes
+    }
+
+    /// Encodes the request as the authenticated bytes followed by the MAC.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.authenticated_bytes();
+        bytes.extend_from_slice(&self.mac);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ResetRequest, &'static str> {
+        if bytes.len() < Self::MIN_ENCODED_LEN {
+            return Err("The reset request has the wrong length.");
+        }
+        let (ephemeral_key, bytes) = bytes.split_at(32);
+        let (flag, bytes) = bytes.split_at(1);
+        let (prekey, bytes) = bytes.split_at(32);
+        let (epoch, bytes) = bytes.split_at(size_of::<u64>());
+        let (transcript, mac) = decode_transcript(bytes, "The reset request has the wrong length.")?;
+        if mac.len() != 32 {
+            return Err("The reset request has the wrong length.");
+        }
+        let prekey = match flag[0] {
+            0 => None,
+            1 => Some(prekey.try_into().unwrap()),
+            _ => return Err("The reset request is invalid."),
+        };
+        Ok(ResetRequest {
+            ephemeral_key: ephemeral_key.try_into().unwrap(),
+            prekey,
+            epoch: u64::from_be_bytes(epoch.try_into().unwrap()).try_into().map_err(|_| "The reset request is invalid.")?,
+            transcript: transcript.to_vec(),
+            mac: mac.try_into().unwrap(),
+        })
+    }
+}
+
+impl ResetResponse {
+    /// Length of an encoded response with an empty transcript.
+    const MIN_ENCODED_LEN: usize = 32 + size_of::<u64>() + size_of::<u32>() + 32;
+
+    /// Bytes covered by the MAC: ratchet_key || epoch || transcript length || transcript.
+    fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::MIN_ENCODED_LEN + self.transcript.len());
+        bytes.extend_from_slice(&self.ratchet_key);
+        bytes.extend_from_slice(&(self.epoch as u64).to_be_bytes());
+        encode_transcript(&mut bytes, &self.transcript);
+        bytes
+    }
+
+    /// Encodes the response as the authenticated bytes followed by the MAC.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.authenticated_bytes();
+        bytes.extend_from_slice(&self.mac);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ResetResponse, &'static str> {
+        if bytes.len() < Self::MIN_ENCODED_LEN {
+            return Err("The reset response has the wrong length.");
+        }
+        let (ratchet_key, bytes) = bytes.split_at(32);
+        let (epoch, bytes) = bytes.split_at(size_of::<u64>());
+        let (transcript, mac) = decode_transcript(bytes, "The reset response has the wrong length.")?;
+        if mac.len() != 32 {
+            return Err("The reset response has the wrong length.");
+        }
+        Ok(ResetResponse {
+            ratchet_key: ratchet_key.try_into().unwrap(),
+            epoch: u64::from_be_bytes(epoch.try_into().unwrap()).try_into().map_err(|_| "The reset response is invalid.")?,
+            transcript: transcript.to_vec(),
+            mac: mac.try_into().unwrap(),
+        })
+    }
+}
+
+/// Key of the request MAC, derived from the DH of both identity keys.
+fn request_key(identity: &DiffieHellmanParameters, peer_identity: &PublicKey) -> [u8; 32] {
+    let shared_secret = identity.secret.diffie_hellman(peer_identity);
+    let info = hex!("734f73526573657441757468"); // 'sOsResetAuth'
+    let hk = Hkdf::<Sha256>::new(None, shared_secret.as_bytes());
+    let mut key = [0u8; 32];
+    hk.expand(&info, &mut key).expect("32 is a valid length for Sha256 to output");
+    key
+}
+
+fn mac(key: &[u8; 32], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    parts.iter().for_each(|part| mac.update(part));
+    mac.finalize().into_bytes().into()
+}
+
+fn verify_mac(key: &[u8; 32], parts: &[&[u8]], tag: &[u8; 32], error: &'static str) -> Result<(), &'static str> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    parts.iter().for_each(|part| mac.update(part));
+    mac.verify_slice(tag).map_err(|_| error)
+}
+
+/// Keys of the new session: its root key, the initial sending chain of the responder, and the key confirmation key.
+struct ResetKeys {
+    root_key: [u8; 32],
+    responder_chain: [u8; 32],
+    confirmation_key: [u8; 32],
+}
+
+/// Derives the keys of the new session from DH(IK_A, R_B) || DH(E_A, IK_B) || DH(E_A, R_B), as in X3DH
+/// with the ratchet key R_B of the responder as signed prekey, salted with a hash of both messages.
+fn derive_keys(dh_outputs: [[u8; 32]; 3], request: &ResetRequest, ratchet_key: &[u8; 32], epoch: usize, responder_transcript: &[u8]) -> ResetKeys {
+    let mut hasher = Sha256::new();
+    hasher.update(request.authenticated_bytes());
+    hasher.update(request.mac);
+    hasher.update(ratchet_key);
+    hasher.update((epoch as u64).to_be_bytes());
+    hasher.update((responder_transcript.len() as u64).to_be_bytes());
+    hasher.update(responder_transcript);
+    let salt: [u8; 32] = hasher.finalize().into();
+
+    let info = hex!("734f735265736574"); // 'sOsReset'
+    let hk = Hkdf::<Sha256>::new(Some(&salt[..]), &dh_outputs.concat());
+    let mut okm = [0u8; 96];
+    hk.expand(&info, &mut okm).expect("96 is a valid length for Sha256 to output");
+    ResetKeys {
+        root_key: okm[0..32].try_into().unwrap(),
+        responder_chain: okm[32..64].try_into().unwrap(),
+        confirmation_key: okm[64..96].try_into().unwrap(),
+    }
+}
+
+/// Carries the settings of the old session over to a new state starting at `epoch`.
+fn restart(mut state: State, epoch: usize, padding: PaddingPolicy, kem: bool, initiator: bool) -> State {
+    state.epoch = epoch;
+    state.reset_epoch = epoch;
+    state.padding = padding;
+    state.kem = kem.then(|| KemRatchet::new(initiator));
+    state
+}
+
+fn initiator_request(identity: &DiffieHellmanParameters, peer_identity: &PublicKey, ephemeral: &DiffieHellmanParameters, prekey: Option<[u8; 32]>, epoch: usize, transcript: Vec<u8>) -> ResetRequest {
+    let mut request = ResetRequest { ephemeral_key: ephemeral.public.to_bytes(), prekey, epoch, transcript, mac: [0; 32] };
+    request.mac = mac(&request_key(identity, peer_identity), &[&request.authenticated_bytes()]);
+    request
+}
+
+fn initiator_keys(identity: &DiffieHellmanParameters, peer_identity: &PublicKey, ephemeral: &DiffieHellmanParameters, request: &ResetRequest, ratchet_key: &PublicKey, epoch: usize, responder_transcript: &[u8]) -> ResetKeys {
+    let dh_outputs = [
+        identity.secret.diffie_hellman(ratchet_key).to_bytes(),
+        ephemeral.secret.diffie_hellman(peer_identity).to_bytes(),
+        ephemeral.secret.diffie_hellman(ratchet_key).to_bytes(),
+    ];
+    derive_keys(dh_outputs, request, &ratchet_key.to_bytes(), epoch, responder_transcript)
+}
+
+/// Checks the MAC of a request and that it is newer than the last reset and the session, and returns the keys of the responder.
+fn responder_keys(state: &State, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, ratchet_key: &DiffieHellmanParameters, request: &ResetRequest, epoch: usize, transcript: &[u8]) -> Result<ResetKeys, &'static str> {
+    verify_mac(&request_key(identity, peer_identity), &[&request.authenticated_bytes()], &request.mac, "The reset request is not authenticated.")?;
+    if request.epoch <= state.reset_epoch {
+        return Err("The reset request is older than the last reset.");
+    }
+    // A request proposes the epoch after the initiator's, and the epochs of two parties in sync differ by at most one,
+    // so an older request was held back by the network, and would reset a session which has since moved on.
+    if request.epoch < state.epoch {
+        return Err("The reset request is older than the session.");
+    }
+    let ephemeral_key = PublicKey::from(request.ephemeral_key);
+    let dh_outputs = [
+        ratchet_key.secret.diffie_hellman(peer_identity).to_bytes(),
+        identity.secret.diffie_hellman(&ephemeral_key).to_bytes(),
+        ratchet_key.secret.diffie_hellman(&ephemeral_key).to_bytes(),
+    ];
+    Ok(derive_keys(dh_outputs, request, &ratchet_key.public.to_bytes(), epoch, transcript))
+}
+
+/// Starts an interactive reset of a session, e.g. once `receive` keeps failing because the states diverged.
+///
+/// The request is sent to the peer, which answers with `reset_respond`, and the new state is obtained
+/// from the response with `reset_finish`. `transcript` is what this party saw of the old session,
+/// which rc only binds into the keys of the new session and the layers above rc compare with their
+/// own view. If both parties start a reset at once, one of them must drop its own, e.g. the one with
+/// the smaller identity key. The peer rejects a request from a party more than one epoch behind it,
+/// e.g. after restoring a backup, in which case the peer must start the reset.
+pub fn reset_request(state: &State, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, transcript: Vec<u8>) -> (PendingReset, ResetRequest) {
+    let ephemeral = generate_dh();
+    let request = initiator_request(identity, peer_identity, &ephemeral, None, state.epoch + 1, transcript);
+    (PendingReset { ephemeral, request: request.clone(), padding: state.padding, kem: state.kem.is_some() }, request)
+}
+
+/// Answers a reset request, and returns the state of the new session with the response to send.
+///
+/// The new session restarts from an epoch above those of both old states, so that its ordinals never
+/// collide with the old ones. The root is keyed from fresh DHs with the identity keys, so nothing of
+/// the old state is reused. Returns Err if the request was not sent by the peer, or replays a request
+/// older than the last reset or than the epoch of this party.
+pub fn reset_respond(state: &State, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest, transcript: Vec<u8>) -> Result<(State, ResetResponse), &'static str> {
+    if request.prekey.is_some() {
+        return Err("The reset request is keyed with a prekey.");
+    }
+    let ratchet_key = generate_dh();
+    let epoch = request.epoch.max(state.epoch + 1);
+    let keys = responder_keys(state, identity, peer_identity, &ratchet_key, request, epoch, &transcript)?;
+    let mut response = ResetResponse { ratchet_key: ratchet_key.public.to_bytes(), epoch, transcript, mac: [0; 32] };
+    response.mac = mac(&keys.confirmation_key, &[&response.authenticated_bytes(), &request.mac]);
+    let new_state = ratchet_init_bob(&keys.root_key, ratchet_key, &keys.responder_chain);
+    Ok((restart(new_state, epoch, state.padding, state.kem.is_some(), false), response))
+}
+
+/// Completes an interactive reset with the response of the peer, and returns the state of the new session.
+pub fn reset_finish(pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse) -> Result<State, &'static str> {
+    if response.epoch < pending.request.epoch {
+        return Err("The reset response is invalid.");
+    }
+    let ratchet_key = PublicKey::from(response.ratchet_key);
+    let keys = initiator_keys(identity, peer_identity, &pending.ephemeral, &pending.request, &ratchet_key, response.epoch, &response.transcript);
+    verify_mac(&keys.confirmation_key, &[&response.authenticated_bytes(), &pending.request.mac], &response.mac, "The reset response is not authenticated.")?;
+    let new_state = ratchet_init_alice(&keys.root_key, &ratchet_key, &keys.responder_chain);
+    Ok(restart(new_state, response.epoch, pending.padding, pending.kem, true))
+}
+
+/// Resets a session without waiting for the peer, by keying the new session with a prekey it published.
+///
+/// The initiator can send in the new session right away. The peer accepts the request with
+/// `reset_accept_with_prekey`, and only if the proposed epoch is above its own, so a party which may be
+/// behind its peer, e.g. after restoring a backup, should let the peer start the reset instead. The
+/// responder sends no transcript, so the keys are bound to an empty one.
+pub fn reset_request_with_prekey(state: &State, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, peer_prekey: &PublicKey, transcript: Vec<u8>) -> (State, ResetRequest) {
+    let ephemeral = generate_dh();
+    // The epochs of two parties in sync differ by at most one, so the peer accepts this one unless it is further ahead.
+    let request = initiator_request(identity, peer_identity, &ephemeral, Some(peer_prekey.to_bytes()), state.epoch + 2, transcript);
+    let keys = initiator_keys(identity, peer_identity, &ephemeral, &request, peer_prekey, request.epoch, &[]);
+    let new_state = ratchet_init_alice(&keys.root_key, peer_prekey, &keys.responder_chain);
+    (restart(new_state, request.epoch, state.padding, state.kem.is_some(), true), request)
+}
+
+/// Accepts a reset request keyed with one of this party's prekeys, and returns the state of the new session.
+pub fn reset_accept_with_prekey(state: &State, identity: &DiffieHellmanParameters, prekey: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest) -> Result<State, &'static str> {
+    if request.prekey != Some(prekey.public.to_bytes()) {
+        return Err("The reset request is keyed with another prekey.");
+    }
+    if request.epoch <= state.epoch {
+        return Err("The reset request is older than the session.");
+    }
+    let keys = responder_keys(state, identity, peer_identity, prekey, request, request.epoch, &[])?;
+    let new_state = ratchet_init_bob(&keys.root_key, prekey.clone(), &keys.responder_chain);
+    Ok(restart(new_state, request.epoch, state.padding, state.kem.is_some(), false))
+}
@@ -0,0 +1,248 @@
+use std::mem::size_of;
+
+use ed25519_dalek::{Keypair, PublicKey as VerifyingKey, Signature, Signer};
+use hex_literal::hex;
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::devices::DeviceAddress;
+use crate::protocol::{decrypt, encrypt, generate_dh, send, DiffieHellmanParameters, Header, MessageKey, State};
+
+const HEADER_LEN: usize = 32 + 3 * size_of::<usize>();
+/// Length of the encrypted identity key of the sender: a block of padding and the HMAC come on top of the 32 bytes.
+const ENCRYPTED_IDENTITY_LEN: usize = 32 + 16 + 32;
+
+/// Stand-in for the server issuing sender certificates, so that a recipient can check who sent a sealed message.
+pub struct CertificateAuthority {
+    /// Ed25519 keypair signing the certificates, stored as bytes since `Keypair` is not Clone.
+    pub signing_keypair: [u8; 64],
+}
+
+/// Binds a device to its identity key, until `expires`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SenderCertificate {
+    pub sender: DeviceAddress,
+    pub identity_key: [u8; 32],
+    /// Time after which the certificate is rejected, in the unit of the `now` given to `unseal`.
+    pub expires: u64,
+    /// Signature of the certificate authority over the other fields.
+    pub signature: [u8; 64],
+}
+
+/// What the relay sees of a sealed message: the recipient, and nothing about the sender.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SealedEnvelope {
+    pub recipient: DeviceAddress,
+    pub ephemeral_key: [u8; 32],
+    /// Encryption of the identity key of the sender, under the ephemeral-static DH.
+    pub encrypted_identity: Vec<u8>,
+    /// Encryption of the sender certificate and of the inner ciphertext, under the DH of both identity keys.
+    pub ciphertext: Vec<u8>,
+}
+
+impl SenderCertificate {
+    const SIGNED_LEN: usize = 2 * size_of::<u32>() + 32 + size_of::<u64>();
+    const ENCODED_LEN: usize = Self::SIGNED_LEN + 64;
+
+    /// Encodes the fields signed by the certificate authority: user || device || identity_key || expires.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::ENCODED_LEN);
+        bytes.extend_from_slice(&self.sender.user.to_be_bytes());
+        bytes.extend_from_slice(&self.sender.device.to_be_bytes());
+        bytes.extend_from_slice(&self.identity_key);
+        bytes.extend_from_slice(&self.expires.to_be_bytes());
+        bytes
+    }
+
+    /// Encodes the certificate as the signed fields followed by the signature.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.signed_bytes();
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SenderCertificate, &'static str> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err("The sender certificate has the wrong length.");
+        }
+        let (user, bytes) = bytes.split_at(size_of::<u32>());
+        let (device, bytes) = bytes.split_at(size_of::<u32>());
+        let (identity_key, bytes) = bytes.split_at(32);
+        let (expires, signature) = bytes.split_at(size_of::<u64>());
+        Ok(SenderCertificate {
+            sender: DeviceAddress { user: u32::from_be_bytes(user.try_into().unwrap()), device: u32::from_be_bytes(device.try_into().unwrap()) },
+            identity_key: identity_key.try_into().unwrap(),
+            expires: u64::from_be_bytes(expires.try_into().unwrap()),
+            signature: signature.try_into().unwrap(),
+        })
+    }
+}
+
+impl SealedEnvelope {
+    /// Encodes the envelope as user || device || ephemeral_key || encrypted_identity || ciphertext.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.recipient.user.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.recipient.device.to_be_bytes());
+        bytes.extend_from_slice(&self.ephemeral_key);
+        bytes.extend_from_slice(&self.encrypted_identity);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SealedEnvelope, &'static str> {
+        if bytes.len() < 2 * size_of::<u32>() + 32 + ENCRYPTED_IDENTITY_LEN {
+            return Err("The sealed envelope is too short.");
+        }
+        let (user, bytes) = bytes.split_at(size_of::<u32>());
+        let (device, bytes) = bytes.split_at(size_of::<u32>());
+        let (ephemeral_key, bytes) = bytes.split_at(32);
+        let (encrypted_identity, ciphertext) = bytes.split_at(ENCRYPTED_IDENTITY_LEN);
+        Ok(SealedEnvelope {
+            recipient: DeviceAddress { user: u32::from_be_bytes(user.try_into().unwrap()), device: u32::from_be_bytes(device.try_into().unwrap()) },
+            ephemeral_key: ephemeral_key.try_into().unwrap(),
+            encrypted_identity: encrypted_identity.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+pub fn certificate_authority_init() -> CertificateAuthority {
+    CertificateAuthority { signing_keypair: Keypair::generate(&mut OsRng).to_bytes() }
+}
+
+/// Returns the key with which recipients verify the certificates of the authority.
+pub fn certificate_authority_key(ca: &CertificateAuthority) -> [u8; 32] {
+    signing_keypair(ca).public.to_bytes()
+}
+
+fn signing_keypair(ca: &CertificateAuthority) -> Keypair {
+    Keypair::from_bytes(&ca.signing_keypair).expect("The keypair was encoded by Keypair::to_bytes().")
+}
+
+pub fn issue_sender_certificate(ca: &CertificateAuthority, sender: DeviceAddress, identity_key: &PublicKey, expires: u64) -> SenderCertificate {
+    let mut certificate = SenderCertificate { sender, identity_key: identity_key.to_bytes(), expires, signature: [0; 64] };
+    certificate.signature = signing_keypair(ca).sign(&certificate.signed_bytes()).to_bytes();
+    certificate
+}
+
+/// Returns the DH of a secret and a public key, or Err if the public key has a small order and the
+/// output does not depend on the secret.
+fn contributory_dh(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32], &'static str> {
+    let shared_secret = secret.diffie_hellman(public).to_bytes();
+    if bool::from(shared_secret.ct_eq(&[0u8; 32])) {
+        return Err("The public key has a small order.");
+    }
+    Ok(shared_secret)
+}
+
+/// Derives the key of the encrypted identity from the ephemeral-static DH, and returns it with the
+/// chain key the second layer starts from and the associated data binding the recipient.
+fn ephemeral_layer_keys(shared_secret: &[u8; 32], recipient: DeviceAddress, ephemeral_key: &[u8; 32], recipient_identity: &PublicKey) -> (MessageKey, [u8; 32], Vec<u8>) {
+    let mut associated_data = recipient.user.to_be_bytes().to_vec();
+    associated_data.extend_from_slice(&recipient.device.to_be_bytes());
+    associated_data.extend_from_slice(ephemeral_key);
+    associated_data.extend_from_slice(recipient_identity.as_bytes());
+
+    let info = hex!("734f735365616c656453656e646572"); // 'sOsSealedSender'
+    let hk = Hkdf::<Sha256>::new(Some(&associated_data[..]), shared_secret);
+    let mut okm = [0u8; 64];
+    hk.expand(&info, &mut okm).expect("64 is a valid length for Sha256 to output");
+    (okm[0..32].try_into().unwrap(), okm[32..64].try_into().unwrap(), associated_data)
+}
+
+/// Derives the key of the certificate and inner ciphertext from the DH of both identity keys, chained to the first layer.
+fn static_layer_key(shared_secret: &[u8; 32], chain_key: &[u8; 32], encrypted_identity: &[u8]) -> MessageKey {
+    let salt = [&chain_key[..], encrypted_identity].concat();
+    let info = hex!("734f735365616c656453656e64657253746174696373"); // 'sOsSealedSenderStatics'
+    let hk = Hkdf::<Sha256>::new(Some(&salt[..]), shared_secret);
+    let mut key = [0u8; 32];
+    hk.expand(&info, &mut key).expect("32 is a valid length for Sha256 to output");
+    key
+}
+
+/// Seals an inner ciphertext, e.g. an encoded rc message or the payload of rrc's `send_bytes`, with the sender certificate.
+///
+/// As in Signal's sealed sender, the envelope has two layers. The identity key of the sender is
+/// encrypted with a DH between a fresh ephemeral key and the identity key of the recipient, so only
+/// the recipient learns who sent it. The certificate and the inner ciphertext are then encrypted with
+/// the DH of both identity keys, which proves that the sender holds the key its certificate names.
+pub fn seal(recipient: DeviceAddress, recipient_identity: &PublicKey, identity: &DiffieHellmanParameters, certificate: &SenderCertificate, inner: &[u8]) -> SealedEnvelope {
+    let ephemeral = generate_dh();
+    let ephemeral_key = ephemeral.public.to_bytes();
+    let ephemeral_secret = ephemeral.secret.diffie_hellman(recipient_identity).to_bytes();
+    let (identity_key, chain_key, associated_data) = ephemeral_layer_keys(&ephemeral_secret, recipient, &ephemeral_key, recipient_identity);
+    let encrypted_identity = encrypt(&identity_key, identity.public.as_bytes(), &associated_data);
+
+    let static_secret = identity.secret.diffie_hellman(recipient_identity).to_bytes();
+    let key = static_layer_key(&static_secret, &chain_key, &encrypted_identity);
+    let mut plaintext = certificate.encode();
+    plaintext.extend_from_slice(inner);
+    let ciphertext = encrypt(&key, &plaintext, &associated_data);
+    SealedEnvelope { recipient, ephemeral_key, encrypted_identity, ciphertext }
+}
+
+/// Opens an envelope sent to this device, and returns the sender certificate and the inner ciphertext.
+///
+/// Returns Err if the envelope was not sealed for this identity key, if its ephemeral key or the
+/// identity key of the sender has a small order, if the certificate was not issued by the authority
+/// or has expired, or if it names another identity key than the one the envelope was sealed with.
+/// The inner ciphertext must then be received with the session of the certified sender, which authenticates it.
+pub fn unseal(identity: &DiffieHellmanParameters, own: DeviceAddress, ca_key: &[u8; 32], now: u64, envelope: &SealedEnvelope) -> Result<(SenderCertificate, Vec<u8>), &'static str> {
+    if envelope.recipient != own {
+        return Err("The envelope is for another device.");
+    }
+    let ephemeral_secret = contributory_dh(&identity.secret, &PublicKey::from(envelope.ephemeral_key))?;
+    let (identity_key, chain_key, associated_data) = ephemeral_layer_keys(&ephemeral_secret, own, &envelope.ephemeral_key, &identity.public);
+    let sender_identity: [u8; 32] = decrypt(&identity_key, &envelope.encrypted_identity, &associated_data)?
+        .try_into()
+        .map_err(|_| "The identity key of the sender has the wrong length.")?;
+
+    let static_secret = contributory_dh(&identity.secret, &PublicKey::from(sender_identity))?;
+    let key = static_layer_key(&static_secret, &chain_key, &envelope.encrypted_identity);
+    let plaintext = decrypt(&key, &envelope.ciphertext, &associated_data)?;
+    if plaintext.len() < SenderCertificate::ENCODED_LEN {
+        return Err("The sealed message is too short.");
+    }
+    let (certificate, inner) = plaintext.split_at(SenderCertificate::ENCODED_LEN);
+    let certificate = SenderCertificate::decode(certificate)?;
+    if certificate.identity_key != sender_identity {
+        return Err("The sender certificate names another identity key.");
+    }
+
+    let ca_key = VerifyingKey::from_bytes(ca_key).map_err(|_| "The key of the certificate authority is invalid.")?;
+    let signature = Signature::try_from(&certificate.signature[..]).map_err(|_| "The sender certificate is invalid.")?;
+    ca_key.verify_strict(&certificate.signed_bytes(), &signature).map_err(|_| "The sender certificate is invalid.")?;
+    if certificate.expires < now {
+        return Err("The sender certificate has expired.");
+    }
+    Ok((certificate, inner.to_vec()))
+}
+
+/// Sends a message with rc and seals it with the identity of the sender, see `seal`.
+pub fn sealed_send(state: &mut State, identity: &DiffieHellmanParameters, certificate: &SenderCertificate, recipient: DeviceAddress, recipient_identity: &PublicKey, associated_data: &[u8], plaintext: &[u8]) -> SealedEnvelope {
+    let (_, header, ct) = send(state, associated_data, plaintext);
+    let mut inner = header.dh_ratchet_key.as_bytes().to_vec();
+    inner.extend_from_slice(&header.prev_chain_len.to_be_bytes());
+    inner.extend_from_slice(&header.msg_nbr.to_be_bytes());
+    inner.extend_from_slice(&header.epoch.to_be_bytes());
+    inner.extend_from_slice(&ct);
+    seal(recipient, recipient_identity, identity, certificate, &inner)
+}
+
+/// Opens an envelope of `sealed_send`, and returns the certificate of the sender with the rc header
+/// and ciphertext, which must be given to `receive` with the session of the sender.
+pub fn unseal_message(identity: &DiffieHellmanParameters, own: DeviceAddress, ca_key: &[u8; 32], now: u64, envelope: &SealedEnvelope) -> Result<(SenderCertificate, Header, Vec<u8>), &'static str> {
+    let (certificate, inner) = unseal(identity, own, ca_key, now, envelope)?;
+    if inner.len() < HEADER_LEN {
+        return Err("The sealed message is too short.");
+    }
+    let (header, ct) = inner.split_at(HEADER_LEN);
+    let (dh_ratchet_key, header) = header.split_at(32);
+    let dh_ratchet_key: [u8; 32] = dh_ratchet_key.try_into().unwrap();
+    let fields: Vec<usize> = header.chunks(size_of::<usize>()).map(|field| usize::from_be_bytes(field.try_into().unwrap())).collect();
+    let header = Header { dh_ratchet_key: PublicKey::from(dh_ratchet_key), prev_chain_len: fields[0], msg_nbr: fields[1], epoch: fields[2] };
+    Ok((certificate, header, ct.to_vec()))
+}
@@ -3,8 +3,18 @@ mod tests {
     use hex_literal::hex;
     use hkdf::Hkdf;
     use sha2::Sha256;
-    use std::{fs::File, io::Write, time::SystemTime};
-    use crate::{init_all, protocol::{generate_dh, dh, encrypt, decrypt, State, ratchet_encrypt, ratchet_decrypt, send, receive, Header, MAX_SKIP}};
+    use std::{collections::HashMap, fs::File, io::Write, time::SystemTime};
+    use crate::devices::{add_session as add_device_session, decrypt_from_device, encrypt_to_user, session_manager_init, update_device_list, DeviceAddress, SessionManager};
+    use crate::sealed::{certificate_authority_init, certificate_authority_key, issue_sender_certificate, seal, sealed_send, unseal, unseal_message, SealedEnvelope};
+    use std::io::Cursor;
+    use crate::attachment::{decrypt_attachment, encrypt_attachment, send_attachment, AttachmentPointer, ATTACHMENT_CHUNK_LEN};
+    use crate::mlkem::{compress, decapsulate, encapsulate, keygen, keygen_internal, reduce, CIPHERTEXT_LEN, ENCAPSULATION_KEY_LEN};
+    use crate::{init_all_with_kem, KEM_CHUNK_LEN};
+    use crate::padding::{pad, unpad, PaddingPolicy};
+    use crate::init_all_with_padding;
+    use crate::reset::{reset_accept_with_prekey, reset_finish, reset_request, reset_request_with_prekey, reset_respond, ResetRequest, ResetResponse};
+    use crate::group::{add_member, group_init, group_receive, group_send, receive_sender_key, remove_member, send_sender_key, GroupMessage, GroupState, MemberId};
+    use crate::{init_all, protocol::{generate_dh, dh, encrypt, decrypt, kdf_ck, State, ratchet_encrypt, ratchet_decrypt, send, receive, Header, MAX_SKIP}};
 
     #[test]
     fn shared_secret_works() {
@@ -286,14 +296,721 @@ mod tests {
             file.write_all(b"=\n").unwrap();
         }
     }
-}
 
+    // Group of members with a pairwise rc session between each two of them, keyed by (owner, peer).
+    fn group_with_sessions(members: &[MemberId]) -> (HashMap<MemberId, GroupState>, HashMap<(MemberId, MemberId), State>) {
+        let groups = members.iter().map(|id| (*id, group_init(*id, members))).collect();
+        let mut sessions = HashMap::new();
+        for (i, a) in members.iter().enumerate() {
+            for b in &members[i + 1..] {
+                add_session(&mut sessions, *a, *b);
+            }
+        }
+        (groups, sessions)
+    }
+
+    fn add_session(sessions: &mut HashMap<(MemberId, MemberId), State>, a: MemberId, b: MemberId) {
+        let (a_state, b_state) = init_all();
+        sessions.insert((a, b), a_state);
+        sessions.insert((b, a), b_state);
+    }
+
+    // Sends the sender key of `from` to each member of `to` over their pairwise sessions.
+    fn distribute(groups: &mut HashMap<MemberId, GroupState>, sessions: &mut HashMap<(MemberId, MemberId), State>, from: MemberId, to: &[MemberId]) {
+        for peer in to {
+            let (header, ct) = send_sender_key(&groups[&from], sessions.get_mut(&(from, *peer)).unwrap());
+            receive_sender_key(groups.get_mut(peer).unwrap(), from, sessions.get_mut(&(*peer, from)).unwrap(), header, &ct).unwrap();
+        }
+    }
+
+    fn distribute_all(groups: &mut HashMap<MemberId, GroupState>, sessions: &mut HashMap<(MemberId, MemberId), State>) {
+        let members: Vec<MemberId> = groups.keys().copied().collect();
+        for from in &members {
+            let others: Vec<MemberId> = members.iter().copied().filter(|id| id != from).collect();
+            distribute(groups, sessions, *from, &others);
+        }
+    }
+
+    #[test]
+    fn group_members_exchange_messages() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2, 3]);
+        distribute_all(&mut groups, &mut sessions);
+        for sender in [1, 2, 3, 1, 1, 3] {
+            let plaintext = format!("Hello from {}", sender).into_bytes();
+            let msg = group_send(groups.get_mut(&sender).unwrap(), &associated_data, &plaintext);
+            for receiver in [1, 2, 3].into_iter().filter(|id| *id != sender) {
+                assert_eq!(group_receive(groups.get_mut(&receiver).unwrap(), &associated_data, &msg).unwrap(), plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn group_messages_can_be_received_out_of_order() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2]);
+        distribute_all(&mut groups, &mut sessions);
+        let msgs: Vec<GroupMessage> = (0..5u8).map(|i| group_send(groups.get_mut(&1).unwrap(), &associated_data, &[i])).collect();
+        for i in [3, 0, 4, 2, 1] {
+            assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &msgs[i]).unwrap(), vec![i as u8]);
+        }
+        assert!(groups[&2].received[&1][0].skipped.is_empty());
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &msgs[2]), Err("The key of this message is no longer available."));
+    }
+
+    #[test]
+    fn group_message_too_far_ahead_is_rejected() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2]);
+        distribute_all(&mut groups, &mut sessions);
+        let mut msg = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"hi");
+        for _ in 0..MAX_SKIP + 1 {
+            msg = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"hi");
+        }
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &msg), Err("No such message exists."));
+    }
+
+    #[test]
+    fn group_message_forged_by_another_member_is_rejected() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2, 3]);
+        distribute_all(&mut groups, &mut sessions);
+        // Member 3 knows the chain key of member 1, but not its signing key.
+        let mut forger = groups[&3].clone();
+        let stolen = forger.received[&1][0].clone();
+        forger.own_id = 1;
+        forger.sender_key.chain_key = stolen.chain_key;
+        forger.sender_key.iteration = stolen.iteration;
+        let forged = group_send(&mut forger, &associated_data, b"I am member 1");
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &forged), Err("The signature is invalid."));
+
+        let mut tampered = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"I am member 1");
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &tampered), Err("The signature is invalid."));
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &[0u8; 32], &forged), Err("The signature is invalid."));
+    }
+
+    #[test]
+    fn sender_key_from_the_wrong_peer_is_rejected() {
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2, 3]);
+        let (header, ct) = send_sender_key(&groups[&1], sessions.get_mut(&(1, 2)).unwrap());
+        // Member 2 is told the key comes from 3, while the rc session authenticates member 1.
+        let result = receive_sender_key(groups.get_mut(&2).unwrap(), 3, sessions.get_mut(&(2, 1)).unwrap(), header, &ct);
+        assert!(result.is_err());
+        assert!(groups[&2].received.is_empty());
+    }
+
+    #[test]
+    fn removed_member_cannot_read_after_rotation() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2, 3]);
+        distribute_all(&mut groups, &mut sessions);
+        let before = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"before");
+
+        for member in [1, 2] {
+            let recipients = remove_member(groups.get_mut(&member).unwrap(), 3);
+            assert_eq!(recipients, vec![3 - member]);
+            distribute(&mut groups, &mut sessions, member, &recipients);
+        }
+        assert_eq!(groups[&1].sender_key.generation, 1);
+        let after = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"after");
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &after).unwrap(), b"after");
+        // A message sent just before the rotation is still received.
+        assert_eq!(group_receive(groups.get_mut(&2).unwrap(), &associated_data, &before).unwrap(), b"before");
+        assert_eq!(group_receive(groups.get_mut(&3).unwrap(), &associated_data, &after), Err("No sender key for this generation."));
+        // The removed member's messages are no longer accepted.
+        let from_removed = group_send(groups.get_mut(&3).unwrap(), &associated_data, b"still here");
+        assert_eq!(group_receive(groups.get_mut(&1).unwrap(), &associated_data, &from_removed), Err("The sender is not a member of the group."));
+    }
+
+    #[test]
+    fn added_member_cannot_read_earlier_messages() {
+        let associated_data = [7u8; 32];
+        let (mut groups, mut sessions) = group_with_sessions(&[1, 2]);
+        distribute_all(&mut groups, &mut sessions);
+        let earlier = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"earlier");
+
+        groups.insert(3, group_init(3, &[1, 2, 3]));
+        add_session(&mut sessions, 1, 3);
+        add_session(&mut sessions, 2, 3);
+        for member in [1, 2] {
+            let recipients = add_member(groups.get_mut(&member).unwrap(), 3);
+            distribute(&mut groups, &mut sessions, member, &recipients);
+        }
+        distribute(&mut groups, &mut sessions, 3, &[1, 2]);
+
+        assert_eq!(group_receive(groups.get_mut(&3).unwrap(), &associated_data, &earlier), Err("The key of this message is no longer available."));
+        let later = group_send(groups.get_mut(&1).unwrap(), &associated_data, b"later");
+        for receiver in [2, 3] {
+            assert_eq!(group_receive(groups.get_mut(&receiver).unwrap(), &associated_data, &later).unwrap(), b"later");
+        }
+        let from_new_member = group_send(groups.get_mut(&3).unwrap(), &associated_data, b"hello");
+        assert_eq!(group_receive(groups.get_mut(&1).unwrap(), &associated_data, &from_new_member).unwrap(), b"hello");
+    }
+
+    fn device(user: u32, device: u32) -> SessionManager<State> {
+        session_manager_init(DeviceAddress { user, device }, 100 * user + device)
+    }
+
+    /// Establishes a session between two devices.
+    fn link(a: &mut SessionManager<State>, b: &mut SessionManager<State>) {
+        let (a_state, b_state) = init_all();
+        add_device_session(a, b.own, b.registration_id, a_state);
+        add_device_session(b, a.own, a.registration_id, b_state);
+    }
 
+    /// Returns the managers of Alice's phone and laptop and of Bob's phone and laptop, all linked with each other.
+    fn two_users_with_two_devices() -> Vec<SessionManager<State>> {
+        let mut devices = vec![device(1, 1), device(1, 2), device(2, 1), device(2, 2)];
+        for i in 0..devices.len() {
+            for j in i + 1..devices.len() {
+                let (first, second) = devices.split_at_mut(j);
+                link(&mut first[i], &mut second[0]);
+            }
+        }
+        devices
+    }
+
+    #[test]
+    fn message_to_a_user_reaches_its_devices_and_the_senders_other_devices() {
+        let associated_data = [3u8; 32];
+        let mut devices = two_users_with_two_devices();
+        let messages = encrypt_to_user(&mut devices[0], 2, &associated_data, b"Hola Bob");
+        let recipients: Vec<DeviceAddress> = messages.iter().map(|msg| msg.recipient).collect();
+        assert_eq!(recipients, vec![DeviceAddress { user: 1, device: 2 }, DeviceAddress { user: 2, device: 1 }, DeviceAddress { user: 2, device: 2 }]);
+        for msg in messages {
+            let recipient = devices.iter_mut().find(|device| device.own == msg.recipient).unwrap();
+            assert_eq!(decrypt_from_device(recipient, &associated_data, msg).unwrap(), b"Hola Bob");
+        }
+    }
+
+    #[test]
+    fn new_device_gets_a_session_before_receiving() {
+        let associated_data = [3u8; 32];
+        let mut devices = two_users_with_two_devices();
+        let mut bob_tablet = device(2, 3);
+        let missing = update_device_list(&mut devices[0], 2, &[(1, 201), (2, 202), (3, 203)]);
+        assert_eq!(missing, vec![bob_tablet.own]);
+        assert_eq!(encrypt_to_user(&mut devices[0], 2, &associated_data, b"one").len(), 3);
+
+        link(&mut devices[0], &mut bob_tablet);
+        let messages = encrypt_to_user(&mut devices[0], 2, &associated_data, b"two");
+        assert_eq!(messages.len(), 4);
+        let msg = messages.into_iter().find(|msg| msg.recipient == bob_tablet.own).unwrap();
+        assert_eq!(decrypt_from_device(&mut bob_tablet, &associated_data, msg).unwrap(), b"two");
+    }
+
+    #[test]
+    fn reregistered_device_replaces_its_stale_sessions() {
+        let associated_data = [3u8; 32];
+        let mut devices = two_users_with_two_devices();
+        // Bob's laptop sends a message, then is reinstalled and registers again.
+        let old_messages = encrypt_to_user(&mut devices[3], 1, &associated_data, b"from the old install");
+        let mut bob_laptop = session_manager_init(DeviceAddress { user: 2, device: 2 }, 999);
+
+        let missing = update_device_list(&mut devices[0], 2, &[(1, 201), (2, 999)]);
+        assert_eq!(missing, vec![bob_laptop.own]);
+        link(&mut devices[0], &mut bob_laptop);
+        let old_msg = old_messages.into_iter().find(|msg| msg.recipient == devices[0].own).unwrap();
+        assert_eq!(decrypt_from_device(&mut devices[0], &associated_data, old_msg).err(), Some("The session with this device is stale."));
+
+        let messages = encrypt_to_user(&mut bob_laptop, 1, &associated_data, b"from the new install");
+        let msg = messages.into_iter().find(|msg| msg.recipient == devices[0].own).unwrap();
+        assert_eq!(decrypt_from_device(&mut devices[0], &associated_data, msg).unwrap(), b"from the new install");
+    }
 
+    #[test]
+    fn removed_device_no_longer_receives() {
+        let associated_data = [3u8; 32];
+        let mut devices = two_users_with_two_devices();
+        assert!(update_device_list(&mut devices[0], 2, &[(1, 201)]).is_empty());
+        let messages = encrypt_to_user(&mut devices[0], 2, &associated_data, b"phone only");
+        assert!(messages.iter().all(|msg| msg.recipient != DeviceAddress { user: 2, device: 2 }));
+        assert_eq!(messages.len(), 2);
+    }
 
+    #[test]
+    fn replaced_session_still_receives_messages_in_flight() {
+        let associated_data = [3u8; 32];
+        let mut alice = device(1, 1);
+        let mut bob = device(2, 1);
+        link(&mut alice, &mut bob);
+        let in_flight = encrypt_to_user(&mut bob, 1, &associated_data, b"sent before the reset").remove(0);
+
+        // Both devices start a new session, e.g. after a reset.
+        link(&mut alice, &mut bob);
+        let msg = encrypt_to_user(&mut bob, 1, &associated_data, b"sent after the reset").remove(0);
+        assert_eq!(decrypt_from_device(&mut alice, &associated_data, msg).unwrap(), b"sent after the reset");
+        assert_eq!(decrypt_from_device(&mut alice, &associated_data, in_flight).unwrap(), b"sent before the reset");
+
+        let mut forged = encrypt_to_user(&mut bob, 1, &associated_data, b"tampered").remove(0);
+        forged.ciphertext.1[0] ^= 1;
+        assert!(decrypt_from_device(&mut alice, &associated_data, forged).is_err());
+    }
 
+    #[test]
+    fn sealed_message_hides_the_sender_from_the_relay() {
+        let associated_data = [5u8; 32];
+        let ca = certificate_authority_init();
+        let (alice, bob) = (DeviceAddress { user: 1, device: 1 }, DeviceAddress { user: 2, device: 1 });
+        let (alice_identity, bob_identity) = (generate_dh(), generate_dh());
+        let certificate = issue_sender_certificate(&ca, alice, &alice_identity.public, 1000);
+        let (mut alice_state, mut bob_state) = init_all();
+
+        let envelope = sealed_send(&mut alice_state, &alice_identity, &certificate, bob, &bob_identity.public, &associated_data, b"Guess who?");
+        // The relay only routes the encoded envelope, which contains neither the ratchet key nor the certificate.
+        let bytes = envelope.encode();
+        let relayed = SealedEnvelope::decode(&bytes).unwrap();
+        assert_eq!(relayed.recipient, bob);
+        assert!(!bytes.windows(32).any(|window| window == alice_state.DHs.public.as_bytes() || window == alice_identity.public.as_bytes()));
+
+        let (sender_certificate, header, ct) = unseal_message(&bob_identity, bob, &certificate_authority_key(&ca), 500, &relayed).unwrap();
+        assert_eq!(sender_certificate, certificate);
+        let (acc, _, pt) = receive(&mut bob_state, &associated_data, header, &ct);
+        assert!(acc);
+        assert_eq!(pt, b"Guess who?");
+    }
 
+    #[test]
+    fn sealed_message_with_an_invalid_certificate_is_rejected() {
+        let ca = certificate_authority_init();
+        let (alice, bob) = (DeviceAddress { user: 1, device: 1 }, DeviceAddress { user: 2, device: 1 });
+        let (alice_identity, bob_identity) = (generate_dh(), generate_dh());
+        let ca_key = certificate_authority_key(&ca);
+        let certificate = issue_sender_certificate(&ca, alice, &alice_identity.public, 1000);
+
+        let envelope = seal(bob, &bob_identity.public, &alice_identity, &certificate, b"inner");
+        assert_eq!(unseal(&bob_identity, bob, &ca_key, 1000, &envelope).unwrap().1, b"inner");
+        assert_eq!(unseal(&bob_identity, bob, &ca_key, 1001, &envelope).err(), Some("The sender certificate has expired."));
+
+        let forged = issue_sender_certificate(&certificate_authority_init(), alice, &alice_identity.public, 1000);
+        let envelope = seal(bob, &bob_identity.public, &alice_identity, &forged, b"inner");
+        assert_eq!(unseal(&bob_identity, bob, &ca_key, 0, &envelope).err(), Some("The sender certificate is invalid."));
+    }
 
+    #[test]
+    fn sealed_message_must_come_from_the_certified_identity() {
+        let ca = certificate_authority_init();
+        let (alice, bob) = (DeviceAddress { user: 1, device: 1 }, DeviceAddress { user: 2, device: 1 });
+        let (alice_identity, bob_identity, eve_identity) = (generate_dh(), generate_dh(), generate_dh());
+        let ca_key = certificate_authority_key(&ca);
+        let certificate = issue_sender_certificate(&ca, alice, &alice_identity.public, 1000);
+
+        // Eve replays Alice's certificate without her identity key.
+        let envelope = seal(bob, &bob_identity.public, &eve_identity, &certificate, b"inner");
+        assert_eq!(unseal(&bob_identity, bob, &ca_key, 0, &envelope).err(), Some("The sender certificate names another identity key."));
+
+        // Eve cannot swap the encrypted identity of Alice's envelope for her own.
+        let alice_envelope = seal(bob, &bob_identity.public, &alice_identity, &certificate, b"inner");
+        let swapped = SealedEnvelope { encrypted_identity: envelope.encrypted_identity.clone(), ..alice_envelope.clone() };
+        assert!(unseal(&bob_identity, bob, &ca_key, 0, &swapped).is_err());
+
+        // An ephemeral key of small order would make the first layer readable by anyone.
+        for low_order in [[0u8; 32], { let mut one = [0u8; 32]; one[0] = 1; one }] {
+            let envelope = SealedEnvelope { ephemeral_key: low_order, ..alice_envelope.clone() };
+            assert_eq!(unseal(&bob_identity, bob, &ca_key, 0, &envelope).err(), Some("The public key has a small order."));
+        }
+    }
 
+    #[test]
+    fn sealed_message_opens_only_for_its_recipient() {
+        let ca = certificate_authority_init();
+        let (alice, bob) = (DeviceAddress { user: 1, device: 1 }, DeviceAddress { user: 2, device: 1 });
+        let (alice_identity, bob_identity) = (generate_dh(), generate_dh());
+        let ca_key = certificate_authority_key(&ca);
+        let certificate = issue_sender_certificate(&ca, alice, &alice_identity.public, 1000);
+
+        let envelope = seal(bob, &bob_identity.public, &alice_identity, &certificate, b"inner");
+        assert!(unseal(&generate_dh(), bob, &ca_key, 0, &envelope).is_err());
+        let redirected = SealedEnvelope { recipient: DeviceAddress { user: 3, device: 1 }, ..envelope.clone() };
+        assert!(unseal(&bob_identity, redirected.recipient, &ca_key, 0, &redirected).is_err());
+        let mut tampered = envelope;
+        tampered.ciphertext[0] ^= 1;
+        assert!(unseal(&bob_identity, bob, &ca_key, 0, &tampered).is_err());
+    }
 
+    #[test]
+    fn padded_lengths_follow_the_policy() {
+        assert_eq!(pad(&[1; 100], PaddingPolicy::None).len(), 100);
+        assert_eq!(pad(&[1; 100], PaddingPolicy::PowerOfTwo).len(), 128);
+        assert_eq!(pad(&[1; 127], PaddingPolicy::PowerOfTwo).len(), 128);
+        assert_eq!(pad(&[1; 128], PaddingPolicy::PowerOfTwo).len(), 256);
+        assert_eq!(pad(&[1; 999], PaddingPolicy::Padme).len(), 1024);
+        assert_eq!(pad(&[1; 1099], PaddingPolicy::Padme).len(), 1152);
+        assert_eq!(pad(&[], PaddingPolicy::Fixed(256)).len(), 256);
+        assert_eq!(pad(&[1; 256], PaddingPolicy::Fixed(256)).len(), 512);
+        for len in 0..2000 {
+            let padded = pad(&vec![0; len], PaddingPolicy::Padme);
+            assert!(padded.len() <= (len + 1) * 112 / 100 + 2);
+            assert_eq!(unpad(&padded, PaddingPolicy::Padme).unwrap(), vec![0; len]);
+        }
+    }
 
+    #[test]
+    fn non_canonical_padding_is_rejected() {
+        let policy = PaddingPolicy::PowerOfTwo;
+        let mut padded = pad(b"message", policy);
+        assert_eq!(unpad(&padded, policy).unwrap(), b"message");
+        padded.extend_from_slice(&[0; 8]);
+        assert_eq!(unpad(&padded, policy).err(), Some("The padding of the message is invalid."));
+        assert_eq!(unpad(&[1; 8], policy).err(), Some("The padding of the message is invalid."));
+        assert_eq!(unpad(&[0; 8], policy).err(), Some("The padding of the message is invalid."));
+    }
+
+    #[test]
+    fn padded_ciphertexts_of_a_bucket_have_the_same_length() {
+        let associated_data = [0u8; 32];
+        for policy in [PaddingPolicy::PowerOfTwo, PaddingPolicy::Padme, PaddingPolicy::Fixed(512)] {
+            let (mut alice_state, mut bob_state) = init_all_with_padding(policy);
+            let mut lengths = Vec::new();
+            for len in [300, 350, 400, 450] {
+                let plaintext = vec![7u8; len];
+                let (_, header, ct) = send(&mut alice_state, &associated_data, &plaintext);
+                lengths.push(ct.len());
+                let (acc, _, pt) = receive(&mut bob_state, &associated_data, header, &ct);
+                assert!(acc);
+                assert_eq!(pt, plaintext);
+            }
+            if policy == PaddingPolicy::Padme {
+                // Padmé keeps more buckets, here one per 16 bytes.
+                assert!(lengths.windows(2).all(|pair| pair[0] < pair[1]));
+            } else {
+                assert!(lengths.iter().all(|len| *len == lengths[0]));
+            }
+        }
+        let (mut alice_state, _) = init_all_with_padding(PaddingPolicy::Padme);
+        let (_, _, first) = send(&mut alice_state, &associated_data, &[7u8; 1000]);
+        let (_, _, second) = send(&mut alice_state, &associated_data, &[7u8; 1020]);
+        assert_eq!(first.len(), second.len());
+    }
+
+    #[test]
+    fn padding_is_checked_after_the_ciphertext_is_authenticated() {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = init_all_with_padding(PaddingPolicy::PowerOfTwo);
+        let (_, header, mut ct) = send(&mut alice_state, &associated_data, b"message");
+        ct[0] ^= 1;
+        let (acc, _, pt) = receive(&mut bob_state, &associated_data, header, &ct);
+        assert!(!acc);
+        assert!(pt.is_empty());
+
+        // The policy is authenticated, so a party with another policy rejects the message before looking at its padding.
+        for policy in [PaddingPolicy::None, PaddingPolicy::Padme, PaddingPolicy::Fixed(128)] {
+            let (mut alice_state, mut bob_state) = init_all_with_padding(PaddingPolicy::PowerOfTwo);
+            bob_state.padding = policy;
+            let (_, header, ct) = send(&mut alice_state, &associated_data, b"message");
+            let (acc, _, pt) = receive(&mut bob_state, &associated_data, header, &ct);
+            assert!(!acc);
+            assert!(pt.is_empty());
+        }
+    }
+
+    fn attachment_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn attachment_is_streamed_with_its_pointer_in_an_rc_message() {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = init_all();
+        for len in [0, 1000, ATTACHMENT_CHUNK_LEN, 3 * ATTACHMENT_CHUNK_LEN + 123] {
+            let attachment = attachment_bytes(len);
+            let mut encrypted = Vec::new();
+            let (_, header, ct) = send_attachment(&mut alice_state, &associated_data, &mut Cursor::new(&attachment), &mut encrypted).unwrap();
+            assert_eq!(encrypted.len(), len + len.div_ceil(ATTACHMENT_CHUNK_LEN).max(1) * 16);
+
+            let (acc, _, pt) = receive(&mut bob_state, &associated_data, header, &ct);
+            assert!(acc);
+            let pointer = AttachmentPointer::decode(&pt).unwrap();
+            assert_eq!(pointer.len, len as u64);
+            let mut decrypted = Vec::new();
+            assert_eq!(decrypt_attachment(&pointer, &mut Cursor::new(&encrypted), &mut decrypted), Ok(len as u64));
+            assert_eq!(decrypted, attachment);
+        }
+    }
+
+    #[test]
+    fn attachment_keys_are_derived_from_the_sending_chain() {
+        let (mut alice_state, _) = init_all();
+        let attachment = attachment_bytes(100);
+        let mut copy = alice_state.clone();
+
+        // Two attachments sent before the same message never share a key.
+        let first = encrypt_attachment(&mut alice_state, &mut Cursor::new(&attachment), &mut Vec::new()).unwrap();
+        let second = encrypt_attachment(&mut alice_state, &mut Cursor::new(&attachment), &mut Vec::new()).unwrap();
+        assert_ne!(first.key, second.key);
+        assert_ne!(first.digest, second.digest);
+
+        // The key is the HKDF of the next message key with the attachment counter.
+        let (_, mk) = kdf_ck(&copy.CKs);
+        let mut expected = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &mk).expand(&[b"sOsAttachment".as_slice(), &0u64.to_be_bytes()].concat(), &mut expected).unwrap();
+        assert_eq!(first.key, expected);
+        assert_eq!(encrypt_attachment(&mut copy, &mut Cursor::new(&attachment), &mut Vec::new()).unwrap().key, first.key);
+
+        // Once the message is sent, the next attachment is keyed from the next message key.
+        let _ = send(&mut copy, &[0u8; 32], b"message");
+        let (_, mk) = kdf_ck(&copy.CKs);
+        let mut expected = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &mk).expand(&[b"sOsAttachment".as_slice(), &1u64.to_be_bytes()].concat(), &mut expected).unwrap();
+        assert_eq!(encrypt_attachment(&mut copy, &mut Cursor::new(&attachment), &mut Vec::new()).unwrap().key, expected);
+    }
+
+    #[test]
+    fn tampered_attachments_are_rejected() {
+        let attachment = attachment_bytes(2 * ATTACHMENT_CHUNK_LEN + 10);
+        let mut encrypted = Vec::new();
+        let pointer = encrypt_attachment(&mut init_all().0, &mut Cursor::new(&attachment), &mut encrypted).unwrap();
+        let chunk = ATTACHMENT_CHUNK_LEN + 16;
+
+        let truncated = &encrypted[..2 * chunk];
+        assert_eq!(decrypt_attachment(&pointer, &mut Cursor::new(truncated), &mut Vec::new()).err(), Some("The attachment is corrupted."));
+        let mut reordered = encrypted[chunk..2 * chunk].to_vec();
+        reordered.extend_from_slice(&encrypted[..chunk]);
+        reordered.extend_from_slice(&encrypted[2 * chunk..]);
+        assert_eq!(decrypt_attachment(&pointer, &mut Cursor::new(&reordered), &mut Vec::new()).err(), Some("The attachment is corrupted."));
+        let mut flipped = encrypted.clone();
+        flipped[chunk + 5] ^= 1;
+        assert_eq!(decrypt_attachment(&pointer, &mut Cursor::new(&flipped), &mut Vec::new()).err(), Some("The attachment is corrupted."));
+
+        let wrong_digest = AttachmentPointer { digest: [0; 32], ..pointer.clone() };
+        assert_eq!(decrypt_attachment(&wrong_digest, &mut Cursor::new(&encrypted), &mut Vec::new()).err(), Some("The digest of the attachment does not match."));
+        let wrong_key = AttachmentPointer { key: [0; 32], ..pointer };
+        assert_eq!(decrypt_attachment(&wrong_key, &mut Cursor::new(&encrypted), &mut Vec::new()).err(), Some("The attachment is corrupted."));
+    }
+
+    #[test]
+    fn ml_kem_shares_a_secret() {
+        let (encapsulation_key, decapsulation_key) = keygen();
+        assert_eq!(encapsulation_key.len(), ENCAPSULATION_KEY_LEN);
+        let (secret, ciphertext) = encapsulate(&encapsulation_key).unwrap();
+        assert_eq!(ciphertext.len(), CIPHERTEXT_LEN);
+        assert_eq!(decapsulate(&decapsulation_key, &ciphertext), Ok(secret));
+
+        // A modified ciphertext decapsulates to an unrelated secret (implicit rejection).
+        let mut modified = ciphertext.clone();
+        modified[0] ^= 1;
+        let rejected = decapsulate(&decapsulation_key, &modified).unwrap();
+        assert_ne!(rejected, secret);
+        assert_eq!(decapsulate(&decapsulation_key, &modified), Ok(rejected));
+        assert_ne!(decapsulate(&keygen().1, &ciphertext), Ok(secret));
+
+        let mut invalid_key = encapsulation_key;
+        invalid_key[0] = 0xff;
+        invalid_key[1] |= 0x0f;
+        assert!(encapsulate(&invalid_key).is_err());
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// Checks the keys, the decapsulation and the implicit rejection against OpenSSL's ML-KEM-768. A ciphertext of
+    /// OpenSSL only decapsulates to its secret if the re-encryption gives it back, so this also checks the encapsulation.
+    #[test]
+    fn ml_kem_matches_known_answers() {
+        let vectors = include_str!("../test_vectors/ml_kem_768.txt");
+        let mut checked = 0;
+        for vector in vectors.split("\n\n").filter(|vector| vector.starts_with("seed")) {
+            let fields: HashMap<&str, Vec<u8>> = vector.lines().map(|line| {
+                let (name, value) = line.split_once(" = ").unwrap();
+                (name, from_hex(value))
+            }).collect();
+            let (d, z) = fields["seed"].split_at(32);
+            let (encapsulation_key, decapsulation_key) = keygen_internal(d.try_into().unwrap(), z.try_into().unwrap());
+            assert_eq!(<sha3::Sha3_256 as sha3::Digest>::digest(&encapsulation_key).to_vec(), fields["ek_sha3"]);
+            assert_eq!(decapsulate(&decapsulation_key, &fields["ct"]).unwrap().to_vec(), fields["ss"]);
+            let mut modified = fields["ct"].clone();
+            modified[0] ^= 1;
+            assert_eq!(decapsulate(&decapsulation_key, &modified).unwrap().to_vec(), fields["rejected_ss"]);
+            checked += 1;
+        }
+        assert_eq!(checked, 3);
+    }
+
+    /// The Barrett reduction and division agree with `%` and `/` on every value ML-KEM gives them, and beyond.
+    #[test]
+    fn ml_kem_arithmetic_matches_division() {
+        const Q: u32 = 3329;
+        for x in (0..2 * Q * Q + Q).chain((u32::MAX - 4 * Q)..=u32::MAX) {
+            assert_eq!(reduce(x), x % Q);
+        }
+        for d in [1, 4, 10, 11] {
+            let f: [u32; 256] = std::array::from_fn(|i| i as u32);
+            for offset in (0..Q).step_by(256) {
+                let f = f.map(|x| (x + offset) % Q);
+                let expected = f.map(|x| (((x << (d + 1)) + Q) / (2 * Q)) & ((1 << d) - 1));
+                assert_eq!(compress(&f, d), expected);
+            }
+        }
+    }
+
+    /// Alice and Bob take turns sending one message each for `rounds` rounds, `drop` telling which messages are lost.
+    fn kem_conversation(alice_state: &mut State, bob_state: &mut State, rounds: usize, drop: impl Fn(usize) -> bool) {
+        let associated_data = [0u8; 32];
+        for round in 0..2 * rounds {
+            let (sender, receiver) = if round % 2 == 0 { (&mut *alice_state, &mut *bob_state) } else { (&mut *bob_state, &mut *alice_state) };
+            let (_, header, ct) = send(sender, &associated_data, b"message");
+            if !drop(round / 2) {
+                let (acc, _, pt) = receive(receiver, &associated_data, header, &ct);
+                assert!(acc);
+                assert_eq!(pt, b"message");
+                // Both chains are derived with the root key, into which both parties mixed the same KEM secrets.
+                assert_eq!(sender.CKs, receiver.CKr);
+            }
+        }
+    }
+
+    #[test]
+    fn kem_ratchet_mixes_a_secret_of_each_party_into_the_root_key() {
+        let (mut alice_state, mut bob_state) = init_all_with_kem();
+        kem_conversation(&mut alice_state, &mut bob_state, 120, |_| false);
+        let (alice_kem, bob_kem) = (alice_state.kem.as_ref().unwrap(), bob_state.kem.as_ref().unwrap());
+        // One epoch takes the rounds to send the encapsulation key and the ciphertext, and one more to mix the secret.
+        assert!(alice_kem.mixed >= 2 && bob_kem.mixed >= 2);
+        assert!(alice_kem.epoch >= 2 && bob_kem.epoch >= 2);
+        // kem_conversation compared the chains on every message, which kdf_rk outputs with the root keys, so those matched too.
+    }
+
+    #[test]
+    fn kem_ratchet_with_a_different_secret_fails_to_decrypt() {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = init_all_with_kem();
+        let mut corrupted = false;
+        let mut rejected = false;
+        for round in 0..400 {
+            let (sender, receiver) = if round % 2 == 0 { (&mut alice_state, &mut bob_state) } else { (&mut bob_state, &mut alice_state) };
+            let (_, header, ct) = send(sender, &associated_data, b"message");
+            if !receive(receiver, &associated_data, header, &ct).0 {
+                rejected = true;
+                break;
+            }
+            // Bob encapsulated a secret which is not mixed yet: replace it with another one.
+            let secrets = &mut bob_state.kem.as_mut().unwrap().secrets;
+            if !corrupted && !secrets.is_empty() {
+                secrets.values_mut().for_each(|secret| secret[0] ^= 1);
+                corrupted = true;
+            }
+        }
+        assert!(corrupted && rejected);
+    }
+
+    #[test]
+    fn kem_ratchet_progresses_despite_lost_messages() {
+        let (mut alice_state, mut bob_state) = init_all_with_kem();
+        kem_conversation(&mut alice_state, &mut bob_state, 180, |round| round % 3 == 1);
+        assert!(alice_state.kem.as_ref().unwrap().mixed >= 2);
+    }
+
+    #[test]
+    fn kem_frames_are_authenticated() {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = init_all_with_kem();
+        let (_, header, ct) = send(&mut alice_state, &associated_data, b"message");
+        let (_, _, plain_ct) = send(&mut init_all().0, &associated_data, b"message");
+        // A frame carrying a chunk: mixed || epoch || kind || index || chunk length || chunk.
+        assert_eq!(ct.len(), plain_ct.len() + 13 + KEM_CHUNK_LEN);
+
+        for position in [3, 8, 12, 20] {
+            let mut tampered = ct.clone();
+            tampered[position] ^= 1;
+            assert!(!receive(&mut bob_state.clone(), &associated_data, header, &tampered).0);
+        }
+        // A chain claiming a KEM secret Bob does not have is rejected before its keys are derived.
+        let mut ahead = ct.clone();
+        ahead[3] = 1;
+        assert!(!receive(&mut bob_state.clone(), &associated_data, header, &ahead).0);
+        assert!(receive(&mut bob_state, &associated_data, header, &ct).0);
+    }
+
+    /// Lets Alice run ahead of Bob by more than MAX_SKIP messages, so that Bob can no longer receive from her.
+    fn desynchronise(alice_state: &mut State, bob_state: &mut State) {
+        let associated_data = [0u8; 32];
+        for _ in 0..MAX_SKIP + 1 {
+            send(alice_state, &associated_data, b"lost");
+        }
+        let (_, header, ct) = send(alice_state, &associated_data, b"too far ahead");
+        assert!(!receive(bob_state, &associated_data, header, &ct).0);
+    }
+
+    #[test]
+    fn reset_recovers_a_desynchronised_session() {
+        let associated_data = [0u8; 32];
+        let (alice_identity, bob_identity) = (generate_dh(), generate_dh());
+        let (mut alice_state, mut bob_state) = init_all_with_padding(PaddingPolicy::Padme);
+        desynchronise(&mut alice_state, &mut bob_state);
+
+        let (pending, request) = reset_request(&alice_state, &alice_identity, &bob_identity.public, vec![1; 32]);
+        let request = ResetRequest::decode(&request.encode()).unwrap();
+        let (mut new_bob_state, response) = reset_respond(&bob_state, &bob_identity, &alice_identity.public, &request, vec![2; 32]).unwrap();
+        let response = ResetResponse::decode(&response.encode()).unwrap();
+        let mut new_alice_state = reset_finish(&pending, &alice_identity, &bob_identity.public, &response).unwrap();
+
+        assert_ne!(new_alice_state.RK, alice_state.RK);
+        assert_eq!(new_alice_state.padding, PaddingPolicy::Padme);
+        assert_eq!(response.epoch, alice_state.epoch.max(bob_state.epoch) + 1);
+        for round in 0..6 {
+            let (sender, receiver) = if round % 2 == 0 { (&mut new_bob_state, &mut new_alice_state) } else { (&mut new_alice_state, &mut new_bob_state) };
+            let (num, header, ct) = send(sender, &associated_data, b"after the reset");
+            assert!(num.epoch >= response.epoch);
+            assert_eq!(receive(receiver, &associated_data, header, &ct), (true, num, b"after the reset".to_vec()));
+        }
+        // The old session stays broken.
+        let (_, header, ct) = send(&mut alice_state, &associated_data, b"old");
+        assert!(!receive(&mut bob_state, &associated_data, header, &ct).0);
+    }
+
+    #[test]
+    fn reset_rejects_forged_and_replayed_messages() {
+        let (alice_identity, bob_identity, eve_identity) = (generate_dh(), generate_dh(), generate_dh());
+        let (alice_state, bob_state) = init_all();
+        let (pending, request) = reset_request(&alice_state, &alice_identity, &bob_identity.public, vec![1; 32]);
+
+        let mut tampered = request.clone();
+        tampered.transcript[0] ^= 1;
+        assert_eq!(reset_respond(&bob_state, &bob_identity, &alice_identity.public, &tampered, vec![2; 32]).err(), Some("The reset request is not authenticated."));
+        let (_, forged) = reset_request(&alice_state, &eve_identity, &bob_identity.public, vec![1; 32]);
+        assert_eq!(reset_respond(&bob_state, &bob_identity, &alice_identity.public, &forged, vec![2; 32]).err(), Some("The reset request is not authenticated."));
+
+        let (new_bob_state, response) = reset_respond(&bob_state, &bob_identity, &alice_identity.public, &request, vec![2; 32]).unwrap();
+        let mut tampered = response.clone();
+        tampered.transcript[0] ^= 1;
+        assert_eq!(reset_finish(&pending, &alice_identity, &bob_identity.public, &tampered).err(), Some("The reset response is not authenticated."));
+        assert!(reset_finish(&pending, &alice_identity, &bob_identity.public, &response).is_ok());
+        // Bob does not reset again on a replay of the request.
+        assert_eq!(reset_respond(&new_bob_state, &bob_identity, &alice_identity.public, &request, vec![2; 32]).err(), Some("The reset request is older than the last reset."));
+    }
+
+    #[test]
+    fn reset_rejects_a_request_held_back_while_the_session_moved_on() {
+        let associated_data = [0u8; 32];
+        let (alice_identity, bob_identity) = (generate_dh(), generate_dh());
+        let (mut alice_state, mut bob_state) = init_all();
+        let (_, request) = reset_request(&alice_state, &alice_identity, &bob_identity.public, vec![1; 32]);
+        for round in 0..4 {
+            let (sender, receiver) = if round % 2 == 0 { (&mut alice_state, &mut bob_state) } else { (&mut bob_state, &mut alice_state) };
+            let (_, header, ct) = send(sender, &associated_data, b"still in sync");
+            assert!(receive(receiver, &associated_data, header, &ct).0);
+        }
+        assert!(bob_state.epoch > request.epoch);
+        assert_eq!(reset_respond(&bob_state, &bob_identity, &alice_identity.public, &request, vec![2; 32]).err(), Some("The reset request is older than the session."));
+    }
+
+    #[test]
+    fn reset_with_prekey_lets_the_initiator_send_first() {
+        let associated_data = [0u8; 32];
+        let (alice_identity, bob_identity, bob_prekey) = (generate_dh(), generate_dh(), generate_dh());
+        let (mut alice_state, mut bob_state) = init_all_with_kem();
+        desynchronise(&mut alice_state, &mut bob_state);
+
+        let (mut new_alice_state, request) = reset_request_with_prekey(&alice_state, &alice_identity, &bob_identity.public, &bob_prekey.public, vec![1; 32]);
+        let (num, header, ct) = send(&mut new_alice_state, &associated_data, b"first");
+        assert_eq!(reset_accept_with_prekey(&bob_state, &bob_identity, &generate_dh(), &alice_identity.public, &request).err(), Some("The reset request is keyed with another prekey."));
+        assert_eq!(reset_respond(&bob_state, &bob_identity, &alice_identity.public, &request, vec![2; 32]).err(), Some("The reset request is keyed with a prekey."));
+        let mut new_bob_state = reset_accept_with_prekey(&bob_state, &bob_identity, &bob_prekey, &alice_identity.public, &request).unwrap();
+        assert!(new_bob_state.kem.is_some());
+        assert_eq!(receive(&mut new_bob_state, &associated_data, header, &ct), (true, num, b"first".to_vec()));
+        kem_conversation(&mut new_alice_state, &mut new_bob_state, 60, |_| false);
+        assert!(new_alice_state.kem.as_ref().unwrap().mixed >= 1);
+    }
+}
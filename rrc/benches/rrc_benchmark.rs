@@ -1,18 +1,49 @@
-use criterion::{criterion_group, criterion_main, Criterion, black_box};
-use rrc::protocol::{rrc_init_all, Security};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, black_box};
+use rrc::protocol::{rrc_init_all, optimized_rrc_receive, Security};
+use rrc::optimized_receive::{optimized_receive_rrc_receive, optimized_receive_rrc_send, rrc_init_all_optimized_receive};
 
 fn init_all_benchmark(c: &mut Criterion) {
     c.bench_function(
-        "Initialize states ", 
+        "Initialize states ",
         |b| b.iter(|| black_box(rrc_init_all(Security::RRidAndSRid)))
     );
 }
 
-  
-
+// Compares receiving one more message after a conversation of the given length, with the
+// optimized receive (rebuilding the digests and sets) and with the indexed optimized receive.
+fn optimized_receive_benchmark(c: &mut Criterion) {
+    let associated_data = [0u8; 32];
+    let mut group = c.benchmark_group("Receive after conversation");
+    for conversation_length in [10, 100, 500] {
+        let (mut alice_state, mut bob_state) = rrc_init_all_optimized_receive(Security::RRidAndSRid);
+        for i in 0..conversation_length {
+            let plaintext = (i as u32).to_be_bytes();
+            let (_, mut ct, header) = optimized_receive_rrc_send(&mut alice_state, &associated_data, &plaintext);
+            assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct, header).0);
+            let (_, mut ct, header) = optimized_receive_rrc_send(&mut bob_state, &associated_data, &plaintext);
+            assert!(optimized_receive_rrc_receive(&mut alice_state, &associated_data, &mut ct, header).0);
+        }
+        let (_, ct, header) = optimized_receive_rrc_send(&mut alice_state, &associated_data, b"one more message");
 
+        group.bench_with_input(BenchmarkId::new("optimized_rrc_receive", conversation_length), &conversation_length, |b, _| {
+            b.iter_batched(
+                || (bob_state.state().clone(), ct.clone(), header),
+                |(mut state, mut ct, header)| black_box(optimized_rrc_receive(&mut state, &associated_data, &mut ct, header)),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("optimized_receive_rrc_receive", conversation_length), &conversation_length, |b, _| {
+            b.iter_batched(
+                || (bob_state.clone(), ct.clone(), header),
+                |(mut state, mut ct, header)| black_box(optimized_receive_rrc_receive(&mut state, &associated_data, &mut ct, header)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
 
 // Lists all benchmark functions from the 'benches' group.
-criterion_group!(benches, init_all_benchmark);
+criterion_group!(benches, init_all_benchmark, optimized_receive_benchmark);
 // Acts as a main function and runs all benchamrks in 'benches' group
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+use crate::protocol::RrcState;
+
+/// What an adversary learns by exposing the state of a party.
+///
+/// An exposure reveals the whole state: the ratchet keys, the hash keys and the sets of sent and
+/// received messages. With it, the adversary can impersonate the party towards its peer until the
+/// forgery is detected by r-RID or s-RID, which is what the detection guarantees are about.
+#[derive(Clone)]
+pub struct RrcCompromisedView {
+    pub state: RrcState,
+}
+
+impl RrcState {
+    /// Returns what an adversary exposing this state right now would learn, i.e. a copy of all of it.
+    pub fn export_compromised_view(&self) -> RrcCompromisedView {
+        RrcCompromisedView { state: self.clone() }
+    }
+
+    /// Returns a state continuing the session from an exposure, as the adversary would use it to impersonate the party.
+    ///
+    /// Nothing of the state is withheld from the view, so the adversary continues exactly where the party was.
+    pub fn resume_from(view: &RrcCompromisedView) -> RrcState {
+        view.state.clone()
+    }
+}
@@ -0,0 +1,136 @@
+use get_size::GetSize;
+use rc::Ordinal;
+use sha2::{Digest, Sha256};
+
+use crate::protocol::RrcState;
+
+const REVERIFICATION_LABEL: &[u8] = b"rrc re-verification code";
+
+/// Which guarantee detected the attack.
+#[derive(Clone, Debug, PartialEq, Eq, GetSize)]
+pub enum DetectionKind {
+    /// r-RID: this party received a message its peer never sent.
+    RRid,
+    /// s-RID: the peer received a message this party never sent.
+    SRid,
+}
+
+/// What the receiver knew about the rejected message when the attack was detected.
+#[derive(Clone, Debug, PartialEq, Eq, GetSize)]
+pub struct Evidence {
+    /// Hash `h` of the rejected message, as computed by the receiver.
+    pub message_hash: [u8; 32],
+    /// Digest of its received set the peer put in the rejected ciphertext.
+    pub peer_received_digest: Vec<u8>,
+}
+
+/// Raised when a message fails the forgery detection checks.
+#[derive(Clone, Debug, PartialEq, Eq, GetSize)]
+pub struct DetectionEvent {
+    pub kind: DetectionKind,
+    pub ordinal: Ordinal,
+    pub evidence: Evidence,
+}
+
+/// What to do with the session once an attack has been detected. The rejected message is dropped in all cases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DetectionPolicy {
+    /// Only drop the message, the session carries on.
+    Drop,
+    /// Refuse to send until the alarm is cleared by an out-of-band re-verification.
+    Freeze,
+    /// Keep the session running, but raise an alarm which must be cleared by an out-of-band re-verification.
+    RequireReverification,
+}
+
+/// Alarm raised on a session, until it is cleared with `clear_alarm`.
+#[derive(Clone, Debug, PartialEq, Eq, GetSize)]
+pub struct Alarm {
+    /// Event which raised the alarm.
+    pub event: DetectionEvent,
+    /// True if the session refuses to send.
+    pub frozen: bool,
+}
+
+/// Decides the policy to apply when an attack is detected on receive.
+pub trait DetectionHandler {
+    fn on_detection(&mut self, event: &DetectionEvent) -> DetectionPolicy;
+}
+
+/// Handler which always applies the same policy. `FixedPolicy(DetectionPolicy::Drop)` is the behaviour of `rrc_receive`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedPolicy(pub DetectionPolicy);
+
+impl DetectionHandler for FixedPolicy {
+    fn on_detection(&mut self, _event: &DetectionEvent) -> DetectionPolicy {
+        self.0.clone()
+    }
+}
+
+/// Returns the kind of detection given the outcome of the r-RID and s-RID checks, r-RID taking precedence.
+pub(crate) fn detection_kind(r_bool: bool, s_bool: bool) -> Option<DetectionKind> {
+    if r_bool {
+        Some(DetectionKind::RRid)
+    } else if s_bool {
+        Some(DetectionKind::SRid)
+    } else {
+        None
+    }
+}
+
+/// Asks the handler for a policy and returns the resulting alarm of a session.
+///
+/// An alarm which is already raised is kept, and a frozen session stays frozen.
+pub fn apply_policy(alarm: Option<Alarm>, event: DetectionEvent, handler: &mut impl DetectionHandler) -> Option<Alarm> {
+    let frozen = match handler.on_detection(&event) {
+        DetectionPolicy::Drop => return alarm,
+        DetectionPolicy::Freeze => true,
+        DetectionPolicy::RequireReverification => false,
+    };
+    match alarm {
+        Some(alarm) => Some(Alarm { frozen: alarm.frozen || frozen, ..alarm }),
+        None => Some(Alarm { event, frozen }),
+    }
+}
+
+/// Returns Err if an alarm froze the session, which every send path checks before touching the state.
+pub fn ensure_not_frozen(alarm: &Option<Alarm>) -> Result<(), &'static str> {
+    if alarm.as_ref().is_some_and(|alarm| alarm.frozen) {
+        return Err("The session is frozen until the alarm is cleared.");
+    }
+    Ok(())
+}
+
+/// Code the users compare out of band to re-verify each other, e.g. read aloud or scanned as a QR code.
+///
+/// It covers the hash key, which each reset rebinds to the transcripts of both parties, so two parties
+/// have the same code only if they completed the same reset.
+pub fn reverification_code(hash_key: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(REVERIFICATION_LABEL);
+    hasher.update(hash_key);
+    hasher.finalize().into()
+}
+
+/// Takes the alarm out of a session once the users re-verified each other.
+///
+/// The session must have been reset past the epoch the attack was detected in, so that it no longer
+/// runs on keys the attacker may hold, and `peer_code` must be the code of the peer as compared out of
+/// band. Returns the alarm which was cleared, if any, and leaves it in place otherwise.
+pub fn take_reverified_alarm(alarm: &mut Option<Alarm>, reset_epoch: usize, hash_key: &[u8; 32], peer_code: &[u8; 32]) -> Result<Option<Alarm>, &'static str> {
+    let Some(raised) = alarm.as_ref() else {
+        return Ok(None);
+    };
+    if reset_epoch <= raised.event.ordinal.epoch {
+        return Err("The session was not reset since the alarm was raised.");
+    }
+    if reverification_code(hash_key) != *peer_code {
+        return Err("The re-verification code of the peer does not match.");
+    }
+    Ok(alarm.take())
+}
+
+/// Clears the alarm of a session once both parties reset it and re-verified each other out of band, see `take_reverified_alarm`.
+pub fn clear_alarm(state: &mut RrcState, peer_code: &[u8; 32]) -> Result<Option<Alarm>, &'static str> {
+    take_reverified_alarm(&mut state.alarm, state.state.reset_epoch, &state.hash_key, peer_code)
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rc::{init_all, receive, send, Header, MemberId, Ordinal, State};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{get_hash_ordinal_set, opti_get_hash_msg_set, Message};
+
+/// What a member acknowledges about the messages of one member: those it received from it, or
+/// those it sent if the member is itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acknowledgment {
+    pub ordinals: HashSet<Ordinal>,
+    /// Multiset hash of the acknowledged messages.
+    pub digest: [u8; 32],
+}
+
+/// State of one member of a group running rrc.
+///
+/// Group messages are delivered over pairwise rc sessions, and every message acknowledges what the
+/// sender sent and received from each member. A member compares these acknowledgments with its own
+/// view of the transcript, so a message forged in the name of a member is detected as soon as the
+/// views of the members receiving it and the others are compared.
+#[derive(Clone)]
+pub struct GroupRrcState {
+    pub own_id: MemberId,
+    /// Pairwise rc sessions with the other members.
+    pub sessions: BTreeMap<MemberId, State>,
+    /// Key shared by the members, with which the messages are hashed.
+    pub hash_key: [u8; 32],
+    /// Index of the next message of this member. The messages of a member are numbered in a single epoch.
+    pub next_index: usize,
+    pub s: HashSet<Message>,
+    /// Messages received from each other member.
+    pub r: BTreeMap<MemberId, HashSet<Message>>,
+    /// Latest acknowledgment of each (acker, subject) pair which refers to messages this member has not received yet.
+    pub pending_acks: HashMap<(MemberId, MemberId), Acknowledgment>,
+    pub detections: Vec<GroupDetectionEvent>,
+}
+
+#[derive(Clone)]
+pub struct GroupRrcCiphertext {
+    pub sender: MemberId,
+    pub num: Ordinal,
+    pub header: Header,
+    pub ciphertext: Vec<u8>,
+    /// Acknowledgment of the messages of every member, the sender included.
+    pub acks: BTreeMap<MemberId, Acknowledgment>,
+}
+
+/// How the view of another member differs from this member's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GroupDetectionKind {
+    /// r-RID: this member received a message in the name of the subject which the subject never sent.
+    RRid,
+    /// s-RID: the acker received a message in this member's name which this member never sent.
+    SRid,
+    /// The acker and this member received different messages from the subject.
+    Divergence,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupDetectionEvent {
+    pub kind: GroupDetectionKind,
+    /// Member whose messages the views disagree on.
+    pub subject: MemberId,
+    /// Member whose acknowledgment disagrees with this member's view.
+    pub acker: MemberId,
+}
+
+enum AckCheck {
+    Consistent,
+    /// The acknowledgment refers to messages this member has not received yet.
+    Pending,
+    Inconsistent(GroupDetectionKind),
+}
+
+/// Initializes the states of all the members of a group, with a pairwise rc session between every two of them.
+pub fn group_rrc_init(members: &[MemberId]) -> Vec<GroupRrcState> {
+    let mut hash_key = [0u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut hash_key);
+    let mut states: Vec<GroupRrcState> = members
+        .iter()
+        .map(|&own_id| GroupRrcState {
+            own_id,
+            sessions: BTreeMap::new(),
+            hash_key,
+            next_index: 0,
+            s: HashSet::new(),
+            r: members.iter().filter(|&&id| id != own_id).map(|&id| (id, HashSet::new())).collect(),
+            pending_acks: HashMap::new(),
+            detections: Vec::new(),
+        })
+        .collect();
+    for i in 0..members.len() {
+        for j in i + 1..members.len() {
+            let (first, second) = init_all();
+            states[i].sessions.insert(members[j], first);
+            states[j].sessions.insert(members[i], second);
+        }
+    }
+    states
+}
+
+fn acknowledgment(messages: &HashSet<Message>) -> Acknowledgment {
+    Acknowledgment { ordinals: messages.iter().map(|msg| msg.ordinal).collect(), digest: opti_get_hash_msg_set(messages) }
+}
+
+/// Returns the associated data given to the ratchet, which binds the sender, the ordinal and the acknowledgments.
+fn group_associated_data(associated_data: &[u8; 32], sender: MemberId, num: Ordinal, acks: &BTreeMap<MemberId, Acknowledgment>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(associated_data);
+    hasher.update(sender.to_be_bytes());
+    hasher.update(num.epoch.to_be_bytes());
+    hasher.update(num.index.to_be_bytes());
+    for (subject, ack) in acks {
+        hasher.update(subject.to_be_bytes());
+        hasher.update(get_hash_ordinal_set(&ack.ordinals));
+        hasher.update(ack.digest);
+    }
+    hasher.finalize().into()
+}
+
+/// Hash `h` of a group message. It is computed over the plaintext rather than the ciphertext, as
+/// every recipient receives a different ciphertext of the same message.
+fn message_hash(hash_key: &[u8; 32], associated_data_prime: &[u8; 32], plaintext: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(hash_key);
+    hasher.update(associated_data_prime);
+    hasher.update(plaintext);
+    hasher.finalize().into()
+}
+
+/// Encrypts a message to every other member, and returns the ciphertext of each.
+pub fn group_rrc_send(state: &mut GroupRrcState, associated_data: &[u8; 32], plaintext: &[u8]) -> (Ordinal, Vec<(MemberId, GroupRrcCiphertext)>) {
+    let sender = state.own_id;
+    let num = Ordinal { epoch: 0, index: state.next_index };
+    let mut acks: BTreeMap<MemberId, Acknowledgment> = state.r.iter().map(|(&id, messages)| (id, acknowledgment(messages))).collect();
+    acks.insert(sender, acknowledgment(&state.s));
+    let associated_data_prime = group_associated_data(associated_data, sender, num, &acks);
+
+    let ciphertexts = state
+        .sessions
+        .iter_mut()
+        .map(|(&recipient, session)| {
+            let (_, header, ciphertext) = send(session, &associated_data_prime, plaintext);
+            (recipient, GroupRrcCiphertext { sender, num, header, ciphertext, acks: acks.clone() })
+        })
+        .collect();
+    state.s.insert(Message { ordinal: num, content: message_hash(&state.hash_key, &associated_data_prime, plaintext) });
+    state.next_index += 1;
+    (num, ciphertexts)
+}
+
+/// Receives a group message, and checks the acknowledgments it carries against the view of this member.
+///
+/// The message is rejected if the ratchet rejects it, or if it conflicts with the messages this
+/// member received from the sender. Disagreements of the sender with this member's view of the other
+/// members are added to `detections`, but do not reject the message.
+pub fn group_rrc_receive(state: &mut GroupRrcState, associated_data: &[u8; 32], ct: &GroupRrcCiphertext) -> (bool, Ordinal, Vec<u8>) {
+    let is_member = |id: &MemberId| *id == state.own_id || state.sessions.contains_key(id);
+    let Some(sender_ack) = ct.acks.get(&ct.sender) else {
+        return (false, ct.num, Vec::new());
+    };
+    if !ct.acks.keys().all(is_member) {
+        return (false, ct.num, Vec::new());
+    }
+    let Some(session) = state.sessions.get(&ct.sender) else {
+        return (false, ct.num, Vec::new());
+    };
+    let associated_data_prime = group_associated_data(associated_data, ct.sender, ct.num, &ct.acks);
+    // The session is only updated once the ratchet accepts the message.
+    let mut session = session.clone();
+    let (acc, _, pt) = receive(&mut session, &associated_data_prime, ct.header, &ct.ciphertext);
+    if !acc {
+        return (false, ct.num, Vec::new());
+    }
+    state.sessions.insert(ct.sender, session);
+
+    let msg = Message { ordinal: ct.num, content: message_hash(&state.hash_key, &associated_data_prime, &pt) };
+    let received = &state.r[&ct.sender];
+    let conflicts = received.iter().any(|other| other.ordinal == msg.ordinal && other.content != msg.content);
+    // Every earlier message received from the sender must be among those it says it sent.
+    let unsent = received.iter().any(|other| other.ordinal < ct.num && !sender_ack.ordinals.contains(&other.ordinal));
+    let forged = conflicts || unsent || matches!(check_ack(state, ct.sender, ct.sender, sender_ack), AckCheck::Inconsistent(_));
+    if forged {
+        record(state, GroupDetectionEvent { kind: GroupDetectionKind::RRid, subject: ct.sender, acker: ct.sender });
+        return (false, ct.num, Vec::new());
+    }
+    if received.contains(&msg) {
+        return (false, ct.num, Vec::new());
+    }
+
+    state.r.get_mut(&ct.sender).unwrap().insert(msg);
+    for (&subject, ack) in ct.acks.iter() {
+        state.pending_acks.insert((ct.sender, subject), ack.clone());
+    }
+    check_pending_acks(state);
+    (true, ct.num, pt)
+}
+
+/// Compares an acknowledgment of `acker` about the messages of `subject` with this member's view.
+fn check_ack(state: &GroupRrcState, acker: MemberId, subject: MemberId, ack: &Acknowledgment) -> AckCheck {
+    let (view, kind) = if subject == state.own_id {
+        (&state.s, GroupDetectionKind::SRid)
+    } else if subject == acker {
+        (&state.r[&subject], GroupDetectionKind::RRid)
+    } else {
+        (&state.r[&subject], GroupDetectionKind::Divergence)
+    };
+    let ordinals: HashSet<Ordinal> = view.iter().map(|msg| msg.ordinal).collect();
+    if !ack.ordinals.is_subset(&ordinals) {
+        // This member knows every message it sent, so an unknown ordinal was forged in its name.
+        return if subject == state.own_id { AckCheck::Inconsistent(kind) } else { AckCheck::Pending };
+    }
+    let acked: HashSet<Message> = view.iter().filter(|msg| ack.ordinals.contains(&msg.ordinal)).cloned().collect();
+    if opti_get_hash_msg_set(&acked) == ack.digest {
+        AckCheck::Consistent
+    } else {
+        AckCheck::Inconsistent(kind)
+    }
+}
+
+/// Checks the pending acknowledgments, and forgets those which could be checked.
+fn check_pending_acks(state: &mut GroupRrcState) {
+    let pairs: Vec<(MemberId, MemberId)> = state.pending_acks.keys().copied().collect();
+    for (acker, subject) in pairs {
+        match check_ack(state, acker, subject, &state.pending_acks[&(acker, subject)]) {
+            AckCheck::Pending => continue,
+            AckCheck::Consistent => (),
+            AckCheck::Inconsistent(kind) => record(state, GroupDetectionEvent { kind, subject, acker }),
+        }
+        state.pending_acks.remove(&(acker, subject));
+    }
+}
+
+fn record(state: &mut GroupRrcState, event: GroupDetectionEvent) {
+    if !state.detections.contains(&event) {
+        state.detections.push(event);
+    }
+}
@@ -1,4 +1,22 @@
+pub mod compromise;
+pub mod detection;
+pub mod group;
+pub mod merkle;
+pub mod negotiation;
+pub mod optimized_receive;
+pub mod parsing;
 pub mod protocol;
+pub mod reset;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 mod tests;
 
-pub use protocol::{send_bytes, receive_bytes, rrc_init_all, rrc_receive, rrc_send, rrc_init_all_optimized_send, optimized_rrc_send, optimized_rrc_receive, Message, Security, RrcState, Ciphertext};
\ No newline at end of file
+pub use protocol::{send_bytes, try_send_bytes, receive_bytes, try_receive_bytes, encode_payload, decode_payload, decode_payload_with_merkle_roots, encode_optimized_payload, decode_optimized_payload, rrc_init_all, rrc_receive, rrc_receive_with_handler, rrc_send, try_rrc_send, rrc_init_all_optimized_send, rrc_init_all_with_epoch_window, rrc_init_all_with_padding, rrc_init_all_with_merkle_roots, rrc_init_all_optimized_send_with_epoch_window, optimized_rrc_send, try_optimized_rrc_send, optimized_rrc_receive, optimized_rrc_receive_with_handler, Message, Security, RrcState, Ciphertext};
+pub use optimized_receive::{rrc_init_all_optimized_receive, optimized_receive_rrc_send, try_optimized_receive_rrc_send, optimized_receive_rrc_receive, optimized_receive_rrc_receive_with_handler, OptimizedReceiveRrcState};
+pub use negotiation::{rrc_init_all_negotiated, rrc_send_with_signal, rrc_receive_with_signal, send_bytes_with_signal, receive_bytes_with_signal, Handshake, SecuritySignal};
+pub use detection::{clear_alarm, ensure_not_frozen, reverification_code, Alarm, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
+pub use compromise::RrcCompromisedView;
+pub use group::{group_rrc_init, group_rrc_send, group_rrc_receive, Acknowledgment, GroupDetectionEvent, GroupDetectionKind, GroupRrcCiphertext, GroupRrcState};
+pub use parsing::DecodeLimits;
+pub use merkle::MerkleRoots;
+pub use reset::{rrc_reset_request, rrc_reset_respond, rrc_reset_respond_with_handler, rrc_reset_finish, rrc_reset_finish_with_handler, reset_transcript};
@@ -0,0 +1,339 @@
+use std::collections::{BTreeSet, HashSet};
+
+use rc::Ordinal;
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{Message, RrcState};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const ROOT_PREFIX: u8 = 0x02;
+const MERKLE_ROOTS_LABEL: &[u8] = b"rrc merkle roots";
+
+/// Commitment to a set of messages (such as `s` or `r`) as a Merkle tree whose leaves are sorted by ordinal.
+///
+/// Unlike the multiset hashes used in the protocol, the tree can produce succinct proofs that a
+/// message is part of the committed set, or that no message with a given ordinal is. A party can
+/// hand these to the other party, or to a third-party arbiter, to show which message was forged or dropped.
+///
+/// The tree follows RFC 6962: leaves are hashed as `SHA-256(0x00 || epoch || index || content)`,
+/// inner nodes as `SHA-256(0x01 || left || right)`, and a tree of n leaves is split at the largest
+/// power of two smaller than n. The root additionally binds the number of leaves:
+/// `SHA-256(0x02 || n || tree_hash)`. The epoch, the index and n are 8 byte big-endian integers,
+/// so the commitments do not depend on the platform.
+#[derive(Clone, Debug)]
+pub struct MessageMerkleTree {
+    leaves: Vec<Message>,
+    leaf_hashes: Vec<[u8; 32]>,
+    root: [u8; 32],
+}
+
+/// Roots of the trees over the sets `s` and `r` of a party, as carried by its ciphertexts.
+///
+/// A party opts into carrying them with rrc_init_all_with_merkle_roots(). The roots are bound into
+/// the associated data of the message, so the roots a party accepted from its peer are the ones the
+/// peer committed to when sending, and cannot be swapped for a tree built later over another set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MerkleRoots {
+    pub sent: [u8; 32],
+    pub received: [u8; 32],
+}
+
+impl MerkleRoots {
+    /// Returns the roots of the current sets `s` and `r` of the party.
+    pub fn of(state: &RrcState) -> MerkleRoots {
+        let (sent, received) = merkle_commitments(state);
+        MerkleRoots { sent: sent.root(), received: received.root() }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[0..32].copy_from_slice(&self.sent);
+        bytes[32..64].copy_from_slice(&self.received);
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 64]) -> MerkleRoots {
+        MerkleRoots { sent: bytes[0..32].try_into().unwrap(), received: bytes[32..64].try_into().unwrap() }
+    }
+}
+
+/// Binds the roots carried by a ciphertext into its associated data, which is left unchanged when the ciphertext carries none.
+pub(crate) fn bind_merkle_roots(associated_data: [u8; 32], roots: &Option<MerkleRoots>) -> [u8; 32] {
+    match roots {
+        None => associated_data,
+        Some(roots) => {
+            let mut hasher = Sha256::new();
+            hasher.update(MERKLE_ROOTS_LABEL);
+            hasher.update(associated_data);
+            hasher.update(roots.to_bytes());
+            hasher.finalize().into()
+        }
+    }
+}
+
+/// Audit path proving that the leaf at `index` belongs to a tree of `leaf_count` leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// Proof that no message with the given ordinal is part of a committed set.
+///
+/// It consists of the messages directly before and after the ordinal in the sorted leaves, and of
+/// their inclusion proofs. Either neighbour is missing when the ordinal is before the first or
+/// after the last leaf, and both are missing for the empty set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExclusionProof {
+    pub ordinal: Ordinal,
+    pub leaf_count: usize,
+    pub predecessor: Option<(Message, InclusionProof)>,
+    pub successor: Option<(Message, InclusionProof)>,
+}
+
+/// What a committed set contains at a given ordinal, together with the proof of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrdinalProof {
+    Present(Message, InclusionProof),
+    Absent(ExclusionProof),
+}
+
+/// Outcome of comparing, at a single ordinal, the set of messages sent by one party with the set of messages received by the other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Both sets agree on the message at this ordinal (or neither contains one).
+    Consistent,
+    /// A message was received which was never sent (`sent` is None) or was sent with a different content.
+    Forged { received: Message, sent: Option<Message> },
+    /// A message was sent but was never received.
+    Dropped { sent: Message },
+}
+
+impl MessageMerkleTree {
+    /// Builds the tree committing to the given set of messages.
+    pub fn new(messages: &HashSet<Message>) -> Self {
+        let leaves: Vec<Message> = messages.iter().cloned().collect::<BTreeSet<Message>>().into_iter().collect();
+        let leaf_hashes: Vec<[u8; 32]> = leaves.iter().map(hash_leaf).collect();
+        let root = hash_root(leaves.len(), &tree_hash(&leaf_hashes));
+        MessageMerkleTree { leaves, leaf_hashes, root }
+    }
+
+    /// Returns the commitment to the set.
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// Returns the number of messages in the committed set.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns true if the committed set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Proves that the message is part of the committed set, or returns None if it is not.
+    pub fn prove_inclusion(&self, message: &Message) -> Option<InclusionProof> {
+        let index = self.leaves.binary_search(message).ok()?;
+        Some(self.inclusion_proof_at(index))
+    }
+
+    /// Proves that no message with this ordinal is part of the committed set, or returns None if there is one.
+    pub fn prove_exclusion(&self, ordinal: Ordinal) -> Option<ExclusionProof> {
+        match self.prove_ordinal(ordinal) {
+            OrdinalProof::Absent(proof) => Some(proof),
+            OrdinalProof::Present(_, _) => None,
+        }
+    }
+
+    /// Proves which message, if any, the committed set contains at this ordinal.
+    pub fn prove_ordinal(&self, ordinal: Ordinal) -> OrdinalProof {
+        // Ordinals are unique within a set of messages, so the leaves are also sorted by ordinal.
+        let position = self.leaves.partition_point(|msg| msg.ordinal < ordinal);
+        if position < self.leaves.len() && self.leaves[position].ordinal == ordinal {
+            return OrdinalProof::Present(self.leaves[position].clone(), self.inclusion_proof_at(position));
+        }
+        let neighbour = |index: usize| (self.leaves[index].clone(), self.inclusion_proof_at(index));
+        OrdinalProof::Absent(ExclusionProof {
+            ordinal,
+            leaf_count: self.leaves.len(),
+            predecessor: if position > 0 { Some(neighbour(position - 1)) } else { None },
+            successor: if position < self.leaves.len() { Some(neighbour(position)) } else { None },
+        })
+    }
+
+    fn inclusion_proof_at(&self, index: usize) -> InclusionProof {
+        let mut siblings = Vec::new();
+        audit_path(index, &self.leaf_hashes, &mut siblings);
+        InclusionProof { index, leaf_count: self.leaves.len(), siblings }
+    }
+}
+
+/// Returns the Merkle commitments to the sent set `s` and to the received set `r` of a party.
+///
+/// The trees are the ones whose roots the party puts in its next ciphertext if it carries them.
+pub fn merkle_commitments(state: &RrcState) -> (MessageMerkleTree, MessageMerkleTree) {
+    (MessageMerkleTree::new(&state.s), MessageMerkleTree::new(&state.r))
+}
+
+/// Checks that the message is part of the set committed to by `root`.
+pub fn verify_inclusion(root: &[u8; 32], message: &Message, proof: &InclusionProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+    match root_from_audit_path(proof.index, proof.leaf_count, hash_leaf(message), &proof.siblings) {
+        Some(tree_hash) => hash_root(proof.leaf_count, &tree_hash) == *root,
+        None => false,
+    }
+}
+
+/// Checks that no message with `proof.ordinal` is part of the set committed to by `root`.
+pub fn verify_exclusion(root: &[u8; 32], proof: &ExclusionProof) -> bool {
+    let neighbour_is_valid = |neighbour: &(Message, InclusionProof)| {
+        neighbour.1.leaf_count == proof.leaf_count && verify_inclusion(root, &neighbour.0, &neighbour.1)
+    };
+    match (&proof.predecessor, &proof.successor) {
+        (None, None) => proof.leaf_count == 0 && *root == hash_root(0, &tree_hash(&[])),
+        (Some(predecessor), None) => {
+            neighbour_is_valid(predecessor)
+                && predecessor.0.ordinal < proof.ordinal
+                && predecessor.1.index + 1 == proof.leaf_count
+        }
+        (None, Some(successor)) => {
+            neighbour_is_valid(successor) && proof.ordinal < successor.0.ordinal && successor.1.index == 0
+        }
+        (Some(predecessor), Some(successor)) => {
+            neighbour_is_valid(predecessor)
+                && neighbour_is_valid(successor)
+                && predecessor.0.ordinal < proof.ordinal
+                && proof.ordinal < successor.0.ordinal
+                && predecessor.1.index + 1 == successor.1.index
+        }
+    }
+}
+
+/// Checks an ordinal proof against `root`, and returns the message it shows at `ordinal` (None if the set has no message there).
+///
+/// Returns Err if the proof is invalid or about another ordinal.
+pub fn verify_ordinal(root: &[u8; 32], ordinal: Ordinal, proof: &OrdinalProof) -> Result<Option<Message>, &'static str> {
+    match proof {
+        OrdinalProof::Present(message, inclusion_proof) => {
+            if message.ordinal != ordinal || !verify_inclusion(root, message, inclusion_proof) {
+                return Err("Invalid inclusion proof.");
+            }
+            Ok(Some(message.clone()))
+        }
+        OrdinalProof::Absent(exclusion_proof) => {
+            if exclusion_proof.ordinal != ordinal || !verify_exclusion(root, exclusion_proof) {
+                return Err("Invalid exclusion proof.");
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Decides, as a third-party arbiter, whether the message at `ordinal` was forged or dropped between a sender and a receiver.
+///
+/// `sent_root` is the commitment to the sender's `s` and `received_root` the commitment to the
+/// receiver's `r`. Only roots which came from an authenticated ciphertext, i.e. the `peer_merkle_roots`
+/// of the other party, prove anything: either party can build a tree over any set after the fact.
+/// Returns Err if either proof does not verify.
+pub fn arbitrate(
+    sent_root: &[u8; 32],
+    sent_proof: &OrdinalProof,
+    received_root: &[u8; 32],
+    received_proof: &OrdinalProof,
+    ordinal: Ordinal,
+) -> Result<Verdict, &'static str> {
+    let sent = verify_ordinal(sent_root, ordinal, sent_proof)?;
+    let received = verify_ordinal(received_root, ordinal, received_proof)?;
+    match (sent, received) {
+        (None, None) => Ok(Verdict::Consistent),
+        (Some(sent), None) => Ok(Verdict::Dropped { sent }),
+        (sent, Some(received)) => {
+            if sent.as_ref() == Some(&received) {
+                Ok(Verdict::Consistent)
+            } else {
+                Ok(Verdict::Forged { received, sent })
+            }
+        }
+    }
+}
+
+fn hash_leaf(message: &Message) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update((message.ordinal.epoch as u64).to_be_bytes());
+    hasher.update((message.ordinal.index as u64).to_be_bytes());
+    hasher.update(message.content);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn hash_root(leaf_count: usize, tree_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([ROOT_PREFIX]);
+    hasher.update((leaf_count as u64).to_be_bytes());
+    hasher.update(tree_hash);
+    hasher.finalize().into()
+}
+
+/// Largest power of two strictly smaller than n, for n > 1.
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn tree_hash(leaf_hashes: &[[u8; 32]]) -> [u8; 32] {
+    match leaf_hashes.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaf_hashes[0],
+        n => {
+            let k = split_point(n);
+            hash_node(&tree_hash(&leaf_hashes[..k]), &tree_hash(&leaf_hashes[k..]))
+        }
+    }
+}
+
+/// Pushes the siblings on the path from the leaf at `index` to the root, starting from the leaf.
+fn audit_path(index: usize, leaf_hashes: &[[u8; 32]], siblings: &mut Vec<[u8; 32]>) {
+    let n = leaf_hashes.len();
+    if n <= 1 {
+        return;
+    }
+    let k = split_point(n);
+    if index < k {
+        audit_path(index, &leaf_hashes[..k], siblings);
+        siblings.push(tree_hash(&leaf_hashes[k..]));
+    } else {
+        audit_path(index - k, &leaf_hashes[k..], siblings);
+        siblings.push(tree_hash(&leaf_hashes[..k]));
+    }
+}
+
+/// Recomputes the tree hash from a leaf and its audit path, mirroring `audit_path`.
+fn root_from_audit_path(index: usize, leaf_count: usize, leaf_hash: [u8; 32], siblings: &[[u8; 32]]) -> Option<[u8; 32]> {
+    if leaf_count <= 1 {
+        return if siblings.is_empty() { Some(leaf_hash) } else { None };
+    }
+    let (sibling, rest) = siblings.split_last()?;
+    let k = split_point(leaf_count);
+    if index < k {
+        Some(hash_node(&root_from_audit_path(index, k, leaf_hash, rest)?, sibling))
+    } else {
+        Some(hash_node(sibling, &root_from_audit_path(index - k, leaf_count - k, leaf_hash, rest)?))
+    }
+}
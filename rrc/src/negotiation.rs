@@ -0,0 +1,217 @@
+use sha2::{Digest, Sha256};
+
+use crate::parsing::{DecodeLimits, Reader};
+use crate::protocol::{decode_payload_prefix, encode_payload, receive_padded, rrc_init_all, rrc_receive, try_rrc_send, Ciphertext, RrcState, Security};
+use rc::{pad, Header, Ordinal};
+
+const HANDSHAKE_LABEL: &[u8] = b"rrc security negotiation";
+const SIGNAL_LABEL: &[u8] = b"rrc security signal";
+
+/// Security levels offered by both parties during the handshake, as seen by one of them.
+///
+/// Each offer lists the levels a party supports, in its order of preference. The negotiated level
+/// is the first level of the initiator's offer which the responder also supports.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Handshake {
+    pub initiator_offer: Vec<Security>,
+    pub responder_offer: Vec<Security>,
+    /// Number of epochs both parties keep the messages of, or None to keep every message.
+    pub epoch_window: Option<usize>,
+}
+
+/// Signal carried next to a ciphertext to change the security level of an ongoing session.
+///
+/// The signal is bound into the associated data of the message carrying it, so it cannot be
+/// added, removed or altered without the message being rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecuritySignal {
+    /// The sender wants to switch to this level.
+    Propose(Security),
+    /// The sender accepts the peer's proposal and has switched to this level.
+    Acknowledge(Security),
+}
+
+impl Handshake {
+    /// Returns the level both parties agree on, or None if they have no level in common.
+    pub fn negotiated_level(&self) -> Option<Security> {
+        self.initiator_offer.iter().find(|level| self.responder_offer.contains(level)).cloned()
+    }
+
+    /// Returns the commitment to both offers, to the negotiated level and to the epoch window.
+    pub fn transcript(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(HANDSHAKE_LABEL);
+        for offer in [&self.initiator_offer, &self.responder_offer] {
+            hasher.update((offer.len() as u64).to_be_bytes());
+            hasher.update(offer.iter().map(security_as_byte).collect::<Vec<u8>>());
+        }
+        hasher.update([self.negotiated_level().as_ref().map_or(0xff, security_as_byte)]);
+        // A window is at least 1, so 0 stands for no window.
+        hasher.update((self.epoch_window.unwrap_or(0) as u64).to_be_bytes());
+        hasher.finalize().into()
+    }
+}
+
+fn security_as_byte(level: &Security) -> u8 {
+    match level {
+        Security::RRid => 0,
+        Security::SRid => 1,
+        Security::RRidAndSRid => 2,
+    }
+}
+
+fn security_from_byte(byte: u8) -> Result<Security, &'static str> {
+    match byte {
+        0 => Ok(Security::RRid),
+        1 => Ok(Security::SRid),
+        2 => Ok(Security::RRidAndSRid),
+        _ => Err("The security signal is malformed."),
+    }
+}
+
+impl SecuritySignal {
+    /// Encodes the signal as its kind (0 for Propose, 1 for Acknowledge) followed by the level.
+    pub fn encode(&self) -> [u8; 2] {
+        match self {
+            SecuritySignal::Propose(level) => [0, security_as_byte(level)],
+            SecuritySignal::Acknowledge(level) => [1, security_as_byte(level)],
+        }
+    }
+
+    /// Decodes a signal built by encode().
+    pub fn decode(bytes: [u8; 2]) -> Result<SecuritySignal, &'static str> {
+        let level = security_from_byte(bytes[1])?;
+        match bytes[0] {
+            0 => Ok(SecuritySignal::Propose(level)),
+            1 => Ok(SecuritySignal::Acknowledge(level)),
+            _ => Err("The security signal is malformed."),
+        }
+    }
+}
+
+/// Initializes both parties with the security level negotiated from their view of the handshake.
+///
+/// Each party keeps the transcript of the handshake it saw, which is bound into the associated data
+/// of every message. If an attacker tampered with the offers (e.g. to remove `RRidAndSRid` from one
+/// of them), the first message of the session is rejected, instead of silently running at a lower level.
+/// Returns Err if either party finds no common level.
+pub fn rrc_init_all_negotiated(
+    initiator_view: &Handshake,
+    responder_view: &Handshake,
+) -> Result<(RrcState, RrcState), &'static str> {
+    let initiator_level = initiator_view.negotiated_level().ok_or("No common security level.")?;
+    let (mut initiator, mut responder) = rrc_init_all(initiator_level);
+    apply_handshake(&mut initiator, initiator_view)?;
+    apply_handshake(&mut responder, responder_view)?;
+    Ok((initiator, responder))
+}
+
+/// Sets the level and the epoch window a party agreed on, and binds its view of the handshake into the associated data.
+pub(crate) fn apply_handshake(state: &mut RrcState, view: &Handshake) -> Result<(), &'static str> {
+    if view.epoch_window == Some(0) {
+        return Err("The epoch window must be at least 1.");
+    }
+    state.security_level = view.negotiated_level().ok_or("No common security level.")?;
+    state.epoch_window = view.epoch_window;
+    state.security_transcript = view.transcript();
+    Ok(())
+}
+
+/// Returns the associated data of a message carrying the signal.
+fn signal_associated_data(associated_data: &[u8; 32], signal: &SecuritySignal) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(SIGNAL_LABEL);
+    hasher.update(associated_data);
+    hasher.update(signal.encode());
+    hasher.finalize().into()
+}
+
+/// Sends a message carrying a security signal.
+///
+/// Sending `Propose` records the proposal, the level only changes once the peer acknowledges it.
+/// Sending `Acknowledge` switches this party to the level the peer proposed. Returns Err if the
+/// peer did not propose that level, or if the session is frozen.
+pub fn rrc_send_with_signal(
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    signal: SecuritySignal,
+    plaintext: &[u8],
+) -> Result<(Ordinal, Ciphertext, Header), &'static str> {
+    if let SecuritySignal::Acknowledge(level) = &signal {
+        if state.peer_proposed_security_level.as_ref() != Some(level) {
+            return Err("The peer did not propose this security level.");
+        }
+    }
+    let associated_data_prime = signal_associated_data(associated_data, &signal);
+    let sent = try_rrc_send(state, &associated_data_prime, plaintext)?;
+    match signal {
+        SecuritySignal::Propose(level) => state.pending_security_level = Some(level),
+        SecuritySignal::Acknowledge(level) => {
+            state.peer_proposed_security_level = None;
+            state.security_level = level;
+        }
+    }
+    Ok(sent)
+}
+
+/// Receives a message carrying a security signal.
+///
+/// The message is rejected if the signal is not the one it was sent with, or if it acknowledges a
+/// level this party did not propose, in which case the state is left untouched. Receiving a `Propose`
+/// only records it: the level changes once this party answers with `Acknowledge`. Receiving the
+/// `Acknowledge` of a pending proposal switches this party to the new level.
+pub fn rrc_receive_with_signal(
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    signal: SecuritySignal,
+    ct: &mut Ciphertext,
+    header: Header,
+) -> (bool, Ordinal, Vec<u8>) {
+    if let SecuritySignal::Acknowledge(level) = &signal {
+        if state.pending_security_level.as_ref() != Some(level) {
+            return (false, Ordinal { epoch: header.epoch, index: header.msg_nbr }, Vec::new());
+        }
+    }
+    let associated_data_prime = signal_associated_data(associated_data, &signal);
+    let (acc, num, pt) = rrc_receive(state, &associated_data_prime, ct, header);
+    if !acc {
+        return (false, num, Vec::new());
+    }
+    match signal {
+        SecuritySignal::Propose(level) => state.peer_proposed_security_level = Some(level),
+        SecuritySignal::Acknowledge(level) => {
+            state.pending_security_level = None;
+            state.security_level = level;
+        }
+    }
+    (acc, num, pt)
+}
+
+/// Same as rrc_send_with_signal(), but encodes the message like send_bytes(), with the signal in front of the payload.
+pub fn send_bytes_with_signal(
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    signal: SecuritySignal,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    let encoded_signal = signal.encode();
+    let (num, ct, header) = rrc_send_with_signal(state, associated_data, signal, plaintext)?;
+    let mut payload = encoded_signal.to_vec();
+    payload.extend_from_slice(&encode_payload(num, &ct, header));
+    Ok(pad(&payload, state.state.padding))
+}
+
+/// Receives a payload built by send_bytes_with_signal(), and returns the signal it carried next to the result of rrc_receive_with_signal().
+///
+/// Returns an error if the payload is malformed, exceeds the limits or has an invalid padding, leaving the state untouched.
+pub fn receive_bytes_with_signal(
+    payload: &[u8],
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    limits: &DecodeLimits,
+) -> Result<(bool, Ordinal, Vec<u8>, SecuritySignal), &'static str> {
+    let signal = SecuritySignal::decode(Reader::new(payload).take_array()?)?;
+    let ((_, mut ct, header), encoded_len) = decode_payload_prefix(&payload[2..], limits, state.merkle_roots)?;
+    let (acc, num, pt) = receive_padded(state, payload, 2 + encoded_len, |state| rrc_receive_with_signal(state, associated_data, signal.clone(), &mut ct, header))?;
+    Ok((acc, num, pt, signal))
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use get_size::GetSize;
+use mset_mu_hash::RistrettoHash;
+use rc::{receive, Header, Ordinal};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::detection::{apply_policy, clear_alarm, detection_kind, Alarm, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
+use crate::protocol::{
+    bind_security_transcript, hash_msg_w_blake2, incremental_hash_matches, optimized_rrc_send_message, ordinal_as_bytes, rrc_init_all_optimized_send, update_incremental_hash_set,
+    Message, OptimizedSendCiphertext, OptimizedSendRrcState, Security, M_BYTES,
+};
+
+/// State of a party using both the optimized send and the optimized receive.
+///
+/// On top of the optimized send state, it keeps the sets of the protocol indexed by ordinal, and
+/// running hashes of what the peer last sent in its ciphertexts. On receive, the digests put in
+/// the associated data and the sets used by the checks are then derived from what changed since
+/// the last accepted message, instead of being rebuilt from the whole conversation: the
+/// multiset hashes are only updated for new or removed elements, and every check is a lookup or
+/// a range query in a BTreeMap. The wire format is unchanged, so this state can talk to a peer
+/// using `optimized_rrc_send` and `optimized_rrc_receive`.
+///
+/// The sets `s`, `r` and `s_ack` of the inner `RrcState` are kept up to date as well. The inner
+/// state is only readable from outside the crate, since a message sent or received through it
+/// would not be indexed.
+#[derive(Clone)]
+pub struct OptimizedReceiveRrcState {
+    pub(crate) state: OptimizedSendRrcState,
+    pub s: BTreeMap<Ordinal, [u8; 32]>,
+    pub r: BTreeMap<Ordinal, [u8; 32]>,
+    pub s_ack: BTreeMap<Ordinal, [u8; 32]>,
+    /// Messages of `r` which are not yet in `s_ack`, i.e. which the peer has not acknowledged having sent.
+    pub r_unacked: BTreeMap<Ordinal, [u8; 32]>,
+    /// Multiset hash of `s_ack`.
+    pub hash_s_ack: RistrettoHash<Sha512>,
+    /// Ordinals the peer claimed to have received in the last accepted ciphertext.
+    pub peer_nums: BTreeSet<Ordinal>,
+    /// Multiset hash of `peer_nums`.
+    pub hash_peer_nums: RistrettoHash<Sha512>,
+    /// Xor of the Blake2 hashes of the messages of `s` whose ordinal is in `peer_nums`.
+    pub r_star_hash: [u8; 32],
+    /// Number of messages of `s` whose ordinal is in `peer_nums`.
+    pub r_star_len: usize,
+}
+
+impl GetSize for OptimizedReceiveRrcState {
+    /// Counts the inner state along with the indexed copies of its sets and their multiset hashes.
+    fn get_heap_size(&self) -> usize {
+        self.state.get_heap_size()
+            + self.s.get_heap_size()
+            + self.r.get_heap_size()
+            + self.s_ack.get_heap_size()
+            + self.r_unacked.get_heap_size()
+            + self.hash_s_ack.get_heap_size()
+            + self.peer_nums.get_heap_size()
+            + self.hash_peer_nums.get_heap_size()
+    }
+}
+
+/// Changes between what the peer sent in a ciphertext and what the receiver already knows.
+struct CiphertextDiff {
+    new_acked: Vec<Message>,
+    removed_acked: Vec<Message>,
+    new_peer_nums: Vec<Ordinal>,
+    removed_peer_nums: Vec<Ordinal>,
+}
+
+pub fn rrc_init_all_optimized_receive(security_level: Security) -> (OptimizedReceiveRrcState, OptimizedReceiveRrcState) {
+    let (alice, bob) = rrc_init_all_optimized_send(security_level);
+    (OptimizedReceiveRrcState::from_windowless(alice), OptimizedReceiveRrcState::from_windowless(bob))
+}
+
+impl OptimizedReceiveRrcState {
+    /// Wraps a freshly initialized optimized send state.
+    ///
+    /// Returns Err if the state has an epoch window, which the indexed sets do not support.
+    pub fn new(state: OptimizedSendRrcState) -> Result<Self, &'static str> {
+        if state.state.epoch_window.is_some() {
+            return Err("The optimized receive does not support epoch windows.");
+        }
+        Ok(Self::from_windowless(state))
+    }
+
+    /// Returns the inner optimized send state, which the indexed sets mirror.
+    pub fn state(&self) -> &OptimizedSendRrcState {
+        &self.state
+    }
+
+    /// Clears the alarm of the session once the peer's re-verification code matches, see `detection::clear_alarm()`.
+    pub fn clear_alarm(&mut self, peer_code: &[u8; 32]) -> Result<Option<Alarm>, &'static str> {
+        clear_alarm(&mut self.state.state, peer_code)
+    }
+
+    /// Indexes the sets of a state without an epoch window.
+    fn from_windowless(state: OptimizedSendRrcState) -> Self {
+        let index = |messages: &std::collections::HashSet<Message>| {
+            messages.iter().map(|msg| (msg.ordinal, msg.content)).collect::<BTreeMap<Ordinal, [u8; 32]>>()
+        };
+        let s = index(&state.state.s);
+        let r = index(&state.state.r);
+        let s_ack = index(&state.state.s_ack);
+        let r_unacked = r.iter().filter(|(ordinal, content)| s_ack.get(*ordinal) != Some(*content)).map(|(o, c)| (*o, *c)).collect();
+        let mut hash_s_ack = RistrettoHash::<Sha512>::default();
+        hash_s_ack.extend(state.state.s_ack.iter().map(|msg| (msg.multiset_element(), 1)));
+        OptimizedReceiveRrcState {
+            state,
+            s,
+            r,
+            s_ack,
+            r_unacked,
+            hash_s_ack,
+            peer_nums: BTreeSet::new(),
+            hash_peer_nums: RistrettoHash::<Sha512>::default(),
+            r_star_hash: [0; 32],
+            r_star_len: 0,
+        }
+    }
+
+    fn xor_into_r_star(&mut self, msg: &Message) {
+        let msg_hash = hash_msg_w_blake2(msg, &self.state.state.hash_key_prime);
+        self.r_star_hash.iter_mut().zip(msg_hash.iter()).for_each(|(acc, byte)| *acc ^= byte);
+    }
+
+    /// Computes how the sets carried by the ciphertext differ from `s_ack` and `peer_nums`.
+    fn diff(&self, ct: &OptimizedSendCiphertext) -> CiphertextDiff {
+        let new_acked: Vec<Message> = ct.s.iter().filter(|msg| self.s_ack.get(&msg.ordinal) != Some(&msg.content)).cloned().collect();
+        // Scanning s_ack is only needed when the ciphertext does not contain all of it, e.g. when it was delivered out of order.
+        let removed_acked = if ct.s.len() - new_acked.len() < self.s_ack.len() {
+            self.s_ack
+                .iter()
+                .map(|(ordinal, content)| Message { ordinal: *ordinal, content: *content })
+                .filter(|msg| !ct.s.contains(msg))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let new_peer_nums: Vec<Ordinal> = ct.r.0.iter().filter(|ordinal| !self.peer_nums.contains(ordinal)).copied().collect();
+        let removed_peer_nums = if ct.r.0.len() - new_peer_nums.len() < self.peer_nums.len() {
+            self.peer_nums.iter().filter(|ordinal| !ct.r.0.contains(ordinal)).copied().collect()
+        } else {
+            Vec::new()
+        };
+
+        CiphertextDiff { new_acked, removed_acked, new_peer_nums, removed_peer_nums }
+    }
+}
+
+/// Same as try_optimized_receive_rrc_send(), but panics if the session is frozen.
+pub fn optimized_receive_rrc_send(
+    state: &mut OptimizedReceiveRrcState,
+    associated_data: &[u8; 32],
+    plaintext: &[u8],
+) -> (Ordinal, OptimizedSendCiphertext, Header) {
+    try_optimized_receive_rrc_send(state, associated_data, plaintext).expect("optimized_receive_rrc_send called on a frozen session")
+}
+
+/// Same as try_optimized_rrc_send(), and additionally indexes the sent message.
+pub fn try_optimized_receive_rrc_send(
+    state: &mut OptimizedReceiveRrcState,
+    associated_data: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(Ordinal, OptimizedSendCiphertext, Header), &'static str> {
+    let (msg, ciphertext, header) = optimized_rrc_send_message(&mut state.state, associated_data, plaintext)?;
+    state.s.insert(msg.ordinal, msg.content);
+    // Only a forged ciphertext can claim that the peer received a message before it was sent.
+    if state.peer_nums.contains(&msg.ordinal) {
+        state.xor_into_r_star(&msg);
+        state.r_star_len += 1;
+    }
+    Ok((msg.ordinal, ciphertext, header))
+}
+
+/// Receives a ciphertext produced by optimized_rrc_send() or optimized_receive_rrc_send().
+///
+/// Accepts and rejects exactly the same ciphertexts as optimized_rrc_receive(), but only does work
+/// proportional to what changed in the peer's sets since the last accepted ciphertext.
+pub fn optimized_receive_rrc_receive(
+    state: &mut OptimizedReceiveRrcState,
+    associated_data: &[u8; 32],
+    ct: &mut OptimizedSendCiphertext,
+    header: Header,
+) -> (bool, Ordinal, Vec<u8>) {
+    optimized_receive_rrc_receive_with_handler(state, associated_data, ct, header, &mut FixedPolicy(DetectionPolicy::Drop))
+}
+
+/// Same as optimized_receive_rrc_receive(), but lets the handler decide what happens to the session when a message fails the forgery detection checks.
+pub fn optimized_receive_rrc_receive_with_handler(
+    state: &mut OptimizedReceiveRrcState,
+    associated_data: &[u8; 32],
+    ct: &mut OptimizedSendCiphertext,
+    header: Header,
+    handler: &mut impl DetectionHandler,
+) -> (bool, Ordinal, Vec<u8>) {
+    let diff = state.diff(ct);
+
+    let mut hash_sent_ct = state.hash_s_ack.clone();
+    diff.new_acked.iter().for_each(|msg| hash_sent_ct.add(msg.multiset_element(), 1));
+    diff.removed_acked.iter().for_each(|msg| hash_sent_ct.remove(msg.multiset_element(), 1));
+    let mut hash_peer_nums = state.hash_peer_nums.clone();
+    diff.new_peer_nums.iter().for_each(|ordinal| hash_peer_nums.add(ordinal_as_bytes(ordinal), 1));
+    diff.removed_peer_nums.iter().for_each(|ordinal| hash_peer_nums.remove(ordinal_as_bytes(ordinal), 1));
+    let hash_sent_ct_digest = hash_sent_ct.finalize();
+    let ordinal_set_hash = hash_peer_nums.clone().finalize();
+
+    let mut associated_data_prime: [u8; 128 + 2 * M_BYTES] = [0; 128 + 2 * M_BYTES];
+    associated_data_prime[0..32].clone_from_slice(&bind_security_transcript(associated_data, &state.state.state.security_transcript));
+    associated_data_prime[32..64].clone_from_slice(&hash_sent_ct_digest);
+    associated_data_prime[64..96].clone_from_slice(&ordinal_set_hash);
+    associated_data_prime[96..128 + 2 * M_BYTES].clone_from_slice(&ct.r.1);
+
+    let (acc, num, pt) = receive(&mut state.state.state.state, &associated_data_prime, header, &ct.ciphertext);
+    if !acc {
+        return (false, num, Vec::new());
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(state.state.state.hash_key);
+    hasher.update(ordinal_as_bytes(&num));
+    hasher.update(associated_data);
+    hasher.update(&ct.ciphertext);
+    hasher.update(hash_sent_ct_digest);
+    hasher.update(ordinal_set_hash);
+    hasher.update(ct.r.1);
+    let h: [u8; 32] = hasher.finalize().into();
+
+    let (r_star_hash, r_star_len) = r_star_after(state, &diff);
+    if let Some(kind) = indexed_checks(state, ct, &diff, &h, num, &r_star_hash, r_star_len) {
+        let event = DetectionEvent {
+            kind,
+            ordinal: num,
+            evidence: Evidence { message_hash: h, peer_received_digest: ct.r.1.to_vec() },
+        };
+        state.state.state.alarm = apply_policy(state.state.state.alarm.take(), event, handler);
+        return (false, num, Vec::new());
+    }
+
+    // The message is accepted: move the indexed sets and running hashes to what the ciphertext carried.
+    // As in optimized_rrc_receive(), s_ack is the union of all the sent sets received so far.
+    for acked in diff.new_acked.into_iter() {
+        if let Some(content) = state.s_ack.insert(acked.ordinal, acked.content) {
+            // Only reachable with Security::SRid, which does not check s_ack: keep the latest content.
+            state.hash_s_ack.remove(Message { ordinal: acked.ordinal, content }.multiset_element(), 1);
+            if state.r.get(&acked.ordinal) == Some(&content) {
+                state.r_unacked.insert(acked.ordinal, content);
+            }
+        }
+        state.hash_s_ack.add(acked.multiset_element(), 1);
+        if state.r_unacked.get(&acked.ordinal) == Some(&acked.content) {
+            state.r_unacked.remove(&acked.ordinal);
+        }
+        state.state.state.s_ack.insert(acked);
+    }
+
+    let msg = Message { ordinal: num, content: h };
+    state.r.insert(num, h);
+    if state.s_ack.get(&num) != Some(&h) {
+        state.r_unacked.insert(num, h);
+    }
+    state.state.state.r.insert(msg.clone());
+    state.state.nums_prime.insert(num);
+    state.state.hash_ordinal_set.add(ordinal_as_bytes(&num), 1);
+    state.state.incremental_hash =
+        update_incremental_hash_set(&mut state.state.incremental_hash, msg, &state.state.state.hash_key_prime);
+    for removed in diff.removed_peer_nums.iter() {
+        state.peer_nums.remove(removed);
+    }
+    state.peer_nums.extend(diff.new_peer_nums);
+    state.hash_peer_nums = hash_peer_nums;
+    state.r_star_hash = r_star_hash;
+    state.r_star_len = r_star_len;
+
+    (acc, num, pt)
+}
+
+/// Returns the xor of the Blake2 hashes and the number of the messages of `s` whose ordinal is in the ciphertext's `r.0`.
+fn r_star_after(state: &OptimizedReceiveRrcState, diff: &CiphertextDiff) -> ([u8; 32], usize) {
+    let mut r_star_hash = state.r_star_hash;
+    let mut r_star_len = state.r_star_len;
+    let mut toggle = |ordinal: &Ordinal| -> bool {
+        match state.s.get(ordinal) {
+            Some(content) => {
+                let msg = Message { ordinal: *ordinal, content: *content };
+                let msg_hash = hash_msg_w_blake2(&msg, &state.state.state.hash_key_prime);
+                r_star_hash.iter_mut().zip(msg_hash.iter()).for_each(|(acc, byte)| *acc ^= byte);
+                true
+            }
+            None => false,
+        }
+    };
+    r_star_len += diff.new_peer_nums.iter().filter(|ordinal| toggle(ordinal)).count();
+    r_star_len -= diff.removed_peer_nums.iter().filter(|ordinal| toggle(ordinal)).count();
+    (r_star_hash, r_star_len)
+}
+
+/// Indexed equivalent of the checks done by optimized_rrc_receive(). Returns the kind of forgery detected if the message must be rejected.
+fn indexed_checks(
+    state: &mut OptimizedReceiveRrcState,
+    ct: &OptimizedSendCiphertext,
+    diff: &CiphertextDiff,
+    h: &[u8; 32],
+    num: Ordinal,
+    r_star_hash: &[u8; 32],
+    r_star_len: usize,
+) -> Option<DetectionKind> {
+    let security_level = state.state.state.security_level.clone();
+    let mut s_bool = false;
+    if security_level != Security::RRid {
+        s_bool = !incremental_hash_matches(&ct.r.1, r_star_hash, r_star_len, &state.state.state.hash_key_prime);
+        if security_level == Security::SRid {
+            return detection_kind(false, s_bool);
+        }
+    }
+
+    // The messages of r up to num must all be in ct.s. Those already in s_ack are, unless the
+    // ciphertext dropped them, and the others are the few messages of r_unacked.
+    let mut r_bool = diff.removed_acked.iter().any(|msg| msg.ordinal <= num && state.r.get(&msg.ordinal) == Some(&msg.content));
+    r_bool = r_bool
+        || state
+            .r_unacked
+            .range(..=num)
+            .any(|(ordinal, content)| !ct.s.contains(&Message { ordinal: *ordinal, content: *content }));
+    r_bool = r_bool || ct.s.iter().any(|msg| msg.ordinal >= num);
+
+    // The messages of s_ack below num must all be in ct.s.
+    r_bool = r_bool || diff.removed_acked.iter().any(|msg| msg.ordinal < num);
+    if num < state.state.state.max_num {
+        r_bool = r_bool || state.s_ack.get(&num) != Some(h);
+        r_bool = r_bool || !diff.new_acked.is_empty();
+    } else {
+        state.state.state.max_num = num;
+    }
+
+    match security_level {
+        Security::RRid => detection_kind(r_bool, false),
+        Security::RRidAndSRid => detection_kind(r_bool, s_bool),
+        Security::SRid => detection_kind(false, s_bool),
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::mem::size_of;
+
+use bytevec::ByteDecodable;
+use rc::Header;
+use x25519_dalek::PublicKey;
+
+/// Largest parts of a payload a decoder accepts, checked before they are decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum length of the encrypted message, in bytes.
+    pub max_ciphertext_len: usize,
+    /// Maximum number of elements of each set carried by a payload.
+    pub max_set_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits { max_ciphertext_len: 1 << 20, max_set_len: 1 << 16 }
+    }
+}
+
+/// Length of the largest element of a set carried by a payload, a `Message` encoded as epoch || index || content.
+const MAX_SET_ELEMENT_LEN: usize = 2 * size_of::<usize>() + 32;
+
+impl DecodeLimits {
+    /// Returns the largest length of an encoded set of at most `max_set_len` elements, bytevec prefixing
+    /// the set and each element with a u32 size.
+    pub fn max_set_bytes(&self) -> usize {
+        self.max_set_len.saturating_mul(4 + MAX_SET_ELEMENT_LEN).saturating_add(4)
+    }
+}
+
+/// Cursor over a payload, which returns an error instead of panicking when the payload is too short.
+pub struct Reader<'a> {
+    payload: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(payload: &'a [u8]) -> Self {
+        Reader { payload, offset: 0 }
+    }
+
+    /// Returns the next `len` bytes of the payload.
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.payload.len())
+            .ok_or("The payload is truncated.")?;
+        let bytes = &self.payload[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        self.take(N)?.try_into().map_err(|_| "The payload is truncated.")
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, &'static str> {
+        Ok(usize::from_be_bytes(self.take_array::<{ size_of::<usize>() }>()?))
+    }
+
+    /// Reads a length, and returns an error if it is larger than `max`.
+    pub fn read_len(&mut self, max: usize) -> Result<usize, &'static str> {
+        let len = self.read_usize()?;
+        if len > max {
+            return Err("A declared length exceeds the limit.");
+        }
+        Ok(len)
+    }
+
+    /// Reads a header encoded as dh_pk || prev_chain_len || msg_nbr || epoch.
+    pub fn read_header(&mut self) -> Result<Header, &'static str> {
+        let dh_ratchet_key = PublicKey::from(self.take_array::<32>()?);
+        Ok(Header {
+            dh_ratchet_key,
+            prev_chain_len: self.read_usize()?,
+            msg_nbr: self.read_usize()?,
+            epoch: self.read_usize()?,
+        })
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns an error if some bytes of the payload were not read.
+    pub fn finish(self) -> Result<(), &'static str> {
+        if self.offset != self.payload.len() {
+            return Err("The payload has trailing bytes.");
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a set encoded by bytevec with u32 sizes.
+///
+/// Returns an error if the set declares more than `max_len` elements, if it is malformed, or if it
+/// contains the same element twice.
+pub fn decode_set<T: ByteDecodable + Eq + Hash>(bytes: &[u8], max_len: usize) -> Result<HashSet<T>, &'static str> {
+    let declared_len = Reader::new(bytes).take_array::<4>().map(u32::from_le_bytes)? as usize;
+    if declared_len > max_len {
+        return Err("A set has more elements than the limit.");
+    }
+    let set = HashSet::<T>::decode::<u32>(bytes).map_err(|_| "A set is malformed.")?;
+    if set.len() != declared_len {
+        return Err("A set contains duplicate elements.");
+    }
+    Ok(set)
+}
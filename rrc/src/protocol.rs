@@ -1,16 +1,22 @@
 extern crate rc;
 use blake2::Blake2s256;
+use bytevec::errors::{BVExpectedSize, ByteVecError};
 use bytevec::{BVDecodeResult, BVEncodeResult, BVSize, ByteDecodable, ByteEncodable};
-use mset_mu_hash::RistrettoHash;
+use get_size::GetSize;
+use mset_mu_hash::{encode_tuple, RistrettoHash};
 use rand::SeedableRng;
 use rand::{rngs::StdRng, RngCore};
-use rc::{dh, generate_dh, init_all, receive, send, Header, Ordinal, State};
+use rc::{dh, generate_dh, init_all, pad, receive, send, unpad, DeviceSession, Header, Ordinal, PaddingPolicy, State};
 use sha2::{Digest, Sha256, Sha512};
 use std::collections::BTreeSet;
 use std::collections::HashSet;
 use std::hash::Hash;
 use std::mem::size_of;
-use x25519_dalek::PublicKey;
+
+use crate::detection::{apply_policy, detection_kind, ensure_not_frozen, Alarm, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
+use crate::merkle::{bind_merkle_roots, MerkleRoots};
+use crate::negotiation::{apply_handshake, rrc_init_all_negotiated, Handshake};
+use crate::parsing::{decode_set, DecodeLimits, Reader};
 
 #[derive(Clone)]
 pub struct RrcState {
@@ -22,6 +28,30 @@ pub struct RrcState {
     pub s_ack: HashSet<Message>,
     pub max_num: Ordinal,
     pub security_level: Security,
+    /// Commitment to the handshake which negotiated the security level and the epoch window, see the `negotiation` module.
+    pub security_transcript: [u8; 32],
+    /// Level this party proposed to switch to and which the peer has not acknowledged yet.
+    pub pending_security_level: Option<Security>,
+    /// Level the peer proposed to switch to and which this party has not acknowledged yet.
+    pub peer_proposed_security_level: Option<Security>,
+    /// Alarm raised by a detected attack, see the `detection` module.
+    pub alarm: Option<Alarm>,
+    /// Number of epochs whose messages are kept for the checks, or None to keep every message.
+    ///
+    /// Both parties must use the same window. Forgeries are detected as long as the peer sends an
+    /// honest message within the window, and messages older than the window are rejected.
+    pub epoch_window: Option<usize>,
+    /// Whether the ciphertexts carry the Merkle roots of `s` and `r`, see the `merkle` module. Both parties must agree on it.
+    pub merkle_roots: bool,
+    /// Roots carried by the last ciphertext accepted from the peer, which an arbiter can check proofs against.
+    pub peer_merkle_roots: Option<MerkleRoots>,
+}
+
+impl GetSize for RrcState {
+    /// Counts the skipped message keys of the ratchet, the sets of messages and the evidence of a raised alarm.
+    fn get_heap_size(&self) -> usize {
+        self.state.get_heap_size() + self.s.get_heap_size() + self.r.get_heap_size() + self.s_ack.get_heap_size() + self.alarm.get_heap_size()
+    }
 }
 
 #[derive(Clone)]
@@ -33,6 +63,13 @@ pub struct OptimizedSendRrcState {
     pub nums_prime: HashSet<Ordinal>,
 }
 
+impl GetSize for OptimizedSendRrcState {
+    /// Counts the inner state, `nums_prime` and the multiset hashes.
+    fn get_heap_size(&self) -> usize {
+        self.state.get_heap_size() + self.hash_s.get_heap_size() + self.hash_ordinal_set.get_heap_size() + self.nums_prime.get_heap_size()
+    }
+}
+
 pub fn rrc_init_all(security_level: Security) -> (RrcState, RrcState) {
     // do key exchange for both hash keys
     let alice_hash_key = generate_dh();
@@ -43,6 +80,12 @@ pub fn rrc_init_all(security_level: Security) -> (RrcState, RrcState) {
     let hash_key = dh(alice_hash_key, bob_hash_key.public);
     let hash_key_prime = dh(alice_hash_key_prime, bob_hash_key_prime.public);
     let (alice_rc_state, bob_rc_state) = init_all();
+    let security_transcript = Handshake {
+        initiator_offer: vec![security_level.clone()],
+        responder_offer: vec![security_level.clone()],
+        epoch_window: None,
+    }
+    .transcript();
     let alice_state = RrcState {
         state: alice_rc_state,
         hash_key: hash_key.to_bytes().clone(),
@@ -52,6 +95,13 @@ pub fn rrc_init_all(security_level: Security) -> (RrcState, RrcState) {
         s_ack: HashSet::new(),
         max_num: Ordinal { epoch: 0, index: 0 },
         security_level: security_level.clone(),
+        security_transcript,
+        pending_security_level: None,
+        peer_proposed_security_level: None,
+        alarm: None,
+        epoch_window: None,
+        merkle_roots: false,
+        peer_merkle_roots: None,
     };
     let bob_state = RrcState {
         state: bob_rc_state,
@@ -62,11 +112,56 @@ pub fn rrc_init_all(security_level: Security) -> (RrcState, RrcState) {
         s_ack: HashSet::new(),
         max_num: Ordinal { epoch: 0, index: 0 },
         security_level: security_level,
+        security_transcript,
+        pending_security_level: None,
+        peer_proposed_security_level: None,
+        alarm: None,
+        epoch_window: None,
+        merkle_roots: false,
+        peer_merkle_roots: None,
     };
 
     return (alice_state, bob_state);
 }
 
+/// Same as rrc_init_all(), but the ciphertexts of both parties carry the Merkle roots of their sets `s` and `r`.
+///
+/// The roots are bound into the associated data, and a party only accepts ciphertexts which carry
+/// them. Only rrc_send() and rrc_receive(), and the functions built on them, support the roots.
+pub fn rrc_init_all_with_merkle_roots(security_level: Security) -> (RrcState, RrcState) {
+    let (mut alice_state, mut bob_state) = rrc_init_all(security_level);
+    alice_state.merkle_roots = true;
+    bob_state.merkle_roots = true;
+    (alice_state, bob_state)
+}
+
+/// Same as rrc_init_all(), but both parties only keep the messages of the last `epoch_window` epochs.
+///
+/// The window is bound into the associated data like a negotiated one, so parties using different windows
+/// cannot exchange any message. Returns Err if the window is 0.
+pub fn rrc_init_all_with_epoch_window(security_level: Security, epoch_window: usize) -> Result<(RrcState, RrcState), &'static str> {
+    let handshake = fixed_handshake(security_level, epoch_window);
+    rrc_init_all_negotiated(&handshake, &handshake)
+}
+
+/// Returns the handshake of two parties which only support `security_level` and agreed on the epoch window.
+fn fixed_handshake(security_level: Security, epoch_window: usize) -> Handshake {
+    Handshake {
+        initiator_offer: vec![security_level.clone()],
+        responder_offer: vec![security_level],
+        epoch_window: Some(epoch_window),
+    }
+}
+
+/// Same as rrc_init_all(), but both parties pad their plaintexts with the given policy, and send_bytes()
+/// also pads its payload so that its length does not reveal the sizes of the sets of the ciphertext.
+pub fn rrc_init_all_with_padding(security_level: Security, padding: PaddingPolicy) -> (RrcState, RrcState) {
+    let (mut alice_state, mut bob_state) = rrc_init_all(security_level);
+    alice_state.state.padding = padding;
+    bob_state.state.padding = padding;
+    (alice_state, bob_state)
+}
+
 pub fn rrc_init_all_optimized_send(
     security_level: Security,
 ) -> (OptimizedSendRrcState, OptimizedSendRrcState) {
@@ -95,7 +190,21 @@ pub fn rrc_init_all_optimized_send(
     );
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd)]
+/// Same as rrc_init_all_optimized_send(), but both parties only keep the messages of the last `epoch_window` epochs.
+///
+/// Returns Err if the window is 0, see rrc_init_all_with_epoch_window().
+pub fn rrc_init_all_optimized_send_with_epoch_window(
+    security_level: Security,
+    epoch_window: usize,
+) -> Result<(OptimizedSendRrcState, OptimizedSendRrcState), &'static str> {
+    let handshake = fixed_handshake(security_level.clone(), epoch_window);
+    let (mut alice_state, mut bob_state) = rrc_init_all_optimized_send(security_level);
+    apply_handshake(&mut alice_state.state, &handshake)?;
+    apply_handshake(&mut bob_state.state, &handshake)?;
+    Ok((alice_state, bob_state))
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, GetSize)]
 pub struct Message {
     pub ordinal: Ordinal,
     pub content: [u8; 32],
@@ -131,6 +240,12 @@ impl ByteDecodable for Message {
     where
         Size: BVSize + ByteDecodable,
     {
+        if bytes.len() != 32 + 2 * size_of::<usize>() {
+            return Err(ByteVecError::BadSizeDecodeError {
+                expected: BVExpectedSize::EqualTo(32 + 2 * size_of::<usize>()),
+                actual: bytes.len(),
+            });
+        }
         let ordinal_epoch = usize::from_be_bytes(bytes[0..size_of::<usize>()].try_into().unwrap());
         let ordinal_index = usize::from_be_bytes(
             bytes[size_of::<usize>()..2 * size_of::<usize>()]
@@ -151,11 +266,34 @@ impl ByteDecodable for Message {
     }
 }
 
+/// Encodes an ordinal as its epoch and its index, both as 8 byte big-endian integers.
+///
+/// The encoding does not depend on the width of `usize`, so 32-bit and 64-bit clients hash the
+/// same ordinals, and the same messages, to the same set commitments.
+pub fn ordinal_as_bytes(ordinal: &Ordinal) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes[0..8].copy_from_slice(&(ordinal.epoch as u64).to_be_bytes());
+    bytes[8..16].copy_from_slice(&(ordinal.index as u64).to_be_bytes());
+    bytes
+}
+
+impl Message {
+    /// Returns the element representing this message in a multiset hash: the tuple (ordinal, content).
+    ///
+    /// Hashing the ordinal and the content as one tuple, rather than as two separate elements,
+    /// binds each content to its ordinal inside the set commitment.
+    pub fn multiset_element(&self) -> Vec<u8> {
+        encode_tuple(&[&ordinal_as_bytes(&self.ordinal), &self.content])
+    }
+}
+
 #[derive(Clone)]
 pub struct Ciphertext {
     pub ciphertext: Vec<u8>,
     pub s: HashSet<Message>,
     pub r: (HashSet<Ordinal>, [u8; 32]),
+    /// Merkle roots of the sets of the sender, when both parties opted into them.
+    pub merkle_roots: Option<MerkleRoots>,
 }
 
 #[derive(Clone)]
@@ -175,44 +313,25 @@ pub(crate) fn get_hash_msg_set(r: &HashSet<Message>, hash_key_prime: [u8; 32]) -
     let iterator = r_sorted.iter();
     hasher.update(hash_key_prime);
     for message in iterator {
-        let usize_for_env = size_of::<usize>();
-        let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&message.ordinal.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-            .clone_from_slice(&message.ordinal.index.to_be_bytes());
-        hasher.update(&ordinal_as_bytes);
-        hasher.update(&ordinal_as_bytes);
+        hasher.update(ordinal_as_bytes(&message.ordinal));
+        hasher.update(ordinal_as_bytes(&message.ordinal));
         hasher.update(&message.content);
     }
     // read hash digest and consume hasher
     return hasher.finalize().try_into().unwrap();
 }
 
-fn opti_get_hash_msg_set(r: &HashSet<Message>) -> [u8; 32] {
+pub(crate) fn opti_get_hash_msg_set(r: &HashSet<Message>) -> [u8; 32] {
     let mut multiset_hash = RistrettoHash::<Sha512>::default();
-    let usize_for_env = size_of::<usize>();
-
-    for message in r.iter() {
-        let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&message.ordinal.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-            .clone_from_slice(&message.ordinal.index.to_be_bytes());
-        multiset_hash.add(&ordinal_as_bytes, 1);
-        multiset_hash.add(&message.content, 1);
-    }
+    multiset_hash.extend(r.iter().map(|message| (message.multiset_element(), 1)));
     return multiset_hash.finalize();
 }
 
 fn opti_get_hash_ordinal_set(r: &HashSet<Ordinal>) -> [u8; 32] {
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
     let mut multiset_hash = RistrettoHash::<Sha512>::default();
-    for ord in r.iter() {
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&ord.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-            .clone_from_slice(&ord.index.to_be_bytes());
-        multiset_hash.add(&ordinal_as_bytes, 1);
-    }
+    multiset_hash.extend(r.iter().map(|ord| {
+        (ordinal_as_bytes(ord), 1)
+    }));
 
     return multiset_hash.finalize();
 }
@@ -224,22 +343,29 @@ pub(crate) fn get_hash_ordinal_set(r: &HashSet<Ordinal>) -> [u8; 32] {
     }
     let mut hasher = Sha256::new();
     let iterator = r_sorted.iter();
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
     for ordinal in iterator {
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&ordinal.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-            .clone_from_slice(&ordinal.index.to_be_bytes());
-        hasher.update(&ordinal_as_bytes);
+        hasher.update(ordinal_as_bytes(ordinal));
     }
     return hasher.finalize().try_into().unwrap();
 }
 
+/// Same as try_rrc_send(), but panics if the session is frozen, for callers which cleared or never raise alarms.
 pub fn rrc_send(
     state: &mut RrcState,
     associated_data: &[u8; 32],
     plaintext: &[u8],
 ) -> (Ordinal, Ciphertext, Header) {
+    try_rrc_send(state, associated_data, plaintext).expect("rrc_send called on a frozen session")
+}
+
+/// Encrypts a message to the peer, or returns an error if an alarm froze the session.
+pub fn try_rrc_send(
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(Ordinal, Ciphertext, Header), &'static str> {
+    ensure_not_frozen(&state.alarm)?;
+    prune_to_epoch_window(state);
     let mut nums_prime: HashSet<Ordinal> = HashSet::new();
     for msg in state.r.iter() {
         nums_prime.insert(msg.ordinal);
@@ -248,8 +374,9 @@ pub fn rrc_send(
         nums_prime.clone(),
         get_hash_msg_set(&state.r, state.hash_key_prime),
     );
+    let merkle_roots = if state.merkle_roots { Some(MerkleRoots::of(state)) } else { None };
     let mut associated_data_prime: [u8; 128] = [0; 128];
-    associated_data_prime[0..32].clone_from_slice(associated_data);
+    associated_data_prime[0..32].clone_from_slice(&bind_merkle_roots(bind_security_transcript(associated_data, &state.security_transcript), &merkle_roots));
     associated_data_prime[32..64].clone_from_slice(&get_hash_msg_set(&state.s, [0; 32]));
     associated_data_prime[64..96].clone_from_slice(&get_hash_ordinal_set(&r_prime.0));
     associated_data_prime[96..128].clone_from_slice(&r_prime.1);
@@ -260,42 +387,59 @@ pub fn rrc_send(
         ciphertext: sent.2,
         s: state.s.clone(),
         r: (nums_prime, r_prime.1.clone()),
+        merkle_roots,
     };
 
     let mut hasher = Sha256::new();
     hasher.update(&state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&sent.0.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2]
-        .clone_from_slice(&sent.0.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&sent.0));
     hasher.update(associated_data);
     hasher.update(&ciphertext.ciphertext);
     hasher.update(get_hash_msg_set(&ciphertext.s, [0; 32]));
     hasher.update(get_hash_ordinal_set(&ciphertext.r.0));
     hasher.update(&ciphertext.r.1);
+    if let Some(roots) = ciphertext.merkle_roots {
+        hasher.update(roots.to_bytes());
+    }
     let h: [u8; 32] = hasher.finalize().try_into().unwrap();
     state.s.insert(Message {
         ordinal: sent.0,
         content: h,
     });
 
-    return (sent.0, ciphertext, sent.1);
+    return Ok((sent.0, ciphertext, sent.1));
 }
 
+/// Drops the messages which fail the forgery detection checks, see rrc_receive_with_handler() to apply another policy.
 pub fn rrc_receive(
     state: &mut RrcState,
     associated_data: &[u8; 32],
     ct: &mut Ciphertext,
     header: Header,
+) -> (bool, Ordinal, Vec<u8>) {
+    rrc_receive_with_handler(state, associated_data, ct, header, &mut FixedPolicy(DetectionPolicy::Drop))
+}
+
+/// Same as rrc_receive(), but lets the handler decide what happens to the session when a message fails the forgery detection checks.
+pub fn rrc_receive_with_handler(
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    ct: &mut Ciphertext,
+    header: Header,
+    handler: &mut impl DetectionHandler,
 ) -> (bool, Ordinal, Vec<u8>) {
     let mut associated_data_prime: [u8; 128] = [0; 128];
 
-    associated_data_prime[0..32].clone_from_slice(associated_data);
+    associated_data_prime[0..32].clone_from_slice(&bind_merkle_roots(bind_security_transcript(associated_data, &state.security_transcript), &ct.merkle_roots));
     associated_data_prime[32..64].clone_from_slice(&get_hash_msg_set(&ct.s, [0; 32]));
     associated_data_prime[64..96].clone_from_slice(&get_hash_ordinal_set(&ct.r.0));
     associated_data_prime[96..128].clone_from_slice(&ct.r.1);
 
+    // A message which can no longer be checked, or which does not carry the roots the parties agreed on, is rejected before its key is used.
+    let header_num = Ordinal { epoch: header.epoch, index: header.msg_nbr };
+    if is_outside_epoch_window(state, header_num) || ct.merkle_roots.is_some() != state.merkle_roots {
+        return (false, header_num, Vec::new());
+    }
     let (acc, num, pt) = receive(
         &mut state.state,
         &associated_data_prime,
@@ -308,18 +452,23 @@ pub fn rrc_receive(
     }
     let mut hasher = Sha256::new();
     hasher.update(&state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&num.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2]
-        .clone_from_slice(&num.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&num));
     hasher.update(associated_data);
     hasher.update(&ct.ciphertext);
     hasher.update(get_hash_msg_set(&ct.s, [0; 32]));
     hasher.update(get_hash_ordinal_set(&ct.r.0));
     hasher.update(&ct.r.1);
+    if let Some(roots) = ct.merkle_roots {
+        hasher.update(roots.to_bytes());
+    }
     let h: [u8; 32] = hasher.finalize().try_into().unwrap();
-    if checks(state, ct, &h, num) {
+    if let Some(kind) = checks(state, ct, &h, num) {
+        let event = DetectionEvent {
+            kind,
+            ordinal: num,
+            evidence: Evidence { message_hash: h, peer_received_digest: ct.r.1.to_vec() },
+        };
+        state.alarm = apply_policy(state.alarm.take(), event, handler);
         return (false, num, Vec::new());
     }
 
@@ -327,21 +476,88 @@ pub fn rrc_receive(
         ordinal: num,
         content: h,
     });
+    if ct.merkle_roots.is_some() {
+        state.peer_merkle_roots = ct.merkle_roots;
+    }
     //state.S_ack.insert(Message { ordinal:Ordinal { epoch: header.epoch, index: header.msg_nbr }, content: h });
     let _ = &ct.s.iter().for_each(|elem| {
         state.s_ack.insert(elem.clone());
     });
+    prune_to_epoch_window(state);
     return (acc, num, pt);
 }
 
-#[derive(Clone, PartialEq)]
+/// Lets an rc `SessionManager` run rrc with each device. A message failing the checks raises an alarm
+/// on the session with that device, which then refuses to send until it is cleared after re-verifying it out of band.
+impl DeviceSession for RrcState {
+    type Ciphertext = (Ordinal, Ciphertext, Header);
+
+    fn encrypt(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        try_rrc_send(self, associated_data, plaintext)
+    }
+
+    fn decrypt(&mut self, associated_data: &[u8; 32], (_, mut ct, header): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let mut state = self.clone();
+        let (acc, _, pt) = rrc_receive_with_handler(&mut state, associated_data, &mut ct, header, &mut FixedPolicy(DetectionPolicy::RequireReverification));
+        if acc {
+            *self = state;
+        } else {
+            self.alarm = state.alarm;
+        }
+        (acc, pt)
+    }
+}
+
+/// Returns the associated data given to the ratchet, which binds the transcript of the handshake that set the security level.
+///
+/// Two parties which saw different handshakes, e.g. because an attacker removed a level from an offer, cannot exchange any message.
+pub(crate) fn bind_security_transcript(associated_data: &[u8; 32], security_transcript: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(security_transcript);
+    hasher.update(associated_data);
+    hasher.finalize().into()
+}
+
+/// Returns the first epoch which is at most `windows` epoch windows before `epoch`, or 0 if the state keeps every epoch.
+fn window_start(state: &RrcState, epoch: usize, windows: usize) -> usize {
+    state.epoch_window.map_or(0, |window| epoch.saturating_sub(windows * window))
+}
+
+/// True if the message is older than the epoch window, in which case it can no longer be checked.
+pub(crate) fn is_outside_epoch_window(state: &RrcState, num: Ordinal) -> bool {
+    num.epoch < window_start(state, state.state.epoch, 1)
+}
+
+/// Forgets the messages of the epochs which left the window, and returns those removed from `s` and from `r`.
+///
+/// `r` only keeps the last window, which is what the peer checks against its sent set. `s` and `s_ack`
+/// keep the last two windows, as a message of the last window still refers to the window before it.
+pub(crate) fn prune_to_epoch_window(state: &mut RrcState) -> (Vec<Message>, Vec<Message>) {
+    if state.epoch_window.is_none() {
+        return (Vec::new(), Vec::new());
+    }
+    let r_start = window_start(state, state.state.epoch, 1);
+    let s_start = window_start(state, state.state.epoch, 2);
+    state.s_ack.retain(|msg| msg.ordinal.epoch >= s_start);
+    (remove_older_than(&mut state.s, s_start), remove_older_than(&mut state.r, r_start))
+}
+
+fn remove_older_than(messages: &mut HashSet<Message>, epoch: usize) -> Vec<Message> {
+    let removed: Vec<Message> = messages.iter().filter(|msg| msg.ordinal.epoch < epoch).cloned().collect();
+    for msg in removed.iter() {
+        messages.remove(msg);
+    }
+    removed
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Security {
     RRid,
     SRid,
     RRidAndSRid,
 }
 
-fn checks(state: &mut RrcState, ct: &mut Ciphertext, h: &[u8; 32], num: Ordinal) -> bool {
+fn checks(state: &mut RrcState, ct: &mut Ciphertext, h: &[u8; 32], num: Ordinal) -> Option<DetectionKind> {
     let mut s_bool: bool = false;
 
     if state.security_level != Security::RRid {
@@ -353,12 +569,14 @@ fn checks(state: &mut RrcState, ct: &mut Ciphertext, h: &[u8; 32], num: Ordinal)
         }
         s_bool = get_hash_msg_set(&r_star, state.hash_key_prime) != ct.r.1;
         if state.security_level == Security::SRid {
-            return s_bool;
+            return detection_kind(false, s_bool);
         }
     }
     let mut r_prime: HashSet<Message> = HashSet::new();
+    // Only the messages within the window of num are still in the sets of both parties.
+    let num_window_start = window_start(state, num.epoch, 1);
     for num_prime in state.r.iter() {
-        if num_prime.ordinal <= num {
+        if num_prime.ordinal <= num && num_prime.ordinal.epoch >= num_window_start {
             r_prime.insert(num_prime.clone());
         }
     }
@@ -374,10 +592,16 @@ fn checks(state: &mut RrcState, ct: &mut Ciphertext, h: &[u8; 32], num: Ordinal)
                 ordinal: num,
                 content: h.to_owned(),
             });
-        r_bool = r_bool || !ct.s.is_subset(&state.s_ack);
+        let ack_window_start = window_start(state, state.state.epoch, 2);
+        r_bool = r_bool
+            || !ct
+                .s
+                .iter()
+                .filter(|msg| msg.ordinal.epoch >= ack_window_start)
+                .all(|msg| state.s_ack.contains(msg));
         let mut s_ack_prime: HashSet<Message> = HashSet::new();
         for acked_msg in state.s_ack.iter() {
-            if acked_msg.ordinal < num {
+            if acked_msg.ordinal < num && acked_msg.ordinal.epoch >= num_window_start {
                 s_ack_prime.insert(acked_msg.clone());
             }
         }
@@ -389,13 +613,13 @@ fn checks(state: &mut RrcState, ct: &mut Ciphertext, h: &[u8; 32], num: Ordinal)
                 .s_ack
                 .difference(&ct.s)
                 .into_iter()
-                .fold(false, |acc, msg| acc || msg.ordinal < state.max_num); // -> fix w.r.t paper
+                .fold(false, |acc, msg| acc || (msg.ordinal < state.max_num && msg.ordinal.epoch >= num_window_start)); // -> fix w.r.t paper
     }
 
     match state.security_level {
-        Security::RRid => return r_bool,
-        Security::RRidAndSRid => return r_bool || s_bool,
-        Security::SRid => return s_bool,
+        Security::RRid => return detection_kind(r_bool, false),
+        Security::RRidAndSRid => return detection_kind(r_bool, s_bool),
+        Security::SRid => return detection_kind(false, s_bool),
     }
 }
 
@@ -404,7 +628,7 @@ fn optimized_checks(
     ct: &mut OptimizedSendCiphertext,
     h: &[u8; 32],
     num: Ordinal,
-) -> bool {
+) -> Option<DetectionKind> {
     let mut s_bool: bool = false;
 
     if state.security_level != Security::RRid {
@@ -420,12 +644,14 @@ fn optimized_checks(
             &state.hash_key_prime,
         );
         if state.security_level == Security::SRid {
-            return s_bool;
+            return detection_kind(false, s_bool);
         }
     }
     let mut r_prime: HashSet<Message> = HashSet::new();
+    // Only the messages within the window of num are still in the sets of both parties.
+    let num_window_start = window_start(state, num.epoch, 1);
     for num_prime in state.r.iter() {
-        if num_prime.ordinal <= num {
+        if num_prime.ordinal <= num && num_prime.ordinal.epoch >= num_window_start {
             r_prime.insert(num_prime.clone());
         }
     }
@@ -441,10 +667,16 @@ fn optimized_checks(
                 ordinal: num,
                 content: h.to_owned(),
             });
-        r_bool = r_bool || !ct.s.is_subset(&state.s_ack);
+        let ack_window_start = window_start(state, state.state.epoch, 2);
+        r_bool = r_bool
+            || !ct
+                .s
+                .iter()
+                .filter(|msg| msg.ordinal.epoch >= ack_window_start)
+                .all(|msg| state.s_ack.contains(msg));
         let mut s_ack_prime: HashSet<Message> = HashSet::new();
         for acked_msg in state.s_ack.iter() {
-            if acked_msg.ordinal < num {
+            if acked_msg.ordinal < num && acked_msg.ordinal.epoch >= num_window_start {
                 s_ack_prime.insert(acked_msg.clone());
             }
         }
@@ -456,13 +688,13 @@ fn optimized_checks(
                 .s_ack
                 .difference(&ct.s)
                 .into_iter()
-                .fold(false, |acc, msg| acc || msg.ordinal < state.max_num);
+                .fold(false, |acc, msg| acc || (msg.ordinal < state.max_num && msg.ordinal.epoch >= num_window_start));
     }
 
     match state.security_level {
-        Security::RRid => r_bool,
-        Security::RRidAndSRid => r_bool || s_bool,
-        Security::SRid => return s_bool,
+        Security::RRid => detection_kind(r_bool, false),
+        Security::RRidAndSRid => detection_kind(r_bool, s_bool),
+        Security::SRid => detection_kind(false, s_bool),
     }
 }
 
@@ -477,7 +709,7 @@ fn generate_nonce() -> [u8; M_BYTES] {
 }
 
 const M: usize = 256 + 24; // We support 2^24 messages with hashes of 256 bits
-const M_BYTES: usize = M / 8;
+pub(crate) const M_BYTES: usize = M / 8;
 /*
  * Hash function 0 is SHA256, Hash function 1 is Blake2s256.
  * Generates a triple [h, c, r] as stated in https://people.csail.mit.edu/devadas/pubs/mhashes.pdf
@@ -519,15 +751,10 @@ pub(crate) fn incremental_hash_fct_of_whole_set(
     return hash;
 }
 
-fn hash_msg_w_blake2(msg: &Message, hash_key_prime: &[u8; 32]) -> [u8; 32] {
+pub(crate) fn hash_msg_w_blake2(msg: &Message, hash_key_prime: &[u8; 32]) -> [u8; 32] {
     let mut hasher = Blake2s256::new();
     hasher.update(&hash_key_prime);
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..usize_for_env].clone_from_slice(&msg.ordinal.epoch.to_be_bytes());
-    ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-        .clone_from_slice(&msg.ordinal.index.to_be_bytes());
-    hasher.update(&ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&msg.ordinal));
     hasher.update(&msg.content);
     return hasher.finalize().try_into().unwrap();
 }
@@ -564,10 +791,55 @@ pub(crate) fn incremental_hash_sets_are_equal(
     return xored1 == xored2 && hash1[32..32 + M_BYTES] == hash2[32..32 + M_BYTES];
 }
 
+/// Checks that an incremental hash [h, c, r] commits to a set whose messages' Blake2 hashes xor to `xored_msg_hashes`
+/// and whose cardinality is `cardinality`, without needing the set itself.
+pub(crate) fn incremental_hash_matches(
+    hash: &[u8; 32 + 2 * M_BYTES],
+    xored_msg_hashes: &[u8; 32],
+    cardinality: usize,
+    hash_key_prime: &[u8; 32],
+) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(hash_key_prime);
+    hasher.update(&hash[32 + M_BYTES..32 + 2 * M_BYTES]);
+    let h_0_r: [u8; 32] = hasher.finalize().into();
+    let h_without_nonce: Vec<u8> = h_0_r
+        .iter()
+        .zip(hash[0..32].iter())
+        .map(|(&byte1, &byte2)| byte1 ^ byte2)
+        .collect();
+
+    let usize_for_env = size_of::<usize>();
+    let mut correct_size_nbr_elems = [0; M_BYTES];
+    correct_size_nbr_elems[M_BYTES - usize_for_env..M_BYTES].clone_from_slice(&cardinality.to_be_bytes());
+    h_without_nonce == xored_msg_hashes && hash[32..32 + M_BYTES] == correct_size_nbr_elems
+}
+
 pub(crate) fn update_incremental_hash_set(
     incremental_hash: &mut [u8; 32 + 2 * M_BYTES],
     msg: Message,
     hash_key_prime: &[u8; 32],
+) -> [u8; 32 + 2 * M_BYTES] {
+    toggle_in_incremental_hash_set(incremental_hash, msg, hash_key_prime, true)
+}
+
+/// Inverse of update_incremental_hash_set(), for a message which is in the hashed set.
+pub(crate) fn remove_from_incremental_hash_set(
+    incremental_hash: &mut [u8; 32 + 2 * M_BYTES],
+    msg: Message,
+    hash_key_prime: &[u8; 32],
+) -> [u8; 32 + 2 * M_BYTES] {
+    toggle_in_incremental_hash_set(incremental_hash, msg, hash_key_prime, false)
+}
+
+/* Xors the hash of the message into the set hash, and increments the cardinality if the message is
+ * added or decrements it if it is removed. The nonce is refreshed in both cases.
+ */
+fn toggle_in_incremental_hash_set(
+    incremental_hash: &mut [u8; 32 + 2 * M_BYTES],
+    msg: Message,
+    hash_key_prime: &[u8; 32],
+    added: bool,
 ) -> [u8; 32 + 2 * M_BYTES] {
     let mut new_hash: [u8; 32 + 2 * M_BYTES] = [0; 32 + 2 * M_BYTES];
     // Update the first element in the tuple
@@ -594,7 +866,11 @@ pub(crate) fn update_incremental_hash_set(
             .try_into()
             .unwrap(),
     );
-    cardinality_r += 1;
+    if added {
+        cardinality_r += 1;
+    } else {
+        cardinality_r -= 1;
+    }
     let nbr_elems_in_r_bytes = (cardinality_r).to_be_bytes();
     let mut correct_size_nbr_elems = [0; M_BYTES];
     correct_size_nbr_elems[M_BYTES - usize_for_env..M_BYTES]
@@ -617,15 +893,37 @@ pub(crate) fn update_incremental_hash_set(
     return new_hash;
 }
 
+/// Same as try_optimized_rrc_send(), but panics if the session is frozen.
 pub fn optimized_rrc_send(
     state: &mut OptimizedSendRrcState,
     associated_data: &[u8; 32],
     plaintext: &[u8],
 ) -> (Ordinal, OptimizedSendCiphertext, Header) {
+    try_optimized_rrc_send(state, associated_data, plaintext).expect("optimized_rrc_send called on a frozen session")
+}
+
+/// Same as try_rrc_send(), with the incremental hashes of the sending-optimized variant.
+pub fn try_optimized_rrc_send(
+    state: &mut OptimizedSendRrcState,
+    associated_data: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(Ordinal, OptimizedSendCiphertext, Header), &'static str> {
+    let (msg, ciphertext, header) = optimized_rrc_send_message(state, associated_data, plaintext)?;
+    Ok((msg.ordinal, ciphertext, header))
+}
+
+/// Same as try_optimized_rrc_send(), but returns the whole message added to the sent set rather than only its ordinal.
+pub(crate) fn optimized_rrc_send_message(
+    state: &mut OptimizedSendRrcState,
+    associated_data: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(Message, OptimizedSendCiphertext, Header), &'static str> {
+    ensure_not_frozen(&state.state.alarm)?;
+    prune_optimized_to_epoch_window(state);
     let r_prime: (HashSet<Ordinal>, [u8; 32 + 2 * M_BYTES]) =
         (state.nums_prime.clone(), state.incremental_hash);
     let mut associated_data_prime: [u8; 128 + 2 * M_BYTES] = [0; 128 + 2 * M_BYTES];
-    associated_data_prime[0..32].clone_from_slice(associated_data);
+    associated_data_prime[0..32].clone_from_slice(&bind_security_transcript(associated_data, &state.state.security_transcript));
     associated_data_prime[32..64].clone_from_slice(&state.hash_s.clone().finalize());
     associated_data_prime[64..96].clone_from_slice(&state.hash_ordinal_set.clone().finalize());
     associated_data_prime[96..128 + 2 * M_BYTES].clone_from_slice(&r_prime.1);
@@ -640,11 +938,7 @@ pub fn optimized_rrc_send(
 
     let mut hasher = Sha256::new();
     hasher.update(&state.state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&sent.0.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2]
-        .clone_from_slice(&sent.0.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&sent.0));
     hasher.update(associated_data);
     hasher.update(&ciphertext.ciphertext);
     hasher.update(&state.hash_s.clone().finalize());
@@ -659,15 +953,9 @@ pub fn optimized_rrc_send(
     state.state.s.insert(new_msg.clone());
 
     // Update the hash of all sent messages using the new message
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..usize_for_env].clone_from_slice(&new_msg.ordinal.epoch.to_be_bytes());
-    ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-        .clone_from_slice(&new_msg.ordinal.index.to_be_bytes());
-    state.hash_s.add(&ordinal_as_bytes, 1);
-    state.hash_s.add(&new_msg.content, 1);
+    state.hash_s.add(new_msg.multiset_element(), 1);
 
-    return (sent.0, ciphertext, sent.1);
+    return Ok((new_msg, ciphertext, sent.1));
 }
 
 pub fn optimized_rrc_receive(
@@ -675,17 +963,33 @@ pub fn optimized_rrc_receive(
     associated_data: &[u8; 32],
     ct: &mut OptimizedSendCiphertext,
     header: Header,
+) -> (bool, Ordinal, Vec<u8>) {
+    optimized_rrc_receive_with_handler(state, associated_data, ct, header, &mut FixedPolicy(DetectionPolicy::Drop))
+}
+
+/// Same as optimized_rrc_receive(), but lets the handler decide what happens to the session when a message fails the forgery detection checks.
+pub fn optimized_rrc_receive_with_handler(
+    state: &mut OptimizedSendRrcState,
+    associated_data: &[u8; 32],
+    ct: &mut OptimizedSendCiphertext,
+    header: Header,
+    handler: &mut impl DetectionHandler,
 ) -> (bool, Ordinal, Vec<u8>) {
     let mut associated_data_prime: [u8; 128 + 2 * M_BYTES] = [0; 128 + 2 * M_BYTES];
 
     let hash_sent_ct = opti_get_hash_msg_set(&ct.s);
     let ordinal_set_hash = opti_get_hash_ordinal_set(&ct.r.0);
 
-    associated_data_prime[0..32].clone_from_slice(associated_data);
+    associated_data_prime[0..32].clone_from_slice(&bind_security_transcript(associated_data, &state.state.security_transcript));
     associated_data_prime[32..64].clone_from_slice(&hash_sent_ct);
     associated_data_prime[64..96].clone_from_slice(&ordinal_set_hash);
     associated_data_prime[96..128 + 2 * M_BYTES].clone_from_slice(&ct.r.1);
 
+    // A message which can no longer be checked is rejected before its key is used.
+    let header_num = Ordinal { epoch: header.epoch, index: header.msg_nbr };
+    if is_outside_epoch_window(&state.state, header_num) {
+        return (false, header_num, Vec::new());
+    }
     let (acc, num, pt) = receive(
         &mut state.state.state,
         &associated_data_prime,
@@ -698,18 +1002,20 @@ pub fn optimized_rrc_receive(
     }
     let mut hasher = Sha256::new();
     hasher.update(&state.state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&num.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2]
-        .clone_from_slice(&num.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&num));
     hasher.update(associated_data);
     hasher.update(&ct.ciphertext);
     hasher.update(&hash_sent_ct);
     hasher.update(ordinal_set_hash);
     hasher.update(&ct.r.1);
     let h: [u8; 32] = hasher.finalize().try_into().unwrap();
-    if optimized_checks(&mut state.state, ct, &h, num) {
+    if let Some(kind) = optimized_checks(&mut state.state, ct, &h, num) {
+        let event = DetectionEvent {
+            kind,
+            ordinal: num,
+            evidence: Evidence { message_hash: h, peer_received_digest: ct.r.1.to_vec() },
+        };
+        state.state.alarm = apply_policy(state.state.alarm.take(), event, handler);
         return (false, num, Vec::new());
     }
 
@@ -721,12 +1027,7 @@ pub fn optimized_rrc_receive(
     state.nums_prime.insert(msg.ordinal.clone());
 
     // Update hash of ordinals you've received using multiset hash
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..usize_for_env].clone_from_slice(&msg.ordinal.epoch.to_be_bytes());
-    ordinal_as_bytes[usize_for_env..2 * usize_for_env]
-        .clone_from_slice(&msg.ordinal.index.to_be_bytes());
-    state.hash_ordinal_set.add(&ordinal_as_bytes, 1);
+    state.hash_ordinal_set.add(ordinal_as_bytes(&msg.ordinal), 1);
     state.incremental_hash = update_incremental_hash_set(
         &mut state.incremental_hash,
         msg,
@@ -735,41 +1036,92 @@ pub fn optimized_rrc_receive(
     let _ = &ct.s.iter().for_each(|elem| {
         state.state.s_ack.insert(elem.clone());
     });
+    prune_optimized_to_epoch_window(state);
     return (acc, num, pt);
 }
 
+/// Same as prune_to_epoch_window(), but also removes the forgotten messages from the incremental hashes.
+pub(crate) fn prune_optimized_to_epoch_window(state: &mut OptimizedSendRrcState) {
+    let (removed_s, removed_r) = prune_to_epoch_window(&mut state.state);
+    for msg in removed_s {
+        state.hash_s.remove(msg.multiset_element(), 1);
+    }
+    for msg in removed_r {
+        state.nums_prime.remove(&msg.ordinal);
+        state.hash_ordinal_set.remove(ordinal_as_bytes(&msg.ordinal), 1);
+        state.incremental_hash = remove_from_incremental_hash_set(
+            &mut state.incremental_hash,
+            msg,
+            &state.state.hash_key_prime,
+        );
+    }
+}
+
+/// Same as try_send_bytes(), but panics if the session is frozen.
 pub fn send_bytes(state: &mut RrcState, associated_data: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
-    // 1. Call send to obtain encrypted message as objects
-    let (num, ct, header) = rrc_send(state, associated_data, plaintext);
-    // 2. Get size of each individual element we will encode
-    // 2.1 Everything for the ciphertext object
-    let msg_ct_len = ct.ciphertext.len();
-    let ct_s_as_bytes = ct.s.encode::<u32>().unwrap();
+    try_send_bytes(state, associated_data, plaintext).expect("send_bytes called on a frozen session")
+}
+
+/// Encrypts a message with try_rrc_send() and encodes it into a padded payload, or returns an error if an alarm froze the session.
+pub fn try_send_bytes(state: &mut RrcState, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (num, ct, header) = try_rrc_send(state, associated_data, plaintext)?;
+    Ok(pad(&encode_payload(num, &ct, header), state.state.padding))
+}
+
+/// Encodes the output of rrc_send() into the payload built by send_bytes().
+///
+/// The Merkle roots, if the ciphertext carries them, are appended after r_1 as `sent root || received root`.
+pub fn encode_payload(num: Ordinal, ct: &Ciphertext, header: Header) -> Vec<u8> {
+    let mut bytes = encode_parts(num, &ct.ciphertext, &ct.s, &ct.r.0, &ct.r.1, header);
+    if let Some(roots) = ct.merkle_roots {
+        bytes.extend_from_slice(&roots.to_bytes());
+    }
+    bytes
+}
+
+/// Encodes the output of optimized_rrc_send() with the layout of send_bytes(), r_1 being the
+/// incremental hash instead of a 32-byte hash.
+pub fn encode_optimized_payload(num: Ordinal, ct: &OptimizedSendCiphertext, header: Header) -> Vec<u8> {
+    encode_parts(num, &ct.ciphertext, &ct.s, &ct.r.0, &ct.r.1, header)
+}
+
+fn encode_parts(
+    num: Ordinal,
+    ciphertext: &[u8],
+    s: &HashSet<Message>,
+    r_0: &HashSet<Ordinal>,
+    r_1: &[u8],
+    header: Header,
+) -> Vec<u8> {
+    // 1. Get size of each individual element we will encode
+    // 1.1 Everything for the ciphertext object
+    let msg_ct_len = ciphertext.len();
+    let ct_s_as_bytes = s.encode::<u32>().unwrap();
     let s_len = ct_s_as_bytes.len();
-    let ct_r_ord_set_as_bytes = ct.r.0.encode::<u32>().unwrap();
+    let ct_r_ord_set_as_bytes = r_0.encode::<u32>().unwrap();
     let r_ord_set_len = ct_r_ord_set_as_bytes.len();
-    let ct_len = msg_ct_len + s_len + r_ord_set_len + 32; // + 32 bytes for r_1
-                                                          // 2.2 Everything for the header
+    let ct_len = msg_ct_len + s_len + r_ord_set_len + r_1.len();
+    // 1.2 Everything for the header
     let dh_pk_len: usize = header.dh_ratchet_key.to_bytes().len();
     let header_len: usize = dh_pk_len + 3 * size_of::<usize>(); // + 3 * usize
-                                                                // 2.3 Ordinal takes 2 * usize_for_env
+    // 1.3 Ordinal takes 2 * usize_for_env
     let ordinal_len: usize = 2 * size_of::<usize>();
-    // 3. Allocate a buffer for all of the elements
+    // 2. Allocate a buffer for all of the elements
     let metadata_len = 3 * size_of::<usize>(); // To store ciphertext, s, and r_0's lenghts in the encoded form
     let total_len = header_len + ordinal_len + ct_len + metadata_len;
     let mut bytes = vec![0u8; total_len];
-    // 4. Fit the elements into the buffer
-    // 4.1 The header: dh_pk || prev_chain_len || msg_nbr || epoch
+    // 3. Fit the elements into the buffer
+    // 3.1 The header: dh_pk || prev_chain_len || msg_nbr || epoch
     bytes[0..32].clone_from_slice(header.dh_ratchet_key.as_bytes());
     bytes[32..32 + size_of::<usize>()].clone_from_slice(&header.prev_chain_len.to_be_bytes());
     bytes[32 + size_of::<usize>()..32 + 2 * size_of::<usize>()]
         .clone_from_slice(&header.msg_nbr.to_be_bytes());
     bytes[32 + 2 * size_of::<usize>()..header_len].clone_from_slice(&header.epoch.to_be_bytes());
-    // 4.2 The ordinal: epoch || index
+    // 3.2 The ordinal: epoch || index
     bytes[header_len..header_len + size_of::<usize>()].clone_from_slice(&num.epoch.to_be_bytes());
     bytes[header_len + size_of::<usize>()..header_len + ordinal_len]
         .clone_from_slice(&num.index.to_be_bytes());
-    // 4.3 The ciphertext: ct_len || s_len || r_0_len || ct || s || r_0 || r_1
+    // 3.3 The ciphertext: ct_len || s_len || r_0_len || ct || s || r_0 || r_1
     bytes[header_len + ordinal_len..header_len + ordinal_len + size_of::<usize>()]
         .clone_from_slice(&msg_ct_len.to_be_bytes());
     bytes[header_len + ordinal_len + size_of::<usize>()
@@ -780,73 +1132,128 @@ pub fn send_bytes(state: &mut RrcState, associated_data: &[u8; 32], plaintext: &
         .clone_from_slice(&r_ord_set_len.to_be_bytes());
     bytes[header_len + ordinal_len + metadata_len
         ..header_len + ordinal_len + metadata_len + msg_ct_len]
-        .clone_from_slice(&ct.ciphertext);
+        .clone_from_slice(ciphertext);
     bytes[header_len + ordinal_len + metadata_len + msg_ct_len
         ..header_len + ordinal_len + metadata_len + msg_ct_len + s_len]
         .clone_from_slice(&ct_s_as_bytes);
     bytes[header_len + ordinal_len + metadata_len + msg_ct_len + s_len
         ..header_len + ordinal_len + metadata_len + msg_ct_len + s_len + r_ord_set_len]
         .clone_from_slice(&ct_r_ord_set_as_bytes);
-    bytes[total_len - 32..total_len].clone_from_slice(&ct.r.1);
+    bytes[total_len - r_1.len()..total_len].clone_from_slice(r_1);
 
-    return bytes;
+    bytes
 }
 
+/// Rejects malformed payloads with acc == false, see try_receive_bytes() to get the reason and set the limits.
 pub fn receive_bytes(
     payload: &[u8],
     state: &mut RrcState,
     associated_data: &[u8; 32],
 ) -> (bool, Ordinal, Vec<u8>) {
-    // 1. Decode header
-    let mut pk_bytes = [0u8; 32];
-    pk_bytes.clone_from_slice(&payload[0..32]);
-    let header = Header {
-        dh_ratchet_key: PublicKey::from(pk_bytes),
-        prev_chain_len: usize::from_be_bytes(
-            payload[32..32 + size_of::<usize>()].try_into().unwrap(),
-        ),
-        msg_nbr: usize::from_be_bytes(
-            payload[32 + size_of::<usize>()..32 + 2 * size_of::<usize>()]
-                .try_into()
-                .unwrap(),
-        ),
-        epoch: usize::from_be_bytes(
-            payload[32 + 2 * size_of::<usize>()..32 + 3 * size_of::<usize>()]
-                .try_into()
-                .unwrap(),
-        ),
-    };
-    // 2. Decode ciphertext
-    let ct_meta_offset = 32 + 5 * size_of::<usize>();
-    let ct_len = usize::from_be_bytes(
-        payload[ct_meta_offset..ct_meta_offset + size_of::<usize>()]
-            .try_into()
-            .unwrap(),
-    );
-    let s_len = usize::from_be_bytes(
-        payload[ct_meta_offset + size_of::<usize>()..ct_meta_offset + 2 * size_of::<usize>()]
-            .try_into()
-            .unwrap(),
-    );
-    let r_0_len = usize::from_be_bytes(
-        payload[ct_meta_offset + 2 * size_of::<usize>()..ct_meta_offset + 3 * size_of::<usize>()]
-            .try_into()
-            .unwrap(),
-    );
+    try_receive_bytes(payload, state, associated_data, &DecodeLimits::default())
+        .unwrap_or((false, Ordinal { epoch: 0, index: 0 }, Vec::new()))
+}
+
+/// Same as receive_bytes(), but returns an error if the payload is malformed or exceeds the limits. The state is left untouched in that case.
+pub fn try_receive_bytes(
+    payload: &[u8],
+    state: &mut RrcState,
+    associated_data: &[u8; 32],
+    limits: &DecodeLimits,
+) -> Result<(bool, Ordinal, Vec<u8>), &'static str> {
+    let ((_, mut ct, header), encoded_len) = decode_payload_prefix(payload, limits, state.merkle_roots)?;
+    receive_padded(state, payload, encoded_len, |state| rrc_receive(state, associated_data, &mut ct, header))
+}
 
-    let ct_offset = ct_meta_offset + 3 * size_of::<usize>();
-    let s: HashSet<Message> =
-        HashSet::decode::<u32>(&payload[ct_offset + ct_len..ct_offset + ct_len + s_len]).unwrap();
-    let r_0: HashSet<Ordinal> = HashSet::decode::<u32>(
-        &payload[ct_offset + ct_len + s_len..ct_offset + ct_len + s_len + r_0_len],
-    )
-    .unwrap();
-    let mut r_1 = [0u8; 32];
-    r_1.clone_from_slice(&payload[ct_offset + ct_len + s_len + r_0_len..payload.len()]);
-    let mut ct = Ciphertext {
-        ciphertext: payload[ct_offset..ct_offset + ct_len].to_vec(),
-        s,
-        r: (r_0, r_1),
+/// Receives the message encoded in the first `encoded_len` bytes of a payload padded by send_bytes().
+///
+/// The padding is only checked once `receive` authenticated the message, on a copy of the state, so
+/// that an invalid padding is reported without touching the state and a forgery is rejected like
+/// any other, whatever its padding.
+pub(crate) fn receive_padded(
+    state: &mut RrcState,
+    payload: &[u8],
+    encoded_len: usize,
+    receive: impl FnOnce(&mut RrcState) -> (bool, Ordinal, Vec<u8>),
+) -> Result<(bool, Ordinal, Vec<u8>), &'static str> {
+    let padding = state.state.padding;
+    if padding == PaddingPolicy::None {
+        if encoded_len != payload.len() {
+            return Err("The payload has trailing bytes.");
+        }
+        return Ok(receive(state));
+    }
+    let mut next = state.clone();
+    let (acc, num, pt) = receive(&mut next);
+    if acc && unpad(payload, padding).map(|encoded| encoded.len()) != Ok(encoded_len) {
+        return Err("The padding of the message is invalid.");
+    }
+    *state = next;
+    Ok((acc, num, pt))
+}
+
+/// Decodes a payload built by send_bytes() into its ordinal, ciphertext and header.
+pub fn decode_payload(payload: &[u8], limits: &DecodeLimits) -> Result<(Ordinal, Ciphertext, Header), &'static str> {
+    decode_whole_payload(payload, limits, false)
+}
+
+/// Same as decode_payload(), for a payload sent by a party which carries the Merkle roots, see rrc_init_all_with_merkle_roots().
+pub fn decode_payload_with_merkle_roots(payload: &[u8], limits: &DecodeLimits) -> Result<(Ordinal, Ciphertext, Header), &'static str> {
+    decode_whole_payload(payload, limits, true)
+}
+
+fn decode_whole_payload(payload: &[u8], limits: &DecodeLimits, merkle_roots: bool) -> Result<(Ordinal, Ciphertext, Header), &'static str> {
+    let (decoded, encoded_len) = decode_payload_prefix(payload, limits, merkle_roots)?;
+    if encoded_len != payload.len() {
+        return Err("The payload has trailing bytes.");
+    }
+    Ok(decoded)
+}
+
+/// Same as decode_payload(), but leaves the bytes after the message, e.g. its padding, and returns the length of the message.
+pub(crate) fn decode_payload_prefix(payload: &[u8], limits: &DecodeLimits, merkle_roots: bool) -> Result<((Ordinal, Ciphertext, Header), usize), &'static str> {
+    let ((num, ciphertext, s, r, header), mut encoded_len) = decode_parts::<32>(payload, limits)?;
+    let merkle_roots = if merkle_roots {
+        let roots = MerkleRoots::from_bytes(Reader::new(&payload[encoded_len..]).take_array()?);
+        encoded_len += 64;
+        Some(roots)
+    } else {
+        None
     };
-    return rrc_receive(state, associated_data, &mut ct, header);
+    Ok(((num, Ciphertext { ciphertext, s, r, merkle_roots }, header), encoded_len))
+}
+
+/// Decodes a payload built by encode_optimized_payload() into its ordinal, ciphertext and header.
+pub fn decode_optimized_payload(
+    payload: &[u8],
+    limits: &DecodeLimits,
+) -> Result<(Ordinal, OptimizedSendCiphertext, Header), &'static str> {
+    let ((num, ciphertext, s, r, header), encoded_len) = decode_parts::<{ 32 + 2 * M_BYTES }>(payload, limits)?;
+    if encoded_len != payload.len() {
+        return Err("The payload has trailing bytes.");
+    }
+    Ok((num, OptimizedSendCiphertext { ciphertext, s, r }, header))
+}
+
+type DecodedParts<const R_1_LEN: usize> = (Ordinal, Vec<u8>, HashSet<Message>, (HashSet<Ordinal>, [u8; R_1_LEN]), Header);
+
+/// Decodes the parts of a message at the start of the payload, and returns them with the number of bytes they took.
+fn decode_parts<const R_1_LEN: usize>(payload: &[u8], limits: &DecodeLimits) -> Result<(DecodedParts<R_1_LEN>, usize), &'static str> {
+    let mut reader = Reader::new(payload);
+    // 1. The header: dh_pk || prev_chain_len || msg_nbr || epoch
+    let header = reader.read_header()?;
+    // 2. The ordinal: epoch || index, which is not authenticated and must be the one of the header
+    let num = Ordinal { epoch: reader.read_usize()?, index: reader.read_usize()? };
+    if num != (Ordinal { epoch: header.epoch, index: header.msg_nbr }) {
+        return Err("The ordinal does not match the header.");
+    }
+    // 3. The ciphertext: ct_len || s_len || r_0_len || ct || s || r_0 || r_1
+    let ct_len = reader.read_len(limits.max_ciphertext_len)?;
+    let s_len = reader.read_len(limits.max_set_bytes())?;
+    let r_0_len = reader.read_len(limits.max_set_bytes())?;
+    let ciphertext = reader.take(ct_len)?.to_vec();
+    let s = decode_set(reader.take(s_len)?, limits.max_set_len)?;
+    let r_0 = decode_set(reader.take(r_0_len)?, limits.max_set_len)?;
+    let r_1 = reader.take_array::<R_1_LEN>()?;
+    Ok(((num, ciphertext, s, (r_0, r_1), header), reader.offset()))
 }
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+
+use bytevec::ByteEncodable;
+use sha2::{Digest, Sha256};
+use x25519_dalek::PublicKey;
+
+use crate::detection::{apply_policy, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
+use crate::parsing::{decode_set, DecodeLimits};
+use crate::protocol::{get_hash_msg_set, Message, RrcState};
+use rc::{reset_finish, reset_request, reset_respond, DiffieHellmanParameters, Ordinal, PendingReset, ResetRequest, ResetResponse};
+
+const HASH_KEY_LABEL: &[u8] = b"rrc reset hash key";
+
+/// Returns the transcript a party sends in a reset: the ordinals of the messages it received followed
+/// by their hash under `hash_key_prime`, as in the `r` component of its ciphertexts.
+pub fn reset_transcript(state: &RrcState) -> Vec<u8> {
+    encode_reset_transcript(&state.r, &get_hash_msg_set(&state.r, state.hash_key_prime))
+}
+
+/// Encodes the ordinals of the received messages followed by their hash, which is how the protocols
+/// built on rrc's reset send their transcript.
+pub fn encode_reset_transcript(received: &HashSet<Message>, received_hash: &[u8; 32]) -> Vec<u8> {
+    let nums: HashSet<Ordinal> = received.iter().map(|msg| msg.ordinal).collect();
+    let mut transcript = nums.encode::<u32>().unwrap();
+    transcript.extend_from_slice(received_hash);
+    transcript
+}
+
+/// Splits a transcript built by encode_reset_transcript() into the ordinals and the hash of the received messages.
+pub fn decode_reset_transcript(transcript: &[u8]) -> Result<(HashSet<Ordinal>, [u8; 32]), &'static str> {
+    if transcript.len() < 32 {
+        return Err("The reset transcript is truncated.");
+    }
+    let (nums, hash) = transcript.split_at(transcript.len() - 32);
+    Ok((decode_set(nums, DecodeLimits::default().max_set_len)?, hash.try_into().unwrap()))
+}
+
+/// Compares the messages the peer received, as told by its transcript, with those this party sent at
+/// the same ordinals, as the s-RID check of a ciphertext does. Messages lost before the reset are not in
+/// the peer's transcript, so they do not raise the alarm. The alarm is dated at `epoch`, the last epoch
+/// before the reset, so the reset which raised it also lets clear_alarm() take it.
+fn check_peer_transcript(state: &mut RrcState, peer_received: &(HashSet<Ordinal>, [u8; 32]), epoch: usize, handler: &mut impl DetectionHandler) {
+    let sent: HashSet<Message> = state.s.iter().filter(|msg| peer_received.0.contains(&msg.ordinal)).cloned().collect();
+    let sent_hash = get_hash_msg_set(&sent, state.hash_key_prime);
+    if sent_hash != peer_received.1 {
+        let event = DetectionEvent {
+            kind: DetectionKind::SRid,
+            ordinal: Ordinal { epoch, index: 0 },
+            evidence: Evidence { message_hash: sent_hash, peer_received_digest: peer_received.1.to_vec() },
+        };
+        state.alarm = apply_policy(state.alarm.take(), event, handler);
+    }
+}
+
+/// Folds the transcripts of both parties into the hash key of the messages of the new session.
+///
+/// The hashes of the messages the checks compare then depend on both views of the old session as
+/// sent at the reset, which chains the transcripts of the old and the new ratchets.
+pub fn rebind_hash_key(hash_key: &[u8; 32], initiator_transcript: &[u8], responder_transcript: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(HASH_KEY_LABEL);
+    hasher.update(hash_key);
+    for transcript in [initiator_transcript, responder_transcript] {
+        hasher.update((transcript.len() as u64).to_be_bytes());
+        hasher.update(transcript);
+    }
+    hasher.finalize().into()
+}
+
+/// Starts a reset of the ratchet of an rrc session, see rc's `reset_request`.
+///
+/// Only the ratchet is replaced, the sets of messages and the alarm are kept. Each party sends its
+/// `reset_transcript`, which the peer checks against the messages it sent, so a forgery accepted
+/// before the reset raises the alarm during the reset, and is still detected by the first messages
+/// after it.
+pub fn rrc_reset_request(state: &RrcState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey) -> (PendingReset, ResetRequest) {
+    reset_request(&state.state, identity, peer_identity, reset_transcript(state))
+}
+
+/// Answers a reset request, switches the session to the new ratchet, and returns the response to send.
+///
+/// Raises an alarm which must be cleared after an out-of-band re-verification if the initiator received
+/// a message this party never sent, see rrc_reset_respond_with_handler() to apply another policy.
+pub fn rrc_reset_respond(state: &mut RrcState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest) -> Result<ResetResponse, &'static str> {
+    rrc_reset_respond_with_handler(state, identity, peer_identity, request, &mut FixedPolicy(DetectionPolicy::RequireReverification))
+}
+
+/// Same as rrc_reset_respond(), but lets the handler decide what happens to the session when the transcript of the initiator fails the check.
+pub fn rrc_reset_respond_with_handler(state: &mut RrcState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest, handler: &mut impl DetectionHandler) -> Result<ResetResponse, &'static str> {
+    let peer_received = decode_reset_transcript(&request.transcript)?;
+    let epoch = state.state.epoch;
+    let (new_state, response) = reset_respond(&state.state, identity, peer_identity, request, reset_transcript(state))?;
+    state.state = new_state;
+    state.hash_key = rebind_hash_key(&state.hash_key, &request.transcript, &response.transcript);
+    check_peer_transcript(state, &peer_received, epoch, handler);
+    Ok(response)
+}
+
+/// Completes a reset with the response of the peer, and switches the session to the new ratchet.
+///
+/// Raises an alarm like rrc_reset_respond() if the responder received a message this party never sent.
+pub fn rrc_reset_finish(state: &mut RrcState, pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse) -> Result<(), &'static str> {
+    rrc_reset_finish_with_handler(state, pending, identity, peer_identity, response, &mut FixedPolicy(DetectionPolicy::RequireReverification))
+}
+
+/// Same as rrc_reset_finish(), but lets the handler decide what happens to the session when the transcript of the responder fails the check.
+pub fn rrc_reset_finish_with_handler(state: &mut RrcState, pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse, handler: &mut impl DetectionHandler) -> Result<(), &'static str> {
+    let peer_received = decode_reset_transcript(&response.transcript)?;
+    let epoch = state.state.epoch;
+    state.state = reset_finish(pending, identity, peer_identity, response)?;
+    state.hash_key = rebind_hash_key(&state.hash_key, &pending.request.transcript, &response.transcript);
+    check_peer_transcript(state, &peer_received, epoch, handler);
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Helpers shared by the tests of the crates which encode their ciphertexts as payloads, enabled by the `test-utils` feature.
+
+use rand::Rng;
+
+/// Returns payloads from a conversation in which both parties take turns, so their sets are not empty,
+/// and the state of the party receiving the last one.
+///
+/// `send` encodes a message as a payload, and `receive` returns whether the receiver accepted it.
+pub fn conversation_payloads<S>(
+    (mut alice_state, mut bob_state): (S, S),
+    mut send: impl FnMut(&mut S, &[u8]) -> Vec<u8>,
+    mut receive: impl FnMut(&mut S, &[u8]) -> bool,
+) -> (Vec<Vec<u8>>, S) {
+    let mut payloads = Vec::new();
+    for i in 0..3u8 {
+        let bytes = send(&mut alice_state, &[i]);
+        assert!(receive(&mut bob_state, &bytes));
+        let bytes = send(&mut bob_state, &[i]);
+        assert!(receive(&mut alice_state, &bytes));
+        payloads.push(bytes);
+    }
+    payloads.push(send(&mut alice_state, b"last"));
+    (payloads, bob_state)
+}
+
+/// Returns a copy of the payload with random truncations, bit flips, overwritten length fields or extra bytes.
+pub fn mutate_payload(payload: &[u8], rng: &mut impl Rng) -> Vec<u8> {
+    let mut mutated = payload.to_vec();
+    for _ in 0..rng.gen_range(1..4) {
+        match rng.gen_range(0..5) {
+            0 => mutated.truncate(rng.gen_range(0..=mutated.len())),
+            1 if !mutated.is_empty() => {
+                let i = rng.gen_range(0..mutated.len());
+                mutated[i] ^= 1 << rng.gen_range(0..8);
+            }
+            2 if mutated.len() >= 8 => {
+                let i = rng.gen_range(0..=mutated.len() - 8);
+                let value: u64 = if rng.gen() { rng.gen() } else { rng.gen_range(0..256) };
+                mutated[i..i + 8].copy_from_slice(&value.to_be_bytes());
+            }
+            3 => mutated.extend((0..rng.gen_range(1..40)).map(|_| rng.gen::<u8>())),
+            _ => mutated = (0..rng.gen_range(0..200)).map(|_| rng.gen()).collect(),
+        }
+    }
+    mutated
+}
@@ -1,13 +1,32 @@
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Write, time::SystemTime, collections::HashSet};
+    use std::{fs::File, io::Write, time::SystemTime, collections::HashSet, mem::size_of};
     use bytevec::{ByteDecodable, ByteEncodable};
-    use mset_mu_hash::RistrettoHash;
+    use mset_mu_hash::{encode_tuple, RistrettoHash};
     use rc::{Ordinal, Header};
     use sha2::Sha512;
 
 
-    use crate::{protocol::{rrc_init_all, rrc_send, rrc_receive, send_bytes, receive_bytes, Message, Security, Ciphertext, incremental_hash_fct_of_whole_set, incremental_hash_sets_are_equal, update_incremental_hash_set, get_hash_ordinal_set, get_hash_msg_set}, optimized_rrc_send, optimized_rrc_receive, rrc_init_all_optimized_send};
+    use crate::merkle::{MerkleRoots, MessageMerkleTree, OrdinalProof, Verdict, merkle_commitments, verify_inclusion, verify_exclusion, arbitrate};
+    use crate::{protocol::{rrc_init_all, rrc_send, rrc_receive, send_bytes, receive_bytes, Message, Security, Ciphertext, incremental_hash_fct_of_whole_set, incremental_hash_sets_are_equal, update_incremental_hash_set, get_hash_ordinal_set, get_hash_msg_set, opti_get_hash_msg_set}, optimized_rrc_send, optimized_rrc_receive, rrc_init_all_optimized_send};
+    use crate::compromise::RrcCompromisedView;
+    use crate::protocol::RrcState;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::detection::{clear_alarm, reverification_code, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, FixedPolicy};
+    use crate::protocol::{rrc_receive_with_handler, try_rrc_send};
+    use crate::negotiation::{Handshake, SecuritySignal, rrc_init_all_negotiated, rrc_send_with_signal, rrc_receive_with_signal, send_bytes_with_signal, receive_bytes_with_signal};
+    use crate::parsing::{decode_set, DecodeLimits};
+    use crate::test_utils::{self, mutate_payload};
+    use crate::protocol::{decode_payload, ordinal_as_bytes, try_receive_bytes};
+    use rc::PaddingPolicy;
+    use rc::{decrypt_attachment, encrypt_attachment, AttachmentPointer};
+    use crate::protocol::{rrc_init_all_with_merkle_roots, rrc_init_all_with_padding};
+    use crate::protocol::{rrc_init_all_with_epoch_window, rrc_init_all_optimized_send_with_epoch_window};
+    use crate::optimized_receive::{OptimizedReceiveRrcState, rrc_init_all_optimized_receive, optimized_receive_rrc_send, optimized_receive_rrc_receive, optimized_receive_rrc_receive_with_handler, try_optimized_receive_rrc_send};
+    use crate::protocol::{optimized_rrc_receive_with_handler, try_optimized_rrc_send};
+    use rc::{add_session, decrypt_from_device, encrypt_to_user, session_manager_init, DeviceAddress, SessionManager};
+    use crate::reset::{reset_transcript, rrc_reset_finish, rrc_reset_request, rrc_reset_respond, rrc_reset_respond_with_handler};
+    use crate::group::{group_rrc_init, group_rrc_send, group_rrc_receive, GroupDetectionKind, GroupRrcCiphertext, GroupRrcState};
 
     #[test]
     fn send_receive_bytes_works() {
@@ -20,6 +39,63 @@ mod tests {
         assert_eq!(plaintext.to_vec(), decrypted_plaintext);
     }
 
+    /// Returns payloads from a conversation, so their sets are not empty, and the state of the party receiving the last one.
+    fn conversation_payloads() -> (Vec<Vec<u8>>, RrcState) {
+        let associated_data = [0u8;32];
+        test_utils::conversation_payloads(
+            rrc_init_all(Security::RRidAndSRid),
+            |state, plaintext| send_bytes(state, &associated_data, plaintext),
+            |state, bytes| receive_bytes(bytes, state, &associated_data).0,
+        )
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected_with_an_error() {
+        let (payloads, bob_state) = conversation_payloads();
+        let limits = DecodeLimits::default();
+        let payload = payloads.last().unwrap();
+        assert!(decode_payload(payload, &limits).is_ok());
+        for len in [0, 31, 32 + 3 * size_of::<usize>(), payload.len() - 1] {
+            assert_eq!(decode_payload(&payload[..len], &limits).err(), Some("The payload is truncated."));
+        }
+        let mut trailing = payload.clone();
+        trailing.push(0);
+        assert_eq!(decode_payload(&trailing, &limits).err(), Some("The payload has trailing bytes."));
+        // The ordinal after the header is not authenticated, so it must match the header.
+        let mut wrong_ordinal = payload.clone();
+        wrong_ordinal[32 + 5 * size_of::<usize>() - 1] ^= 1;
+        assert_eq!(decode_payload(&wrong_ordinal, &limits).err(), Some("The ordinal does not match the header."));
+        let small_limits = DecodeLimits { max_ciphertext_len: 8, ..DecodeLimits::default() };
+        assert_eq!(decode_payload(payload, &small_limits).err(), Some("A declared length exceeds the limit."));
+        // The length of an encoded set is capped by the length of `max_set_len` elements before the set is read.
+        let small_limits = DecodeLimits { max_set_len: 1, ..DecodeLimits::default() };
+        assert_eq!(decode_payload(payload, &small_limits).err(), Some("A declared length exceeds the limit."));
+        let set: HashSet<Message> = (0..2).map(|index| Message { ordinal: Ordinal { epoch: 0, index }, content: [0; 32] }).collect();
+        assert_eq!(decode_set::<Message>(&set.encode::<u32>().unwrap(), 1).err(), Some("A set has more elements than the limit."));
+
+        // A rejected payload leaves the state untouched, so the real one is still accepted.
+        let mut bob_state = bob_state;
+        assert!(try_receive_bytes(&trailing, &mut bob_state, &[0u8;32], &limits).is_err());
+        assert!(!receive_bytes(&trailing, &mut bob_state, &[0u8;32]).0);
+        assert!(try_receive_bytes(payload, &mut bob_state, &[0u8;32], &limits).unwrap().0);
+    }
+
+    /// Feeds payloads mutated from a fixed seed to receive_bytes(): none may panic the receiver.
+    #[test]
+    fn mutated_payloads_never_panic_the_receiver() {
+        let (payloads, bob_state) = conversation_payloads();
+        let mut rng = StdRng::seed_from_u64(38);
+        for i in 0..3000 {
+            let mutated = mutate_payload(&payloads[rng.gen_range(0..payloads.len())], &mut rng);
+            let decoded = decode_payload(&mutated, &DecodeLimits::default());
+            // Decrypting is slower than decoding, only a part of the payloads go through the whole receive.
+            if i % 10 == 0 || decoded.is_ok() {
+                let (acc, _, pt) = receive_bytes(&mutated, &mut bob_state.clone(), &[0u8;32]);
+                assert!(acc || pt.is_empty());
+            }
+        }
+    }
+
     //#[test]
     #[allow(dead_code)]
     fn memory_benchmark_for_encoded_data_to_send() {
@@ -490,6 +566,565 @@ mod tests {
     }
     }
 
+    #[test]
+    fn optimized_receive_interoperates_with_optimized_send() {
+        let (alice_state, mut bob_state) = rrc_init_all_optimized_send(Security::RRidAndSRid);
+        let mut alice_state = OptimizedReceiveRrcState::new(alice_state).unwrap();
+        let associated_data = [0u8;32];
+
+        for i in 0..4u8 {
+            // Alice's messages are delivered in reverse order, Bob's in order.
+            let mut alice_cts = Vec::new();
+            for j in 0..3u8 {
+                alice_cts.push((optimized_receive_rrc_send(&mut alice_state, &associated_data, &[i, j]), [i, j]));
+            }
+            for ((_, mut ciphertext, header), plaintext) in alice_cts.into_iter().rev() {
+                let (acc, _, decrypted_plaintext) = optimized_rrc_receive(&mut bob_state, &associated_data, &mut ciphertext, header);
+                assert!(acc);
+                assert_eq!(plaintext.to_vec(), decrypted_plaintext);
+            }
+            for j in 0..2u8 {
+                let plaintext = [i, j, 17];
+                let (_, mut ciphertext, header) = optimized_rrc_send(&mut bob_state, &associated_data, &plaintext);
+                let (acc, _, decrypted_plaintext) = optimized_receive_rrc_receive(&mut alice_state, &associated_data, &mut ciphertext, header);
+                assert!(acc);
+                assert_eq!(plaintext.to_vec(), decrypted_plaintext);
+            }
+        }
+        // The indexed sets agree with the ones of the inner state.
+        assert_eq!(alice_state.r.len(), alice_state.state.state.r.len());
+        assert_eq!(alice_state.s_ack.len(), alice_state.state.state.s_ack.len());
+        assert_eq!(alice_state.s.len(), alice_state.state.state.s.len());
+        // Only Bob's last message is not acknowledged by a later one of his.
+        assert_eq!(alice_state.r_unacked.len(), 1);
+    }
+
+    #[test]
+    fn optimized_receive_out_of_order_both_send() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_optimized_receive(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+
+        let mut ct_a1 = optimized_receive_rrc_send(&mut alice_state, &associated_data, b"a1");
+        let mut ct_a2 = optimized_receive_rrc_send(&mut alice_state, &associated_data, b"a2");
+        let mut ct_b1 = optimized_receive_rrc_send(&mut bob_state, &associated_data, b"b1");
+        assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct_a2.1, ct_a2.2).0);
+        let mut ct_b2 = optimized_receive_rrc_send(&mut bob_state, &associated_data, b"b2");
+        assert!(optimized_receive_rrc_receive(&mut alice_state, &associated_data, &mut ct_b2.1, ct_b2.2).0);
+        assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct_a1.1, ct_a1.2).0);
+        assert!(optimized_receive_rrc_receive(&mut alice_state, &associated_data, &mut ct_b1.1, ct_b1.2).0);
+
+        let mut ct_a3 = optimized_receive_rrc_send(&mut alice_state, &associated_data, b"a3");
+        let (acc, _, pt) = optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct_a3.1, ct_a3.2);
+        assert!(acc);
+        assert_eq!(pt, b"a3".to_vec());
+    }
+
+    #[test]
+    fn optimized_receive_detects_forgeries_like_optimized_receive() {
+        let associated_data = [0u8;32];
+
+        // Bob received a forgery, which Alice notices from Bob's next message.
+        for indexed in [false, true] {
+            let (alice_state, bob_state) = rrc_init_all_optimized_send(Security::RRidAndSRid);
+            let (mut alice_state, mut bob_state) = (OptimizedReceiveRrcState::new(alice_state).unwrap(), OptimizedReceiveRrcState::new(bob_state).unwrap());
+            let mut eve_state = alice_state.clone();
+            let mut forged = optimized_receive_rrc_send(&mut eve_state, &associated_data, b"fake");
+            assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut forged.1, forged.2).0);
+            let mut reply = optimized_receive_rrc_send(&mut bob_state, &associated_data, b"reply");
+            let acc = if indexed {
+                optimized_receive_rrc_receive(&mut alice_state, &associated_data, &mut reply.1, reply.2).0
+            } else {
+                optimized_rrc_receive(&mut alice_state.state, &associated_data, &mut reply.1, reply.2).0
+            };
+            assert!(!acc);
+        }
+
+        // Bob received a forgery, and notices it when Alice's real message arrives.
+        for indexed in [false, true] {
+            let (mut alice_state, bob_state) = rrc_init_all_optimized_send(Security::RRid);
+            let mut bob_state = OptimizedReceiveRrcState::new(bob_state).unwrap();
+            let mut eve_state = alice_state.clone();
+            let mut forged = optimized_rrc_send(&mut eve_state, &associated_data, b"fake");
+            let mut second = optimized_rrc_send(&mut alice_state, &associated_data, b"second");
+            let mut real = optimized_rrc_send(&mut alice_state, &associated_data, b"real");
+            let (acc, _, _) = optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut forged.1, forged.2);
+            assert!(acc);
+            let acc = if indexed {
+                optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut second.1, second.2).0
+                    || optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut real.1, real.2).0
+            } else {
+                optimized_rrc_receive(&mut bob_state.state, &associated_data, &mut second.1, second.2).0
+                    || optimized_rrc_receive(&mut bob_state.state, &associated_data, &mut real.1, real.2).0
+            };
+            assert!(!acc);
+        }
+
+        // A corrupted state sends an older message after a newer real one was received.
+        for indexed in [false, true] {
+            let (mut alice_state, bob_state) = rrc_init_all_optimized_send(Security::RRidAndSRid);
+            let mut bob_state = OptimizedReceiveRrcState::new(bob_state).unwrap();
+            let _ = optimized_rrc_send(&mut alice_state, &associated_data, b"1");
+            let mut corrupted_state = alice_state.clone();
+            let _ = optimized_rrc_send(&mut alice_state, &associated_data, b"2");
+            let mut ct3_real = optimized_rrc_send(&mut alice_state, &associated_data, b"3");
+            let mut ct2_fake = optimized_rrc_send(&mut corrupted_state, &associated_data, b"I am malicious");
+            assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct3_real.1, ct3_real.2).0);
+            let acc = if indexed {
+                optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut ct2_fake.1, ct2_fake.2).0
+            } else {
+                optimized_rrc_receive(&mut bob_state.state, &associated_data, &mut ct2_fake.1, ct2_fake.2).0
+            };
+            assert!(!acc);
+        }
+    }
+
+    #[test]
+    fn negotiation_picks_initiators_preferred_common_level() {
+        let handshake = Handshake {
+            initiator_offer: vec![Security::RRidAndSRid, Security::SRid, Security::RRid],
+            responder_offer: vec![Security::RRid, Security::SRid],
+            epoch_window: None,
+        };
+        assert_eq!(handshake.negotiated_level(), Some(Security::SRid));
+        let (mut alice_state, mut bob_state) = rrc_init_all_negotiated(&handshake, &handshake).unwrap();
+        assert_eq!(alice_state.security_level, Security::SRid);
+        assert_eq!(bob_state.security_level, Security::SRid);
+
+        let associated_data = [0u8;32];
+        let (_, mut ciphertext, header) = rrc_send(&mut alice_state, &associated_data, b"hello");
+        let (acc, _, plaintext) = rrc_receive(&mut bob_state, &associated_data, &mut ciphertext, header);
+        assert!(acc);
+        assert_eq!(plaintext, b"hello".to_vec());
+
+        let no_common_level = Handshake { initiator_offer: vec![Security::RRidAndSRid], responder_offer: vec![Security::RRid], epoch_window: None };
+        assert!(rrc_init_all_negotiated(&no_common_level, &no_common_level).is_err());
+    }
+
+    #[test]
+    fn tampered_handshake_cannot_downgrade_silently() {
+        let sent = Handshake {
+            initiator_offer: vec![Security::RRidAndSRid, Security::RRid],
+            responder_offer: vec![Security::RRidAndSRid, Security::RRid],
+            epoch_window: None,
+        };
+        // The attacker removes RRidAndSRid from the initiator's offer before it reaches the responder.
+        let tampered = Handshake { initiator_offer: vec![Security::RRid], ..sent.clone() };
+        let (mut alice_state, mut bob_state) = rrc_init_all_negotiated(&sent, &tampered).unwrap();
+        assert_eq!(alice_state.security_level, Security::RRidAndSRid);
+        assert_eq!(bob_state.security_level, Security::RRid);
+
+        // The parties do not share the same keys, so no message goes through.
+        let associated_data = [0u8;32];
+        let (_, mut ciphertext, header) = rrc_send(&mut alice_state, &associated_data, b"hello");
+        assert!(!rrc_receive(&mut bob_state, &associated_data, &mut ciphertext, header).0);
+        let (_, mut ciphertext, header) = rrc_send(&mut bob_state, &associated_data, b"hello");
+        assert!(!rrc_receive(&mut alice_state, &associated_data, &mut ciphertext, header).0);
+    }
+
+    #[test]
+    fn security_level_changes_once_acknowledged() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+
+        let (_, mut ciphertext, header) = rrc_send_with_signal(&mut alice_state, &associated_data, SecuritySignal::Propose(Security::RRid), b"switch?").unwrap();
+        assert_eq!(alice_state.security_level, Security::RRidAndSRid);
+        assert!(rrc_receive_with_signal(&mut bob_state, &associated_data, SecuritySignal::Propose(Security::RRid), &mut ciphertext, header).0);
+        assert_eq!(bob_state.security_level, Security::RRidAndSRid);
+
+        // Bob cannot acknowledge a level Alice did not propose.
+        assert!(rrc_send_with_signal(&mut bob_state, &associated_data, SecuritySignal::Acknowledge(Security::SRid), b"ok").is_err());
+        let (_, mut ciphertext, header) = rrc_send_with_signal(&mut bob_state, &associated_data, SecuritySignal::Acknowledge(Security::RRid), b"ok").unwrap();
+        assert_eq!(bob_state.security_level, Security::RRid);
+        assert!(rrc_receive_with_signal(&mut alice_state, &associated_data, SecuritySignal::Acknowledge(Security::RRid), &mut ciphertext, header).0);
+        assert_eq!(alice_state.security_level, Security::RRid);
+
+        // Both sides keep talking normally after the change.
+        let (_, mut ciphertext, header) = rrc_send(&mut alice_state, &associated_data, b"done");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut ciphertext, header).0);
+    }
+
+    #[test]
+    fn injected_security_signal_cannot_downgrade() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+
+        // The attacker relabels an ordinary message as a downgrade proposal.
+        let (_, mut ciphertext, header) = rrc_send(&mut alice_state, &associated_data, b"hello");
+        assert!(!rrc_receive_with_signal(&mut bob_state, &associated_data, SecuritySignal::Propose(Security::RRid), &mut ciphertext, header).0);
+        assert_eq!(bob_state.peer_proposed_security_level, None);
+
+        // The attacker turns Bob's proposal to stay at RRidAndSRid into a downgrade.
+        let (_, mut ciphertext, header) = rrc_send_with_signal(&mut bob_state, &associated_data, SecuritySignal::Propose(Security::RRidAndSRid), b"stay").unwrap();
+        assert!(!rrc_receive_with_signal(&mut alice_state, &associated_data, SecuritySignal::Propose(Security::RRid), &mut ciphertext, header).0);
+        assert_eq!(alice_state.peer_proposed_security_level, None);
+
+        // An acknowledgement of a downgrade nobody proposed is rejected as well, even from a corrupted state.
+        let mut eve_state = bob_state.clone();
+        eve_state.peer_proposed_security_level = Some(Security::RRid);
+        let (_, mut ciphertext, header) = rrc_send_with_signal(&mut eve_state, &associated_data, SecuritySignal::Acknowledge(Security::RRid), b"ok").unwrap();
+        let (r, nr, epoch) = (alice_state.r.clone(), alice_state.state.Nr, alice_state.state.epoch);
+        assert!(!rrc_receive_with_signal(&mut alice_state, &associated_data, SecuritySignal::Acknowledge(Security::RRid), &mut ciphertext, header).0);
+        assert_eq!(alice_state.security_level, Security::RRidAndSRid);
+        assert_eq!(bob_state.security_level, Security::RRidAndSRid);
+        // The unexpected acknowledgement is rejected before the ratchet or the sets are touched.
+        assert_eq!((alice_state.r.clone(), alice_state.state.Nr, alice_state.state.epoch), (r, nr, epoch));
+    }
+
+    #[test]
+    fn security_signal_is_carried_in_the_payload() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let limits = DecodeLimits::default();
+
+        let bytes = send_bytes_with_signal(&mut alice_state, &associated_data, SecuritySignal::Propose(Security::RRid), b"switch?").unwrap();
+        let (acc, _, pt, signal) = receive_bytes_with_signal(&bytes, &mut bob_state, &associated_data, &limits).unwrap();
+        assert!(acc);
+        assert_eq!(pt, b"switch?");
+        assert_eq!(signal, SecuritySignal::Propose(Security::RRid));
+        assert_eq!(bob_state.peer_proposed_security_level, Some(Security::RRid));
+
+        // A signal altered on the wire is caught by the associated data, and an unknown one by the decoding.
+        let bytes = send_bytes_with_signal(&mut bob_state, &associated_data, SecuritySignal::Acknowledge(Security::RRid), b"ok").unwrap();
+        let mut tampered = bytes.clone();
+        tampered[0] = 0;
+        assert!(!receive_bytes_with_signal(&tampered, &mut alice_state.clone(), &associated_data, &limits).unwrap().0);
+        let mut malformed = bytes.clone();
+        malformed[1] = 7;
+        assert!(receive_bytes_with_signal(&malformed, &mut alice_state, &associated_data, &limits).is_err());
+
+        let (acc, _, _, signal) = receive_bytes_with_signal(&bytes, &mut alice_state, &associated_data, &limits).unwrap();
+        assert!(acc);
+        assert_eq!(signal, SecuritySignal::Acknowledge(Security::RRid));
+        assert_eq!(alice_state.security_level, Security::RRid);
+    }
+
+    /// Records the events it is given and applies a fixed policy.
+    struct RecordingHandler {
+        policy: DetectionPolicy,
+        events: Vec<DetectionEvent>,
+    }
+
+    impl DetectionHandler for RecordingHandler {
+        fn on_detection(&mut self, event: &DetectionEvent) -> DetectionPolicy {
+            self.events.push(event.clone());
+            self.policy.clone()
+        }
+    }
+
+    #[test]
+    fn detection_handler_receives_forgery_event() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::RequireReverification, events: Vec::new() };
+
+        let _ = rrc_send(&mut alice_state, &associated_data, b"1");
+        let mut eve_state = alice_state.clone();
+        let _ = rrc_send(&mut alice_state, &associated_data, b"2");
+        let mut ct3_real = rrc_send(&mut alice_state, &associated_data, b"3");
+        let mut ct2_fake = rrc_send(&mut eve_state, &associated_data, b"I am malicious");
+
+        assert!(rrc_receive_with_handler(&mut bob_state, &associated_data, &mut ct3_real.1, ct3_real.2, &mut handler).0);
+        assert!(handler.events.is_empty());
+        assert!(!rrc_receive_with_handler(&mut bob_state, &associated_data, &mut ct2_fake.1, ct2_fake.2, &mut handler).0);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::RRid);
+        assert_eq!(handler.events[0].ordinal, ct2_fake.0);
+        assert_eq!(handler.events[0].evidence.peer_received_digest, ct2_fake.1.r.1.to_vec());
+
+        // Re-verification is required, but the session is not frozen.
+        let alarm = bob_state.alarm.clone().unwrap();
+        assert!(!alarm.frozen);
+        assert_eq!(alarm.event, handler.events[0]);
+        assert!(try_rrc_send(&mut bob_state, &associated_data, b"Are you really Alice?").is_ok());
+    }
+
+    #[test]
+    fn frozen_session_refuses_to_send_until_cleared() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let mut eve_state = alice_state.clone();
+        let associated_data = [0u8;32];
+
+        let mut malicious_msg = rrc_send(&mut eve_state, &associated_data, b"Wassup my dude?");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut malicious_msg.1, malicious_msg.2).0);
+        let mut reply = rrc_send(&mut bob_state, &associated_data, b"I'm fine how are you Alice?");
+        let (acc, _, _) = rrc_receive_with_handler(&mut alice_state, &associated_data, &mut reply.1, reply.2, &mut FixedPolicy(DetectionPolicy::Freeze));
+        assert!(!acc);
+        assert_eq!(alice_state.alarm.as_ref().unwrap().event.kind, DetectionKind::SRid);
+        assert!(alice_state.alarm.as_ref().unwrap().frozen);
+        assert!(try_rrc_send(&mut alice_state, &associated_data, b"Hello?").is_err());
+
+        // Another detection with a milder policy does not unfreeze the session.
+        let mut reply = rrc_send(&mut bob_state, &associated_data, b"Alice?");
+        rrc_receive_with_handler(&mut alice_state, &associated_data, &mut reply.1, reply.2, &mut FixedPolicy(DetectionPolicy::RequireReverification));
+        assert!(try_rrc_send(&mut alice_state, &associated_data, b"Hello?").is_err());
+
+        // The alarm is only cleared once the session was reset and the users compared their codes out of band.
+        assert!(clear_alarm(&mut alice_state, &reverification_code(&bob_state.hash_key)).is_err());
+        let (alice_identity, bob_identity) = (rc::generate_dh(), rc::generate_dh());
+        let (pending, request) = rrc_reset_request(&alice_state, &alice_identity, &bob_identity.public);
+        let response = rrc_reset_respond(&mut bob_state, &bob_identity, &alice_identity.public, &request).unwrap();
+        rrc_reset_finish(&mut alice_state, &pending, &alice_identity, &bob_identity.public, &response).unwrap();
+        assert!(clear_alarm(&mut alice_state, &reverification_code(&eve_state.hash_key)).is_err());
+        assert!(alice_state.alarm.is_some());
+        assert!(clear_alarm(&mut alice_state, &reverification_code(&bob_state.hash_key)).unwrap().is_some());
+        assert!(try_rrc_send(&mut alice_state, &associated_data, b"Hello?").is_ok());
+    }
+
+    #[test]
+    fn optimized_variants_freeze_through_the_handler() {
+        let associated_data = [0u8;32];
+        for indexed in [false, true] {
+            let (alice_state, bob_state) = rrc_init_all_optimized_send(Security::RRidAndSRid);
+            let (mut alice_state, mut bob_state) = (OptimizedReceiveRrcState::new(alice_state).unwrap(), OptimizedReceiveRrcState::new(bob_state).unwrap());
+            let mut eve_state = alice_state.clone();
+            let mut forged = optimized_receive_rrc_send(&mut eve_state, &associated_data, b"fake");
+            assert!(optimized_receive_rrc_receive(&mut bob_state, &associated_data, &mut forged.1, forged.2).0);
+            let mut reply = optimized_receive_rrc_send(&mut bob_state, &associated_data, b"reply");
+            let mut handler = FixedPolicy(DetectionPolicy::Freeze);
+            let acc = if indexed {
+                optimized_receive_rrc_receive_with_handler(&mut alice_state, &associated_data, &mut reply.1, reply.2, &mut handler).0
+            } else {
+                optimized_rrc_receive_with_handler(&mut alice_state.state, &associated_data, &mut reply.1, reply.2, &mut handler).0
+            };
+            assert!(!acc);
+            assert_eq!(alice_state.state.state.alarm.as_ref().unwrap().event.kind, DetectionKind::SRid);
+            assert!(try_optimized_receive_rrc_send(&mut alice_state, &associated_data, b"Hello?").is_err());
+            assert!(try_optimized_rrc_send(&mut alice_state.state, &associated_data, b"Hello?").is_err());
+        }
+    }
+
+    #[test]
+    fn dropping_policy_raises_no_alarm() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let mut eve_state = alice_state.clone();
+        let associated_data = [0u8;32];
+
+        let mut malicious_msg = rrc_send(&mut eve_state, &associated_data, b"fake");
+        rrc_receive(&mut bob_state, &associated_data, &mut malicious_msg.1, malicious_msg.2);
+        let mut legit_msg = rrc_send(&mut alice_state, &associated_data, b"real");
+        assert!(!rrc_receive(&mut bob_state, &associated_data, &mut legit_msg.1, legit_msg.2).0);
+        assert!(bob_state.alarm.is_none());
+    }
+
+    #[test]
+    fn impersonator_is_caught_by_s_rid_on_first_reply() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Drop, events: Vec::new() };
+        for i in 0..3u8 {
+            let mut ct = rrc_send(&mut alice_state, &associated_data, &[i]);
+            assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+            let mut ct = rrc_send(&mut bob_state, &associated_data, &[i]);
+            assert!(rrc_receive_with_handler(&mut alice_state, &associated_data, &mut ct.1, ct.2, &mut handler).0);
+        }
+
+        let view: RrcCompromisedView = alice_state.export_compromised_view();
+        let mut eve_state = RrcState::resume_from(&view);
+        let mut forged = rrc_send(&mut eve_state, &associated_data, b"I am Alice");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut forged.1, forged.2).0);
+
+        // The first message Bob sends after accepting the forgery gives it away.
+        let mut reply = rrc_send(&mut bob_state, &associated_data, b"Hi Alice");
+        assert!(!rrc_receive_with_handler(&mut alice_state, &associated_data, &mut reply.1, reply.2, &mut handler).0);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::SRid);
+    }
+
+    #[test]
+    fn impersonator_is_caught_by_r_rid_on_next_honest_message() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRid);
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Drop, events: Vec::new() };
+        let mut ct = rrc_send(&mut alice_state, &associated_data, b"before the exposure");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+
+        let mut eve_state = RrcState::resume_from(&alice_state.export_compromised_view());
+        // The adversary suppresses Alice's real message and delivers a forgery with the same ordinal instead.
+        let _real = rrc_send(&mut alice_state, &associated_data, b"real");
+        let mut next = rrc_send(&mut alice_state, &associated_data, b"next");
+        let mut forged = rrc_send(&mut eve_state, &associated_data, b"forged");
+        assert!(rrc_receive_with_handler(&mut bob_state, &associated_data, &mut forged.1, forged.2, &mut handler).0);
+        assert!(handler.events.is_empty());
+        // Alice's next message does not acknowledge the forged one as sent.
+        assert!(!rrc_receive_with_handler(&mut bob_state, &associated_data, &mut next.1, next.2, &mut handler).0);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::RRid);
+        assert_eq!(handler.events[0].ordinal, next.0);
+    }
+
+    #[test]
+    fn honest_traffic_is_never_flagged_after_exposure() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let mut rng = StdRng::seed_from_u64(34);
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Freeze, events: Vec::new() };
+        // Eve exposes Bob's state and eavesdrops on what Alice sends him, but forges nothing, which must not be noticed.
+        let mut eve_state = RrcState::resume_from(&bob_state.export_compromised_view());
+        let mut eve_handler = RecordingHandler { policy: DetectionPolicy::Drop, events: Vec::new() };
+        let mut eavesdropped = Vec::new();
+
+        for round in 0..20u8 {
+            // Each party sends a few messages, which are delivered in a random order.
+            for alice_sends in [true, false] {
+                let (sender, receiver) = if alice_sends { (&mut alice_state, &mut bob_state) } else { (&mut bob_state, &mut alice_state) };
+                let mut in_flight: Vec<_> = (0..rng.gen_range(1..4u8)).map(|i| (rrc_send(sender, &associated_data, &[round, i]), [round, i])).collect();
+                while !in_flight.is_empty() {
+                    let ((_, mut ct, header), plaintext) = in_flight.remove(rng.gen_range(0..in_flight.len()));
+                    if alice_sends {
+                        let (acc, _, pt) = rrc_receive_with_handler(&mut eve_state, &associated_data, &mut ct.clone(), header, &mut eve_handler);
+                        if acc {
+                            eavesdropped.push(pt);
+                        }
+                    }
+                    let (acc, _, pt) = rrc_receive_with_handler(receiver, &associated_data, &mut ct, header, &mut handler);
+                    assert!(acc);
+                    assert_eq!(pt, plaintext.to_vec());
+                }
+            }
+        }
+        // The exposed keys read the first messages, until Bob's reply moves the ratchet on.
+        assert!(eavesdropped.iter().any(|pt| pt[0] == 0));
+        assert!(eavesdropped.iter().all(|pt| pt[0] == 0));
+        assert!(handler.events.is_empty());
+        assert!(alice_state.alarm.is_none() && bob_state.alarm.is_none());
+    }
+
+    #[test]
+    fn epoch_window_keeps_sets_bounded() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_with_epoch_window(Security::RRidAndSRid, 2).unwrap();
+        let associated_data = [0u8;32];
+        let mut rng = StdRng::seed_from_u64(35);
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Freeze, events: Vec::new() };
+
+        for round in 0..50u8 {
+            for alice_sends in [true, false] {
+                let (sender, receiver) = if alice_sends { (&mut alice_state, &mut bob_state) } else { (&mut bob_state, &mut alice_state) };
+                let mut in_flight: Vec<_> = (0..3u8).map(|i| (rrc_send(sender, &associated_data, &[round, i]), [round, i])).collect();
+                while !in_flight.is_empty() {
+                    let ((_, mut ct, header), plaintext) = in_flight.remove(rng.gen_range(0..in_flight.len()));
+                    let (acc, _, pt) = rrc_receive_with_handler(receiver, &associated_data, &mut ct, header, &mut handler);
+                    assert!(acc);
+                    assert_eq!(pt, plaintext.to_vec());
+                }
+            }
+            // Each party sends 3 messages per epoch, and keeps the received messages of the last 2 + 1 epochs and the sent ones of the last 2 * 2 + 1.
+            for state in [&alice_state, &bob_state] {
+                assert!(state.r.len() <= 3 * (2 + 1));
+                assert!(state.s.len() <= 3 * (2 * 2 + 1));
+                assert!(state.s_ack.len() <= 3 * (2 * 2 + 1));
+            }
+        }
+        assert!(handler.events.is_empty());
+    }
+
+    #[test]
+    fn epoch_window_keeps_incremental_hashes_in_sync() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_optimized_send_with_epoch_window(Security::RRidAndSRid, 1).unwrap();
+        let associated_data = [0u8;32];
+
+        for round in 0..20u8 {
+            for alice_sends in [true, false] {
+                let (sender, receiver) = if alice_sends { (&mut alice_state, &mut bob_state) } else { (&mut bob_state, &mut alice_state) };
+                let mut in_flight: Vec<_> = (0..2u8).map(|i| optimized_rrc_send(sender, &associated_data, &[round, i])).collect();
+                in_flight.reverse();
+                for (_, mut ct, header) in in_flight {
+                    assert!(optimized_rrc_receive(receiver, &associated_data, &mut ct, header).0);
+                }
+            }
+            for state in [&alice_state, &bob_state] {
+                assert!(state.state.r.len() <= 2 * (1 + 1) && state.state.s.len() <= 2 * (2 + 1));
+                let nums_prime: HashSet<Ordinal> = state.state.r.iter().map(|msg| msg.ordinal).collect();
+                assert_eq!(state.nums_prime, nums_prime);
+                assert_eq!(state.hash_s.clone().finalize(), opti_get_hash_msg_set(&state.state.s));
+                assert!(incremental_hash_sets_are_equal(
+                    state.incremental_hash,
+                    incremental_hash_fct_of_whole_set(&state.state.r, &state.state.hash_key_prime),
+                    &state.state.hash_key_prime,
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn epoch_window_preserves_r_rid_detection() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_with_epoch_window(Security::RRid, 2).unwrap();
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Drop, events: Vec::new() };
+        // Talk long enough for both parties to have pruned their sets.
+        for round in 0..10u8 {
+            let mut ct = rrc_send(&mut alice_state, &associated_data, &[round]);
+            assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+            let mut ct = rrc_send(&mut bob_state, &associated_data, &[round]);
+            assert!(rrc_receive(&mut alice_state, &associated_data, &mut ct.1, ct.2).0);
+        }
+        assert!(bob_state.r.len() < 10);
+
+        let mut eve_state = RrcState::resume_from(&alice_state.export_compromised_view());
+        let _real = rrc_send(&mut alice_state, &associated_data, b"real");
+        let mut forged = rrc_send(&mut eve_state, &associated_data, b"forged");
+        assert!(rrc_receive_with_handler(&mut bob_state, &associated_data, &mut forged.1, forged.2, &mut handler).0);
+        // Bob answers, which moves both parties to the next epochs, still within the window of the forgery.
+        let mut reply = rrc_send(&mut bob_state, &associated_data, b"reply");
+        assert!(rrc_receive(&mut alice_state, &associated_data, &mut reply.1, reply.2).0);
+        let mut next = rrc_send(&mut alice_state, &associated_data, b"next");
+        assert!(!rrc_receive_with_handler(&mut bob_state, &associated_data, &mut next.1, next.2, &mut handler).0);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::RRid);
+    }
+
+    #[test]
+    fn messages_older_than_epoch_window_are_rejected() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_with_epoch_window(Security::RRidAndSRid, 1).unwrap();
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Freeze, events: Vec::new() };
+        let mut delayed = rrc_send(&mut alice_state, &associated_data, b"delayed");
+        for round in 0..3u8 {
+            let mut ct = rrc_send(&mut alice_state, &associated_data, &[round]);
+            assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+            let mut ct = rrc_send(&mut bob_state, &associated_data, &[round]);
+            assert!(rrc_receive(&mut alice_state, &associated_data, &mut ct.1, ct.2).0);
+        }
+        // The message can no longer be checked, it is rejected without raising an alarm, and before its skipped key is used.
+        let skipped = bob_state.state.MKSKIPPED.len();
+        let (acc, num, _) = rrc_receive_with_handler(&mut bob_state, &associated_data, &mut delayed.1, delayed.2, &mut handler);
+        assert!(!acc);
+        assert_eq!(num, delayed.0);
+        assert!(handler.events.is_empty() && bob_state.alarm.is_none());
+        assert_eq!(bob_state.state.MKSKIPPED.len(), skipped);
+    }
+
+    #[test]
+    fn epoch_window_is_validated_and_bound() {
+        assert!(rrc_init_all_with_epoch_window(Security::RRidAndSRid, 0).is_err());
+        assert!(rrc_init_all_optimized_send_with_epoch_window(Security::RRidAndSRid, 0).is_err());
+        let (alice_state, _) = rrc_init_all_optimized_send_with_epoch_window(Security::RRidAndSRid, 1).unwrap();
+        assert!(OptimizedReceiveRrcState::new(alice_state).is_err());
+
+        // The attacker shrinks the window seen by the responder, so that it forgets the messages sooner.
+        let sent = Handshake { initiator_offer: vec![Security::RRidAndSRid], responder_offer: vec![Security::RRidAndSRid], epoch_window: Some(4) };
+        let tampered = Handshake { epoch_window: Some(1), ..sent.clone() };
+        assert!(rrc_init_all_negotiated(&sent, &Handshake { epoch_window: Some(0), ..sent.clone() }).is_err());
+        let (mut alice_state, mut bob_state) = rrc_init_all_negotiated(&sent, &tampered).unwrap();
+        assert_eq!((alice_state.epoch_window, bob_state.epoch_window), (Some(4), Some(1)));
+        let associated_data = [0u8;32];
+        let (_, mut ciphertext, header) = rrc_send(&mut alice_state, &associated_data, b"hello");
+        assert!(!rrc_receive(&mut bob_state, &associated_data, &mut ciphertext, header).0);
+    }
+
+    #[test]
+    fn multiset_hash_of_msg_set_binds_content_to_ordinal() {
+        let mut first_set: HashSet<Message> = HashSet::new();
+        first_set.insert(Message { ordinal: Ordinal { epoch: 0, index: 1 }, content: [1; 32] });
+        first_set.insert(Message { ordinal: Ordinal { epoch: 0, index: 2 }, content: [2; 32] });
+
+        // Same ordinals and same contents, but the contents are swapped between the ordinals.
+        let mut second_set: HashSet<Message> = HashSet::new();
+        second_set.insert(Message { ordinal: Ordinal { epoch: 0, index: 1 }, content: [2; 32] });
+        second_set.insert(Message { ordinal: Ordinal { epoch: 0, index: 2 }, content: [1; 32] });
+
+        assert_ne!(opti_get_hash_msg_set(&first_set), opti_get_hash_msg_set(&second_set));
+    }
+
     #[test]
     fn incremental_ristretto_hash_works_w_finalize() {
         let mut hash1 = RistrettoHash::<Sha512>::default();
@@ -504,6 +1139,170 @@ mod tests {
 
 
 
+    #[test]
+    fn merkle_inclusion_and_exclusion_proofs_verify() {
+        for nbr_msgs in 0..12 {
+            let mut msgs: HashSet<Message> = HashSet::new();
+            for i in 0..nbr_msgs {
+                // Only even indices are present so that odd ones can be proven absent.
+                msgs.insert(Message { ordinal: Ordinal { epoch: 1, index: 2 * i }, content: [i as u8; 32] });
+            }
+            let tree = MessageMerkleTree::new(&msgs);
+            let root = tree.root();
+            assert_eq!(tree.len(), nbr_msgs);
+
+            for msg in msgs.iter() {
+                let proof = tree.prove_inclusion(msg).unwrap();
+                assert_eq!(true, verify_inclusion(&root, msg, &proof));
+                let forged = Message { ordinal: msg.ordinal, content: [255; 32] };
+                assert_eq!(false, verify_inclusion(&root, &forged, &proof));
+                assert_eq!(None, tree.prove_exclusion(msg.ordinal));
+            }
+            for i in 0..=nbr_msgs {
+                let proof = tree.prove_exclusion(Ordinal { epoch: 1, index: 2 * i + 1 }).unwrap();
+                assert_eq!(true, verify_exclusion(&root, &proof));
+            }
+            let proof = tree.prove_exclusion(Ordinal { epoch: 0, index: 0 }).unwrap();
+            assert_eq!(true, verify_exclusion(&root, &proof));
+        }
+    }
+
+    #[test]
+    fn merkle_leaves_follow_the_documented_encoding() {
+        use sha2::{Digest, Sha256};
+        let msg = Message { ordinal: Ordinal { epoch: 3, index: 7 }, content: [9; 32] };
+        let leaf: [u8; 32] = Sha256::new()
+            .chain_update([0x00])
+            .chain_update(3u64.to_be_bytes())
+            .chain_update(7u64.to_be_bytes())
+            .chain_update([9; 32])
+            .finalize()
+            .into();
+        let root: [u8; 32] = Sha256::new().chain_update([0x02]).chain_update(1u64.to_be_bytes()).chain_update(leaf).finalize().into();
+        assert_eq!(MessageMerkleTree::new(&HashSet::from([msg])).root(), root);
+    }
+
+    #[test]
+    fn multiset_elements_encode_ordinals_as_u64() {
+        let msg = Message { ordinal: Ordinal { epoch: 3, index: 7 }, content: [9; 32] };
+        let mut ordinal = [0u8; 16];
+        ordinal[0..8].copy_from_slice(&3u64.to_be_bytes());
+        ordinal[8..16].copy_from_slice(&7u64.to_be_bytes());
+        assert_eq!(ordinal_as_bytes(&msg.ordinal), ordinal);
+        assert_eq!(msg.multiset_element(), encode_tuple(&[&ordinal, &[9; 32]]));
+    }
+
+    #[test]
+    fn merkle_exclusion_proof_cannot_hide_a_message() {
+        let mut msgs: HashSet<Message> = HashSet::new();
+        for i in 0..5 {
+            msgs.insert(Message { ordinal: Ordinal { epoch: 0, index: i }, content: [i as u8; 32] });
+        }
+        let tree = MessageMerkleTree::new(&msgs);
+        // Claim that index 2 is absent by showing its non-adjacent neighbours 1 and 3.
+        let mut proof = tree.prove_exclusion(Ordinal { epoch: 0, index: 5 }).unwrap();
+        proof.ordinal = Ordinal { epoch: 0, index: 2 };
+        let one = Message { ordinal: Ordinal { epoch: 0, index: 1 }, content: [1; 32] };
+        let three = Message { ordinal: Ordinal { epoch: 0, index: 3 }, content: [3; 32] };
+        proof.predecessor = Some((one.clone(), tree.prove_inclusion(&one).unwrap()));
+        proof.successor = Some((three.clone(), tree.prove_inclusion(&three).unwrap()));
+        assert_eq!(false, verify_exclusion(&tree.root(), &proof));
+        // The proof also fails against the commitment to another set.
+        let valid_proof = tree.prove_exclusion(Ordinal { epoch: 0, index: 5 }).unwrap();
+        assert_eq!(false, verify_exclusion(&MessageMerkleTree::new(&HashSet::new()).root(), &valid_proof));
+    }
+
+    #[test]
+    fn arbiter_identifies_forged_message() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+
+        let mut ct1 = rrc_send(&mut alice_state, &associated_data, b"Hi Bob");
+        assert_eq!(true, rrc_receive(&mut bob_state, &associated_data, &mut ct1.1, ct1.2).0);
+
+        // Eve steals Alice's state and impersonates her.
+        let mut eve_state = alice_state.clone();
+        let (forged_ordinal, mut forged_ct, forged_header) = rrc_send(&mut eve_state, &associated_data, b"Send me money");
+        assert_eq!(true, rrc_receive(&mut bob_state, &associated_data, &mut forged_ct, forged_header).0);
+
+        let (alice_s, _) = merkle_commitments(&alice_state);
+        let (_, bob_r) = merkle_commitments(&bob_state);
+
+        let verdict = arbitrate(&alice_s.root(), &alice_s.prove_ordinal(forged_ordinal), &bob_r.root(), &bob_r.prove_ordinal(forged_ordinal), forged_ordinal).unwrap();
+        match verdict {
+            Verdict::Forged { received, sent } => {
+                assert_eq!(received.ordinal, forged_ordinal);
+                assert_eq!(sent, None);
+            }
+            _ => panic!("The forgery should have been identified"),
+        }
+        // The first message was delivered untouched.
+        assert_eq!(Verdict::Consistent, arbitrate(&alice_s.root(), &alice_s.prove_ordinal(ct1.0), &bob_r.root(), &bob_r.prove_ordinal(ct1.0), ct1.0).unwrap());
+
+        // Alice's own message at the same ordinal is the one the forgery replaced.
+        let (replaced_ordinal, _, _) = rrc_send(&mut alice_state, &associated_data, b"Are you there?");
+        let (alice_s, _) = merkle_commitments(&alice_state);
+        let verdict = arbitrate(&alice_s.root(), &alice_s.prove_ordinal(replaced_ordinal), &bob_r.root(), &bob_r.prove_ordinal(replaced_ordinal), replaced_ordinal).unwrap();
+        assert!(matches!(verdict, Verdict::Forged { sent: Some(_), .. }));
+
+        // A dropped message is reported as such.
+        let (dropped_ordinal, _, _) = rrc_send(&mut alice_state, &associated_data, b"Hello?");
+        let (alice_s, _) = merkle_commitments(&alice_state);
+        let verdict = arbitrate(&alice_s.root(), &alice_s.prove_ordinal(dropped_ordinal), &bob_r.root(), &bob_r.prove_ordinal(dropped_ordinal), dropped_ordinal).unwrap();
+        assert!(matches!(verdict, Verdict::Dropped { .. }));
+
+        // Proofs which do not match the commitments are rejected.
+        let proof = OrdinalProof::Absent(alice_s.prove_exclusion(Ordinal { epoch: 100, index: 0 }).unwrap());
+        assert!(arbitrate(&alice_s.root(), &proof, &bob_r.root(), &bob_r.prove_ordinal(forged_ordinal), forged_ordinal).is_err());
+    }
+
+    #[test]
+    fn arbiter_only_trusts_roots_from_authenticated_ciphertexts() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_with_merkle_roots(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+
+        let mut ct1 = rrc_send(&mut alice_state, &associated_data, b"Hi Bob");
+        assert_eq!(true, rrc_receive(&mut bob_state, &associated_data, &mut ct1.1, ct1.2).0);
+        // The second message is dropped on the way.
+        let (dropped_ordinal, _, _) = rrc_send(&mut alice_state, &associated_data, b"Hello?");
+
+        // The next ciphertext of Alice commits to her sent set, which holds the dropped message.
+        let (alice_s, _) = merkle_commitments(&alice_state);
+        let (_, mut ct3, header3) = rrc_send(&mut alice_state, &associated_data, b"Are you there?");
+        assert_eq!(ct3.merkle_roots.unwrap().sent, alice_s.root());
+
+        // Roots swapped on the way, or missing, are rejected along with the message.
+        let mut swapped = ct3.clone();
+        swapped.merkle_roots = Some(MerkleRoots { sent: [0; 32], received: [0; 32] });
+        let mut bob_copy = bob_state.clone();
+        assert_eq!(false, rrc_receive(&mut bob_copy, &associated_data, &mut swapped, header3).0);
+        assert_eq!(bob_state.peer_merkle_roots, bob_copy.peer_merkle_roots);
+        let mut stripped = ct3.clone();
+        stripped.merkle_roots = None;
+        assert_eq!(false, rrc_receive(&mut bob_state.clone(), &associated_data, &mut stripped, header3).0);
+
+        assert_eq!(true, rrc_receive(&mut bob_state, &associated_data, &mut ct3, header3).0);
+        let alice_roots = bob_state.peer_merkle_roots.unwrap();
+
+        // The reply of Bob commits to his received set, which lacks the dropped message.
+        let (_, bob_r) = merkle_commitments(&bob_state);
+        let bob_received = bob_state.r.clone();
+        let reply = send_bytes(&mut bob_state, &associated_data, b"Yes");
+        assert_eq!(true, receive_bytes(&reply, &mut alice_state, &associated_data).0);
+        let bob_roots = alice_state.peer_merkle_roots.unwrap();
+        assert_eq!(bob_roots.received, bob_r.root());
+
+        let verdict = arbitrate(&alice_roots.sent, &alice_s.prove_ordinal(dropped_ordinal), &bob_roots.received, &bob_r.prove_ordinal(dropped_ordinal), dropped_ordinal).unwrap();
+        assert!(matches!(verdict, Verdict::Dropped { .. }));
+        assert_eq!(Verdict::Consistent, arbitrate(&alice_roots.sent, &alice_s.prove_ordinal(ct1.0), &bob_roots.received, &bob_r.prove_ordinal(ct1.0), ct1.0).unwrap());
+
+        // A tree built afterwards over another set convinces an arbiter trusting its root, but not one using the authenticated root.
+        let rewritten = MessageMerkleTree::new(&bob_received.into_iter().filter(|msg| msg.ordinal != ct1.0).collect());
+        let fake_proof = rewritten.prove_ordinal(ct1.0);
+        assert!(matches!(arbitrate(&alice_roots.sent, &alice_s.prove_ordinal(ct1.0), &rewritten.root(), &fake_proof, ct1.0), Ok(Verdict::Dropped { .. })));
+        assert!(arbitrate(&alice_roots.sent, &alice_s.prove_ordinal(ct1.0), &bob_roots.received, &fake_proof, ct1.0).is_err());
+    }
+
     // The goal of this test is to have a similar benchmark to the one in "Optimal Symmetric Ratcheting for Secure Communication" p25/26
     // Since this does not actually test anything, it is not run by default: uncomment the following line to do so.
     #[allow(dead_code)]
@@ -794,5 +1593,255 @@ mod tests {
     
     }
 
-}
+    const GROUP: [u32; 5] = [1, 2, 3, 4, 5];
+
+    /// Delivers the ciphertexts of a group message to their recipients, and returns whether each accepted it.
+    fn deliver(states: &mut [GroupRrcState], ciphertexts: Vec<(u32, GroupRrcCiphertext)>, plaintext: &[u8]) -> Vec<bool> {
+        let associated_data = [0u8;32];
+        ciphertexts.into_iter().map(|(recipient, ct)| {
+            let state = states.iter_mut().find(|state| state.own_id == recipient).unwrap();
+            let (acc, _, pt) = group_rrc_receive(state, &associated_data, &ct);
+            assert!(!acc || pt == plaintext);
+            acc
+        }).collect()
+    }
+
+    /// Each member broadcasts one message, in the order of the group, and returns whether every recipient accepted every message.
+    fn group_round(states: &mut [GroupRrcState], round: u8) -> bool {
+        let associated_data = [0u8;32];
+        let mut all_accepted = true;
+        for i in 0..states.len() {
+            let plaintext = [round, i as u8];
+            let (_, ciphertexts) = group_rrc_send(&mut states[i], &associated_data, &plaintext);
+            all_accepted &= deliver(states, ciphertexts, &plaintext).into_iter().all(|acc| acc);
+        }
+        all_accepted
+    }
+
+    #[test]
+    fn group_of_five_exchanges_messages_without_detection() {
+        let mut states = group_rrc_init(&GROUP);
+        for round in 0..5 {
+            assert!(group_round(&mut states, round));
+        }
+        for state in states.iter() {
+            assert!(state.detections.is_empty());
+            assert!(state.pending_acks.is_empty());
+            assert!(state.r.values().all(|received| received.len() == 5));
+        }
+    }
+
+    #[test]
+    fn compromised_group_member_is_detected_by_every_member() {
+        let associated_data = [0u8;32];
+        let mut states = group_rrc_init(&GROUP);
+        assert!(group_round(&mut states, 0));
+
+        // The adversary exposes the state of member 1 and forges a message in its name to member 3 only.
+        let mut eve_state = states[0].clone();
+        let (_, ciphertexts) = group_rrc_send(&mut eve_state, &associated_data, b"forged");
+        let forged: Vec<(u32, GroupRrcCiphertext)> = ciphertexts.into_iter().filter(|(recipient, _)| *recipient == 3).collect();
+        assert_eq!(deliver(&mut states, forged, b"forged"), vec![true]);
+
+        for round in 1..4 {
+            group_round(&mut states, round);
+        }
+        assert!(states[0].detections.iter().any(|event| event.kind == GroupDetectionKind::SRid && event.acker == 3));
+        assert!(states[2].detections.iter().any(|event| event.kind == GroupDetectionKind::RRid && event.subject == 1));
+        for state in states.iter().skip(1) {
+            assert!(state.detections.iter().all(|event| event.subject == 1));
+            assert!(!state.detections.is_empty());
+        }
+    }
+
+    #[test]
+    fn group_message_from_a_non_member_is_rejected() {
+        let associated_data = [0u8;32];
+        let mut states = group_rrc_init(&GROUP);
+        let mut outsider = group_rrc_init(&[1, 6]).remove(1);
+        let (_, ciphertexts) = group_rrc_send(&mut outsider, &associated_data, b"let me in");
+        let (_, ct) = ciphertexts.into_iter().next().unwrap();
+        let (acc, _, _) = group_rrc_receive(&mut states[0], &associated_data, &ct);
+        assert!(!acc);
+        assert!(states[0].detections.is_empty());
+    }
+
+    #[test]
+    fn forgery_is_detected_on_the_device_pair_it_was_sent_on() {
+        let associated_data = [0u8;32];
+        let alice_phone = DeviceAddress { user: 1, device: 1 };
+        let alice_laptop = DeviceAddress { user: 1, device: 2 };
+        let mut alice_devices: Vec<SessionManager<RrcState>> = vec![session_manager_init(alice_phone, 11), session_manager_init(alice_laptop, 12)];
+        let mut bob_phone: SessionManager<RrcState> = session_manager_init(DeviceAddress { user: 2, device: 1 }, 21);
+        for alice_device in alice_devices.iter_mut() {
+            let (alice_state, bob_state) = rrc_init_all(Security::RRidAndSRid);
+            add_session(alice_device, bob_phone.own, bob_phone.registration_id, alice_state);
+            add_session(&mut bob_phone, alice_device.own, alice_device.registration_id, bob_state);
+        }
+        let msg = encrypt_to_user(&mut alice_devices[0], 2, &associated_data, b"before the exposure").remove(0);
+        assert!(decrypt_from_device(&mut bob_phone, &associated_data, msg).is_ok());
+
+        // The adversary exposes Alice's phone, suppresses its real message and delivers a forgery with the same ordinal instead.
+        let mut eve = alice_devices[0].clone();
+        let _real = encrypt_to_user(&mut alice_devices[0], 2, &associated_data, b"real");
+        let next = encrypt_to_user(&mut alice_devices[0], 2, &associated_data, b"next").remove(0);
+        let forged = encrypt_to_user(&mut eve, 2, &associated_data, b"forged").remove(0);
+        assert!(decrypt_from_device(&mut bob_phone, &associated_data, forged).is_ok());
+        assert!(decrypt_from_device(&mut bob_phone, &associated_data, next).is_err());
+
+        let alarm = bob_phone.devices[&1][&1].active.alarm.clone().unwrap();
+        assert_eq!(alarm.event.kind, DetectionKind::RRid);
+        assert!(bob_phone.devices[&1][&2].active.alarm.is_none());
+        let msg = encrypt_to_user(&mut alice_devices[1], 2, &associated_data, b"from the laptop").remove(0);
+        assert_eq!(decrypt_from_device(&mut bob_phone, &associated_data, msg).unwrap(), b"from the laptop");
+    }
 
+    #[test]
+    fn padded_payloads_hide_the_sizes_of_the_sets() {
+        let (mut alice_state, mut bob_state) = rrc_init_all_with_padding(Security::RRidAndSRid, PaddingPolicy::Fixed(4096));
+        let associated_data = [0u8;32];
+        let mut lengths = HashSet::new();
+        for i in 0..20u8 {
+            let plaintext = vec![i; 10 * i as usize];
+            let bytes = send_bytes(&mut alice_state, &associated_data, &plaintext);
+            lengths.insert(bytes.len());
+            let (acc, _, pt) = receive_bytes(&bytes, &mut bob_state, &associated_data);
+            assert!(acc);
+            assert_eq!(pt, plaintext);
+            if i % 3 == 0 {
+                let bytes = send_bytes(&mut bob_state, &associated_data, b"ack");
+                lengths.insert(bytes.len());
+                assert!(receive_bytes(&bytes, &mut alice_state, &associated_data).0);
+            }
+        }
+        assert_eq!(lengths.len(), 1);
+        assert_eq!(*lengths.iter().next().unwrap() % 4096, 0);
+
+        // The padding is checked once the message is authenticated, and leaves the state untouched if it is invalid.
+        let bytes = send_bytes(&mut alice_state, &associated_data, b"tampered");
+        let mut tampered = bytes.clone();
+        tampered.push(0);
+        let max_num = bob_state.max_num;
+        assert_eq!(try_receive_bytes(&tampered, &mut bob_state, &associated_data, &DecodeLimits::default()).err(), Some("The padding of the message is invalid."));
+        assert_eq!(bob_state.max_num, max_num);
+        assert!(receive_bytes(&bytes, &mut bob_state, &associated_data).0);
+
+        // A forgery is rejected by the authentication, whatever its padding.
+        let mut forged = send_bytes(&mut alice_state, &associated_data, b"forged");
+        let ct_start = 32 + 8 * size_of::<usize>();
+        forged[ct_start] ^= 1;
+        forged.push(0);
+        assert_eq!(try_receive_bytes(&forged, &mut bob_state, &associated_data, &DecodeLimits::default()), Ok((false, Ordinal { epoch: 0, index: 0 }, Vec::new())));
+    }
+
+    #[test]
+    fn attachment_pointer_sent_with_rrc_is_covered_by_the_detection() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let attachment = vec![42u8; 100_000];
+        let mut encrypted = Vec::new();
+        let pointer = encrypt_attachment(&mut alice_state.state, &mut std::io::Cursor::new(&attachment), &mut encrypted).unwrap();
+        let bytes = send_bytes(&mut alice_state, &associated_data, &pointer.encode());
+        let (acc, _, pt) = receive_bytes(&bytes, &mut bob_state, &associated_data);
+        assert!(acc);
+        let mut decrypted = Vec::new();
+        decrypt_attachment(&AttachmentPointer::decode(&pt).unwrap(), &mut std::io::Cursor::new(&encrypted), &mut decrypted).unwrap();
+        assert_eq!(decrypted, attachment);
+
+        // The pointer is in Bob's received set, so a different attachment forged in Alice's name is detected.
+        let mut eve_state = alice_state.clone();
+        let _real = send_bytes(&mut alice_state, &associated_data, b"real");
+        let forged_pointer = encrypt_attachment(&mut eve_state.state, &mut std::io::Cursor::new(b"forged"), &mut Vec::new()).unwrap();
+        let forged = send_bytes(&mut eve_state, &associated_data, &forged_pointer.encode());
+        assert!(receive_bytes(&forged, &mut bob_state, &associated_data).0);
+        let next = send_bytes(&mut alice_state, &associated_data, b"next");
+        assert!(!receive_bytes(&next, &mut bob_state, &associated_data).0);
+    }
+
+    /// Resets the ratchet of both parties, Alice initiating.
+    fn reset_both(alice_state: &mut RrcState, bob_state: &mut RrcState) {
+        let (alice_identity, bob_identity) = (rc::generate_dh(), rc::generate_dh());
+        let (pending, request) = rrc_reset_request(alice_state, &alice_identity, &bob_identity.public);
+        let response = rrc_reset_respond(bob_state, &bob_identity, &alice_identity.public, &request).unwrap();
+        rrc_reset_finish(alice_state, &pending, &alice_identity, &bob_identity.public, &response).unwrap();
+    }
+
+    #[test]
+    fn reset_recovers_a_desynchronised_session_without_alarm() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Freeze, events: Vec::new() };
+        let mut ct = rrc_send(&mut bob_state, &associated_data, b"before");
+        assert!(rrc_receive(&mut alice_state, &associated_data, &mut ct.1, ct.2).0);
+        // Alice runs too far ahead for Bob's ratchet to follow.
+        for _ in 0..102 {
+            rrc_send(&mut alice_state, &associated_data, b"lost");
+        }
+        let mut ct = rrc_send(&mut alice_state, &associated_data, b"too far ahead");
+        assert!(!rrc_receive_with_handler(&mut bob_state, &associated_data, &mut ct.1, ct.2, &mut handler).0);
+
+        let (s_len, r_len) = (bob_state.s.len(), bob_state.r.len());
+        let transcript = reset_transcript(&bob_state);
+        reset_both(&mut alice_state, &mut bob_state);
+        assert_eq!((bob_state.s.len(), bob_state.r.len()), (s_len, r_len));
+        assert_eq!(reset_transcript(&bob_state), transcript);
+        // The messages Bob never received are left out of his transcript, so Alice's check passes.
+        assert!(alice_state.alarm.is_none() && bob_state.alarm.is_none());
+        for i in 0..4u8 {
+            let mut ct = rrc_send(&mut alice_state, &associated_data, &[i]);
+            assert!(rrc_receive_with_handler(&mut bob_state, &associated_data, &mut ct.1, ct.2, &mut handler).0);
+            let mut ct = rrc_send(&mut bob_state, &associated_data, &[i]);
+            assert!(rrc_receive_with_handler(&mut alice_state, &associated_data, &mut ct.1, ct.2, &mut handler).0);
+        }
+        assert!(handler.events.is_empty());
+    }
+
+    #[test]
+    fn forgery_before_a_reset_is_detected_after_it() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Drop, events: Vec::new() };
+        let mut ct = rrc_send(&mut alice_state, &associated_data, b"before the exposure");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+
+        let mut eve_state = RrcState::resume_from(&alice_state.export_compromised_view());
+        let mut forged = rrc_send(&mut eve_state, &associated_data, b"I am Alice");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut forged.1, forged.2).0);
+
+        // Alice checks Bob's transcript during the reset, and a reset does not wipe the sets, so Bob's
+        // first message in the new session still gives the forgery away.
+        reset_both(&mut alice_state, &mut bob_state);
+        assert_eq!(alice_state.alarm.as_ref().map(|alarm| &alarm.event.kind), Some(&DetectionKind::SRid));
+        assert!(bob_state.alarm.is_none());
+        let mut reply = rrc_send(&mut bob_state, &associated_data, b"Hi Alice");
+        assert!(!rrc_receive_with_handler(&mut alice_state, &associated_data, &mut reply.1, reply.2, &mut handler).0);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::SRid);
+    }
+
+    #[test]
+    fn reset_responder_checks_the_transcript_of_the_initiator() {
+        let (mut alice_state, mut bob_state) = rrc_init_all(Security::RRidAndSRid);
+        let associated_data = [0u8;32];
+        let (alice_identity, bob_identity) = (rc::generate_dh(), rc::generate_dh());
+        let mut handler = RecordingHandler { policy: DetectionPolicy::Freeze, events: Vec::new() };
+        let mut ct = rrc_send(&mut alice_state, &associated_data, b"before the exposure");
+        assert!(rrc_receive(&mut bob_state, &associated_data, &mut ct.1, ct.2).0);
+        // Eve impersonates Bob to Alice, who then starts the reset.
+        let mut eve_state = RrcState::resume_from(&bob_state.export_compromised_view());
+        let mut forged = rrc_send(&mut eve_state, &associated_data, b"I am Bob");
+        assert!(rrc_receive(&mut alice_state, &associated_data, &mut forged.1, forged.2).0);
+
+        let (_, request) = rrc_reset_request(&alice_state, &alice_identity, &bob_identity.public);
+        assert!(rrc_reset_respond_with_handler(&mut bob_state, &bob_identity, &alice_identity.public, &request, &mut handler).is_ok());
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].kind, DetectionKind::SRid);
+        assert!(bob_state.alarm.as_ref().unwrap().frozen);
+        // A transcript which does not decode is rejected before the state is touched.
+        let mut malformed = request.clone();
+        malformed.transcript.truncate(16);
+        let rk = bob_state.state.RK;
+        assert!(rrc_reset_respond(&mut bob_state, &bob_identity, &alice_identity.public, &malformed).is_err());
+        assert_eq!(bob_state.state.RK, rk);
+    }
+}
@@ -0,0 +1,185 @@
+//! Compares the protocols of the project as a conversation grows.
+//!
+//! Alice and Bob take turns sending one message each. At every checkpoint, one CSV row is printed
+//! per protocol with the mean latency of send and receive and the mean number of bytes on the wire
+//! over the messages since the previous checkpoint, and the resident size of the states of both parties.
+//!
+//! Run with `cargo bench --bench comparison > comparison.csv`.
+
+use std::io::{stdout, Write};
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use get_size::GetSize;
+use rc::{Header, Ordinal, State};
+use rrc::protocol::{OptimizedSendCiphertext, OptimizedSendRrcState};
+use rrc::{encode_optimized_payload, optimized_rrc_receive, optimized_rrc_send, receive_bytes, rrc_init_all, rrc_init_all_optimized_send, send_bytes, RrcState, Security};
+use s_rid_rc::{s_rid_rc_init_with_config, s_rid_rc_receive_bytes, s_rid_rc_send_bytes, SRidConfig, SRidState};
+use unf_arc_based_on_rc::{arc_init, arc_receive, arc_send, ArcOrdinal, ArcState};
+use unf_arc_based_on_rrc::{rc_arc_init, rc_arc_receive, rc_arc_send, RrcSendCtWrapper};
+
+/// Number of rounds after which a row is printed, a round being one message from each party.
+const CHECKPOINTS: [usize; 5] = [10, 50, 100, 250, 500];
+const ASSOCIATED_DATA: [u8; 32] = [0; 32];
+const PLAINTEXT: &[u8] = b"J'ai mis cerbere en enfer.";
+/// dh_pk || prev_chain_len || msg_nbr || epoch
+const HEADER_LEN: usize = 32 + 3 * size_of::<usize>();
+
+/// A protocol under comparison, along with the states of both parties.
+struct Variant<S, P> {
+    name: String,
+    alice: S,
+    bob: S,
+    send: fn(&mut S, &[u8]) -> P,
+    receive: fn(&mut S, P) -> bool,
+    wire_len: fn(&P) -> usize,
+}
+
+/// Totals over the messages sent since the last checkpoint.
+#[derive(Default)]
+struct Totals {
+    messages: usize,
+    send: Duration,
+    receive: Duration,
+    wire_bytes: usize,
+}
+
+impl Totals {
+    fn mean_micros(&self, duration: Duration) -> f64 {
+        duration.as_secs_f64() * 1e6 / self.messages as f64
+    }
+}
+
+/// Sends one message from `sender` to `receiver` and adds its cost to the totals.
+fn exchange<S, P>(variant_send: fn(&mut S, &[u8]) -> P, variant_receive: fn(&mut S, P) -> bool, wire_len: fn(&P) -> usize, sender: &mut S, receiver: &mut S, totals: &mut Totals) {
+    let start = Instant::now();
+    let payload = variant_send(sender, PLAINTEXT);
+    totals.send += start.elapsed();
+    totals.wire_bytes += wire_len(&payload);
+    let start = Instant::now();
+    let acc = variant_receive(receiver, payload);
+    totals.receive += start.elapsed();
+    assert!(acc, "an honest message was rejected");
+    totals.messages += 1;
+}
+
+fn run<S: GetSize, P>(mut variant: Variant<S, P>, out: &mut impl Write) {
+    let mut rounds = 0;
+    for checkpoint in CHECKPOINTS {
+        let mut totals = Totals::default();
+        while rounds < checkpoint {
+            exchange(variant.send, variant.receive, variant.wire_len, &mut variant.alice, &mut variant.bob, &mut totals);
+            exchange(variant.send, variant.receive, variant.wire_len, &mut variant.bob, &mut variant.alice, &mut totals);
+            rounds += 1;
+        }
+        writeln!(
+            out,
+            "{},{},{:.2},{:.2},{},{}",
+            variant.name,
+            2 * rounds,
+            totals.mean_micros(totals.send),
+            totals.mean_micros(totals.receive),
+            totals.wire_bytes / totals.messages,
+            variant.alice.get_size() + variant.bob.get_size(),
+        )
+        .unwrap();
+    }
+}
+
+fn rc_variant() -> Variant<State, (Header, Vec<u8>)> {
+    let (alice, bob) = rc::init_all();
+    Variant {
+        name: "rc".to_string(),
+        alice,
+        bob,
+        send: |state, plaintext| {
+            let (_, header, ct) = rc::send(state, &ASSOCIATED_DATA, plaintext);
+            (header, ct)
+        },
+        receive: |state, (header, ct)| rc::receive(state, &ASSOCIATED_DATA, header, &ct).0,
+        wire_len: |(_, ct)| HEADER_LEN + ct.len(),
+    }
+}
+
+/// rc with the KEM ratchet, whose frames are part of the ciphertext.
+fn rc_kem_variant() -> Variant<State, (Header, Vec<u8>)> {
+    let (alice, bob) = rc::init_all_with_kem();
+    Variant { name: "rc with KEM ratchet".to_string(), alice, bob, ..rc_variant() }
+}
+
+fn rrc_variant(security: Security) -> Variant<RrcState, Vec<u8>> {
+    let (alice, bob) = rrc_init_all(security.clone());
+    Variant {
+        name: format!("rrc {:?}", security),
+        alice,
+        bob,
+        send: |state, plaintext| send_bytes(state, &ASSOCIATED_DATA, plaintext),
+        receive: |state, payload| receive_bytes(&payload, state, &ASSOCIATED_DATA).0,
+        wire_len: |payload| payload.len(),
+    }
+}
+
+fn optimized_rrc_variant(security: Security) -> Variant<OptimizedSendRrcState, (Ordinal, OptimizedSendCiphertext, Header)> {
+    let (alice, bob) = rrc_init_all_optimized_send(security.clone());
+    Variant {
+        name: format!("optimized rrc {:?}", security),
+        alice,
+        bob,
+        send: |state, plaintext| optimized_rrc_send(state, &ASSOCIATED_DATA, plaintext),
+        receive: |state, (_, mut ct, header)| optimized_rrc_receive(state, &ASSOCIATED_DATA, &mut ct, header).0,
+        wire_len: |(num, ct, header)| encode_optimized_payload(*num, ct, *header).len(),
+    }
+}
+
+fn s_rid_rc_variant(security: Security) -> Variant<SRidState, Vec<u8>> {
+    let config = SRidConfig { security: security.clone(), ..SRidConfig::default() };
+    let (alice, bob) = s_rid_rc_init_with_config(&config).unwrap();
+    Variant {
+        name: format!("s-rid-rc {:?}", security),
+        alice,
+        bob,
+        send: |state, plaintext| s_rid_rc_send_bytes(state, &ASSOCIATED_DATA, plaintext),
+        receive: |state, payload| s_rid_rc_receive_bytes(state, &ASSOCIATED_DATA, &payload).0,
+        wire_len: |payload| payload.len(),
+    }
+}
+
+fn arc_rc_variant() -> Variant<ArcState, (ArcOrdinal, Header, Vec<u8>)> {
+    let (alice, bob) = arc_init();
+    Variant {
+        name: "unf-arc-based-on-rc".to_string(),
+        alice,
+        bob,
+        send: |state, plaintext| arc_send(state, &ASSOCIATED_DATA, plaintext),
+        receive: |state, (_, header, ct)| arc_receive(state, &ASSOCIATED_DATA, header, ct).0,
+        wire_len: |(_, _, ct)| HEADER_LEN + ct.len(),
+    }
+}
+
+fn arc_rrc_variant() -> Variant<RrcState, RrcSendCtWrapper> {
+    let (alice, bob) = rc_arc_init();
+    Variant {
+        name: "unf-arc-based-on-rrc".to_string(),
+        alice,
+        bob,
+        send: |state, plaintext| rc_arc_send(state, &ASSOCIATED_DATA, plaintext),
+        receive: |state, mut ct| rc_arc_receive(state, &ASSOCIATED_DATA, &mut ct).0,
+        wire_len: |ct| ct.encode().len(),
+    }
+}
+
+fn main() {
+    let mut out = stdout().lock();
+    writeln!(out, "variant,messages,send_us,receive_us,wire_bytes,state_bytes_both").unwrap();
+    run(rc_variant(), &mut out);
+    run(rc_kem_variant(), &mut out);
+    for security in [Security::RRid, Security::SRid, Security::RRidAndSRid] {
+        run(rrc_variant(security), &mut out);
+    }
+    run(optimized_rrc_variant(Security::RRidAndSRid), &mut out);
+    for security in [Security::SRid, Security::RRidAndSRid] {
+        run(s_rid_rc_variant(security), &mut out);
+    }
+    run(arc_rc_variant(), &mut out);
+    run(arc_rrc_variant(), &mut out);
+}
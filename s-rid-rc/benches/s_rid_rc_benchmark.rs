@@ -0,0 +1,59 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, black_box};
+use rrc::Security;
+use s_rid_rc::{s_rid_rc_init, s_rid_rc_init_with_config, s_rid_rc_receive, s_rid_rc_send, SRidConfig, SRidState};
+
+fn init_all_benchmark(c: &mut Criterion) {
+    c.bench_function(
+        "Initialize states ",
+        |b| b.iter(|| black_box(s_rid_rc_init()))
+    );
+}
+
+// Runs a conversation of the given length, each party sending one message per round.
+fn conversation(config: &SRidConfig, conversation_length: usize) -> (SRidState, SRidState) {
+    let associated_data = [0u8; 32];
+    let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(config).unwrap();
+    for i in 0..conversation_length {
+        let plaintext = (i as u32).to_be_bytes();
+        let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &plaintext);
+        s_rid_rc_receive(&mut bob_state, &associated_data, ct);
+        let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &plaintext);
+        s_rid_rc_receive(&mut alice_state, &associated_data, ct);
+    }
+    (alice_state, bob_state)
+}
+
+// Measures sending and receiving one more message after a conversation of the given length, with
+// s-RID only and with both r-RID and s-RID.
+fn send_receive_benchmark(c: &mut Criterion) {
+    let associated_data = [0u8; 32];
+    for security in [Security::SRid, Security::RRidAndSRid] {
+        let config = SRidConfig { security: security.clone(), ..SRidConfig::default() };
+        let mut group = c.benchmark_group(format!("{:?} after conversation", security));
+        for conversation_length in [10, 100, 500] {
+            let (mut alice_state, bob_state) = conversation(&config, conversation_length);
+
+            group.bench_with_input(BenchmarkId::new("s_rid_rc_send", conversation_length), &conversation_length, |b, _| {
+                b.iter_batched(
+                    || alice_state.clone(),
+                    |mut state| black_box(s_rid_rc_send(&mut state, &associated_data, b"one more message")),
+                    BatchSize::LargeInput,
+                )
+            });
+            let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, b"one more message");
+            group.bench_with_input(BenchmarkId::new("s_rid_rc_receive", conversation_length), &conversation_length, |b, _| {
+                b.iter_batched(
+                    || (bob_state.clone(), ct.clone()),
+                    |(mut state, ct)| black_box(s_rid_rc_receive(&mut state, &associated_data, ct)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+// Lists all benchmark functions from the 'benches' group.
+criterion_group!(benches, init_all_benchmark, send_receive_benchmark);
+// Acts as a main function and runs all benchamrks in 'benches' group
+criterion_main!(benches);
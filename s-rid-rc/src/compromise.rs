@@ -0,0 +1,25 @@
+use crate::protocol::SRidState;
+
+/// What an adversary learns by exposing the state of a party.
+///
+/// An exposure reveals the whole state, including the fresh received sets and their hashes which
+/// replace the current ones when the acknowledged epoch moves. With it, the adversary can
+/// impersonate the party until its peer's next message shows, through s-RID, that it received a forgery.
+#[derive(Clone)]
+pub struct SRidCompromisedView {
+    pub state: SRidState,
+}
+
+impl SRidState {
+    /// Returns what an adversary exposing this state right now would learn, i.e. a copy of all of it.
+    pub fn export_compromised_view(&self) -> SRidCompromisedView {
+        SRidCompromisedView { state: self.clone() }
+    }
+
+    /// Returns a state continuing the session from an exposure, as the adversary would use it to impersonate the party.
+    ///
+    /// Nothing of the state is withheld from the view, so the adversary continues exactly where the party was.
+    pub fn resume_from(view: &SRidCompromisedView) -> SRidState {
+        view.state.clone()
+    }
+}
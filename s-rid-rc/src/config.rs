@@ -0,0 +1,73 @@
+use rrc::Security;
+use rrc::parsing::DecodeLimits;
+
+/// Which side of the session a party is on. It fixes the parity of the party's epochs, so both
+/// parties never use the same epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Uses the even epochs, starting at 0.
+    Initiator,
+    /// Uses the odd epochs, starting at 1.
+    Responder,
+}
+
+impl Role {
+    /// Returns the role of the peer.
+    pub fn peer(self) -> Role {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+
+    /// Returns the epoch a party with this role starts at.
+    pub fn first_epoch(self) -> usize {
+        match self {
+            Role::Initiator => 0,
+            Role::Responder => 1,
+        }
+    }
+}
+
+/// Parameters of an s-RID session, shared by both parties.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SRidConfig {
+    /// Number of epochs after which the received set is replaced by the messages received since the
+    /// last rotation. A party's epoch moves by 2, so it must be a positive even number.
+    pub rotation_window: usize,
+    /// How many epochs ahead of the receiver a message may claim to be before it is rejected. An
+    /// honest peer is at most 1 epoch ahead, so it must be at least 1.
+    pub epoch_skew: usize,
+    /// Role of the first state returned by `s_rid_rc_init_with_config`, the second state gets the other one.
+    pub alice_role: Role,
+    /// `SRid`, or `RRidAndSRid` to also detect that a party received a message its peer never sent.
+    ///
+    /// With `RRidAndSRid`, each ciphertext also carries the messages sent during the last two rotation
+    /// windows (ordinal and hash), and delayed messages older than a window are rejected.
+    pub security: Security,
+    /// Limits checked by `s_rid_rc_receive_bytes` before decoding a payload.
+    pub decode_limits: DecodeLimits,
+}
+
+impl Default for SRidConfig {
+    /// The parameters used by `s_rid_rc_init`.
+    fn default() -> Self {
+        SRidConfig { rotation_window: 4, epoch_skew: 1, alice_role: Role::Initiator, security: Security::SRid, decode_limits: DecodeLimits::default() }
+    }
+}
+
+impl SRidConfig {
+    /// Returns Err if the parameters would make honest messages fail the checks.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.rotation_window == 0 || self.rotation_window % 2 != 0 {
+            return Err("The rotation window must be a positive even number of epochs.");
+        }
+        if self.epoch_skew == 0 {
+            return Err("The epoch skew must be at least 1 epoch.");
+        }
+        if self.security == Security::RRid {
+            return Err("s-rid-rc always provides s-RID, the security level must be SRid or RRidAndSRid.");
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,10 @@
+mod compromise;
+mod config;
 mod protocol;
+mod reset;
 mod tests;
 
-pub use protocol::{s_rid_rc_init, s_rid_rc_receive, s_rid_rc_send, s_rid_rc_receive_bytes, s_rid_rc_send_bytes};
\ No newline at end of file
+pub use protocol::{s_rid_rc_init, s_rid_rc_init_with_config, s_rid_rc_receive, s_rid_rc_receive_with_handler, s_rid_rc_send, try_s_rid_rc_send, s_rid_rc_clear_alarm, s_rid_rc_reverification_code, s_rid_rc_receive_bytes, try_s_rid_rc_receive_bytes, encode_payload, decode_payload, s_rid_rc_send_bytes, try_s_rid_rc_send_bytes, SRidState, OptimizedSendCiphertext};
+pub use compromise::SRidCompromisedView;
+pub use reset::{s_rid_rc_reset_request, s_rid_rc_reset_respond, s_rid_rc_reset_respond_with_handler, s_rid_rc_reset_finish, s_rid_rc_reset_finish_with_handler, s_rid_rc_reset_transcript};
+pub use config::{Role, SRidConfig};
@@ -2,11 +2,15 @@ use std::collections::HashSet;
 
 use mset_mu_hash::RistrettoHash;
 use rc::{State, Ordinal, init_all, generate_dh, dh, send, Header, receive};
-use rrc::Message;
+use rrc::{Message, Security};
+use rrc::protocol::ordinal_as_bytes;
+use rrc::parsing::{decode_set, Reader};
+use crate::config::SRidConfig;
+use rrc::detection::{apply_policy, ensure_not_frozen, reverification_code, take_reverified_alarm, Alarm, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
 use sha2::{Sha256, Sha512, Digest};
 use std::mem::size_of;
-use bytevec::{ByteEncodable, ByteDecodable};
-use x25519_dalek::PublicKey;
+use bytevec::ByteEncodable;
+use get_size::GetSize;
 
 
 
@@ -26,7 +30,33 @@ pub struct SRidState {
     pub incremental_hash: RistrettoHash<Sha512>,
     pub fresh_incremental_hash: RistrettoHash<Sha512>,
     pub hash_ordinal_set: RistrettoHash<Sha512>,
-    pub fresh_ordinal_set_hash: RistrettoHash<Sha512>
+    pub fresh_ordinal_set_hash: RistrettoHash<Sha512>,
+    /// Alarm raised by a detected attack, see rrc's `detection` module.
+    pub alarm: Option<Alarm>,
+    pub config: SRidConfig,
+    /// Messages received during the last window, only kept for the r-RID checks of `Security::RRidAndSRid`.
+    pub r_rid_r: HashSet<Message>,
+    /// Messages the peer claimed to have sent, only kept for the r-RID checks of `Security::RRidAndSRid`.
+    pub s_ack: HashSet<Message>
+}
+
+impl GetSize for SRidState {
+    /// Counts the sets of messages and ordinals along with the multiset hashes, the config is stored inline.
+    fn get_heap_size(&self) -> usize {
+        self.state.get_heap_size()
+            + self.s.get_heap_size()
+            + self.r.get_heap_size()
+            + self.fresh_r.get_heap_size()
+            + self.nums_prime.get_heap_size()
+            + self.fresh_nums_prime.get_heap_size()
+            + self.incremental_hash.get_heap_size()
+            + self.fresh_incremental_hash.get_heap_size()
+            + self.hash_ordinal_set.get_heap_size()
+            + self.fresh_ordinal_set_hash.get_heap_size()
+            + self.alarm.get_heap_size()
+            + self.r_rid_r.get_heap_size()
+            + self.s_ack.get_heap_size()
+    }
 }
 
 #[derive(Clone)]
@@ -34,6 +64,9 @@ pub struct OptimizedSendCiphertext {
     pub ciphertext: Vec<u8>,
     pub epoch: usize,
     pub r_prime: (HashSet<Ordinal>, [u8;32]),
+    /// Messages sent during the last two windows with `Security::RRidAndSRid`, None otherwise, in which
+    /// case neither the payload nor the associated data carry them.
+    pub s: Option<HashSet<Message>>,
     pub header: Header
 }
 
@@ -41,16 +74,27 @@ pub struct OptimizedSendCiphertext {
 /// 
 /// Should be decoded and received using the s_rid_rc_receive_bytes() function 
 pub fn s_rid_rc_send_bytes(state: &mut SRidState, associated_data: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
-    let (_, opti_ct) = s_rid_rc_send(state, associated_data, plaintext);
+    try_s_rid_rc_send_bytes(state, associated_data, plaintext).expect("s_rid_rc_send_bytes called on a frozen session")
+}
+
+/// Same as s_rid_rc_send_bytes(), but returns an error instead of panicking if the session is frozen.
+pub fn try_s_rid_rc_send_bytes(state: &mut SRidState, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (_, opti_ct) = try_s_rid_rc_send(state, associated_data, plaintext)?;
+    Ok(encode_payload(&opti_ct))
+}
 
+/// Encodes the output of s_rid_rc_send() into the payload built by s_rid_rc_send_bytes().
+pub fn encode_payload(opti_ct: &OptimizedSendCiphertext) -> Vec<u8> {
     // 1. Calculate total length for buffer
     let ct_len = opti_ct.ciphertext.len();
     let r_prime_0 = opti_ct.r_prime.0.encode::<u32>().unwrap();
     let r_prime_0_len = r_prime_0.len();
+    let s = opti_ct.s.as_ref().map(|s| s.encode::<u32>().unwrap());
+    let s_len = s.as_ref().map_or(0, Vec::len);
     let header_len = 32 + 3 * size_of::<usize>();
-    let metadata_len = 3 * size_of::<usize>();
-    // header || epoch || ct_len || r_prime_0_len || ct || r_prime_0 || r_prime_1
-    let total_buf_len = header_len + metadata_len + ct_len + r_prime_0_len + 32;
+    let metadata_len = if s.is_some() { 4 } else { 3 } * size_of::<usize>();
+    // header || epoch || ct_len || r_prime_0_len || [s_len] || ct || r_prime_0 || [s] || r_prime_1, s only with `Security::RRidAndSRid`
+    let total_buf_len = header_len + metadata_len + ct_len + r_prime_0_len + s_len + 32;
     let mut bytes = vec![0u8; total_buf_len];
 
     // Header
@@ -62,36 +106,66 @@ pub fn s_rid_rc_send_bytes(state: &mut SRidState, associated_data: &[u8; 32], pl
     bytes[header_len..header_len + size_of::<usize>()].clone_from_slice(&opti_ct.epoch.to_be_bytes());
     // Metadata
     bytes[header_len + size_of::<usize>()..header_len + 2 * size_of::<usize>()].clone_from_slice(&ct_len.to_be_bytes());
-    bytes[header_len + 2 * size_of::<usize>()..header_len + metadata_len].clone_from_slice(&r_prime_0_len.to_be_bytes());
+    bytes[header_len + 2 * size_of::<usize>()..header_len + 3 * size_of::<usize>()].clone_from_slice(&r_prime_0_len.to_be_bytes());
+    if s.is_some() {
+        bytes[header_len + 3 * size_of::<usize>()..header_len + metadata_len].clone_from_slice(&s_len.to_be_bytes());
+    }
     // Ciphertext
     bytes[header_len + metadata_len..header_len + metadata_len + ct_len].clone_from_slice(&opti_ct.ciphertext);
     // R_prime
     bytes[header_len + metadata_len + ct_len..header_len + metadata_len + ct_len + r_prime_0_len].clone_from_slice(&r_prime_0);
-    bytes[header_len + metadata_len + ct_len + r_prime_0_len..total_buf_len].clone_from_slice(&opti_ct.r_prime.1);
+    // Sent window
+    if let Some(s) = &s {
+        bytes[header_len + metadata_len + ct_len + r_prime_0_len..header_len + metadata_len + ct_len + r_prime_0_len + s_len].clone_from_slice(s);
+    }
+    bytes[header_len + metadata_len + ct_len + r_prime_0_len + s_len..total_buf_len].clone_from_slice(&opti_ct.r_prime.1);
 
-    return bytes;
+    bytes
 }
 
 /// Receive wrapper which decodes a byte array into an S-RID RC package payload (ciphertext, header and metadata for forgery detection).
 /// 
-/// Should only be called after a call to s_rid_rc_send_bytes(), otherwise, there are no guarantees the decoding will succeed.
+/// Should only be called after a call to s_rid_rc_send_bytes(). Malformed payloads are rejected with acc == false, see try_s_rid_rc_receive_bytes() to get the reason.
 pub fn s_rid_rc_receive_bytes(state: &mut SRidState, associated_data: &[u8; 32], payload: &[u8]) -> (bool, Ordinal, Vec<u8>) {
-    let mut pk_bytes = [0u8; 32];
-    pk_bytes.clone_from_slice(&payload[0..32]);
-    let ct_meta_offset = 32 + 4 * size_of::<usize>();
-    let ct_len = usize::from_be_bytes(payload[ct_meta_offset..ct_meta_offset + size_of::<usize>()].try_into().unwrap());
-    let r_prime_0_len = usize::from_be_bytes(payload[ct_meta_offset + size_of::<usize>()..ct_meta_offset + 2 * size_of::<usize>()].try_into().unwrap());
-    let ct_offset = ct_meta_offset + 2 * size_of::<usize>();
-    let r_prime_0: HashSet<Ordinal> = HashSet::decode::<u32>(&payload[ct_offset + ct_len..ct_offset + ct_len + r_prime_0_len]).unwrap();
-
-    let ct = OptimizedSendCiphertext { ciphertext: payload[ct_offset..ct_offset + ct_len].to_vec(),
-        epoch: usize::from_be_bytes(payload[32 + 3 * size_of::<usize>()..32 + 4 * size_of::<usize>()].try_into().unwrap()),
-        r_prime: (r_prime_0, payload[ct_offset + ct_len + r_prime_0_len..payload.len()].try_into().unwrap()), 
-        header: Header { dh_ratchet_key: PublicKey::from(pk_bytes), prev_chain_len: usize::from_be_bytes(payload[32..32 + size_of::<usize>()].try_into().unwrap()), msg_nbr: usize::from_be_bytes(payload[32 + size_of::<usize>()..32 + 2 * size_of::<usize>()].try_into().unwrap()), epoch: usize::from_be_bytes(payload[32 + 2 * size_of::<usize>()..32 + 3 * size_of::<usize>()].try_into().unwrap()) }};
-    return s_rid_rc_receive(state, associated_data, ct);
+    try_s_rid_rc_receive_bytes(state, associated_data, payload).unwrap_or((false, Ordinal{epoch: 0, index: 0}, Vec::new()))
+}
+
+/// Same as s_rid_rc_receive_bytes(), but returns an error if the payload is malformed or exceeds the decode limits of the config. The state is left untouched in that case.
+pub fn try_s_rid_rc_receive_bytes(state: &mut SRidState, associated_data: &[u8; 32], payload: &[u8]) -> Result<(bool, Ordinal, Vec<u8>), &'static str> {
+    let ct = decode_payload(payload, &state.config)?;
+    Ok(s_rid_rc_receive(state, associated_data, ct))
+}
+
+/// Decodes a payload built by s_rid_rc_send_bytes() with the same security level, within the decode limits of the config.
+pub fn decode_payload(payload: &[u8], config: &SRidConfig) -> Result<OptimizedSendCiphertext, &'static str> {
+    let limits = &config.decode_limits;
+    let carries_s = config.security == Security::RRidAndSRid;
+    let mut reader = Reader::new(payload);
+    // header || epoch || ct_len || r_prime_0_len || [s_len] || ct || r_prime_0 || [s] || r_prime_1
+    let header = reader.read_header()?;
+    let epoch = reader.read_usize()?;
+    let ct_len = reader.read_len(limits.max_ciphertext_len)?;
+    let r_prime_0_len = reader.read_len(limits.max_set_bytes())?;
+    let s_len = if carries_s { Some(reader.read_len(limits.max_set_bytes())?) } else { None };
+    let ciphertext = reader.take(ct_len)?.to_vec();
+    let r_prime_0 = decode_set(reader.take(r_prime_0_len)?, limits.max_set_len)?;
+    let s = match s_len {
+        Some(s_len) => Some(decode_set(reader.take(s_len)?, limits.max_set_len)?),
+        None => None,
+    };
+    let r_prime_1 = reader.take_array::<32>()?;
+    reader.finish()?;
+    Ok(OptimizedSendCiphertext { ciphertext, epoch, r_prime: (r_prime_0, r_prime_1), s, header })
 }
 
+/// Initializes both parties with the default `SRidConfig`.
 pub fn s_rid_rc_init() -> (SRidState, SRidState) {
+    s_rid_rc_init_with_config(&SRidConfig::default()).expect("the default config is valid")
+}
+
+/// Initializes both parties with the given config, or returns Err if the config is invalid.
+pub fn s_rid_rc_init_with_config(config: &SRidConfig) -> Result<(SRidState, SRidState), &'static str> {
+    config.validate()?;
     // Do key exchange for both hash keys
     let alice_hash_key = generate_dh();
     let alice_hash_key_prime = generate_dh(); 
@@ -102,8 +176,8 @@ pub fn s_rid_rc_init() -> (SRidState, SRidState) {
     
     let (rc_state_alice, rc_state_bob) = init_all();
 
-    let epoch: usize = 0;
-    let acked_epoch: usize = 0;
+    let alice_epoch = config.alice_role.first_epoch();
+    let bob_epoch = config.alice_role.peer().first_epoch();
     let max_num = Ordinal{epoch: 0, index: 0};
 
     // Initialize the incremental hashes with the shared hash keys
@@ -117,60 +191,137 @@ pub fn s_rid_rc_init() -> (SRidState, SRidState) {
     let mut fresh_incremental_hash_bob = RistrettoHash::<Sha512>::default();
     fresh_incremental_hash_bob.add(hash_key_prime.to_bytes().clone(), 1);
 
-    let state_alice = SRidState{state: rc_state_alice, hash_key: hash_key.to_bytes().clone(), hash_key_prime: hash_key_prime.to_bytes().clone(), s: HashSet::<Message>::new(), r: HashSet::<Message>::new(), fresh_r: HashSet::<Message>::new(), max_num: max_num, epoch: epoch, acked_epoch: acked_epoch, nums_prime: HashSet::new(), incremental_hash: incremental_hash_alice, hash_ordinal_set: RistrettoHash::<Sha512>::default(), fresh_nums_prime: HashSet::new(), fresh_incremental_hash: fresh_incremental_hash_alice, fresh_ordinal_set_hash: RistrettoHash::<Sha512>::default()};
-    let state_bob = SRidState{state: rc_state_bob, hash_key: hash_key.to_bytes(), hash_key_prime: hash_key_prime.to_bytes(), s: HashSet::<Message>::new(), r: HashSet::<Message>::new(), fresh_r: HashSet::<Message>::new(), max_num: max_num, epoch: epoch + 1, acked_epoch: acked_epoch + 1, nums_prime: HashSet::new(),  incremental_hash: incremental_hash_bob, hash_ordinal_set: RistrettoHash::<Sha512>::default(), fresh_nums_prime: HashSet::new(), fresh_incremental_hash: fresh_incremental_hash_bob, fresh_ordinal_set_hash: RistrettoHash::<Sha512>::default()};
+    let state_alice = SRidState{state: rc_state_alice, hash_key: hash_key.to_bytes().clone(), hash_key_prime: hash_key_prime.to_bytes().clone(), s: HashSet::<Message>::new(), r: HashSet::<Message>::new(), fresh_r: HashSet::<Message>::new(), max_num: max_num, epoch: alice_epoch, acked_epoch: alice_epoch, nums_prime: HashSet::new(), incremental_hash: incremental_hash_alice, hash_ordinal_set: RistrettoHash::<Sha512>::default(), fresh_nums_prime: HashSet::new(), fresh_incremental_hash: fresh_incremental_hash_alice, fresh_ordinal_set_hash: RistrettoHash::<Sha512>::default(), alarm: None, config: config.clone(), r_rid_r: HashSet::new(), s_ack: HashSet::new()};
+    let state_bob = SRidState{state: rc_state_bob, hash_key: hash_key.to_bytes(), hash_key_prime: hash_key_prime.to_bytes(), s: HashSet::<Message>::new(), r: HashSet::<Message>::new(), fresh_r: HashSet::<Message>::new(), max_num: max_num, epoch: bob_epoch, acked_epoch: bob_epoch, nums_prime: HashSet::new(),  incremental_hash: incremental_hash_bob, hash_ordinal_set: RistrettoHash::<Sha512>::default(), fresh_nums_prime: HashSet::new(), fresh_incremental_hash: fresh_incremental_hash_bob, fresh_ordinal_set_hash: RistrettoHash::<Sha512>::default(), alarm: None, config: config.clone(), r_rid_r: HashSet::new(), s_ack: HashSet::new()};
 
-    return (state_alice, state_bob);
+    return Ok((state_alice, state_bob));
 }
 
+/// Same as try_s_rid_rc_send(), but panics if the session is frozen, for callers which cleared or never raise alarms.
 pub fn s_rid_rc_send(state: &mut SRidState, associated_data: &[u8; 32], plaintext: &[u8]) -> (Ordinal, OptimizedSendCiphertext){
+    try_s_rid_rc_send(state, associated_data, plaintext).expect("s_rid_rc_send called on a frozen session")
+}
 
+/// Encrypts a message to the peer, or returns an error if an alarm froze the session.
+pub fn try_s_rid_rc_send(state: &mut SRidState, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<(Ordinal, OptimizedSendCiphertext), &'static str> {
+    ensure_not_frozen(&state.alarm)?;
+
+    prune_sent_set(state);
     let r_prime: (HashSet<Ordinal>, [u8; 32]) = (state.nums_prime.clone(), state.incremental_hash.clone().finalize());
-    let mut ad_prime: [u8; 96] = [0; 96];
-    ad_prime[0..32].clone_from_slice(associated_data);
-    ad_prime[32..64].clone_from_slice(&state.hash_ordinal_set.clone().finalize());
-    ad_prime[64..96].clone_from_slice(&r_prime.1);
-    
+    let s_window = sent_window(state);
+    let s_window_hash = s_window.as_ref().map(|s| opti_get_hash_msg_set(s, &state.hash_key_prime));
+    let ad_prime = associated_data_prime(associated_data, &state.hash_ordinal_set.clone().finalize(), &r_prime.1, s_window_hash);
+
     let (num, header, ct_prime) = send(&mut state.state, &ad_prime, plaintext);
     let ct:(Vec<u8>, usize, (HashSet<Ordinal>, [u8; 32])) = (ct_prime, state.epoch, r_prime);
 
     let mut hasher = Sha256::new();
     hasher.update(&state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&num.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2].clone_from_slice(&num.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&num));
     hasher.update(associated_data);
     hasher.update(&ct.0);
     hasher.update(&state.epoch.to_be_bytes());
     hasher.update(&state.hash_ordinal_set.clone().finalize());
     hasher.update(&state.incremental_hash.clone().finalize());
+    if let Some(s_window_hash) = s_window_hash {
+        hasher.update(s_window_hash);
+    }
     let h: [u8;32] = hasher.finalize().try_into().unwrap();
 
     state.s.insert(Message { ordinal: num.clone(), content: h });
-    return (num, OptimizedSendCiphertext{ciphertext: ct.0, epoch: state.epoch, r_prime: ct.2, header: header});
+    return Ok((num, OptimizedSendCiphertext{ciphertext: ct.0, epoch: state.epoch, r_prime: ct.2, s: s_window, header: header}));
 
 }
 
 #[allow(non_snake_case)] // To allow ourselves to use the naming convention from the project paper's pseudocode.
-fn opti_get_hash_msg_set(R: &HashSet<Message>, hash_key_prime: &[u8; 32]) -> [u8; 32] {
+pub(crate) fn opti_get_hash_msg_set(R: &HashSet<Message>, hash_key_prime: &[u8; 32]) -> [u8; 32] {
     let mut multiset_hash = RistrettoHash::<Sha512>::default();
-    let usize_for_env = size_of::<usize>();
-    
+
     multiset_hash.add(hash_key_prime, 1);
     for message in R.iter() {
-        let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&message.ordinal.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env].clone_from_slice(&message.ordinal.index.to_be_bytes());
-        multiset_hash.add(&ordinal_as_bytes, 1);
-        multiset_hash.add(&message.content, 1);
+        multiset_hash.add(message.multiset_element(), 1);
     }
     return multiset_hash.finalize();
 }
 
+/// Returns the first epoch of the ratchet which is at most `windows` r-RID windows before `epoch`.
+///
+/// The rotation window counts the epochs of s-rid-rc, which move by 2 on each round trip, while the
+/// ratchet epochs in the ordinals move by 1, so an r-RID window is half the rotation window.
+fn window_start(state: &SRidState, epoch: usize, windows: usize) -> usize {
+    epoch.saturating_sub(windows * (state.config.rotation_window / 2))
+}
+
+/// Returns the associated data given to the ratchet, which only binds the sent window with `Security::RRidAndSRid`.
+fn associated_data_prime(associated_data: &[u8; 32], ordinal_hash: &[u8; 32], r_prime_hash: &[u8; 32], s_window_hash: Option<[u8; 32]>) -> Vec<u8> {
+    let mut ad_prime = Vec::with_capacity(128);
+    ad_prime.extend_from_slice(associated_data);
+    ad_prime.extend_from_slice(ordinal_hash);
+    ad_prime.extend_from_slice(r_prime_hash);
+    if let Some(s_window_hash) = s_window_hash {
+        ad_prime.extend_from_slice(&s_window_hash);
+    }
+    ad_prime
+}
+
+/// Returns the sent messages carried by the next ciphertext: those of the last two windows with
+/// `Security::RRidAndSRid`, as a message of the last window still refers to the window before it, and None otherwise.
+fn sent_window(state: &SRidState) -> Option<HashSet<Message>> {
+    if state.config.security != Security::RRidAndSRid {
+        return None;
+    }
+    let start = window_start(state, state.state.epoch, 2);
+    Some(state.s.iter().filter(|msg| msg.ordinal.epoch >= start).cloned().collect())
+}
+
+/// Number of r-RID windows of sent messages kept with `Security::RRidAndSRid`: the received set the peer
+/// acknowledges spans up to two rotations (4 windows), a message may be delayed by one more window before
+/// it is rejected, and the peer may be one window ahead of this party.
+const SENT_WINDOWS: usize = 6;
+
+/// Forgets the sent messages the peer can no longer acknowledge, with `Security::RRidAndSRid`.
+///
+/// Without r-RID, a delayed message is accepted at any age and may still be acknowledged, so every sent message is kept.
+fn prune_sent_set(state: &mut SRidState) {
+    if state.config.security != Security::RRidAndSRid {
+        return;
+    }
+    let start = window_start(state, state.state.epoch, SENT_WINDOWS);
+    state.s.retain(|msg| msg.ordinal.epoch >= start);
+}
+
+/// Forgets the messages which left the r-RID window.
+fn prune_r_rid_sets(state: &mut SRidState) {
+    let r_start = window_start(state, state.state.epoch, 1);
+    let s_start = window_start(state, state.state.epoch, 2);
+    state.r_rid_r.retain(|msg| msg.ordinal.epoch >= r_start);
+    state.s_ack.retain(|msg| msg.ordinal.epoch >= s_start);
+    prune_sent_set(state);
+}
+
+/// The r-RID checks of rrc, restricted to the messages both parties still keep: returns true if the
+/// receiver got a message the peer never sent, or if the peer claims to have sent messages it never got.
+fn r_rid_checks(state: &mut SRidState, peer_sent: &HashSet<Message>, h: &[u8; 32], num: Ordinal) -> bool {
+    let num_window_start = window_start(state, num.epoch, 1);
+    let ack_window_start = window_start(state, state.state.epoch, 2);
+    let in_num_window = |msg: &&Message| msg.ordinal.epoch >= num_window_start;
+
+    let mut r_bool = state.r_rid_r.iter().filter(in_num_window).any(|msg| msg.ordinal <= num && !peer_sent.contains(msg));
+    r_bool = r_bool || peer_sent.iter().any(|msg| msg.ordinal >= num);
+    if num < state.max_num {
+        r_bool = r_bool || !state.s_ack.contains(&Message { ordinal: num, content: h.to_owned() });
+        r_bool = r_bool || peer_sent.iter().filter(|msg| msg.ordinal.epoch >= ack_window_start).any(|msg| !state.s_ack.contains(msg));
+        r_bool = r_bool || state.s_ack.iter().filter(in_num_window).any(|msg| msg.ordinal < num && !peer_sent.contains(msg));
+    } else {
+        state.max_num = num;
+        r_bool = r_bool || state.s_ack.iter().filter(in_num_window).any(|msg| msg.ordinal < num && !peer_sent.contains(msg));
+    }
+    r_bool
+}
+
 pub fn checks(state: &SRidState, ct: &OptimizedSendCiphertext) -> bool {
     let mut s_bool = false;
-    if ct.epoch > state.epoch + 1 {
+    if ct.epoch > state.epoch + state.config.epoch_skew {
         s_bool = true;
     }
 
@@ -186,34 +337,59 @@ pub fn checks(state: &SRidState, ct: &OptimizedSendCiphertext) -> bool {
     return s_bool;
 }
 
+/// Drops the messages which fail the forgery detection checks, see s_rid_rc_receive_with_handler() to apply another policy.
 pub fn s_rid_rc_receive(state: &mut SRidState, associated_data: &[u8; 32], ct: OptimizedSendCiphertext) -> (bool, Ordinal, Vec<u8>) {
-    let mut associated_data_prime: [u8; 96] = [0; 96];
+    s_rid_rc_receive_with_handler(state, associated_data, ct, &mut FixedPolicy(DetectionPolicy::Drop))
+}
+
+/// Same as s_rid_rc_receive(), but lets the handler decide what happens to the session when a message fails the forgery detection checks.
+pub fn s_rid_rc_receive_with_handler(state: &mut SRidState, associated_data: &[u8; 32], ct: OptimizedSendCiphertext, handler: &mut impl DetectionHandler) -> (bool, Ordinal, Vec<u8>) {
+    // The sent window is carried with r-RID only.
+    let r_rid = state.config.security == Security::RRidAndSRid;
+    if ct.s.is_some() != r_rid {
+        return (false, Ordinal{epoch: 0, index: 0}, Vec::new());
+    }
     let ordinal_hash = get_ordinal_set_hash(&ct.r_prime.0);
-    associated_data_prime[0..32].clone_from_slice(associated_data);
-    associated_data_prime[32..64].clone_from_slice(&ordinal_hash);
-    associated_data_prime[64..96].clone_from_slice(&ct.r_prime.1);
+    let s_window_hash = ct.s.as_ref().map(|s| opti_get_hash_msg_set(s, &state.hash_key_prime));
+    let associated_data_prime = associated_data_prime(associated_data, &ordinal_hash, &ct.r_prime.1, s_window_hash);
 
+    // With r-RID, a message older than the window can no longer be checked, and is rejected before its key is used.
+    let header_num = Ordinal { epoch: ct.header.epoch, index: ct.header.msg_nbr };
+    if r_rid && header_num.epoch < window_start(state, state.state.epoch, 1) {
+        return (false, header_num, Vec::new());
+    }
     let (acc, num, pt) = receive(&mut state.state, &associated_data_prime, ct.header, &ct.ciphertext);
     if !acc {
         return (false, Ordinal{epoch: 0, index: 0}, Vec::new());
     }
     let mut hasher = Sha256::new();
     hasher.update(&state.hash_key);
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..size_of::<usize>()].clone_from_slice(&num.epoch.to_be_bytes());
-    ordinal_as_bytes[size_of::<usize>()..size_of::<usize>() * 2].clone_from_slice(&num.index.to_be_bytes());
-    hasher.update(ordinal_as_bytes);
+    hasher.update(ordinal_as_bytes(&num));
     hasher.update(associated_data);
     hasher.update(&ct.ciphertext);
     hasher.update(&ct.epoch.to_be_bytes());
     hasher.update(ordinal_hash);
     hasher.update(ct.r_prime.1);
+    if let Some(s_window_hash) = s_window_hash {
+        hasher.update(s_window_hash);
+    }
     let h: [u8;32] = hasher.finalize().try_into().unwrap();
 
-    if checks(&state, &ct) {
+    let peer_sent = ct.s.clone().unwrap_or_default();
+    let r_bool = r_rid && r_rid_checks(state, &peer_sent, &h, num);
+    let s_bool = checks(&state, &ct);
+    if r_bool || s_bool {
+        let kind = if r_bool { DetectionKind::RRid } else { DetectionKind::SRid };
+        let event = DetectionEvent { kind, ordinal: num, evidence: Evidence { message_hash: h, peer_received_digest: ct.r_prime.1.to_vec() } };
+        state.alarm = apply_policy(state.alarm.take(), event, handler);
         return (false, Ordinal{epoch: 0, index: 0}, Vec::new()); 
     }
 
+    if r_rid {
+        state.r_rid_r.insert(Message { ordinal: num, content: h });
+        state.s_ack.extend(peer_sent);
+        prune_r_rid_sets(state);
+    }
     state.r.insert(Message { ordinal: num.clone(), content: h });
     state.nums_prime.insert(num.clone());
     update_receive_hashed(state, Message { ordinal: num.clone(), content: h}, false);
@@ -228,11 +404,11 @@ pub fn s_rid_rc_receive(state: &mut SRidState, associated_data: &[u8; 32], ct: O
         state.epoch = state.epoch + 2;
     }
 
-    if state.epoch == state.acked_epoch + 4 {
+    if state.epoch == state.acked_epoch + state.config.rotation_window {
         // Update the received sets
         state.r = state.fresh_r.clone();
         state.fresh_r.clear();
-        state.acked_epoch = state.acked_epoch + 4;
+        state.acked_epoch = state.acked_epoch + state.config.rotation_window;
 
         // Update ordinals of received, and hash of ordinal set
         state.nums_prime = state.fresh_nums_prime.clone();
@@ -247,14 +423,21 @@ pub fn s_rid_rc_receive(state: &mut SRidState, associated_data: &[u8; 32], ct: O
     return (true, num, pt);
 }
 
+/// Code the users compare out of band before clearing an alarm, see rrc's `reverification_code`.
+pub fn s_rid_rc_reverification_code(state: &SRidState) -> [u8; 32] {
+    reverification_code(&state.hash_key)
+}
+
+/// Clears the alarm of a session once both parties reset it and re-verified each other out of band,
+/// see rrc's `take_reverified_alarm`. Returns the alarm which was cleared, if any.
+pub fn s_rid_rc_clear_alarm(state: &mut SRidState, peer_code: &[u8; 32]) -> Result<Option<Alarm>, &'static str> {
+    take_reverified_alarm(&mut state.alarm, state.state.reset_epoch, &state.hash_key, peer_code)
+}
+
 fn get_ordinal_set_hash(ordinal_set: &HashSet<Ordinal>) -> [u8; 32] {
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
     let mut multiset_hash = RistrettoHash::<Sha512>::default();
     for ord in ordinal_set.iter() {
-        ordinal_as_bytes[0..usize_for_env].clone_from_slice(&ord.epoch.to_be_bytes());
-        ordinal_as_bytes[usize_for_env..2 * usize_for_env].clone_from_slice(&ord.index.to_be_bytes());
-        multiset_hash.add(&ordinal_as_bytes, 1);
+        multiset_hash.add(ordinal_as_bytes(ord), 1);
     }
     
     return multiset_hash.finalize();
@@ -262,31 +445,22 @@ fn get_ordinal_set_hash(ordinal_set: &HashSet<Ordinal>) -> [u8; 32] {
 /// Updates the state incremental hashes with the given message. is_fresh_hash indicates if we wish to update the hash functions of the current
 /// received set, or if we wish to update the fresh received set.
 fn update_receive_hashed(state: &mut SRidState, msg: Message, is_fresh_hash: bool) -> () {
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..usize_for_env].clone_from_slice(&msg.ordinal.epoch.to_be_bytes());
-    ordinal_as_bytes[usize_for_env..2 * usize_for_env].clone_from_slice(&msg.ordinal.index.to_be_bytes());
     if is_fresh_hash {
-        state.fresh_incremental_hash.add(&ordinal_as_bytes, 1);
-        state.fresh_incremental_hash.add(&msg.content, 1);
+        state.fresh_incremental_hash.add(msg.multiset_element(), 1);
     }
     else {
-        state.incremental_hash.add(&ordinal_as_bytes, 1);
-        state.incremental_hash.add(&msg.content, 1);
+        state.incremental_hash.add(msg.multiset_element(), 1);
     }
 }
 
 /// Updates the state incremental hashes with the given ordinal. is_fresh_hash indicates if we wish to update the hash functions of the current
 /// received ordinal set, or if we wish to update the fresh received ordinal set.
 fn update_ordinal_set_hash(state: &mut SRidState, ordinal: Ordinal, is_fresh_hash: bool) -> () {
-    let usize_for_env = size_of::<usize>();
-    let mut ordinal_as_bytes = [0u8; 2 * size_of::<usize>()];
-    ordinal_as_bytes[0..usize_for_env].clone_from_slice(&ordinal.epoch.to_be_bytes());
-    ordinal_as_bytes[usize_for_env..2 * usize_for_env].clone_from_slice(&ordinal.index.to_be_bytes());
+    let ordinal_bytes = ordinal_as_bytes(&ordinal);
     if is_fresh_hash {
-        state.fresh_ordinal_set_hash.add(&ordinal_as_bytes, 1);
+        state.fresh_ordinal_set_hash.add(ordinal_bytes, 1);
     }
     else {
-        state.hash_ordinal_set.add(&ordinal_as_bytes, 1);
+        state.hash_ordinal_set.add(ordinal_bytes, 1);
     }
 }
\ No newline at end of file
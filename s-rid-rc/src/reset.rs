@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+
+use x25519_dalek::PublicKey;
+
+use crate::protocol::{opti_get_hash_msg_set, SRidState};
+use rc::{reset_finish, reset_request, reset_respond, DiffieHellmanParameters, Ordinal, PendingReset, ResetRequest, ResetResponse};
+use rrc::detection::{apply_policy, DetectionEvent, DetectionHandler, DetectionKind, DetectionPolicy, Evidence, FixedPolicy};
+use rrc::reset::{decode_reset_transcript, encode_reset_transcript, rebind_hash_key};
+use rrc::Message;
+
+/// Returns the transcript a party sends in a reset: the ordinals of the messages it received followed by their multiset hash.
+pub fn s_rid_rc_reset_transcript(state: &SRidState) -> Vec<u8> {
+    encode_reset_transcript(&state.r, &opti_get_hash_msg_set(&state.r, &state.hash_key_prime))
+}
+
+/// Raises an alarm if the peer received a message this party did not send at the same ordinal, dated at `epoch`, the last epoch before the reset.
+fn check_peer_transcript(state: &mut SRidState, peer_received: &(HashSet<Ordinal>, [u8; 32]), epoch: usize, handler: &mut impl DetectionHandler) {
+    let sent: HashSet<Message> = state.s.iter().filter(|msg| peer_received.0.contains(&msg.ordinal)).cloned().collect();
+    let sent_hash = opti_get_hash_msg_set(&sent, &state.hash_key_prime);
+    if sent_hash != peer_received.1 {
+        let event = DetectionEvent {
+            kind: DetectionKind::SRid,
+            ordinal: Ordinal { epoch, index: 0 },
+            evidence: Evidence { message_hash: sent_hash, peer_received_digest: peer_received.1.to_vec() },
+        };
+        state.alarm = apply_policy(state.alarm.take(), event, handler);
+    }
+}
+
+/// Starts a reset of the ratchet of a session, see rc's `reset_request`.
+///
+/// Only the ratchet is replaced: the sets, the incremental hashes and the epochs of s-rid-rc are kept.
+/// As in rrc, each party checks the transcript of its peer against the messages it sent, so a forgery
+/// accepted before the reset raises the alarm during the reset.
+pub fn s_rid_rc_reset_request(state: &SRidState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey) -> (PendingReset, ResetRequest) {
+    reset_request(&state.state, identity, peer_identity, s_rid_rc_reset_transcript(state))
+}
+
+/// Answers a reset request, switches the session to the new ratchet, and returns the response to send.
+///
+/// Raises an alarm which must be cleared after an out-of-band re-verification if the initiator received
+/// a message this party never sent, see s_rid_rc_reset_respond_with_handler() to apply another policy.
+pub fn s_rid_rc_reset_respond(state: &mut SRidState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest) -> Result<ResetResponse, &'static str> {
+    s_rid_rc_reset_respond_with_handler(state, identity, peer_identity, request, &mut FixedPolicy(DetectionPolicy::RequireReverification))
+}
+
+/// Same as s_rid_rc_reset_respond(), but lets the handler decide what happens to the session when the transcript of the initiator fails the check.
+pub fn s_rid_rc_reset_respond_with_handler(state: &mut SRidState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest, handler: &mut impl DetectionHandler) -> Result<ResetResponse, &'static str> {
+    let peer_received = decode_reset_transcript(&request.transcript)?;
+    let epoch = state.state.epoch;
+    let (new_state, response) = reset_respond(&state.state, identity, peer_identity, request, s_rid_rc_reset_transcript(state))?;
+    state.state = new_state;
+    state.hash_key = rebind_hash_key(&state.hash_key, &request.transcript, &response.transcript);
+    check_peer_transcript(state, &peer_received, epoch, handler);
+    Ok(response)
+}
+
+/// Completes a reset with the response of the peer, and switches the session to the new ratchet.
+///
+/// Raises an alarm like s_rid_rc_reset_respond() if the responder received a message this party never sent.
+pub fn s_rid_rc_reset_finish(state: &mut SRidState, pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse) -> Result<(), &'static str> {
+    s_rid_rc_reset_finish_with_handler(state, pending, identity, peer_identity, response, &mut FixedPolicy(DetectionPolicy::RequireReverification))
+}
+
+/// Same as s_rid_rc_reset_finish(), but lets the handler decide what happens to the session when the transcript of the responder fails the check.
+pub fn s_rid_rc_reset_finish_with_handler(state: &mut SRidState, pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse, handler: &mut impl DetectionHandler) -> Result<(), &'static str> {
+    let peer_received = decode_reset_transcript(&response.transcript)?;
+    let epoch = state.state.epoch;
+    state.state = reset_finish(pending, identity, peer_identity, response)?;
+    state.hash_key = rebind_hash_key(&state.hash_key, &pending.request.transcript, &response.transcript);
+    check_peer_transcript(state, &peer_received, epoch, handler);
+    Ok(())
+}
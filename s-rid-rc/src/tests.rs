@@ -1,10 +1,23 @@
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, time::SystemTime, io::Write, collections::HashSet};
+    use std::{fs::File, time::SystemTime, io::Write, collections::HashSet, mem::size_of};
     use rc::Ordinal;
+    use bytevec::ByteEncodable;
     use rrc::Message;
 
-    use crate::protocol::{s_rid_rc_init, s_rid_rc_receive, s_rid_rc_receive_bytes, s_rid_rc_send, s_rid_rc_send_bytes};
+    use rrc::detection::{DetectionKind, DetectionPolicy, FixedPolicy};
+
+    use crate::protocol::SRidState;
+    use crate::config::{Role, SRidConfig};
+    use crate::protocol::{s_rid_rc_init_with_config, OptimizedSendCiphertext};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rrc::Security;
+    use rrc::parsing::DecodeLimits;
+    use rrc::test_utils::{self, mutate_payload};
+    use crate::protocol::{decode_payload, encode_payload, try_s_rid_rc_receive_bytes};
+    use crate::reset::{s_rid_rc_reset_finish, s_rid_rc_reset_request, s_rid_rc_reset_respond};
+
+    use crate::protocol::{s_rid_rc_init, s_rid_rc_receive, s_rid_rc_receive_bytes, s_rid_rc_send, s_rid_rc_send_bytes, s_rid_rc_receive_with_handler, try_s_rid_rc_send, s_rid_rc_clear_alarm, s_rid_rc_reverification_code};
 
     #[test]
     fn test_w_rust_sets_for_fresh_r_into_r() {
@@ -89,6 +102,285 @@ mod tests {
         assert_eq!(pt, Vec::new());
     }
 
+    #[test]
+    fn frozen_session_refuses_to_send_until_cleared() {
+        let (mut alice_state, mut bob_state) = s_rid_rc_init();
+        let associated_data: [u8; 32] = [0;32];
+        let mut eve_state = alice_state.clone();
+        let (_, ct_eve) = s_rid_rc_send(&mut eve_state, &associated_data, b"Hello I am Alxce");
+        let _ = s_rid_rc_send(&mut alice_state, &associated_data, b"Hello I am Alice");
+        assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct_eve).0);
+
+        let (_, ct2) = s_rid_rc_send(&mut bob_state, &associated_data, b"Hello Alxce");
+        let (acc, _, _) = s_rid_rc_receive_with_handler(&mut alice_state, &associated_data, ct2, &mut FixedPolicy(DetectionPolicy::Freeze));
+        assert!(!acc);
+        let alarm = alice_state.alarm.clone().unwrap();
+        assert!(alarm.frozen);
+        assert_eq!(alarm.event.kind, DetectionKind::SRid);
+        assert!(try_s_rid_rc_send(&mut alice_state, &associated_data, b"Bob, are you there?").is_err());
+
+        // After resetting the session and comparing the codes with Bob out of band, Alice can talk again.
+        assert!(s_rid_rc_clear_alarm(&mut alice_state, &s_rid_rc_reverification_code(&bob_state)).is_err());
+        let (alice_identity, bob_identity) = (rc::generate_dh(), rc::generate_dh());
+        let (pending, request) = s_rid_rc_reset_request(&alice_state, &alice_identity, &bob_identity.public);
+        let response = s_rid_rc_reset_respond(&mut bob_state, &bob_identity, &alice_identity.public, &request).unwrap();
+        s_rid_rc_reset_finish(&mut alice_state, &pending, &alice_identity, &bob_identity.public, &response).unwrap();
+        assert!(s_rid_rc_clear_alarm(&mut alice_state, &s_rid_rc_reverification_code(&eve_state)).is_err());
+        assert!(alice_state.alarm.is_some());
+        assert!(s_rid_rc_clear_alarm(&mut alice_state, &s_rid_rc_reverification_code(&bob_state)).unwrap().is_some());
+        assert!(try_s_rid_rc_send(&mut alice_state, &associated_data, b"Bob, are you there?").is_ok());
+    }
+
+    #[test]
+    fn impersonator_is_caught_by_s_rid_across_epochs() {
+        let associated_data: [u8; 32] = [0;32];
+        // Expose Alice after a varying number of round trips, so the forgery lands in different epochs.
+        for exposure_round in 0..6 {
+            let (mut alice_state, mut bob_state) = s_rid_rc_init();
+            for i in 0..exposure_round {
+                let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[i]);
+                assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+                let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[i]);
+                assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+            }
+            let mut eve_state = SRidState::resume_from(&alice_state.export_compromised_view());
+            let (_, forged) = s_rid_rc_send(&mut eve_state, &associated_data, b"I am Alice");
+            assert!(s_rid_rc_receive(&mut bob_state, &associated_data, forged).0);
+
+            // Bob's first message after the forgery gives it away.
+            let (_, reply) = s_rid_rc_send(&mut bob_state, &associated_data, b"Hi Alice");
+            let (acc, _, _) = s_rid_rc_receive_with_handler(&mut alice_state, &associated_data, reply, &mut FixedPolicy(DetectionPolicy::RequireReverification));
+            assert!(!acc);
+            assert_eq!(alice_state.alarm.as_ref().unwrap().event.kind, DetectionKind::SRid);
+        }
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected_at_init() {
+        for rotation_window in [0, 3, 7] {
+            let config = SRidConfig { rotation_window, ..SRidConfig::default() };
+            assert!(s_rid_rc_init_with_config(&config).is_err());
+        }
+        let config = SRidConfig { epoch_skew: 0, ..SRidConfig::default() };
+        assert!(s_rid_rc_init_with_config(&config).is_err());
+        assert!(s_rid_rc_init_with_config(&SRidConfig { rotation_window: 8, epoch_skew: 3, alice_role: Role::Responder, ..SRidConfig::default() }).is_ok());
+    }
+
+    #[test]
+    fn roles_set_the_epoch_parity() {
+        let (alice_state, bob_state) = s_rid_rc_init();
+        assert_eq!((alice_state.epoch, bob_state.epoch), (0, 1));
+
+        let config = SRidConfig { alice_role: Role::Responder, ..SRidConfig::default() };
+        let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+        assert_eq!((alice_state.epoch, bob_state.epoch), (1, 0));
+        let associated_data: [u8; 32] = [0;32];
+        for i in 0..10u8 {
+            let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[i]);
+            assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+            let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[i]);
+            assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+        }
+        assert_eq!(alice_state.epoch % 2, 1);
+        assert_eq!(bob_state.epoch % 2, 0);
+    }
+
+    #[test]
+    fn larger_windows_tolerate_heavy_reordering() {
+        let associated_data: [u8; 32] = [0;32];
+        for rotation_window in [4, 8, 16] {
+            let config = SRidConfig { rotation_window, ..SRidConfig::default() };
+            let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+            let mut rng = StdRng::seed_from_u64(rotation_window as u64);
+            let mut policy = FixedPolicy(DetectionPolicy::Freeze);
+            // Messages in flight towards Bob and towards Alice. Each round, a party sends a few messages and its peer
+            // receives a random part of those in flight, in a random order, so some of them are delivered rounds later.
+            let mut to_bob: Vec<(OptimizedSendCiphertext, Vec<u8>)> = Vec::new();
+            let mut to_alice: Vec<(OptimizedSendCiphertext, Vec<u8>)> = Vec::new();
+            for round in 0..rotation_window as u8 + 6 {
+                for alice_sends in [true, false] {
+                    let (sender, receiver, in_flight) = if alice_sends { (&mut alice_state, &mut bob_state, &mut to_bob) } else { (&mut bob_state, &mut alice_state, &mut to_alice) };
+                    for i in 0..3u8 {
+                        let plaintext = vec![round, i];
+                        in_flight.push((s_rid_rc_send(sender, &associated_data, &plaintext).1, plaintext));
+                    }
+                    for _ in 0..rng.gen_range(1..=in_flight.len()) {
+                        let (ct, plaintext) = in_flight.remove(rng.gen_range(0..in_flight.len()));
+                        let (acc, _, pt) = s_rid_rc_receive_with_handler(receiver, &associated_data, ct, &mut policy);
+                        assert!(acc);
+                        assert_eq!(pt, plaintext);
+                    }
+                }
+            }
+            for (ct, plaintext) in to_bob {
+                let (acc, _, pt) = s_rid_rc_receive_with_handler(&mut bob_state, &associated_data, ct, &mut policy);
+                assert!(acc);
+                assert_eq!(pt, plaintext);
+            }
+            for (ct, plaintext) in to_alice {
+                let (acc, _, pt) = s_rid_rc_receive_with_handler(&mut alice_state, &associated_data, ct, &mut policy);
+                assert!(acc);
+                assert_eq!(pt, plaintext);
+            }
+            assert!(alice_state.alarm.is_none() && bob_state.alarm.is_none());
+            // The received sets were rotated along the way.
+            assert!(alice_state.acked_epoch >= rotation_window && bob_state.acked_epoch >= rotation_window);
+        }
+    }
+
+    #[test]
+    fn larger_windows_still_detect_impersonation() {
+        let associated_data: [u8; 32] = [0;32];
+        for rotation_window in [8, 16] {
+            let config = SRidConfig { rotation_window, ..SRidConfig::default() };
+            for exposure_round in [0u8, 5, 11] {
+                let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+                for i in 0..exposure_round {
+                    let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[i]);
+                    assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+                    let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[i]);
+                    assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+                }
+                let mut eve_state = SRidState::resume_from(&alice_state.export_compromised_view());
+                let (_, forged) = s_rid_rc_send(&mut eve_state, &associated_data, b"I am Alice");
+                assert!(s_rid_rc_receive(&mut bob_state, &associated_data, forged).0);
+                let (_, reply) = s_rid_rc_send(&mut bob_state, &associated_data, b"Hi Alice");
+                assert!(!s_rid_rc_receive(&mut alice_state, &associated_data, reply).0);
+            }
+        }
+    }
+
+    #[test]
+    fn combined_mode_detects_received_forgery() {
+        let associated_data: [u8; 32] = [0;32];
+        for security in [Security::SRid, Security::RRidAndSRid] {
+            let config = SRidConfig { security: security.clone(), ..SRidConfig::default() };
+            let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+            for i in 0..3u8 {
+                let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[i]);
+                assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+                let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[i]);
+                assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+            }
+            // The adversary suppresses Alice's real message and delivers a forgery with the same ordinal instead.
+            let mut eve_state = SRidState::resume_from(&alice_state.export_compromised_view());
+            let _real = s_rid_rc_send(&mut alice_state, &associated_data, b"real");
+            let (_, next) = s_rid_rc_send(&mut alice_state, &associated_data, b"next");
+            let (_, forged) = s_rid_rc_send(&mut eve_state, &associated_data, b"forged");
+            assert!(s_rid_rc_receive(&mut bob_state, &associated_data, forged).0);
+
+            // Only r-RID lets Bob notice, from Alice's next message, that she never sent what he received.
+            let (acc, _, _) = s_rid_rc_receive_with_handler(&mut bob_state, &associated_data, next, &mut FixedPolicy(DetectionPolicy::RequireReverification));
+            if security == Security::RRidAndSRid {
+                assert!(!acc);
+                assert_eq!(bob_state.alarm.as_ref().unwrap().event.kind, DetectionKind::RRid);
+            } else {
+                assert!(acc);
+                assert!(bob_state.alarm.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn combined_mode_detects_sent_forgery() {
+        let config = SRidConfig { security: Security::RRidAndSRid, ..SRidConfig::default() };
+        let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+        let associated_data: [u8; 32] = [0;32];
+        let mut eve_state = SRidState::resume_from(&alice_state.export_compromised_view());
+        let (_, forged) = s_rid_rc_send(&mut eve_state, &associated_data, b"I am Alice");
+        assert!(s_rid_rc_receive(&mut bob_state, &associated_data, forged).0);
+        let (_, reply) = s_rid_rc_send(&mut bob_state, &associated_data, b"Hi Alice");
+        let (acc, _, _) = s_rid_rc_receive_with_handler(&mut alice_state, &associated_data, reply, &mut FixedPolicy(DetectionPolicy::Freeze));
+        assert!(!acc);
+        assert_eq!(alice_state.alarm.as_ref().unwrap().event.kind, DetectionKind::SRid);
+    }
+
+    #[test]
+    fn combined_mode_keeps_ciphertexts_bounded_under_reordering() {
+        let config = SRidConfig { rotation_window: 4, security: Security::RRidAndSRid, ..SRidConfig::default() };
+        let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+        let associated_data: [u8; 32] = [0;32];
+        let mut rng = StdRng::seed_from_u64(37);
+        let mut policy = FixedPolicy(DetectionPolicy::Freeze);
+        let mut to_bob: Vec<(OptimizedSendCiphertext, Vec<u8>)> = Vec::new();
+        let mut to_alice: Vec<(OptimizedSendCiphertext, Vec<u8>)> = Vec::new();
+        let mut carried = 0;
+        for round in 0..30u8 {
+            for alice_sends in [true, false] {
+                let (sender, receiver, in_flight) = if alice_sends { (&mut alice_state, &mut bob_state, &mut to_bob) } else { (&mut bob_state, &mut alice_state, &mut to_alice) };
+                let delayed = in_flight.len();
+                for i in 0..3u8 {
+                    let plaintext = vec![round, i];
+                    let (_, ct) = s_rid_rc_send(sender, &associated_data, &plaintext);
+                    // The ciphertext only carries the messages of the last 2 * 2 ratchet epochs, out of all those sent so far.
+                    let sent_window = ct.s.as_ref().unwrap();
+                    assert!(sent_window.iter().all(|msg| msg.ordinal.epoch + 2 * 2 >= sender.state.epoch));
+                    carried = sent_window.len();
+                    in_flight.push((ct, plaintext));
+                }
+                // The messages delayed from the previous round and some of the new ones are delivered, in a random order.
+                let mut delivered: Vec<_> = in_flight.drain(..delayed).collect();
+                for _ in 0..rng.gen_range(0..=3) {
+                    delivered.push(in_flight.remove(rng.gen_range(0..in_flight.len())));
+                }
+                while !delivered.is_empty() {
+                    let (ct, plaintext) = delivered.remove(rng.gen_range(0..delivered.len()));
+                    let (acc, _, pt) = s_rid_rc_receive_with_handler(receiver, &associated_data, ct, &mut policy);
+                    assert!(acc);
+                    assert_eq!(pt, plaintext);
+                }
+                assert!(receiver.r_rid_r.iter().chain(receiver.s_ack.iter()).all(|msg| msg.ordinal.epoch + 2 * 2 >= receiver.state.epoch));
+            }
+        }
+        assert!(alice_state.alarm.is_none() && bob_state.alarm.is_none());
+        assert!(2 * carried < bob_state.s.len());
+        // The sent set itself only keeps the messages of the last 6 * 2 ratchet epochs.
+        assert!(bob_state.s.iter().all(|msg| msg.ordinal.epoch + 6 * 2 >= bob_state.state.epoch));
+    }
+
+    #[test]
+    fn combined_mode_rejects_messages_older_than_the_window_before_decrypting_them() {
+        let config = SRidConfig { rotation_window: 4, security: Security::RRidAndSRid, ..SRidConfig::default() };
+        let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+        let associated_data: [u8; 32] = [0;32];
+        let (_, _) = s_rid_rc_send(&mut alice_state, &associated_data, b"first");
+        let (_, delayed) = s_rid_rc_send(&mut alice_state, &associated_data, b"delayed");
+        for round in 0..4u8 {
+            let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[round]);
+            assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+            let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[round]);
+            assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+        }
+        assert!(delayed.header.epoch + 2 < bob_state.state.epoch);
+
+        // The skipped key of the delayed message is left untouched, so the ratchet state does not change.
+        let skipped = bob_state.state.MKSKIPPED.len();
+        let (acc, num, _) = s_rid_rc_receive(&mut bob_state, &associated_data, delayed.clone());
+        assert!(!acc);
+        assert_eq!(num, Ordinal { epoch: delayed.header.epoch, index: delayed.header.msg_nbr });
+        assert_eq!(bob_state.state.MKSKIPPED.len(), skipped);
+    }
+
+    #[test]
+    fn s_rid_mode_does_not_carry_the_sent_window() {
+        let (mut alice_state, mut bob_state) = s_rid_rc_init();
+        let associated_data = [0u8; 32];
+        let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, b"hello");
+        assert!(ct.s.is_none());
+        let payload = encode_payload(&ct);
+        // header || epoch || ct_len || r_prime_0_len || ct || r_prime_0 || r_prime_1, without s_len and s.
+        assert_eq!(payload.len(), 32 + 6 * size_of::<usize>() + ct.ciphertext.len() + ct.r_prime.0.encode::<u32>().unwrap().len() + 32);
+        let combined = SRidConfig { security: Security::RRidAndSRid, ..SRidConfig::default() };
+        assert!(decode_payload(&payload, &combined).is_err());
+
+        // A ciphertext carrying a sent window is rejected by a party which does not expect one.
+        let mut with_window = ct.clone();
+        with_window.s = Some(HashSet::new());
+        assert!(!s_rid_rc_receive(&mut bob_state, &associated_data, with_window).0);
+        let decoded = decode_payload(&payload, &bob_state.config).unwrap();
+        assert!(s_rid_rc_receive(&mut bob_state, &associated_data, decoded).0);
+    }
+
     #[test]
     fn send_and_receive_bytes_works() {
         let (mut alice_state, mut bob_state) = s_rid_rc_init();
@@ -100,6 +392,71 @@ mod tests {
         assert_eq!(plaintext.to_vec(), received_plaintext);
     }
 
+    #[test]
+    fn send_and_receive_bytes_works_in_combined_mode() {
+        let config = SRidConfig { security: Security::RRidAndSRid, ..SRidConfig::default() };
+        let (mut alice_state, mut bob_state) = s_rid_rc_init_with_config(&config).unwrap();
+        let associated_data = [34u8; 32];
+        for i in 0..4u8 {
+            let bytes = s_rid_rc_send_bytes(&mut alice_state, &associated_data, &[i]);
+            let (acc, _, received_plaintext) = s_rid_rc_receive_bytes(&mut bob_state, &associated_data, &bytes);
+            assert!(acc);
+            assert_eq!(received_plaintext, vec![i]);
+        }
+        assert_eq!(bob_state.s_ack.len(), 3);
+    }
+
+    /// Returns payloads from a conversation in the combined mode, so their sets are not empty, and the state of the party receiving the last one.
+    fn conversation_payloads() -> (Vec<Vec<u8>>, SRidState) {
+        let config = SRidConfig { security: Security::RRidAndSRid, ..SRidConfig::default() };
+        let associated_data = [0u8; 32];
+        test_utils::conversation_payloads(
+            s_rid_rc_init_with_config(&config).unwrap(),
+            |state, plaintext| s_rid_rc_send_bytes(state, &associated_data, plaintext),
+            |state, bytes| s_rid_rc_receive_bytes(state, &associated_data, bytes).0,
+        )
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected_with_an_error() {
+        let (payloads, mut bob_state) = conversation_payloads();
+        let config = bob_state.config.clone();
+        let payload = payloads.last().unwrap();
+        assert!(decode_payload(payload, &config).is_ok());
+        for len in [0, 31, 32 + 4 * size_of::<usize>(), payload.len() - 1] {
+            assert_eq!(decode_payload(&payload[..len], &config).err(), Some("The payload is truncated."));
+        }
+        let mut trailing = payload.clone();
+        trailing.push(0);
+        assert_eq!(decode_payload(&trailing, &config).err(), Some("The payload has trailing bytes."));
+        let small_limits = SRidConfig { decode_limits: DecodeLimits { max_ciphertext_len: 8, ..DecodeLimits::default() }, ..config.clone() };
+        assert_eq!(decode_payload(payload, &small_limits).err(), Some("A declared length exceeds the limit."));
+
+        // The limits come from the config of the receiver.
+        bob_state.config.decode_limits = DecodeLimits { max_set_len: 1, ..DecodeLimits::default() };
+        assert_eq!(try_s_rid_rc_receive_bytes(&mut bob_state, &[0u8; 32], payload).err(), Some("A declared length exceeds the limit."));
+        assert!(!s_rid_rc_receive_bytes(&mut bob_state, &[0u8; 32], payload).0);
+        // A rejected payload leaves the state untouched, so the payload is accepted once the limits allow it.
+        bob_state.config = config;
+        assert!(try_s_rid_rc_receive_bytes(&mut bob_state, &[0u8; 32], payload).unwrap().0);
+    }
+
+    /// Feeds payloads mutated from a fixed seed to s_rid_rc_receive_bytes(): none may panic the receiver.
+    #[test]
+    fn mutated_payloads_never_panic_the_receiver() {
+        let (payloads, bob_state) = conversation_payloads();
+        let mut rng = StdRng::seed_from_u64(38);
+        for i in 0..3000 {
+            let mutated = mutate_payload(&payloads[rng.gen_range(0..payloads.len())], &mut rng);
+            let decoded = decode_payload(&mutated, &bob_state.config);
+            // Decrypting is slower than decoding, only a part of the payloads go through the whole receive.
+            if i % 10 == 0 || decoded.is_ok() {
+                let (acc, _, pt) = s_rid_rc_receive_bytes(&mut bob_state.clone(), &[0u8; 32], &mutated);
+                assert!(acc || pt.is_empty());
+            }
+        }
+    }
+
     #[allow(dead_code)]
     //#[test]
     fn send_and_receive_bytes_alternate_for_benchmark() {
@@ -171,4 +528,31 @@ mod tests {
                 }
             }
     }
+
+    #[test]
+    fn reset_raises_the_alarm_only_for_a_forgery() {
+        let associated_data: [u8; 32] = [0;32];
+        let (alice_identity, bob_identity) = (rc::generate_dh(), rc::generate_dh());
+        let (mut alice_state, mut bob_state) = s_rid_rc_init();
+        for i in 0..2 {
+            let (_, ct) = s_rid_rc_send(&mut alice_state, &associated_data, &[i]);
+            assert!(s_rid_rc_receive(&mut bob_state, &associated_data, ct).0);
+            let (_, ct) = s_rid_rc_send(&mut bob_state, &associated_data, &[i]);
+            assert!(s_rid_rc_receive(&mut alice_state, &associated_data, ct).0);
+        }
+        let (mut honest_alice_state, mut honest_bob_state) = (alice_state.clone(), bob_state.clone());
+        // A message lost before the reset is not in Bob's transcript, so it is not mistaken for a forgery.
+        s_rid_rc_send(&mut honest_alice_state, &associated_data, b"lost");
+        let mut eve_state = SRidState::resume_from(&alice_state.export_compromised_view());
+        let (_, forged) = s_rid_rc_send(&mut eve_state, &associated_data, b"I am Alice");
+        assert!(s_rid_rc_receive(&mut bob_state, &associated_data, forged).0);
+
+        for (alice_state, bob_state, forged) in [(&mut honest_alice_state, &mut honest_bob_state, false), (&mut alice_state, &mut bob_state, true)] {
+            let (pending, request) = s_rid_rc_reset_request(alice_state, &alice_identity, &bob_identity.public);
+            let response = s_rid_rc_reset_respond(bob_state, &bob_identity, &alice_identity.public, &request).unwrap();
+            s_rid_rc_reset_finish(alice_state, &pending, &alice_identity, &bob_identity.public, &response).unwrap();
+            assert_eq!(alice_state.alarm.as_ref().map(|alarm| alarm.event.kind.clone()), forged.then_some(DetectionKind::SRid));
+            assert!(bob_state.alarm.is_none());
+        }
+    }
 }
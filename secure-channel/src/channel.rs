@@ -0,0 +1,50 @@
+use rrc::DecodeLimits;
+
+/// Common API of the protocol variants of the project, so that an application, a test or a
+/// benchmark can switch protocols with a type parameter.
+///
+/// The type implementing the trait is the state of one party. The associated data is always 32
+/// bytes, as required by the variants built on rrc.
+pub trait SecureChannel: Sized {
+    /// Parameters both parties are initialized with.
+    type Config: Default;
+    /// What send() returns and receive() takes, before it is encoded into bytes.
+    type Ciphertext;
+
+    /// Initializes the states of both parties, or returns Err if the config is not supported by the variant.
+    fn init(config: &Self::Config) -> Result<(Self, Self), &'static str>;
+
+    /// Returns an error if the party refuses to send, e.g. because an alarm froze its session.
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str>;
+
+    /// Returns the plaintext with acc == true, or acc == false if the message was rejected.
+    fn receive(&mut self, associated_data: &[u8; 32], ct: Self::Ciphertext) -> (bool, Vec<u8>);
+
+    fn encode(ct: &Self::Ciphertext) -> Vec<u8>;
+
+    /// Decodes a payload built by encode() for this party, or returns an error if it is malformed or exceeds
+    /// the decode_limits() of this party. The layout of the payload may depend on the config of the party.
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str>;
+
+    /// Limits decode() checks payloads against.
+    fn decode_limits(&self) -> DecodeLimits {
+        DecodeLimits::default()
+    }
+
+    /// Initializes the states of both parties with the default config.
+    fn init_default() -> (Self, Self) {
+        Self::init(&Self::Config::default()).expect("The default config is supported by every variant.")
+    }
+
+    fn send_bytes(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        Ok(Self::encode(&self.send(associated_data, plaintext)?))
+    }
+
+    /// Rejects malformed payloads with acc == false, like receive() rejects forgeries.
+    fn receive_bytes(&mut self, associated_data: &[u8; 32], payload: &[u8]) -> (bool, Vec<u8>) {
+        match self.decode(payload) {
+            Ok(ct) => self.receive(associated_data, ct),
+            Err(_) => (false, Vec::new()),
+        }
+    }
+}
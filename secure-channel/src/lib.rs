@@ -0,0 +1,6 @@
+mod channel;
+mod tests;
+mod variants;
+
+pub use channel::SecureChannel;
+pub use variants::{ArcRrcState, RrcConfig};
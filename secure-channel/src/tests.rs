@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests {
+    use rc::{Ordinal, State};
+    use rrc::protocol::OptimizedSendRrcState;
+    use rrc::{Alarm, DetectionEvent, DetectionKind, Evidence, OptimizedReceiveRrcState, RrcState, Security};
+    use s_rid_rc::{SRidConfig, SRidState};
+    use unf_arc_based_on_rc::ArcState;
+
+    use crate::{ArcRrcState, RrcConfig, SecureChannel};
+
+    // Alice sends bursts of messages as bytes, Bob answers each burst with one message as a ciphertext.
+    fn conversation<C: SecureChannel>(config: &C::Config) {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = C::init(config).unwrap();
+        for i in 0..10u32 {
+            for j in 0..3u32 {
+                let plaintext = [i.to_be_bytes(), j.to_be_bytes()].concat();
+                let bytes = alice_state.send_bytes(&associated_data, &plaintext).unwrap();
+                let (acc, decrypted_plaintext) = bob_state.receive_bytes(&associated_data, &bytes);
+                assert!(acc);
+                assert_eq!(decrypted_plaintext, plaintext);
+            }
+            let ct = bob_state.send(&associated_data, &i.to_be_bytes()).unwrap();
+            let (acc, decrypted_plaintext) = alice_state.receive(&associated_data, ct);
+            assert!(acc);
+            assert_eq!(decrypted_plaintext, i.to_be_bytes());
+        }
+    }
+
+    fn malformed_payloads_are_rejected<C: SecureChannel>() {
+        let associated_data = [0u8; 32];
+        let (mut alice_state, mut bob_state) = C::init_default();
+        let bytes = alice_state.send_bytes(&associated_data, b"Hello I am Alice").unwrap();
+        assert!(bob_state.decode(&bytes[..20]).is_err());
+
+        let mut tampered = bytes;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let (acc, _) = bob_state.receive_bytes(&associated_data, &tampered);
+        assert!(!acc);
+    }
+
+    fn frozen_alarm() -> Option<Alarm> {
+        let event = DetectionEvent {
+            kind: DetectionKind::RRid,
+            ordinal: Ordinal { epoch: 0, index: 0 },
+            evidence: Evidence { message_hash: [0; 32], peer_received_digest: Vec::new() },
+        };
+        Some(Alarm { event, frozen: true })
+    }
+
+    #[test]
+    fn every_variant_exchanges_messages() {
+        conversation::<State>(&());
+        for security in [Security::RRid, Security::SRid, Security::RRidAndSRid] {
+            conversation::<RrcState>(&RrcConfig { security: security.clone(), epoch_window: None });
+            conversation::<RrcState>(&RrcConfig { security: security.clone(), epoch_window: Some(2) });
+            conversation::<OptimizedSendRrcState>(&RrcConfig { security: security.clone(), epoch_window: None });
+            conversation::<OptimizedReceiveRrcState>(&RrcConfig { security, epoch_window: None });
+        }
+        conversation::<SRidState>(&SRidConfig::default());
+        conversation::<SRidState>(&SRidConfig { security: Security::RRidAndSRid, ..SRidConfig::default() });
+        conversation::<ArcState>(&());
+        conversation::<ArcRrcState>(&());
+    }
+
+    #[test]
+    fn every_variant_rejects_malformed_payloads() {
+        malformed_payloads_are_rejected::<State>();
+        malformed_payloads_are_rejected::<RrcState>();
+        malformed_payloads_are_rejected::<OptimizedSendRrcState>();
+        malformed_payloads_are_rejected::<OptimizedReceiveRrcState>();
+        malformed_payloads_are_rejected::<SRidState>();
+        malformed_payloads_are_rejected::<ArcState>();
+        malformed_payloads_are_rejected::<ArcRrcState>();
+    }
+
+    #[test]
+    fn frozen_sessions_refuse_to_send() {
+        let associated_data = [0u8; 32];
+        let (mut rrc_state, _) = RrcState::init_default();
+        rrc_state.alarm = frozen_alarm();
+        assert!(rrc_state.send_bytes(&associated_data, b"Hello").is_err());
+        let (mut optimized_send_state, _) = OptimizedSendRrcState::init_default();
+        optimized_send_state.state.alarm = frozen_alarm();
+        assert!(optimized_send_state.send(&associated_data, b"Hello").is_err());
+        let (mut frozen_optimized_send_state, _) = OptimizedSendRrcState::init_default();
+        frozen_optimized_send_state.state.alarm = frozen_alarm();
+        let mut optimized_receive_state = OptimizedReceiveRrcState::new(frozen_optimized_send_state).unwrap();
+        assert!(optimized_receive_state.send(&associated_data, b"Hello").is_err());
+        let (mut s_rid_state, _) = SRidState::init_default();
+        s_rid_state.alarm = frozen_alarm();
+        assert!(s_rid_state.send(&associated_data, b"Hello").is_err());
+        let (mut arc_rrc_state, _) = ArcRrcState::init_default();
+        arc_rrc_state.0.alarm = frozen_alarm();
+        assert!(arc_rrc_state.send(&associated_data, b"Hello").is_err());
+    }
+
+    #[test]
+    fn unsupported_configs_are_rejected_at_init() {
+        let config = RrcConfig { epoch_window: Some(2), ..RrcConfig::default() };
+        assert!(OptimizedReceiveRrcState::init(&config).is_err());
+        let config = SRidConfig { rotation_window: 3, ..SRidConfig::default() };
+        assert!(SRidState::init(&config).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::mem::size_of;
+
+use rc::{Header, Ordinal, State};
+use rrc::parsing::Reader;
+use rrc::protocol::{OptimizedSendCiphertext, OptimizedSendRrcState};
+use rrc::{
+    decode_optimized_payload, decode_payload, decode_payload_with_merkle_roots, encode_optimized_payload, encode_payload, ensure_not_frozen, optimized_receive_rrc_receive,
+    optimized_rrc_receive, rrc_init_all, rrc_init_all_optimized_receive, rrc_init_all_optimized_send, rrc_init_all_optimized_send_with_epoch_window,
+    rrc_init_all_with_epoch_window, rrc_receive, try_optimized_receive_rrc_send, try_optimized_rrc_send, try_rrc_send, Ciphertext, DecodeLimits,
+    OptimizedReceiveRrcState, RrcState, Security,
+};
+use s_rid_rc::{s_rid_rc_init_with_config, s_rid_rc_receive, try_s_rid_rc_send, OptimizedSendCiphertext as SRidCiphertext, SRidConfig, SRidState};
+use unf_arc_based_on_rc::{arc_init, arc_receive, arc_send, ArcState};
+use unf_arc_based_on_rrc::{rc_arc_init, rc_arc_receive, rc_arc_send, RrcSendCtWrapper};
+
+use crate::channel::SecureChannel;
+
+/// Parameters of the variants built on rrc.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RrcConfig {
+    pub security: Security,
+    /// Number of epochs whose messages are kept for the checks, or None to keep every message.
+    pub epoch_window: Option<usize>,
+}
+
+impl Default for RrcConfig {
+    fn default() -> Self {
+        RrcConfig { security: Security::RRidAndSRid, epoch_window: None }
+    }
+}
+
+/// State of a party of unf-arc-based-on-rrc, which uses the rrc state with its own ciphertexts.
+#[derive(Clone)]
+pub struct ArcRrcState(pub RrcState);
+
+/// Encodes an rc header and ciphertext as dh_pk || prev_chain_len || msg_nbr || epoch || ct.
+fn encode_header_and_ciphertext(header: &Header, ciphertext: &[u8]) -> Vec<u8> {
+    let mut bytes = header.dh_ratchet_key.as_bytes().to_vec();
+    bytes.extend_from_slice(&header.prev_chain_len.to_be_bytes());
+    bytes.extend_from_slice(&header.msg_nbr.to_be_bytes());
+    bytes.extend_from_slice(&header.epoch.to_be_bytes());
+    bytes.extend_from_slice(ciphertext);
+    bytes
+}
+
+fn decode_header_and_ciphertext(payload: &[u8], limits: &DecodeLimits) -> Result<(Header, Vec<u8>), &'static str> {
+    let header_len = 32 + 3 * size_of::<usize>();
+    let mut reader = Reader::new(payload);
+    let header = reader.read_header()?;
+    let ciphertext = reader.take(payload.len() - header_len)?;
+    if ciphertext.len() > limits.max_ciphertext_len {
+        return Err("A declared length exceeds the limit.");
+    }
+    Ok((header, ciphertext.to_vec()))
+}
+
+impl SecureChannel for State {
+    type Config = ();
+    type Ciphertext = (Header, Vec<u8>);
+
+    fn init(_config: &()) -> Result<(Self, Self), &'static str> {
+        Ok(rc::init_all())
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        let (_, header, ct) = rc::send(self, associated_data, plaintext);
+        Ok((header, ct))
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], (header, ct): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = rc::receive(self, associated_data, header, &ct);
+        (acc, pt)
+    }
+
+    fn encode((header, ct): &Self::Ciphertext) -> Vec<u8> {
+        encode_header_and_ciphertext(header, ct)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        decode_header_and_ciphertext(payload, &self.decode_limits())
+    }
+}
+
+impl SecureChannel for RrcState {
+    type Config = RrcConfig;
+    type Ciphertext = (Ordinal, Ciphertext, Header);
+
+    fn init(config: &RrcConfig) -> Result<(Self, Self), &'static str> {
+        Ok(match config.epoch_window {
+            Some(epoch_window) => rrc_init_all_with_epoch_window(config.security.clone(), epoch_window)?,
+            None => rrc_init_all(config.security.clone()),
+        })
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        try_rrc_send(self, associated_data, plaintext)
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], (_, mut ct, header): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = rrc_receive(self, associated_data, &mut ct, header);
+        (acc, pt)
+    }
+
+    fn encode((num, ct, header): &Self::Ciphertext) -> Vec<u8> {
+        encode_payload(*num, ct, *header)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        if self.merkle_roots {
+            return decode_payload_with_merkle_roots(payload, &self.decode_limits());
+        }
+        decode_payload(payload, &self.decode_limits())
+    }
+}
+
+impl SecureChannel for OptimizedSendRrcState {
+    type Config = RrcConfig;
+    type Ciphertext = (Ordinal, OptimizedSendCiphertext, Header);
+
+    fn init(config: &RrcConfig) -> Result<(Self, Self), &'static str> {
+        Ok(match config.epoch_window {
+            Some(epoch_window) => rrc_init_all_optimized_send_with_epoch_window(config.security.clone(), epoch_window)?,
+            None => rrc_init_all_optimized_send(config.security.clone()),
+        })
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        try_optimized_rrc_send(self, associated_data, plaintext)
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], (_, mut ct, header): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = optimized_rrc_receive(self, associated_data, &mut ct, header);
+        (acc, pt)
+    }
+
+    fn encode((num, ct, header): &Self::Ciphertext) -> Vec<u8> {
+        encode_optimized_payload(*num, ct, *header)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        decode_optimized_payload(payload, &self.decode_limits())
+    }
+}
+
+impl SecureChannel for OptimizedReceiveRrcState {
+    type Config = RrcConfig;
+    type Ciphertext = (Ordinal, OptimizedSendCiphertext, Header);
+
+    /// Returns Err if the config has an epoch window, which the optimized receive does not support.
+    fn init(config: &RrcConfig) -> Result<(Self, Self), &'static str> {
+        if config.epoch_window.is_some() {
+            return Err("The optimized receive does not support epoch windows.");
+        }
+        Ok(rrc_init_all_optimized_receive(config.security.clone()))
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        try_optimized_receive_rrc_send(self, associated_data, plaintext)
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], (_, mut ct, header): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = optimized_receive_rrc_receive(self, associated_data, &mut ct, header);
+        (acc, pt)
+    }
+
+    fn encode((num, ct, header): &Self::Ciphertext) -> Vec<u8> {
+        encode_optimized_payload(*num, ct, *header)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        decode_optimized_payload(payload, &self.decode_limits())
+    }
+}
+
+impl SecureChannel for SRidState {
+    type Config = SRidConfig;
+    type Ciphertext = SRidCiphertext;
+
+    fn init(config: &SRidConfig) -> Result<(Self, Self), &'static str> {
+        s_rid_rc_init_with_config(config)
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        Ok(try_s_rid_rc_send(self, associated_data, plaintext)?.1)
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], ct: Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = s_rid_rc_receive(self, associated_data, ct);
+        (acc, pt)
+    }
+
+    fn encode(ct: &Self::Ciphertext) -> Vec<u8> {
+        s_rid_rc::encode_payload(ct)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        s_rid_rc::decode_payload(payload, &self.config)
+    }
+
+    fn decode_limits(&self) -> DecodeLimits {
+        self.config.decode_limits.clone()
+    }
+}
+
+impl SecureChannel for ArcState {
+    type Config = ();
+    type Ciphertext = (Header, Vec<u8>);
+
+    fn init(_config: &()) -> Result<(Self, Self), &'static str> {
+        Ok(arc_init())
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        let (_, header, ct) = arc_send(self, associated_data, plaintext);
+        Ok((header, ct))
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], (header, ct): Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = arc_receive(self, associated_data, header, ct);
+        (acc, pt)
+    }
+
+    fn encode((header, ct): &Self::Ciphertext) -> Vec<u8> {
+        encode_header_and_ciphertext(header, ct)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        decode_header_and_ciphertext(payload, &self.decode_limits())
+    }
+}
+
+impl SecureChannel for ArcRrcState {
+    type Config = ();
+    type Ciphertext = RrcSendCtWrapper;
+
+    fn init(_config: &()) -> Result<(Self, Self), &'static str> {
+        let (alice, bob) = rc_arc_init();
+        Ok((ArcRrcState(alice), ArcRrcState(bob)))
+    }
+
+    fn send(&mut self, associated_data: &[u8; 32], plaintext: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        ensure_not_frozen(&self.0.alarm)?;
+        Ok(rc_arc_send(&mut self.0, associated_data, plaintext))
+    }
+
+    fn receive(&mut self, associated_data: &[u8; 32], mut ct: Self::Ciphertext) -> (bool, Vec<u8>) {
+        let (acc, _, pt) = rc_arc_receive(&mut self.0, associated_data, &mut ct);
+        (acc, pt)
+    }
+
+    fn encode(ct: &Self::Ciphertext) -> Vec<u8> {
+        ct.encode()
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Self::Ciphertext, &'static str> {
+        RrcSendCtWrapper::decode(payload, &self.decode_limits())
+    }
+}
@@ -0,0 +1,15 @@
+mod tests;
+
+pub use rc;
+#[cfg(feature = "rrc")]
+pub use rrc;
+#[cfg(feature = "srid")]
+pub use s_rid_rc;
+#[cfg(feature = "arc-rc")]
+pub use unf_arc_based_on_rc;
+#[cfg(feature = "arc-rrc")]
+pub use unf_arc_based_on_rrc;
+#[cfg(feature = "mset-mu")]
+pub use mset_mu_hash;
+#[cfg(feature = "secure-channel")]
+pub use secure_channel;
@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    const ASSOCIATED_DATA: [u8; 32] = [0; 32];
+
+    #[test]
+    fn rc_is_reexported() {
+        let (mut alice_state, mut bob_state) = crate::rc::init_all();
+        let (_, header, ct) = crate::rc::send(&mut alice_state, &ASSOCIATED_DATA, b"Hello I am Alice");
+        let (acc, _, pt) = crate::rc::receive(&mut bob_state, &ASSOCIATED_DATA, header, &ct);
+        assert!(acc);
+        assert_eq!(pt, b"Hello I am Alice");
+    }
+
+    #[cfg(feature = "rrc")]
+    #[test]
+    fn rrc_is_reexported() {
+        let (mut alice_state, mut bob_state) = crate::rrc::rrc_init_all(crate::rrc::Security::RRidAndSRid);
+        let bytes = crate::rrc::send_bytes(&mut alice_state, &ASSOCIATED_DATA, b"Hello I am Alice");
+        let (acc, _, pt) = crate::rrc::receive_bytes(&bytes, &mut bob_state, &ASSOCIATED_DATA);
+        assert!(acc);
+        assert_eq!(pt, b"Hello I am Alice");
+    }
+
+    #[cfg(feature = "srid")]
+    #[test]
+    fn s_rid_rc_is_reexported() {
+        let (mut alice_state, mut bob_state) = crate::s_rid_rc::s_rid_rc_init();
+        let bytes = crate::s_rid_rc::s_rid_rc_send_bytes(&mut alice_state, &ASSOCIATED_DATA, b"Hello I am Alice");
+        let (acc, _, pt) = crate::s_rid_rc::s_rid_rc_receive_bytes(&mut bob_state, &ASSOCIATED_DATA, &bytes);
+        assert!(acc);
+        assert_eq!(pt, b"Hello I am Alice");
+    }
+
+    #[cfg(feature = "arc-rc")]
+    #[test]
+    fn unf_arc_based_on_rc_is_reexported() {
+        let (mut alice_state, mut bob_state) = crate::unf_arc_based_on_rc::arc_init();
+        let (_, header, ct) = crate::unf_arc_based_on_rc::arc_send(&mut alice_state, &ASSOCIATED_DATA, b"Hello I am Alice");
+        let (acc, _, pt) = crate::unf_arc_based_on_rc::arc_receive(&mut bob_state, &ASSOCIATED_DATA, header, ct);
+        assert!(acc);
+        assert_eq!(pt, b"Hello I am Alice");
+    }
+
+    #[cfg(feature = "arc-rrc")]
+    #[test]
+    fn unf_arc_based_on_rrc_is_reexported() {
+        let (mut alice_state, mut bob_state) = crate::unf_arc_based_on_rrc::rc_arc_init();
+        let mut ct = crate::unf_arc_based_on_rrc::rc_arc_send(&mut alice_state, &ASSOCIATED_DATA, b"Hello I am Alice");
+        let (acc, _, pt) = crate::unf_arc_based_on_rrc::rc_arc_receive(&mut bob_state, &ASSOCIATED_DATA, &mut ct);
+        assert!(acc);
+        assert_eq!(pt, b"Hello I am Alice");
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashSet};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rc::{Header, MemberId};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{arc_init, arc_receive, arc_send, ArcOrdinal, ArcState, AuthenticationTag};
+
+/// State of one member of a group running UNF-ARC.
+///
+/// Group messages are delivered over pairwise ARC sessions. Each member keeps its view of the group
+/// transcript, i.e. the messages it sent and received from every other member, so that any two
+/// members, or all of them in a meeting, can compare it out of band.
+#[derive(Clone)]
+pub struct ArcGroupState {
+    pub own_id: MemberId,
+    /// Pairwise ARC sessions with the other members.
+    pub sessions: BTreeMap<MemberId, ArcState>,
+    /// Key shared by the members, with which the messages are hashed.
+    hash_key: [u8; 32],
+    /// Hashes of the messages of each member by index, this member included.
+    transcript: BTreeMap<MemberId, BTreeMap<usize, [u8; 32]>>,
+}
+
+#[derive(Clone)]
+pub struct ArcGroupCiphertext {
+    pub sender: MemberId,
+    /// Index of the message among those of the sender.
+    pub index: usize,
+    pub header: Header,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Commitment to the messages of one member, as seen by the member sending the tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderCommitment {
+    /// Number of messages committed to, i.e. the messages of indices 0 to count - 1, which were all received.
+    pub count: usize,
+    pub digest: [u8; 32],
+}
+
+/// Initializes the states of all the members of a group, with a pairwise ARC session between every two of them.
+pub fn arc_group_init(members: &[MemberId]) -> Vec<ArcGroupState> {
+    let mut hash_key = [0u8; 32];
+    OsRng.fill_bytes(&mut hash_key);
+    let mut states: Vec<ArcGroupState> = members
+        .iter()
+        .map(|&own_id| ArcGroupState {
+            own_id,
+            sessions: BTreeMap::new(),
+            hash_key,
+            transcript: members.iter().map(|&id| (id, BTreeMap::new())).collect(),
+        })
+        .collect();
+    for i in 0..members.len() {
+        for j in i + 1..members.len() {
+            let (first, second) = arc_init();
+            states[i].sessions.insert(members[j], first);
+            states[j].sessions.insert(members[i], second);
+        }
+    }
+    states
+}
+
+/// Returns the associated data given to the pairwise sessions, which binds the sender and the index of the message.
+fn group_associated_data(associated_data: &[u8; 32], sender: MemberId, index: usize) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(associated_data);
+    hasher.update(sender.to_be_bytes());
+    hasher.update(index.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Hash of a group message, computed over the plaintext as every recipient receives a different ciphertext.
+fn message_hash(hash_key: &[u8; 32], associated_data_prime: &[u8; 32], plaintext: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(hash_key);
+    hasher.update(associated_data_prime);
+    hasher.update(plaintext);
+    hasher.finalize().into()
+}
+
+/// Encrypts a message to every other member, and returns the ciphertext of each.
+pub fn arc_group_send(state: &mut ArcGroupState, associated_data: &[u8; 32], plaintext: &[u8]) -> Vec<(MemberId, ArcGroupCiphertext)> {
+    let sender = state.own_id;
+    let sent = state.transcript.get_mut(&sender).unwrap();
+    let index = sent.len();
+    let associated_data_prime = group_associated_data(associated_data, sender, index);
+    sent.insert(index, message_hash(&state.hash_key, &associated_data_prime, plaintext));
+    state
+        .sessions
+        .iter_mut()
+        .map(|(&recipient, session)| {
+            let (_, header, ciphertext) = arc_send(session, &associated_data_prime, plaintext);
+            (recipient, ArcGroupCiphertext { sender, index, header, ciphertext })
+        })
+        .collect()
+}
+
+/// Receives a group message from another member. Only the first message received at each index of a sender is kept in the transcript.
+pub fn arc_group_receive(state: &mut ArcGroupState, associated_data: &[u8; 32], ct: ArcGroupCiphertext) -> (bool, ArcOrdinal, Vec<u8>) {
+    let Some(session) = state.sessions.get_mut(&ct.sender) else {
+        return (false, ArcOrdinal { epoch: 0, index: 0 }, Vec::new());
+    };
+    let associated_data_prime = group_associated_data(associated_data, ct.sender, ct.index);
+    let (acc, num, pt) = arc_receive(session, &associated_data_prime, ct.header, ct.ciphertext);
+    if !acc {
+        return (false, num, pt);
+    }
+    let h = message_hash(&state.hash_key, &associated_data_prime, &pt);
+    state.transcript.get_mut(&ct.sender).unwrap().entry(ct.index).or_insert(h);
+    (acc, num, pt)
+}
+
+/// Commits to the first `count` messages of a member, which must all have been received.
+fn digest(member: MemberId, messages: &BTreeMap<usize, [u8; 32]>, count: usize) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(member.to_be_bytes());
+    hasher.update(count.to_be_bytes());
+    for h in messages.values().take(count) {
+        hasher.update(h);
+    }
+    hasher.finalize().into()
+}
+
+/// Number of messages of indices 0, 1, ... which were all received.
+fn received_prefix(messages: &BTreeMap<usize, [u8; 32]>) -> usize {
+    messages.keys().enumerate().take_while(|(position, index)| position == *index).count()
+}
+
+/// Returns an authentication tag committing to this member's view of the group transcript, with one sub-commitment per member.
+///
+/// Its `commitment()` is what all the members compare in a meeting. The pairwise sets of the tag are
+/// empty, and `num.index` is the number of messages this member sent, 0 if it sent none.
+pub fn arc_group_auth_send(state: &ArcGroupState) -> AuthenticationTag {
+    let senders = state
+        .transcript
+        .iter()
+        .map(|(&member, messages)| {
+            let count = received_prefix(messages);
+            (member, SenderCommitment { count, digest: digest(member, messages, count) })
+        })
+        .collect();
+    let sent = state.transcript[&state.own_id].len() as i32;
+    AuthenticationTag { s: HashSet::new(), r: HashSet::new(), num: ArcOrdinal { epoch: 0, index: sent }, senders }
+}
+
+/// Compares the view of another member, sent in an authentication tag, with the view of this member.
+///
+/// Returns true only if every sub-commitment was checked and agrees, then the members whose messages
+/// the two views disagree on, and the members whose sub-commitment covers more messages than this
+/// member received. The latter cannot be checked here, but will be when the other member receives the
+/// tag of this member, or once this member received the missing messages.
+pub fn arc_group_auth_receive(state: &ArcGroupState, at: &AuthenticationTag) -> (bool, Vec<MemberId>, Vec<MemberId>) {
+    let mut divergent = Vec::new();
+    let mut unchecked = Vec::new();
+    for (&member, messages) in state.transcript.iter() {
+        let Some(theirs) = at.senders.get(&member) else {
+            divergent.push(member);
+            continue;
+        };
+        if theirs.count > received_prefix(messages) {
+            unchecked.push(member);
+        } else if digest(member, messages, theirs.count) != theirs.digest {
+            divergent.push(member);
+        }
+    }
+    divergent.extend(at.senders.keys().filter(|member| !state.transcript.contains_key(member)));
+    (divergent.is_empty() && unchecked.is_empty(), divergent, unchecked)
+}
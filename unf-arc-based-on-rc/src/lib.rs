@@ -1,4 +1,6 @@
+mod group;
 mod protocol;
 mod tests;
 
-pub use protocol::{ArcState, AuthenticationTag, arc_init, arc_receive, arc_send, arc_auth_receive, arc_auth_send};
+pub use protocol::{ArcState, ArcCompromisedView, ArcOrdinal, AuthenticationTag, arc_init, arc_receive, arc_send, arc_auth_receive, arc_auth_send, arc_reset_request, arc_reset_respond, arc_reset_finish, arc_transcript_commitment};
+pub use group::{arc_group_init, arc_group_send, arc_group_receive, arc_group_auth_send, arc_group_auth_receive, ArcGroupCiphertext, ArcGroupState, SenderCommitment};
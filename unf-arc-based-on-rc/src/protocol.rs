@@ -1,9 +1,13 @@
-use std::{collections::HashSet, mem::size_of, hash::Hash};
+use std::{collections::{BTreeMap, HashSet}, mem::size_of, hash::Hash};
 use rc::*;
 use sha2::{Sha256, Digest};
+use get_size::GetSize;
+use x25519_dalek::PublicKey;
+
+use crate::group::SenderCommitment;
 
 /// We use the same message structure as in the rest of the project but adjust it to use ArcOrdinals rather than usual ordinals.
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, GetSize)]
 pub struct Message {
     pub ordinal: ArcOrdinal,
     pub content: [u8;32]
@@ -20,11 +24,64 @@ pub struct ArcState {
     max_num: ArcOrdinal
 }
 
+impl GetSize for ArcState {
+    /// Counts the skipped message keys of the ratchet and the sets of messages.
+    fn get_heap_size(&self) -> usize {
+        self.state.get_heap_size() + self.s.get_heap_size() + self.r.get_heap_size() + self.s_ack.get_heap_size()
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthenticationTag {
-    s: HashSet<Message>,
-    r: HashSet<Message>,
-    pub num: ArcOrdinal
+    pub(crate) s: HashSet<Message>,
+    pub(crate) r: HashSet<Message>,
+    pub num: ArcOrdinal,
+    /// Sub-commitment to the messages of each member of a group, see `arc_group_auth_send`. Empty in the tags of two parties.
+    pub senders: BTreeMap<MemberId, SenderCommitment>
+}
+
+impl AuthenticationTag {
+    /// Returns one compact commitment to the view of the group transcript, which all the members of a
+    /// group compare out of band. The sub-commitments then tell which member the views diverge on.
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for (member, sub_commitment) in self.senders.iter() {
+            hasher.update(member.to_be_bytes());
+            hasher.update(sub_commitment.count.to_be_bytes());
+            hasher.update(sub_commitment.digest);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// What an adversary learns by exposing the state of a party.
+///
+/// An exposure reveals the whole state, whose fields are otherwise private. With it, the adversary
+/// can impersonate the party until the next exchange of authentication tags.
+#[derive(Clone)]
+pub struct ArcCompromisedView {
+    pub state: State,
+    pub hash_key: [u8; 32],
+    pub s: HashSet<Message>,
+    pub r: HashSet<Message>,
+    pub s_ack: HashSet<Message>,
+    pub num: ArcOrdinal,
+    pub max_num: ArcOrdinal
+}
+
+impl ArcState {
+    /// Returns what an adversary exposing this state right now would learn, i.e. a copy of all of it.
+    pub fn export_compromised_view(&self) -> ArcCompromisedView {
+        ArcCompromisedView { state: self.state.clone(), hash_key: self.hash_key, s: self.s.clone(), r: self.r.clone(), s_ack: self.s_ack.clone(), num: self.num, max_num: self.max_num }
+    }
+
+    /// Returns a state continuing the session from an exposure, as the adversary would use it to impersonate the party.
+    ///
+    /// Nothing of the state is withheld from the view, so the adversary continues exactly where the party was.
+    pub fn resume_from(view: &ArcCompromisedView) -> ArcState {
+        let view = view.clone();
+        ArcState { state: view.state, hash_key: view.hash_key, s: view.s, r: view.r, s_ack: view.s_ack, num: view.num, max_num: view.max_num }
+    }
 }
 
 /// These ordinals are identical to the Ordinals presented in Signal and used in the rest of the project apart from one slight difference:
@@ -34,7 +91,7 @@ pub struct AuthenticationTag {
 /// This is done to account for the fact that one comparison in arc_receive() checks if num <= max_num and if this message has already been acked.
 /// This check is crucial to avoid forgeries but fails with unsigned integers upon receiving the first message. To deal with this, we provide an easy
 /// fix by giving max_num a value it will never have and that is smaller than any possible real ordinal.
-#[derive(Clone, Hash, Eq, PartialEq, Debug, Ord, PartialOrd, Copy)]
+#[derive(Clone, Hash, Eq, PartialEq, Debug, Ord, PartialOrd, Copy, GetSize)]
 pub struct ArcOrdinal {
     pub epoch: i32,
     pub index: i32
@@ -81,7 +138,7 @@ pub fn arc_send(state: &mut ArcState, associated_data: &[u8; 32], plaintext: &[u
 
 /// Send an authentication tag to the other party for out-of-bands verification
 pub fn arc_auth_send(state: &mut ArcState) -> (ArcOrdinal, AuthenticationTag) {
-    let at = AuthenticationTag{s: state.s.clone(), r: state.r.clone(), num: state.num.clone()};
+    let at = AuthenticationTag{s: state.s.clone(), r: state.r.clone(), num: state.num.clone(), senders: BTreeMap::new()};
     return (state.num.clone(), at);
 }
 
@@ -139,4 +196,59 @@ pub fn arc_auth_receive(state: &mut ArcState, at: AuthenticationTag) -> (bool, A
         state.max_num = at.num;
     }
     return (true, at.num);
-}
\ No newline at end of file
+}
+
+/// Returns the commitment of a party to its transcript of the session: the messages it sent and received, by ordinal.
+///
+/// The reset only binds the commitments of both parties into the keys and the hash key of the new
+/// session, and does not compare them: ARC compares the transcripts with its authentication tags.
+pub fn arc_transcript_commitment(state: &ArcState) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"arc reset transcript");
+    for set in [&state.s, &state.r] {
+        let mut messages: Vec<&Message> = set.iter().collect();
+        messages.sort();
+        hasher.update((messages.len() as u64).to_be_bytes());
+        for msg in messages {
+            hasher.update(msg.ordinal.epoch.to_be_bytes());
+            hasher.update(msg.ordinal.index.to_be_bytes());
+            hasher.update(msg.content);
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Folds the commitments of both parties into the hash key of the messages of the new session.
+fn rebind_hash_key(state: &mut ArcState, initiator_transcript: &[u8], responder_transcript: &[u8]) {
+    let mut hasher = Sha256::new();
+    hasher.update(b"arc reset hash key");
+    hasher.update(state.hash_key);
+    for transcript in [initiator_transcript, responder_transcript] {
+        hasher.update((transcript.len() as u64).to_be_bytes());
+        hasher.update(transcript);
+    }
+    state.hash_key = hasher.finalize().into();
+}
+
+/// Starts a reset of the ratchet of a session, see rc's `reset_request`.
+///
+/// Only the ratchet is replaced, the sets of messages are kept, so the next exchange of authentication
+/// tags still covers the messages of the old ratchet.
+pub fn arc_reset_request(state: &ArcState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey) -> (PendingReset, ResetRequest) {
+    reset_request(&state.state, identity, peer_identity, arc_transcript_commitment(state).to_vec())
+}
+
+/// Answers a reset request, switches the session to the new ratchet, and returns the response to send.
+pub fn arc_reset_respond(state: &mut ArcState, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, request: &ResetRequest) -> Result<ResetResponse, &'static str> {
+    let (new_state, response) = reset_respond(&state.state, identity, peer_identity, request, arc_transcript_commitment(state).to_vec())?;
+    state.state = new_state;
+    rebind_hash_key(state, &request.transcript, &response.transcript);
+    Ok(response)
+}
+
+/// Completes a reset with the response of the peer, and switches the session to the new ratchet.
+pub fn arc_reset_finish(state: &mut ArcState, pending: &PendingReset, identity: &DiffieHellmanParameters, peer_identity: &PublicKey, response: &ResetResponse) -> Result<(), &'static str> {
+    state.state = reset_finish(pending, identity, peer_identity, response)?;
+    rebind_hash_key(state, &pending.request.transcript, &response.transcript);
+    Ok(())
+}
@@ -4,7 +4,8 @@ mod tests {
 
     use rand::Rng;
 
-    use crate::{arc_init, arc_send, arc_receive, arc_auth_send, arc_auth_receive};
+    use crate::{arc_init, arc_send, arc_receive, arc_auth_send, arc_auth_receive, ArcState};
+    use crate::{arc_group_init, arc_group_send, arc_group_receive, arc_group_auth_send, arc_group_auth_receive, ArcGroupCiphertext, ArcGroupState};
 
 
     #[test]
@@ -77,6 +78,101 @@ mod tests {
         assert_eq!(acc, false);
     }
 
+    #[test]
+    fn impersonator_is_caught_at_next_authentication() {
+        let associated_data: [u8; 32] = [22; 32];
+        let (mut alice_state, mut bob_state) = arc_init();
+        let (_, header, ct) = arc_send(&mut alice_state, &associated_data, b"before the exposure");
+        assert!(arc_receive(&mut bob_state, &associated_data, header, ct).0);
+
+        let mut eve_state = ArcState::resume_from(&alice_state.export_compromised_view());
+        let (_, header, ct) = arc_send(&mut eve_state, &associated_data, b"I am Alice");
+        assert!(arc_receive(&mut bob_state, &associated_data, header, ct).0);
+
+        // Both parties keep talking, the forgery is only caught when authenticating.
+        for i in 0..5u8 {
+            let (_, header, ct) = arc_send(&mut bob_state, &associated_data, &[i]);
+            assert!(arc_receive(&mut alice_state, &associated_data, header, ct).0);
+        }
+        let (_, at) = arc_auth_send(&mut bob_state);
+        assert!(!arc_auth_receive(&mut alice_state, at).0);
+    }
+
+    /// Delivers the ciphertexts of a group message to their recipients, and returns whether they all accepted it.
+    fn deliver(states: &mut [ArcGroupState], ciphertexts: Vec<(u32, ArcGroupCiphertext)>) -> bool {
+        let associated_data: [u8; 32] = [22; 32];
+        ciphertexts.into_iter().all(|(recipient, ct)| {
+            let state = states.iter_mut().find(|state| state.own_id == recipient).unwrap();
+            arc_group_receive(state, &associated_data, ct).0
+        })
+    }
+
+    fn group_conversation(states: &mut [ArcGroupState], rounds: u8) {
+        let associated_data: [u8; 32] = [22; 32];
+        for round in 0..rounds {
+            for i in 0..states.len() {
+                let ciphertexts = arc_group_send(&mut states[i], &associated_data, &[round, i as u8]);
+                assert!(deliver(states, ciphertexts));
+            }
+        }
+    }
+
+    #[test]
+    fn group_members_agree_on_the_transcript() {
+        let mut states = arc_group_init(&[1, 2, 3, 4]);
+        // A member which sent nothing counts 0 messages.
+        assert_eq!(arc_group_auth_send(&states[0]).num.index, 0);
+        group_conversation(&mut states, 5);
+
+        // In a meeting, all the members compare one commitment.
+        let tags: Vec<_> = states.iter().map(arc_group_auth_send).collect();
+        assert!(tags.iter().all(|tag| tag.commitment() == tags[0].commitment()));
+        for state in states.iter() {
+            for tag in tags.iter() {
+                assert_eq!(arc_group_auth_receive(state, tag), (true, Vec::new(), Vec::new()));
+            }
+        }
+    }
+
+    #[test]
+    fn group_authentication_localises_a_forgery_to_its_sender() {
+        let associated_data: [u8; 32] = [22; 32];
+        let mut states = arc_group_init(&[1, 2, 3, 4]);
+        group_conversation(&mut states, 2);
+
+        // Eve exposes the state of member 2 and sends a forgery in its name to member 3 only.
+        let mut eve_state = states[1].clone();
+        let forged: Vec<_> = arc_group_send(&mut eve_state, &associated_data, b"I am surely member 2").into_iter().filter(|(recipient, _)| *recipient == 3).collect();
+        assert!(deliver(&mut states, forged));
+        let honest: Vec<_> = arc_group_send(&mut states[1], &associated_data, b"I am member 2").into_iter().filter(|(recipient, _)| *recipient != 3).collect();
+        assert!(deliver(&mut states, honest));
+
+        let tags: Vec<_> = states.iter().map(arc_group_auth_send).collect();
+        assert_ne!(tags[2].commitment(), tags[0].commitment());
+        assert_eq!(tags[3].commitment(), tags[0].commitment());
+        for i in [0, 1, 3] {
+            assert_eq!(arc_group_auth_receive(&states[i], &tags[2]), (false, vec![2], Vec::new()));
+            assert_eq!(arc_group_auth_receive(&states[2], &tags[i]), (false, vec![2], Vec::new()));
+        }
+        assert!(arc_group_auth_receive(&states[0], &tags[3]).0);
+    }
+
+    #[test]
+    fn group_member_behind_on_a_sender_is_not_flagged() {
+        let associated_data: [u8; 32] = [22; 32];
+        let mut states = arc_group_init(&[1, 2, 3]);
+        group_conversation(&mut states, 2);
+        // Member 3 has not received the last message of member 1 yet.
+        let ciphertexts: Vec<_> = arc_group_send(&mut states[0], &associated_data, b"late").into_iter().filter(|(recipient, _)| *recipient == 2).collect();
+        assert!(deliver(&mut states, ciphertexts));
+
+        let behind = arc_group_auth_send(&states[2]);
+        assert_ne!(behind.commitment(), arc_group_auth_send(&states[1]).commitment());
+        assert_eq!(arc_group_auth_receive(&states[0], &behind), (true, Vec::new(), Vec::new()));
+        // The member behind cannot check the messages of member 1 it did not receive, and says so.
+        assert_eq!(arc_group_auth_receive(&states[2], &arc_group_auth_send(&states[0])), (false, Vec::new(), vec![1]));
+    }
+
     #[allow(dead_code)]
     //#[test]
     fn receive_send_bench() {
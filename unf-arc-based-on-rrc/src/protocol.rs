@@ -1,7 +1,8 @@
 use rrc::*;
 use rc::*;
+use rrc::parsing::Reader;
+use std::mem::size_of;
 
-#[allow(dead_code)]
 pub struct RrcSendCtWrapper {
     pub flag: usize, 
     ordinal: Ordinal,
@@ -9,6 +10,22 @@ pub struct RrcSendCtWrapper {
     header: Header
 }
 
+impl RrcSendCtWrapper {
+    /// Encodes the wrapper as its flag followed by the payload of rrc's send_bytes().
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.flag.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&encode_payload(self.ordinal, &self.ct, self.header));
+        bytes
+    }
+
+    /// Decodes a wrapper built by encode(), or returns an error if the payload is malformed or exceeds the limits.
+    pub fn decode(payload: &[u8], limits: &DecodeLimits) -> Result<RrcSendCtWrapper, &'static str> {
+        let flag = Reader::new(payload).read_usize()?;
+        let (ordinal, ct, header) = decode_payload(&payload[size_of::<usize>()..], limits)?;
+        Ok(RrcSendCtWrapper{flag, ordinal, ct, header})
+    }
+}
+
 /// Initialize the states of both parties
 pub fn rc_arc_init() -> (RrcState, RrcState) {
     return rrc_init_all(Security::RRid);